@@ -0,0 +1,170 @@
+use log::info;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+    Collection, IndexModel,
+};
+
+use crate::account_service::MyAccountService;
+use bank_common::account_number::{check_digits, MAX_ACCOUNT_NUMBER_LENGTH};
+pub use bank_common::account_number::{is_valid, normalize};
+
+// The BBAN of the account numbers is made of the bank code followed by a zero
+// padded sequence number.
+const ACCOUNT_SEQUENCE_DIGITS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct AccountNumberConfig {
+    pub country_code: String,
+    pub bank_code: String,
+}
+
+impl AccountNumberConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.country_code.len() != 2
+            || !self.country_code.chars().all(|c| c.is_ascii_uppercase())
+        {
+            return Err(format!("Invalid country code: {}", self.country_code));
+        }
+        if self.bank_code.is_empty()
+            || !self
+                .bank_code
+                .chars()
+                .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+        {
+            return Err(format!("Invalid bank code: {}", self.bank_code));
+        }
+        if 4 + self.bank_code.len() + ACCOUNT_SEQUENCE_DIGITS > MAX_ACCOUNT_NUMBER_LENGTH {
+            return Err(format!("Bank code {} is too long", self.bank_code));
+        }
+        Ok(())
+    }
+
+    pub fn account_number(&self, sequence: u64) -> String {
+        let bban = format!(
+            "{}{:0width$}",
+            self.bank_code,
+            sequence,
+            width = ACCOUNT_SEQUENCE_DIGITS
+        );
+        format!(
+            "{}{}{}",
+            self.country_code,
+            check_digits(&self.country_code, &bban),
+            bban
+        )
+    }
+}
+
+impl MyAccountService {
+    fn accounts_collection(&self) -> Collection<Document> {
+        self.db.collection("accounts")
+    }
+
+    // Accounts can be referenced either by their id or by their IBAN-style
+    // account number
+    pub async fn resolve_account_id(&self, account: &str) -> Result<ObjectId, Status> {
+        bank_common::account_number::resolve_account_id(&self.db, account).await
+    }
+
+    // Sequence numbers come from an atomic counter, so concurrent account
+    // creations can never be handed the same account number.
+    pub async fn allocate_account_number(&self) -> Result<String, Status> {
+        let counters_collection: Collection<Document> = self.db.collection("counters");
+
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let counter = counters_collection
+            .find_one_and_update(
+                doc! { "_id": "account_number" },
+                doc! { "$inc": { "sequence": 1_i64 } },
+                options,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to allocate account number: {}", e)))?
+            .ok_or_else(|| Status::internal("Failed to allocate account number"))?;
+
+        let sequence = counter.get_i64("sequence").unwrap();
+
+        Ok(self.account_number_config.account_number(sequence as u64))
+    }
+
+    // Creates the unique index on account numbers and assigns numbers to
+    // accounts that were created before account numbers existed.
+    pub async fn ensure_account_numbers(&self) -> Result<(), Status> {
+        let index = IndexModel::builder()
+            .keys(doc! { "account_number": 1 })
+            .options(IndexOptions::builder().unique(true).sparse(true).build())
+            .build();
+
+        self.accounts_collection()
+            .create_index(index, None)
+            .await
+            .map_err(|e| {
+                Status::internal(format!("Failed to create account number index: {}", e))
+            })?;
+
+        let mut assigned = 0;
+        while let Some(account) = self
+            .accounts_collection()
+            .find_one(doc! { "account_number": { "$exists": false } }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+        {
+            let account_number = self.allocate_account_number().await?;
+
+            self.accounts_collection()
+                .update_one(
+                    doc! { "_id": account.get_object_id("_id").unwrap() },
+                    doc! { "$set": { "account_number": account_number } },
+                    None,
+                )
+                .await
+                .map_err(|e| Status::internal(format!("Failed to update account: {}", e)))?;
+            assigned += 1;
+        }
+
+        if assigned > 0 {
+            info!("Assigned account numbers to {} existing accounts", assigned);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bank_code: &str) -> AccountNumberConfig {
+        AccountNumberConfig {
+            country_code: "GB".to_string(),
+            bank_code: bank_code.to_string(),
+        }
+    }
+
+    #[test]
+    fn account_numbers_are_valid_ibans() {
+        let config = config("WEST");
+        assert_eq!(config.account_number(42), "GB47WEST0000000042");
+        for sequence in [0, 1, 42, 9_999_999_999] {
+            assert!(is_valid(&config.account_number(sequence)));
+        }
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        assert!(config("WEST").validate().is_ok());
+        assert!(config("").validate().is_err());
+        assert!(config("west").validate().is_err());
+        assert!(config(&"1".repeat(21)).validate().is_err());
+        let mut lowercase_country = config("WEST");
+        lowercase_country.country_code = "gb".to_string();
+        assert!(lowercase_country.validate().is_err());
+    }
+}
@@ -19,11 +19,31 @@ pub mod account {
     tonic::include_proto!("account");
 }
 
+use crate::account_number::{self, AccountNumberConfig};
+use crate::adjustment::BALANCE_ADJUSTMENT;
+use crate::approval;
+use bank_common::approval::{ApprovalConfig, ApprovalService};
+use crate::closure::{self, ACCOUNT_CLOSURE};
+use crate::currency::CurrencyConfig;
+use bank_common::health::Dependency;
+use crate::holds::{self, HoldConfig};
+use crate::interest::{Clock, InterestConfig};
+use crate::metadata;
+use crate::outbox;
+use bank_common::outbox::{OutboxConfig, OutboxService};
+use crate::overdraft::OVERDRAFT_PRODUCT;
+
 use account::account_service_server::AccountService;
 use account::{
-    Account, AccountType, CreateAccountRequest, CreateAccountResponse, GetAccountRequest,
-    GetAccountResponse, GetUserAccountsRequest, GetUserAccountsResponse,
-    UpdateAccountRequest, UpdateAccountResponse
+    Account, AccountType, CreateAccountRequest, CreateAccountResponse, GetAccountByNumberRequest,
+    GetAccountByNumberResponse, GetAccountRequest, GetAccountResponse, GetInterestAccrualsRequest,
+    GetInterestAccrualsResponse, GetUserAccountsRequest, GetUserAccountsResponse,
+    SetOverdraftLimitRequest, SetOverdraftLimitResponse, UpdateAccountRequest,
+    UpdateAccountResponse, PlaceHoldRequest, PlaceHoldResponse, CaptureHoldRequest,
+    CaptureHoldResponse, ReleaseHoldRequest, ReleaseHoldResponse, ListHoldsRequest,
+    ListHoldsResponse, AdjustBalanceRequest, AdjustBalanceResponse, CloseAccountRequest,
+    CloseAccountResponse, ListPendingOperationsRequest, ListPendingOperationsResponse,
+    ReviewOperationRequest, ReviewOperationResponse
 };
 
 impl Display for AccountType {
@@ -51,15 +71,46 @@ impl FromStr for AccountType {
 
 #[derive(Debug, Clone)]
 pub struct MyAccountService {
-    db: Arc<mongodb::Database>,
+    pub(crate) client: Client,
+    pub(crate) db: Arc<mongodb::Database>,
+    pub(crate) account_number_config: AccountNumberConfig,
+    pub(crate) interest_config: InterestConfig,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) currency_config: CurrencyConfig,
+    pub(crate) hold_config: HoldConfig,
+    pub(crate) approval_config: ApprovalConfig,
+    pub(crate) outbox_config: OutboxConfig,
 }
 
 impl MyAccountService {
-    pub async fn new(uri: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        uri: &str,
+        account_number_config: AccountNumberConfig,
+        interest_config: InterestConfig,
+        clock: Arc<dyn Clock>,
+        currency_config: CurrencyConfig,
+        hold_config: HoldConfig,
+        approval_config: ApprovalConfig,
+        outbox_config: OutboxConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        account_number_config.validate()?;
+        currency_config.validate()?;
+
         let client_options = ClientOptions::parse(uri).await?;
         let client = Client::with_options(client_options)?;
         let db = client.database("bank");
-        Ok(Self { db: Arc::new(db) })
+        Ok(Self {
+            client,
+            db: Arc::new(db),
+            account_number_config,
+            interest_config,
+            clock,
+            currency_config,
+            hold_config,
+            approval_config,
+            outbox_config,
+        })
     }
 
     pub async fn test_connection(&self) -> Result<(), mongodb::error::Error> {
@@ -68,6 +119,27 @@ impl MyAccountService {
     }
 }
 
+#[tonic::async_trait]
+impl ApprovalService for MyAccountService {
+    const SERVICE: &'static str = "account";
+
+    fn approval_db(&self) -> &mongodb::Database {
+        &self.db
+    }
+
+    fn approval_config(&self) -> &ApprovalConfig {
+        &self.approval_config
+    }
+
+    async fn execute_operation(&self, operation: &Document) -> Result<Document, Status> {
+        match operation.get_str("kind").unwrap() {
+            BALANCE_ADJUSTMENT => self.apply_adjustment(operation).await,
+            ACCOUNT_CLOSURE => self.apply_closure(operation).await,
+            kind => Err(Status::internal(format!("Unknown operation kind: {}", kind))),
+        }
+    }
+}
+
 pub fn account_type_to_string(value: i32) -> String {
     match value {
         0 => "CHECKING".to_string(),
@@ -76,6 +148,19 @@ pub fn account_type_to_string(value: i32) -> String {
     }
 }
 
+#[tonic::async_trait]
+impl Dependency for MyAccountService {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.test_connection()
+            .await
+            .map_err(|e| format!("Failed to ping MongoDB: {}", e))
+    }
+}
+
 #[tonic::async_trait]
 impl AccountService for MyAccountService {
     async fn create_account(
@@ -88,33 +173,76 @@ impl AccountService for MyAccountService {
         // Log the account creation request
         info!("Creating account for user_id: {}", req.user_id);
 
-        let new_account = doc! {
-            "user_id": req.user_id,
-            "account_type": account_type_to_string(req.account_type),
+        let interest_product = if req.account_type == AccountType::Savings as i32 {
+            let product = self
+                .interest_product(&req.interest_product)
+                .map_err(Status::invalid_argument)?;
+            Some(product.name.clone())
+        } else {
+            None
+        };
+
+        let currency = self
+            .currency_config
+            .account_currency(&req.currency)
+            .map_err(Status::invalid_argument)?;
+
+        let account_number = self.allocate_account_number().await?;
+
+        let account_type = account_type_to_string(req.account_type);
+        let mut new_account = doc! {
+            "user_id": &req.user_id,
+            "account_type": &account_type,
             "account_name": req.account_name,
+            "account_number": &account_number,
+            "currency": &currency,
             "balance": 0.0
         };
 
+        // Savings accounts earn interest from their first full day
+        if let Some(interest_product) = interest_product {
+            new_account.insert("interest_product", interest_product);
+            new_account.insert("interest_accrued_through", self.interest_start_date());
+            new_account.insert("accrued_interest", 0.0);
+        }
+
+        // The account and its AccountCreated event are written atomically
+        let mut session = self.start_transaction().await?;
+
         let insert_result = accounts_collection
-            .insert_one(new_account, None)
+            .insert_one_with_session(new_account, None, &mut session)
             .await
             .map_err(|e| Status::internal(format!("Failed to create account: {}", e)))?;
 
-        let account_id = insert_result
-            .inserted_id
-            .as_object_id()
-            .map(|id| id.to_hex())
-            .ok_or_else(|| {
-                Status::internal("Failed to create account: missing inserted_id".to_string())
-            })?;
+        let account_object_id = insert_result.inserted_id.as_object_id().ok_or_else(|| {
+            Status::internal("Failed to create account: missing inserted_id".to_string())
+        })?;
+        let account_id = account_object_id.to_hex();
+
+        let event = outbox::new_event(
+            outbox::ACCOUNT_CREATED,
+            account_object_id,
+            doc! {
+                "account_id": &account_id,
+                "user_id": &req.user_id,
+                "account_number": &account_number,
+                "account_type": account_type,
+                "currency": currency,
+            },
+        );
+        self.record_events(&mut session, vec![event]).await?;
+        self.commit_transaction(&mut session).await?;
 
         // Log the successful account creation
         info!(
-            "Account created successfully with account_id: {}",
-            account_id
+            "Account created successfully with account_id: {} and account_number: {}",
+            account_id, account_number
         );
 
-        let response = CreateAccountResponse { account_id };
+        let response = CreateAccountResponse {
+            account_id,
+            account_number,
+        };
         Ok(Response::new(response))
     }
 
@@ -148,11 +276,31 @@ impl AccountService for MyAccountService {
                     account_id: account_doc.get_object_id("_id").unwrap().to_string(),
                     user_id: account_doc.get_str("user_id").unwrap().to_string(),
                     account_name: account_doc.get_str("account_name").unwrap().to_string(),
+                    account_number: account_doc
+                        .get_str("account_number")
+                        .unwrap_or_default()
+                        .to_string(),
+                    interest_product: account_doc
+                        .get_str("interest_product")
+                        .unwrap_or_default()
+                        .to_string(),
+                    accrued_interest: account_doc.get_f64("accrued_interest").unwrap_or_default(),
+                    overdraft_limit: account_doc.get_f64("overdraft_limit").unwrap_or_default(),
+                    overdraft_rate: account_doc.get_f64("overdraft_rate").unwrap_or_default(),
+                    accrued_overdraft_interest: account_doc
+                        .get_f64("accrued_overdraft_interest")
+                        .unwrap_or_default(),
+                    currency: account_doc.get_str("currency").unwrap_or_default().to_string(),
                     account_type: AccountType::from_str(
                         account_doc.get_str("account_type").unwrap(),
                     )
                     .unwrap() as i32,
                     balance: account_doc.get_f64("balance").unwrap(),
+                    held_amount: holds::held_amount(&account_doc),
+                    available_balance: holds::available_balance(&account_doc),
+                    nickname: account_doc.get_str("nickname").unwrap_or_default().to_string(),
+                    settings: metadata::account_settings(&account_doc),
+                    closed: closure::is_closed(&account_doc),
                     created_at: None, // We didn't store created_at and updated_at in the database, so we can't return them here.
                     updated_at: None,
                 }),
@@ -186,19 +334,24 @@ impl AccountService for MyAccountService {
             Err(_) => return Err(Status::invalid_argument("Invalid account id")),
         };
 
+        metadata::validate_update(&req).map_err(Status::invalid_argument)?;
+
         let filter_update = doc! {
             "_id": object_id,
         };
 
-        let update = doc! {
-            "$set": {
-                "balance": req.balance,
-                //"updated_at": Bson::DateTime(bson::DateTime::now()) TODO
-            }
-        };
+        // Customers can only change the metadata of their own accounts
+        let current_account = accounts_collection
+            .find_one(filter_update.clone(), None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+        if !req.user_id.is_empty() && current_account.get_str("user_id") != Ok(req.user_id.as_str()) {
+            return Err(Status::permission_denied("Account belongs to another user"));
+        }
 
         let _ = accounts_collection
-            .update_one(filter_update, update, None)
+            .update_one(filter_update, metadata::metadata_update(&req), None)
             .await
             .map_err(|e| Status::internal(format!("Failed to update account: {}", e)))?;
 
@@ -220,7 +373,27 @@ impl AccountService for MyAccountService {
                 account_type: AccountType::from_str(account_doc.get_str("account_type").unwrap())
                     .unwrap() as i32,
                 account_name: account_doc.get_str("account_name").unwrap().to_string(),
+                account_number: account_doc
+                    .get_str("account_number")
+                    .unwrap_or_default()
+                    .to_string(),
+                interest_product: account_doc
+                    .get_str("interest_product")
+                    .unwrap_or_default()
+                    .to_string(),
+                accrued_interest: account_doc.get_f64("accrued_interest").unwrap_or_default(),
+                overdraft_limit: account_doc.get_f64("overdraft_limit").unwrap_or_default(),
+                overdraft_rate: account_doc.get_f64("overdraft_rate").unwrap_or_default(),
+                accrued_overdraft_interest: account_doc
+                    .get_f64("accrued_overdraft_interest")
+                    .unwrap_or_default(),
+                currency: account_doc.get_str("currency").unwrap_or_default().to_string(),
                 balance: account_doc.get_f64("balance").unwrap(),
+                held_amount: holds::held_amount(&account_doc),
+                available_balance: holds::available_balance(&account_doc),
+                nickname: account_doc.get_str("nickname").unwrap_or_default().to_string(),
+                settings: metadata::account_settings(&account_doc),
+                closed: closure::is_closed(&account_doc),
                 created_at: None,
                 updated_at: None
             };
@@ -271,9 +444,29 @@ impl AccountService for MyAccountService {
                     _ => return Err(Status::internal("Invalid account type")),
                 },
                 balance: result.get_f64("balance").unwrap(),
+                held_amount: holds::held_amount(&result),
+                available_balance: holds::available_balance(&result),
+                nickname: result.get_str("nickname").unwrap_or_default().to_string(),
+                settings: metadata::account_settings(&result),
+                closed: closure::is_closed(&result),
                 created_at: None,
                 updated_at: None,
-                account_name: result.get_str("account_name").unwrap().to_string()
+                account_name: result.get_str("account_name").unwrap().to_string(),
+                account_number: result
+                    .get_str("account_number")
+                    .unwrap_or_default()
+                    .to_string(),
+                interest_product: result
+                    .get_str("interest_product")
+                    .unwrap_or_default()
+                    .to_string(),
+                accrued_interest: result.get_f64("accrued_interest").unwrap_or_default(),
+                overdraft_limit: result.get_f64("overdraft_limit").unwrap_or_default(),
+                overdraft_rate: result.get_f64("overdraft_rate").unwrap_or_default(),
+                accrued_overdraft_interest: result
+                    .get_f64("accrued_overdraft_interest")
+                    .unwrap_or_default(),
+                currency: result.get_str("currency").unwrap_or_default().to_string(),
             };
             accounts.push(account);
         }
@@ -281,4 +474,302 @@ impl AccountService for MyAccountService {
         let response = GetUserAccountsResponse { accounts };
         Ok(Response::new(response))
     }
+
+    async fn get_account_by_number(
+        &self,
+        request: Request<GetAccountByNumberRequest>,
+    ) -> Result<Response<GetAccountByNumberResponse>, Status> {
+        let req = request.into_inner();
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        // Log the account fetching request
+        info!("Fetching account with account_number: {}", req.account_number);
+
+        if !account_number::is_valid(&req.account_number) {
+            error!("Invalid account number: {}", req.account_number);
+            return Err(Status::invalid_argument("Invalid account number"));
+        }
+
+        let filter = doc! {
+            "account_number": account_number::normalize(&req.account_number),
+        };
+
+        let account_doc = accounts_collection
+            .find_one(filter, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| {
+                error!("Account not found with account_number: {}", req.account_number);
+                Status::not_found("Account not found")
+            })?;
+
+        let account = Account {
+            account_id: account_doc.get_object_id("_id").unwrap().to_string(),
+            user_id: account_doc.get_str("user_id").unwrap().to_string(),
+            account_type: AccountType::from_str(account_doc.get_str("account_type").unwrap())
+                .unwrap() as i32,
+            account_name: account_doc.get_str("account_name").unwrap().to_string(),
+            account_number: account_doc.get_str("account_number").unwrap().to_string(),
+            interest_product: account_doc
+                .get_str("interest_product")
+                .unwrap_or_default()
+                .to_string(),
+            accrued_interest: account_doc.get_f64("accrued_interest").unwrap_or_default(),
+            overdraft_limit: account_doc.get_f64("overdraft_limit").unwrap_or_default(),
+            overdraft_rate: account_doc.get_f64("overdraft_rate").unwrap_or_default(),
+            accrued_overdraft_interest: account_doc
+                .get_f64("accrued_overdraft_interest")
+                .unwrap_or_default(),
+            currency: account_doc.get_str("currency").unwrap_or_default().to_string(),
+            balance: account_doc.get_f64("balance").unwrap(),
+            held_amount: holds::held_amount(&account_doc),
+            available_balance: holds::available_balance(&account_doc),
+            nickname: account_doc.get_str("nickname").unwrap_or_default().to_string(),
+            settings: metadata::account_settings(&account_doc),
+            closed: closure::is_closed(&account_doc),
+            created_at: None,
+            updated_at: None
+        };
+
+        let response = GetAccountByNumberResponse {
+            account: Some(account),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn get_interest_accruals(
+        &self,
+        request: Request<GetInterestAccrualsRequest>,
+    ) -> Result<Response<GetInterestAccrualsResponse>, Status> {
+        let req = request.into_inner();
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        info!("Fetching interest accruals for account_id: {}", req.account_id);
+
+        let account_id = match ObjectId::from_str(&req.account_id) {
+            Ok(oid) => oid,
+            Err(_) => return Err(Status::invalid_argument("Invalid account id")),
+        };
+
+        let account_doc = accounts_collection
+            .find_one(doc! { "_id": account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        // Checking accounts with an arranged overdraft accrue overdraft
+        // interest, which is reported as a negative accrual
+        let (interest_product, accrued_interest) =
+            if account_doc.get_str("account_type").unwrap() == AccountType::Savings.to_string() {
+                let product = self
+                    .interest_product(account_doc.get_str("interest_product").unwrap_or_default())
+                    .map_err(Status::internal)?;
+                (
+                    product.name.clone(),
+                    account_doc.get_f64("accrued_interest").unwrap_or_default(),
+                )
+            } else if account_doc.get_str("overdraft_accrued_through").is_ok() {
+                (
+                    OVERDRAFT_PRODUCT.to_string(),
+                    -account_doc
+                        .get_f64("accrued_overdraft_interest")
+                        .unwrap_or_default(),
+                )
+            } else {
+                return Err(Status::failed_precondition(
+                    "Only savings accounts and accounts with an overdraft accrue interest",
+                ));
+            };
+
+        let accruals = self
+            .get_interest_accruals(account_id, &req.from_date, &req.to_date)
+            .await?;
+
+        let response = GetInterestAccrualsResponse {
+            interest_product,
+            accrued_interest,
+            accruals,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn set_overdraft_limit(
+        &self,
+        request: Request<SetOverdraftLimitRequest>,
+    ) -> Result<Response<SetOverdraftLimitResponse>, Status> {
+        let req = request.into_inner();
+
+        info!(
+            "Setting overdraft limit for account_id: {} requested by {}",
+            req.account_id, req.updated_by
+        );
+
+        let account_doc = self.set_overdraft_limit(&req).await?;
+
+        let account = Account {
+            account_id: account_doc.get_object_id("_id").unwrap().to_string(),
+            user_id: account_doc.get_str("user_id").unwrap().to_string(),
+            account_type: AccountType::from_str(account_doc.get_str("account_type").unwrap())
+                .unwrap() as i32,
+            account_name: account_doc.get_str("account_name").unwrap().to_string(),
+            account_number: account_doc
+                .get_str("account_number")
+                .unwrap_or_default()
+                .to_string(),
+            interest_product: String::new(),
+            accrued_interest: 0.0,
+            overdraft_limit: account_doc.get_f64("overdraft_limit").unwrap_or_default(),
+            overdraft_rate: account_doc.get_f64("overdraft_rate").unwrap_or_default(),
+            accrued_overdraft_interest: account_doc
+                .get_f64("accrued_overdraft_interest")
+                .unwrap_or_default(),
+            currency: account_doc.get_str("currency").unwrap_or_default().to_string(),
+            balance: account_doc.get_f64("balance").unwrap(),
+            held_amount: holds::held_amount(&account_doc),
+            available_balance: holds::available_balance(&account_doc),
+            nickname: account_doc.get_str("nickname").unwrap_or_default().to_string(),
+            settings: metadata::account_settings(&account_doc),
+            closed: closure::is_closed(&account_doc),
+            created_at: None,
+            updated_at: None
+        };
+
+        let response = SetOverdraftLimitResponse {
+            account: Some(account),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn place_hold(
+        &self,
+        request: Request<PlaceHoldRequest>,
+    ) -> Result<Response<PlaceHoldResponse>, Status> {
+        let req = request.into_inner();
+
+        info!("Placing hold of {} on account_id: {}", req.amount, req.account_id);
+
+        let hold = self.place_hold(&req).await?;
+
+        let response = PlaceHoldResponse {
+            hold: Some(holds::hold_to_proto(&hold)),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn capture_hold(
+        &self,
+        request: Request<CaptureHoldRequest>,
+    ) -> Result<Response<CaptureHoldResponse>, Status> {
+        let req = request.into_inner();
+
+        info!("Capturing hold_id: {}", req.hold_id);
+
+        let (hold, transaction_id) = self.capture_hold(&req).await?;
+
+        let response = CaptureHoldResponse {
+            hold: Some(holds::hold_to_proto(&hold)),
+            transaction_id: transaction_id.to_hex(),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn release_hold(
+        &self,
+        request: Request<ReleaseHoldRequest>,
+    ) -> Result<Response<ReleaseHoldResponse>, Status> {
+        let req = request.into_inner();
+
+        info!("Releasing hold_id: {}", req.hold_id);
+
+        let hold = self.release_hold(&req).await?;
+
+        let response = ReleaseHoldResponse {
+            hold: Some(holds::hold_to_proto(&hold)),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_holds(
+        &self,
+        request: Request<ListHoldsRequest>,
+    ) -> Result<Response<ListHoldsResponse>, Status> {
+        let req = request.into_inner();
+
+        let holds = self.list_holds(&req).await?;
+
+        let response = ListHoldsResponse {
+            holds: holds.iter().map(holds::hold_to_proto).collect(),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn adjust_balance(
+        &self,
+        request: Request<AdjustBalanceRequest>,
+    ) -> Result<Response<AdjustBalanceResponse>, Status> {
+        let req = request.into_inner();
+
+        let operation = self.request_adjustment(&req).await?;
+
+        let response = AdjustBalanceResponse {
+            operation: Some(approval::operation_to_proto(&operation)),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn close_account(
+        &self,
+        request: Request<CloseAccountRequest>,
+    ) -> Result<Response<CloseAccountResponse>, Status> {
+        let req = request.into_inner();
+
+        let operation = self.request_closure(&req).await?;
+
+        let response = CloseAccountResponse {
+            operation: Some(approval::operation_to_proto(&operation)),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_pending_operations(
+        &self,
+        request: Request<ListPendingOperationsRequest>,
+    ) -> Result<Response<ListPendingOperationsResponse>, Status> {
+        let req = request.into_inner();
+
+        let operations = self.list_operations(&req.status, &req.kind).await?;
+
+        let response = ListPendingOperationsResponse {
+            operations: operations.iter().map(approval::operation_to_proto).collect(),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn approve_operation(
+        &self,
+        request: Request<ReviewOperationRequest>,
+    ) -> Result<Response<ReviewOperationResponse>, Status> {
+        let req = request.into_inner();
+
+        let operation = self.review_operation(&approval::review(&req), true).await?;
+
+        let response = ReviewOperationResponse {
+            operation: Some(approval::operation_to_proto(&operation)),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn reject_operation(
+        &self,
+        request: Request<ReviewOperationRequest>,
+    ) -> Result<Response<ReviewOperationResponse>, Status> {
+        let req = request.into_inner();
+
+        let operation = self.review_operation(&approval::review(&req), false).await?;
+
+        let response = ReviewOperationResponse {
+            operation: Some(approval::operation_to_proto(&operation)),
+        };
+        Ok(Response::new(response))
+    }
 }
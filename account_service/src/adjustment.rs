@@ -0,0 +1,121 @@
+use log::info;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use bank_common::overdraft;
+
+use crate::account_service::account::AdjustBalanceRequest;
+use crate::account_service::MyAccountService;
+use bank_common::approval::{ApprovalService, NewOperation, ADMIN_ROLE};
+use crate::closure::{self, CLOSED_STATUS};
+
+// Balances are never overwritten. Corrections are requested as signed balance
+// adjustments with a reason, and are only booked, as `Adjustment`
+// transactions, once an administrator other than the requester approves them.
+pub const BALANCE_ADJUSTMENT: &str = "BALANCE_ADJUSTMENT";
+
+fn validate_adjustment(req: &AdjustBalanceRequest) -> Result<(), String> {
+    if !req.amount.is_finite() || req.amount == 0.0 {
+        return Err("Adjustment amount must be a non-zero number".to_string());
+    }
+    if ((req.amount * 100.0).round() - req.amount * 100.0).abs() > 1e-6 {
+        return Err("Amount cannot have more than 2 decimal places".to_string());
+    }
+    if req.reason.trim().is_empty() {
+        return Err("A reason is required for balance adjustments".to_string());
+    }
+    if req.requested_by.is_empty() {
+        return Err("The requester of the adjustment is required".to_string());
+    }
+    Ok(())
+}
+
+impl MyAccountService {
+    pub async fn request_adjustment(&self, req: &AdjustBalanceRequest) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        validate_adjustment(req).map_err(Status::invalid_argument)?;
+        let account_id = ObjectId::parse_str(&req.account_id)
+            .map_err(|_| Status::invalid_argument("Invalid account id"))?;
+
+        let account = accounts_collection
+            .find_one(doc! { "_id": account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+        if closure::is_closed(&account) {
+            return Err(Status::failed_precondition("Account is closed"));
+        }
+
+        self.request_operation(NewOperation {
+            kind: BALANCE_ADJUSTMENT,
+            summary: format!(
+                "Adjust the balance of account {} by {:.2}: {}",
+                account.get_str("account_number").unwrap_or_default(),
+                req.amount,
+                req.reason.trim()
+            ),
+            payload: doc! {
+                "account_id": account_id,
+                "amount": req.amount,
+                "reason": req.reason.trim(),
+            },
+            requested_by: &req.requested_by,
+            required_role: ADMIN_ROLE,
+        })
+        .await
+    }
+
+    pub async fn apply_adjustment(&self, operation: &Document) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+
+        let operation_id = operation.get_object_id("_id").unwrap();
+        let payload = operation.get_document("payload").unwrap();
+        let account_id = payload.get_object_id("account_id").unwrap();
+        let amount = payload.get_f64("amount").unwrap();
+
+        let account = accounts_collection
+            .find_one_and_update(
+                doc! { "_id": account_id, "status": { "$ne": CLOSED_STATUS } },
+                doc! { "$inc": { "balance": amount } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to adjust balance: {}", e)))?
+            .ok_or_else(|| Status::failed_precondition("Account not found or closed"))?;
+
+        let transaction = doc! {
+            "account_id": account_id,
+            "amount": amount,
+            "type": "Adjustment",
+            "operation_id": operation_id,
+            "reason": payload.get_str("reason").unwrap_or_default(),
+            "timestamp": DateTime::now(),
+        };
+        let transaction_id = transactions_collection
+            .insert_one(transaction, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap();
+
+        info!(
+            "Balance adjustment of {} on account {} booked as transaction {}",
+            amount, account_id, transaction_id
+        );
+
+        let balance = account.get_f64("balance").unwrap();
+        overdraft::notify_overdraft(&self.db, &account, balance - amount, balance).await?;
+
+        Ok(doc! { "transaction_id": transaction_id.to_hex() })
+    }
+}
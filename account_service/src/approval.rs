@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+use mongodb::bson::{Bson, Document};
+
+use crate::account_service::account::{OperationEvent, PendingOperation, ReviewOperationRequest};
+use bank_common::approval::{OperationStatus, Review};
+
+// The maker-checker workflow itself lives in bank_common::approval; this maps
+// the operations it stores to the protos of the service
+fn event_to_proto(event: &Document) -> OperationEvent {
+    OperationEvent {
+        action: event.get_str("action").unwrap_or_default().to_string(),
+        user_id: event.get_str("user_id").unwrap_or_default().to_string(),
+        comment: event.get_str("comment").unwrap_or_default().to_string(),
+        timestamp: event.get_datetime("timestamp").unwrap().timestamp_millis(),
+    }
+}
+
+pub fn operation_to_proto(operation: &Document) -> PendingOperation {
+    PendingOperation {
+        operation_id: operation.get_object_id("_id").unwrap().to_hex(),
+        kind: operation.get_str("kind").unwrap().to_string(),
+        summary: operation.get_str("summary").unwrap_or_default().to_string(),
+        status: OperationStatus::from_str(operation.get_str("status").unwrap()).unwrap() as i32,
+        requested_by: operation.get_str("requested_by").unwrap().to_string(),
+        required_role: operation.get_str("required_role").unwrap().to_string(),
+        reviewed_by: operation
+            .get_str("reviewed_by")
+            .unwrap_or_default()
+            .to_string(),
+        review_comment: operation
+            .get_str("review_comment")
+            .unwrap_or_default()
+            .to_string(),
+        result: operation
+            .get_document("result")
+            .map(|result| {
+                result
+                    .iter()
+                    .filter_map(|(key, value)| value.as_str().map(|v| (key.clone(), v.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        error: operation.get_str("error").unwrap_or_default().to_string(),
+        created_timestamp: operation
+            .get_datetime("created_at")
+            .unwrap()
+            .timestamp_millis(),
+        expires_timestamp: operation
+            .get_datetime("expires_at")
+            .unwrap()
+            .timestamp_millis(),
+        reviewed_timestamp: operation
+            .get_datetime("reviewed_at")
+            .map(|reviewed_at| reviewed_at.timestamp_millis())
+            .unwrap_or_default(),
+        history: operation
+            .get_array("history")
+            .map(|history| {
+                history
+                    .iter()
+                    .filter_map(Bson::as_document)
+                    .map(event_to_proto)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+pub fn review(req: &ReviewOperationRequest) -> Review<'_> {
+    Review {
+        operation_id: &req.operation_id,
+        reviewed_by: &req.reviewed_by,
+        reviewer_roles: &req.reviewer_roles,
+        comment: &req.comment,
+    }
+}
@@ -0,0 +1,131 @@
+use log::info;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    Collection,
+};
+
+use crate::account_service::account::CloseAccountRequest;
+use crate::account_service::MyAccountService;
+use bank_common::approval::{ApprovalService, NewOperation, ADMIN_ROLE};
+use crate::holds;
+
+// Accounts are never deleted. Closing one goes through approval and is only
+// possible once the account is empty; closed accounts keep their history but
+// no longer take part in transactions.
+pub const ACCOUNT_CLOSURE: &str = "ACCOUNT_CLOSURE";
+pub const CLOSED_STATUS: &str = "CLOSED";
+
+pub fn is_closed(account: &Document) -> bool {
+    account.get_str("status") == Ok(CLOSED_STATUS)
+}
+
+fn check_closable(account: &Document) -> Result<(), String> {
+    if is_closed(account) {
+        return Err("Account is already closed".to_string());
+    }
+    if holds::held_amount(account) > 0.0 {
+        return Err("Account has funds on hold".to_string());
+    }
+    let balance = account.get_f64("balance").unwrap();
+    if balance.abs() >= 0.005 {
+        return Err(format!(
+            "Account balance must be zero to close it, it is {:.2}",
+            balance
+        ));
+    }
+    Ok(())
+}
+
+impl MyAccountService {
+    pub(crate) async fn find_account(&self, account_id: ObjectId) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        accounts_collection
+            .find_one(doc! { "_id": account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))
+    }
+
+    pub async fn request_closure(&self, req: &CloseAccountRequest) -> Result<Document, Status> {
+        let account_id = ObjectId::parse_str(&req.account_id)
+            .map_err(|_| Status::invalid_argument("Invalid account id"))?;
+        if req.reason.trim().is_empty() {
+            return Err(Status::invalid_argument(
+                "A reason is required to close an account",
+            ));
+        }
+        if req.requested_by.is_empty() {
+            return Err(Status::invalid_argument(
+                "The requester of the closure is required",
+            ));
+        }
+
+        let account = self.find_account(account_id).await?;
+        if req.requester_roles.is_empty()
+            && account.get_str("user_id") != Ok(req.requested_by.as_str())
+        {
+            return Err(Status::permission_denied("Account belongs to another user"));
+        }
+        check_closable(&account).map_err(Status::failed_precondition)?;
+
+        self.request_operation(NewOperation {
+            kind: ACCOUNT_CLOSURE,
+            summary: format!(
+                "Close account {}: {}",
+                account.get_str("account_number").unwrap_or_default(),
+                req.reason.trim()
+            ),
+            payload: doc! {
+                "account_id": account_id,
+                "reason": req.reason.trim(),
+            },
+            requested_by: &req.requested_by,
+            required_role: ADMIN_ROLE,
+        })
+        .await
+    }
+
+    pub async fn apply_closure(&self, operation: &Document) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let payload = operation.get_document("payload").unwrap();
+        let account_id = payload.get_object_id("account_id").unwrap();
+
+        let account = self.find_account(account_id).await?;
+        check_closable(&account).map_err(Status::failed_precondition)?;
+
+        // The balance or holds may have changed since the check above
+        let update_result = accounts_collection
+            .update_one(
+                doc! {
+                    "_id": account_id,
+                    "status": { "$ne": CLOSED_STATUS },
+                    "balance": { "$gt": -0.005, "$lt": 0.005 },
+                    "held_amount": { "$not": { "$gt": 0.0 } },
+                },
+                doc! {
+                    "$set": {
+                        "status": CLOSED_STATUS,
+                        "closed_at": DateTime::now(),
+                        "closed_by": operation.get_str("reviewed_by").unwrap_or_default(),
+                        "closure_reason": payload.get_str("reason").unwrap_or_default(),
+                    }
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to close account: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Err(Status::failed_precondition(
+                "Account changed while it was being closed",
+            ));
+        }
+
+        info!("Account {} closed", account_id);
+
+        Ok(doc! { "account_id": account_id.to_hex() })
+    }
+}
@@ -0,0 +1,76 @@
+use log::info;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, Document},
+    Collection,
+};
+
+use crate::account_service::MyAccountService;
+
+// Every account is held in a single ISO 4217 currency chosen when it is
+// opened. Accounts opened before currencies existed use the default one.
+#[derive(Debug, Clone)]
+pub struct CurrencyConfig {
+    pub default_currency: String,
+    pub supported_currencies: Vec<String>,
+}
+
+fn is_currency_code(currency: &str) -> bool {
+    currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase())
+}
+
+impl CurrencyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for currency in &self.supported_currencies {
+            if !is_currency_code(currency) {
+                return Err(format!("Invalid currency code: {}", currency));
+            }
+        }
+        if !self.supported_currencies.contains(&self.default_currency) {
+            return Err(format!(
+                "Default currency {} is not a supported currency",
+                self.default_currency
+            ));
+        }
+        Ok(())
+    }
+
+    // Currency of a new account; an empty one means the default currency
+    pub fn account_currency(&self, requested: &str) -> Result<String, String> {
+        if requested.is_empty() {
+            return Ok(self.default_currency.clone());
+        }
+
+        let currency = requested.to_uppercase();
+        if self.supported_currencies.contains(&currency) {
+            Ok(currency)
+        } else {
+            Err(format!("Unsupported currency: {}", requested))
+        }
+    }
+}
+
+impl MyAccountService {
+    pub async fn ensure_account_currencies(&self) -> Result<(), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let update_result = accounts_collection
+            .update_many(
+                doc! { "currency": { "$exists": false } },
+                doc! { "$set": { "currency": &self.currency_config.default_currency } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update accounts: {}", e)))?;
+
+        if update_result.modified_count > 0 {
+            info!(
+                "Assigned currency {} to {} existing accounts",
+                self.currency_config.default_currency, update_result.modified_count
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,447 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use log::{error, info};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Collection,
+};
+
+use bank_common::outbox::OutboxService;
+use bank_common::overdraft;
+
+use crate::account_service::account::{
+    CaptureHoldRequest, Hold, HoldStatus, ListHoldsRequest, PlaceHoldRequest, ReleaseHoldRequest,
+};
+use crate::account_service::MyAccountService;
+use crate::closure;
+use crate::outbox;
+
+// Holds reserve funds for card-like and two-phase payments. The reserved
+// total is kept in the account's `held_amount`, so the available balance is
+// the ledger balance minus `held_amount`, plus any arranged overdraft when
+// checking whether new debits fit.
+#[derive(Debug, Clone)]
+pub struct HoldConfig {
+    pub default_ttl: Duration,
+    pub expiry_interval: std::time::Duration,
+}
+
+impl Display for HoldStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let hold_status_str = match self {
+            HoldStatus::Active => "ACTIVE",
+            HoldStatus::Captured => "CAPTURED",
+            HoldStatus::Released => "RELEASED",
+            HoldStatus::Expired => "EXPIRED",
+        };
+
+        write!(f, "{}", hold_status_str)
+    }
+}
+
+impl FromStr for HoldStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ACTIVE" => Ok(HoldStatus::Active),
+            "CAPTURED" => Ok(HoldStatus::Captured),
+            "RELEASED" => Ok(HoldStatus::Released),
+            "EXPIRED" => Ok(HoldStatus::Expired),
+            _ => Err(format!("Invalid hold status: {}", s)),
+        }
+    }
+}
+
+pub fn held_amount(account: &Document) -> f64 {
+    account.get_f64("held_amount").unwrap_or_default()
+}
+
+// The ledger balance less the funds reserved by active holds
+pub fn available_balance(account: &Document) -> f64 {
+    account.get_f64("balance").unwrap() - held_amount(account)
+}
+
+pub fn hold_to_proto(hold: &Document) -> Hold {
+    Hold {
+        hold_id: hold.get_object_id("_id").unwrap().to_hex(),
+        account_id: hold.get_object_id("account_id").unwrap().to_hex(),
+        amount: hold.get_f64("amount").unwrap(),
+        captured_amount: hold.get_f64("captured_amount").unwrap_or_default(),
+        status: HoldStatus::from_str(hold.get_str("status").unwrap()).unwrap() as i32,
+        reference: hold.get_str("reference").unwrap_or_default().to_string(),
+        description: hold.get_str("description").unwrap_or_default().to_string(),
+        created_timestamp: hold.get_datetime("created_at").unwrap().timestamp_millis(),
+        expires_timestamp: hold.get_datetime("expires_at").unwrap().timestamp_millis(),
+    }
+}
+
+fn validate_hold_amount(amount: f64) -> Result<(), String> {
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err("Amount must be greater than zero".to_string());
+    }
+    if ((amount * 100.0).round() - amount * 100.0).abs() > 1e-6 {
+        return Err("Amount cannot have more than 2 decimal places".to_string());
+    }
+    Ok(())
+}
+
+impl MyAccountService {
+    fn holds_collection(&self) -> Collection<Document> {
+        self.db.collection("holds")
+    }
+
+    async fn get_hold(&self, hold_id: ObjectId) -> Result<Document, Status> {
+        self.holds_collection()
+            .find_one(doc! { "_id": hold_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get hold: {}", e)))?
+            .ok_or_else(|| Status::not_found("Hold not found"))
+    }
+
+    // The account a hold is placed on, if the requester owns it or is bank
+    // staff
+    async fn hold_account(
+        &self,
+        account_id: ObjectId,
+        requested_by: &str,
+        requester_roles: &[String],
+    ) -> Result<Document, Status> {
+        if requested_by.is_empty() {
+            return Err(Status::invalid_argument(
+                "The requester of the hold is required",
+            ));
+        }
+
+        let account = self.find_account(account_id).await?;
+        if requester_roles.is_empty() && account.get_str("user_id") != Ok(requested_by) {
+            return Err(Status::permission_denied("Account belongs to another user"));
+        }
+        Ok(account)
+    }
+
+    // Gives the reserved funds back to the account of an active hold
+    async fn end_hold(&self, hold: &Document, status: HoldStatus) -> Result<bool, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let hold_id = hold.get_object_id("_id").unwrap();
+
+        let update_result = self
+            .holds_collection()
+            .update_one(
+                doc! { "_id": hold_id, "status": HoldStatus::Active.to_string() },
+                doc! { "$set": { "status": status.to_string(), "updated_at": DateTime::now() } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update hold: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Ok(false);
+        }
+
+        accounts_collection
+            .update_one(
+                doc! { "_id": hold.get_object_id("account_id").unwrap() },
+                doc! { "$inc": { "held_amount": -hold.get_f64("amount").unwrap() } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update account: {}", e)))?;
+
+        Ok(true)
+    }
+
+    pub async fn place_hold(&self, req: &PlaceHoldRequest) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        validate_hold_amount(req.amount).map_err(Status::invalid_argument)?;
+        if req.expires_in_seconds < 0 {
+            return Err(Status::invalid_argument("Hold expiry cannot be negative"));
+        }
+        let account_id = self.resolve_account_id(&req.account_id).await?;
+
+        let account = self
+            .hold_account(account_id, &req.requested_by, &req.requester_roles)
+            .await?;
+        if closure::is_closed(&account) {
+            return Err(Status::failed_precondition("Account is closed"));
+        }
+
+        // The funds are only reserved if they are available at this very moment
+        let filter = doc! {
+            "_id": account_id,
+            "$expr": {
+                "$gte": [
+                    {
+                        "$subtract": [
+                            { "$add": ["$balance", { "$ifNull": ["$overdraft_limit", 0.0] }] },
+                            { "$ifNull": ["$held_amount", 0.0] },
+                        ]
+                    },
+                    req.amount,
+                ]
+            },
+        };
+        let update_result = accounts_collection
+            .update_one(filter, doc! { "$inc": { "held_amount": req.amount } }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to reserve funds: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Err(Status::failed_precondition(
+                "Insufficient available balance for hold",
+            ));
+        }
+
+        let ttl = if req.expires_in_seconds > 0 {
+            Duration::seconds(req.expires_in_seconds)
+        } else {
+            self.hold_config.default_ttl
+        };
+        let hold = doc! {
+            "account_id": account_id,
+            "amount": req.amount,
+            "captured_amount": 0.0,
+            "status": HoldStatus::Active.to_string(),
+            "reference": &req.reference,
+            "description": &req.description,
+            "expires_at": DateTime::from_millis((Utc::now() + ttl).timestamp_millis()),
+            "created_at": DateTime::now(),
+        };
+
+        let insert_result = self.holds_collection().insert_one(hold, None).await;
+        let hold_id = match insert_result {
+            Ok(result) => result.inserted_id.as_object_id().unwrap(),
+            Err(e) => {
+                accounts_collection
+                    .update_one(
+                        doc! { "_id": account_id },
+                        doc! { "$inc": { "held_amount": -req.amount } },
+                        None,
+                    )
+                    .await
+                    .map_err(|e| Status::internal(format!("Failed to release funds: {}", e)))?;
+                return Err(Status::internal(format!("Failed to create hold: {}", e)));
+            }
+        };
+
+        info!(
+            "Placed hold {} of {} on account {}",
+            hold_id, req.amount, account_id
+        );
+
+        self.get_hold(hold_id).await
+    }
+
+    // Debits the captured amount and releases whatever remains of the hold
+    pub async fn capture_hold(
+        &self,
+        req: &CaptureHoldRequest,
+    ) -> Result<(Document, ObjectId), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+
+        let hold_id = ObjectId::parse_str(&req.hold_id)
+            .map_err(|_| Status::invalid_argument("Invalid hold id"))?;
+        let hold = self.get_hold(hold_id).await?;
+        self.hold_account(
+            hold.get_object_id("account_id").unwrap(),
+            &req.requested_by,
+            &req.requester_roles,
+        )
+        .await?;
+        let hold_amount = hold.get_f64("amount").unwrap();
+
+        if hold.get_str("status") != Ok("ACTIVE") {
+            return Err(Status::failed_precondition(format!(
+                "Hold is {}",
+                hold.get_str("status").unwrap_or_default()
+            )));
+        }
+        if hold.get_datetime("expires_at").unwrap().timestamp_millis()
+            <= Utc::now().timestamp_millis()
+        {
+            return Err(Status::failed_precondition("Hold has expired"));
+        }
+
+        let captured_amount = if req.amount == 0.0 {
+            hold_amount
+        } else {
+            validate_hold_amount(req.amount).map_err(Status::invalid_argument)?;
+            req.amount
+        };
+        if captured_amount > hold_amount {
+            return Err(Status::invalid_argument(
+                "Cannot capture more than the held amount",
+            ));
+        }
+
+        // The hold, the debit, its transaction and the HoldCaptured event are
+        // written atomically
+        let mut session = self.start_transaction().await?;
+
+        let update_result = self
+            .holds_collection()
+            .update_one_with_session(
+                doc! { "_id": hold_id, "status": HoldStatus::Active.to_string() },
+                doc! {
+                    "$set": {
+                        "status": HoldStatus::Captured.to_string(),
+                        "captured_amount": captured_amount,
+                        "updated_at": DateTime::now(),
+                    }
+                },
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update hold: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Err(Status::failed_precondition("Hold is no longer active"));
+        }
+
+        let account_id = hold.get_object_id("account_id").unwrap();
+        let account = accounts_collection
+            .find_one_and_update_with_session(
+                doc! { "_id": account_id },
+                doc! { "$inc": { "balance": -captured_amount, "held_amount": -hold_amount } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to capture hold: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        let transaction = doc! {
+            "account_id": account_id,
+            "amount": captured_amount,
+            "type": "Withdrawal",
+            "hold_id": hold_id,
+            "reference": hold.get_str("reference").unwrap_or_default(),
+            "timestamp": DateTime::now(),
+        };
+        let transaction_id = transactions_collection
+            .insert_one_with_session(transaction, None, &mut session)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap();
+
+        let event = outbox::new_event(
+            outbox::HOLD_CAPTURED,
+            hold_id,
+            doc! {
+                "hold_id": hold_id.to_hex(),
+                "account_id": account_id.to_hex(),
+                "transaction_id": transaction_id.to_hex(),
+                "amount": captured_amount,
+                "released_amount": hold_amount - captured_amount,
+            },
+        );
+        self.record_events(&mut session, vec![event]).await?;
+        self.commit_transaction(&mut session).await?;
+
+        info!(
+            "Captured {} of hold {} on account {}",
+            captured_amount, hold_id, account_id
+        );
+
+        let balance = account.get_f64("balance").unwrap();
+        overdraft::notify_overdraft(&self.db, &account, balance + captured_amount, balance).await?;
+
+        Ok((self.get_hold(hold_id).await?, transaction_id))
+    }
+
+    pub async fn release_hold(&self, req: &ReleaseHoldRequest) -> Result<Document, Status> {
+        let hold_id = ObjectId::parse_str(&req.hold_id)
+            .map_err(|_| Status::invalid_argument("Invalid hold id"))?;
+        let hold = self.get_hold(hold_id).await?;
+        self.hold_account(
+            hold.get_object_id("account_id").unwrap(),
+            &req.requested_by,
+            &req.requester_roles,
+        )
+        .await?;
+
+        if !self.end_hold(&hold, HoldStatus::Released).await? {
+            return Err(Status::failed_precondition(format!(
+                "Hold is {}",
+                hold.get_str("status").unwrap_or_default()
+            )));
+        }
+
+        info!("Released hold {}", hold_id);
+
+        self.get_hold(hold_id).await
+    }
+
+    pub async fn list_holds(&self, req: &ListHoldsRequest) -> Result<Vec<Document>, Status> {
+        let account_id = self.resolve_account_id(&req.account_id).await?;
+        self.hold_account(account_id, &req.requested_by, &req.requester_roles)
+            .await?;
+
+        let mut filter = doc! { "account_id": account_id };
+        if req.active_only {
+            filter.insert("status", HoldStatus::Active.to_string());
+        }
+
+        self.holds_collection()
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! { "created_at": -1 })
+                    .build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get holds: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get holds: {}", e)))
+    }
+
+    pub async fn expire_holds(&self) -> Result<(), Status> {
+        let expired_holds: Vec<Document> = self
+            .holds_collection()
+            .find(
+                doc! {
+                    "status": HoldStatus::Active.to_string(),
+                    "expires_at": { "$lte": DateTime::now() },
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get holds: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get holds: {}", e)))?;
+
+        for hold in expired_holds {
+            if self.end_hold(&hold, HoldStatus::Expired).await? {
+                info!("Hold {} expired", hold.get_object_id("_id").unwrap());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn run_hold_expiry(self) {
+        info!(
+            "Hold expiry started, running every {:?}",
+            self.hold_config.expiry_interval
+        );
+
+        let mut interval = tokio::time::interval(self.hold_config.expiry_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.expire_holds().await {
+                error!("Failed to expire holds: {:?}", e);
+            }
+        }
+    }
+}
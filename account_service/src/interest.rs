@@ -0,0 +1,738 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use futures::stream::TryStreamExt;
+use log::{error, info};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
+};
+use tonic::Status;
+
+use bank_common::outbox::OutboxService;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{FindOptions, IndexOptions},
+    Collection, IndexModel,
+};
+
+use crate::account_service::account::InterestAccrual;
+use crate::account_service::MyAccountService;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DUPLICATE_KEY_ERROR: i32 = 11000;
+
+// Source of the current time for the interest engine. Accrual and
+// capitalization only ever look at this clock, so the tests can replay a
+// period with a fixed one.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayCountConvention {
+    Act365,
+    Thirty360,
+}
+
+impl Display for DayCountConvention {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let day_count_str = match self {
+            DayCountConvention::Act365 => "ACT/365",
+            DayCountConvention::Thirty360 => "30/360",
+        };
+
+        write!(f, "{}", day_count_str)
+    }
+}
+
+impl FromStr for DayCountConvention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ACT/365" => Ok(DayCountConvention::Act365),
+            "30/360" => Ok(DayCountConvention::Thirty360),
+            _ => Err(format!("Invalid day count convention: {}", s)),
+        }
+    }
+}
+
+impl DayCountConvention {
+    // 30/360 uses the bond basis: day 31 counts as day 30, and so does the end
+    // date when the period starts on day 30 or 31. Every month then adds up to
+    // exactly 30 days of interest.
+    pub fn year_fraction(&self, start: NaiveDate, end: NaiveDate) -> f64 {
+        match self {
+            DayCountConvention::Act365 => (end - start).num_days() as f64 / 365.0,
+            DayCountConvention::Thirty360 => {
+                let start_day = start.day().min(30) as i32;
+                let end_day = if start_day == 30 {
+                    end.day().min(30) as i32
+                } else {
+                    end.day() as i32
+                };
+                let days = 360 * (end.year() - start.year())
+                    + 30 * (end.month() as i32 - start.month() as i32)
+                    + (end_day - start_day);
+                days as f64 / 360.0
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateTier {
+    pub up_to: Option<f64>,
+    pub annual_rate: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterestProduct {
+    pub name: String,
+    pub day_count: DayCountConvention,
+    pub tiers: Vec<RateTier>,
+}
+
+impl InterestProduct {
+    // Tiers are marginal: each slice of the balance earns the rate of the tier
+    // it falls in, so crossing a threshold never lowers the interest earned.
+    pub fn annual_interest(&self, balance: f64) -> f64 {
+        let mut interest = 0.0;
+        let mut lower = 0.0;
+
+        for tier in &self.tiers {
+            if balance <= lower {
+                break;
+            }
+            let upper = tier.up_to.map_or(balance, |up_to| up_to.min(balance));
+            interest += (upper - lower) * tier.annual_rate / 100.0;
+            lower = upper;
+        }
+
+        interest
+    }
+
+    pub fn daily_interest(&self, balance: f64, date: NaiveDate) -> f64 {
+        if balance <= 0.0 {
+            return 0.0;
+        }
+        let next_day = date + Duration::days(1);
+        self.annual_interest(balance) * self.day_count.year_fraction(date, next_day)
+    }
+}
+
+// Products are configured as `NAME|DAY_COUNT|TIERS` where tiers are
+// `upper_bound:annual_rate_percent` pairs in ascending order and the last one
+// uses `*` as open-ended upper bound, e.g. `SAVINGS|ACT/365|1000:0.5,*:1.5`.
+impl FromStr for InterestProduct {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split('|').collect();
+        if parts.len() != 3 {
+            return Err(format!("Invalid interest product: {}", s));
+        }
+
+        let name = parts[0].trim().to_string();
+        if name.is_empty() {
+            return Err(format!("Missing interest product name: {}", s));
+        }
+        let day_count = DayCountConvention::from_str(parts[1].trim())?;
+
+        let mut tiers = Vec::new();
+        for tier in parts[2].split(',') {
+            let (up_to, annual_rate) = tier
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid rate tier {} in product {}", tier, name))?;
+            let up_to =
+                match up_to.trim() {
+                    "*" => None,
+                    value => Some(value.parse::<f64>().map_err(|_| {
+                        format!("Invalid tier bound {} in product {}", value, name)
+                    })?),
+                };
+            let annual_rate = annual_rate
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid rate {} in product {}", annual_rate, name))?;
+            tiers.push(RateTier { up_to, annual_rate });
+        }
+
+        let bounds: Vec<Option<f64>> = tiers.iter().map(|tier| tier.up_to).collect();
+        let ascending = bounds.windows(2).all(|pair| {
+            matches!(pair, [Some(lower), Some(upper)] if lower < upper)
+                || matches!(pair, [Some(_), None])
+        });
+        if bounds.last() != Some(&None) || !ascending {
+            return Err(format!(
+                "Rate tiers of product {} must be ascending and end with an open tier",
+                name
+            ));
+        }
+
+        Ok(InterestProduct {
+            name,
+            day_count,
+            tiers,
+        })
+    }
+}
+
+pub fn parse_products(value: &str) -> Result<Vec<InterestProduct>, String> {
+    let products = value
+        .split(';')
+        .filter(|product| !product.trim().is_empty())
+        .map(InterestProduct::from_str)
+        .collect::<Result<Vec<InterestProduct>, String>>()?;
+
+    if products.is_empty() {
+        return Err("At least one interest product must be configured".to_string());
+    }
+
+    Ok(products)
+}
+
+#[derive(Debug, Clone)]
+pub struct InterestConfig {
+    pub products: Vec<InterestProduct>,
+    pub interval: std::time::Duration,
+    // Annual rate charged on overdrawn balances when an account has no rate of its own
+    pub overdraft_rate: f64,
+}
+
+pub(crate) fn format_date(date: NaiveDate) -> String {
+    date.format(DATE_FORMAT).to_string()
+}
+
+pub(crate) fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, DATE_FORMAT).map_err(|_| format!("Invalid date: {}", value))
+}
+
+pub(crate) fn is_month_end(date: NaiveDate) -> bool {
+    (date + Duration::days(1)).month() != date.month()
+}
+
+// Every day completed since `accrued_through`, up to the clock's current day
+pub(crate) fn days_to_accrue(
+    clock: &dyn Clock,
+    accrued_through: NaiveDate,
+) -> impl Iterator<Item = NaiveDate> {
+    let today = clock.now().date_naive();
+    accrued_through
+        .iter_days()
+        .skip(1)
+        .take_while(move |date| *date < today)
+}
+
+// Only whole cents of the accrued interest are posted
+pub(crate) fn posted_interest(accrued_interest: f64) -> f64 {
+    (accrued_interest * 100.0).floor() / 100.0
+}
+
+pub(crate) fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == DUPLICATE_KEY_ERROR
+    )
+}
+
+impl MyAccountService {
+    fn interest_accruals_collection(&self) -> Collection<Document> {
+        self.db.collection("interest_accruals")
+    }
+
+    // Savings accounts without an explicit product use the first configured one
+    pub fn interest_product(&self, name: &str) -> Result<&InterestProduct, String> {
+        if name.is_empty() {
+            return Ok(&self.interest_config.products[0]);
+        }
+        self.interest_config
+            .products
+            .iter()
+            .find(|product| product.name == name)
+            .ok_or_else(|| format!("Unknown interest product: {}", name))
+    }
+
+    // Date up to which a newly created savings account counts as accrued, so
+    // that the first accrual covers its first full day.
+    pub fn interest_start_date(&self) -> String {
+        format_date(self.clock.now().date_naive() - Duration::days(1))
+    }
+
+    pub async fn ensure_interest_indexes(&self) -> Result<(), Status> {
+        let index = IndexModel::builder()
+            .keys(doc! { "account_id": 1, "date": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        self.interest_accruals_collection()
+            .create_index(index, None)
+            .await
+            .map_err(|e| {
+                Status::internal(format!("Failed to create interest accrual index: {}", e))
+            })?;
+
+        Ok(())
+    }
+
+    pub async fn run_interest_engine(self) {
+        info!(
+            "Interest engine started, running every {:?}",
+            self.interest_config.interval
+        );
+
+        let mut interval = tokio::time::interval(self.interest_config.interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.accrue_interest().await {
+                error!("Failed to accrue interest: {:?}", e);
+            }
+            if let Err(e) = self.accrue_overdraft_interest().await {
+                error!("Failed to accrue overdraft interest: {:?}", e);
+            }
+        }
+    }
+
+    // Accrues interest for every completed day since the last run and
+    // capitalizes it at the end of each month.
+    pub async fn accrue_interest(&self) -> Result<(), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let savings_accounts: Vec<Document> = accounts_collection
+            .find(doc! { "account_type": "SAVINGS" }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get accounts: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get accounts: {}", e)))?;
+
+        for account in savings_accounts {
+            let account_id = account.get_object_id("_id").unwrap();
+
+            let accrued_through = match account.get_str("interest_accrued_through") {
+                Ok(date) => parse_date(date).map_err(Status::internal)?,
+                Err(_) => {
+                    // Accounts opened before interest existed start accruing today
+                    accounts_collection
+                        .update_one(
+                            doc! { "_id": account_id },
+                            doc! { "$set": {
+                                "interest_accrued_through": self.interest_start_date(),
+                                "accrued_interest": 0.0,
+                            } },
+                            None,
+                        )
+                        .await
+                        .map_err(|e| {
+                            Status::internal(format!("Failed to update account: {}", e))
+                        })?;
+                    continue;
+                }
+            };
+
+            let product = self
+                .interest_product(account.get_str("interest_product").unwrap_or_default())
+                .map_err(Status::internal)?
+                .clone();
+
+            for date in days_to_accrue(self.clock.as_ref(), accrued_through) {
+                if !self.accrue_day(account_id, &product, date).await? {
+                    // Another instance is accruing this account
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn accrue_day(
+        &self,
+        account_id: ObjectId,
+        product: &InterestProduct,
+        date: NaiveDate,
+    ) -> Result<bool, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let previous_date = date - Duration::days(1);
+
+        let account_filter = doc! {
+            "_id": account_id,
+            "interest_accrued_through": format_date(previous_date),
+        };
+        let account = match accounts_collection
+            .find_one(account_filter.clone(), None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+        {
+            Some(account) => account,
+            None => return Ok(false),
+        };
+
+        let balance = account.get_f64("balance").unwrap();
+        let amount = product.daily_interest(balance, date);
+
+        let accrual = doc! {
+            "account_id": account_id,
+            "date": format_date(date),
+            "balance": balance,
+            "amount": amount,
+            "interest_product": &product.name,
+            "day_count": product.day_count.to_string(),
+            "capitalized": false,
+            "created_at": bson::DateTime::from_millis(self.clock.now().timestamp_millis()),
+        };
+
+        // The unique (account_id, date) index makes a retried day a no-op
+        if let Err(e) = self
+            .interest_accruals_collection()
+            .insert_one(accrual, None)
+            .await
+        {
+            if !is_duplicate_key(&e) {
+                return Err(Status::internal(format!(
+                    "Failed to create interest accrual: {}",
+                    e
+                )));
+            }
+        }
+
+        let update = doc! {
+            "$set": { "interest_accrued_through": format_date(date) },
+            "$inc": { "accrued_interest": amount },
+        };
+        let update_result = accounts_collection
+            .update_one(account_filter, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update account: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Ok(false);
+        }
+
+        if is_month_end(date) {
+            self.capitalize_interest(account_id, date).await?;
+        }
+
+        Ok(true)
+    }
+
+    // Posts the interest accrued during the month as a transaction. Only whole
+    // cents are posted; the remainder is carried over to the next month. The
+    // balance, the interest transaction and the capitalized accruals are
+    // written in one transaction.
+    async fn capitalize_interest(
+        &self,
+        account_id: ObjectId,
+        month_end: NaiveDate,
+    ) -> Result<(), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+
+        let mut session = self.start_transaction().await?;
+
+        let account = accounts_collection
+            .find_one_with_session(doc! { "_id": account_id }, None, &mut session)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        let accrued_interest = account.get_f64("accrued_interest").unwrap_or_default();
+        let posted_interest = posted_interest(accrued_interest);
+
+        if posted_interest > 0.0 {
+            let filter = doc! {
+                "_id": account_id,
+                "accrued_interest": accrued_interest,
+            };
+            let update = doc! {
+                "$inc": {
+                    "balance": posted_interest,
+                    "accrued_interest": -posted_interest,
+                }
+            };
+            let update_result = accounts_collection
+                .update_one_with_session(filter, update, None, &mut session)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to capitalize interest: {}", e)))?;
+            if update_result.modified_count == 0 {
+                return Ok(());
+            }
+
+            let transaction = doc! {
+                "account_id": account_id,
+                "amount": posted_interest,
+                "type": "Interest",
+                "period": month_end.format("%Y-%m").to_string(),
+                "timestamp": bson::DateTime::from_millis(self.clock.now().timestamp_millis()),
+            };
+            transactions_collection
+                .insert_one_with_session(transaction, None, &mut session)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?;
+        }
+
+        let month_start = month_end.with_day(1).unwrap();
+        self.interest_accruals_collection()
+            .update_many_with_session(
+                doc! {
+                    "account_id": account_id,
+                    "date": { "$gte": format_date(month_start), "$lte": format_date(month_end) },
+                },
+                doc! { "$set": { "capitalized": true } },
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update interest accruals: {}", e)))?;
+
+        self.commit_transaction(&mut session).await?;
+
+        if posted_interest > 0.0 {
+            info!(
+                "Capitalized {} of interest on account {} for {}",
+                posted_interest,
+                account_id,
+                month_end.format("%Y-%m")
+            );
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_interest_accruals(
+        &self,
+        account_id: ObjectId,
+        from_date: &str,
+        to_date: &str,
+    ) -> Result<Vec<InterestAccrual>, Status> {
+        let mut date_filter = doc! {};
+        if !from_date.is_empty() {
+            let from_date = parse_date(from_date).map_err(Status::invalid_argument)?;
+            date_filter.insert("$gte", format_date(from_date));
+        }
+        if !to_date.is_empty() {
+            let to_date = parse_date(to_date).map_err(Status::invalid_argument)?;
+            date_filter.insert("$lte", format_date(to_date));
+        }
+
+        let mut filter = doc! {
+            "account_id": account_id,
+        };
+        if !date_filter.is_empty() {
+            filter.insert("date", date_filter);
+        }
+
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! {
+            "date": 1,
+        });
+
+        let mut cursor = self
+            .interest_accruals_collection()
+            .find(filter, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get interest accruals: {}", e)))?;
+
+        let mut accruals = Vec::new();
+        while let Some(result) = cursor
+            .try_next()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get interest accruals: {}", e)))?
+        {
+            accruals.push(InterestAccrual {
+                date: result.get_str("date").unwrap().to_string(),
+                balance: result.get_f64("balance").unwrap(),
+                amount: result.get_f64("amount").unwrap(),
+                day_count: result.get_str("day_count").unwrap().to_string(),
+                capitalized: result.get_bool("capitalized").unwrap(),
+            });
+        }
+
+        Ok(accruals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn clock_at(year: i32, month: u32, day: u32, hour: u32) -> FixedClock {
+        FixedClock(Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap())
+    }
+
+    fn product(value: &str) -> InterestProduct {
+        InterestProduct::from_str(value).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn act_365_counts_actual_days() {
+        let act365 = DayCountConvention::Act365;
+        assert_close(
+            act365.year_fraction(date(2023, 1, 1), date(2024, 1, 1)),
+            1.0,
+        );
+        // Leap years still divide by 365
+        assert_close(
+            act365.year_fraction(date(2024, 1, 1), date(2025, 1, 1)),
+            366.0 / 365.0,
+        );
+        assert_close(
+            act365.year_fraction(date(2024, 2, 1), date(2024, 3, 1)),
+            29.0 / 365.0,
+        );
+    }
+
+    #[test]
+    fn thirty_360_months_have_30_days() {
+        let thirty360 = DayCountConvention::Thirty360;
+        for (start, end) in [
+            (date(2023, 1, 1), date(2023, 2, 1)),
+            (date(2023, 2, 1), date(2023, 3, 1)),
+            (date(2024, 2, 1), date(2024, 3, 1)),
+            (date(2023, 3, 31), date(2023, 4, 30)),
+        ] {
+            assert_close(thirty360.year_fraction(start, end), 30.0 / 360.0);
+        }
+        assert_close(
+            thirty360.year_fraction(date(2023, 1, 15), date(2024, 1, 15)),
+            1.0,
+        );
+        // The 31st counts as the 30th
+        assert_close(
+            thirty360.year_fraction(date(2023, 1, 30), date(2023, 1, 31)),
+            0.0,
+        );
+        assert_close(
+            thirty360.year_fraction(date(2023, 1, 31), date(2023, 2, 1)),
+            1.0 / 360.0,
+        );
+    }
+
+    #[test]
+    fn thirty_360_daily_accruals_add_up_to_30_days_a_month() {
+        let thirty360 = DayCountConvention::Thirty360;
+        for (year, month, days) in [(2023, 1, 31), (2023, 2, 28), (2024, 2, 29), (2023, 4, 30)] {
+            let total: f64 = (1..=days)
+                .map(|day| {
+                    let start = date(year, month, day);
+                    thirty360.year_fraction(start, start + Duration::days(1))
+                })
+                .sum();
+            assert_close(total * 360.0, 30.0);
+        }
+    }
+
+    #[test]
+    fn tiers_are_marginal() {
+        let product = product("SAVINGS|ACT/365|1000:0.5,10000:1.0,*:1.5");
+        assert_close(product.annual_interest(0.0), 0.0);
+        assert_close(product.annual_interest(500.0), 2.5);
+        assert_close(product.annual_interest(1000.0), 5.0);
+        assert_close(product.annual_interest(5000.0), 45.0);
+        assert_close(product.annual_interest(20000.0), 245.0);
+        // Crossing a threshold never lowers the interest
+        assert!(product.annual_interest(1000.01) > product.annual_interest(1000.0));
+    }
+
+    #[test]
+    fn negative_balances_earn_nothing() {
+        let product = product("SAVINGS|ACT/365|*:1.5");
+        assert_close(product.daily_interest(-100.0, date(2024, 1, 1)), 0.0);
+        assert_close(product.daily_interest(36500.0, date(2024, 1, 1)), 1.5);
+    }
+
+    #[test]
+    fn invalid_products_are_rejected() {
+        assert!(InterestProduct::from_str("SAVINGS|ACT/360|*:1.5").is_err());
+        assert!(InterestProduct::from_str("SAVINGS|ACT/365|1000:0.5").is_err());
+        assert!(InterestProduct::from_str("SAVINGS|ACT/365|1000:0.5,500:1.0,*:1.5").is_err());
+        assert!(InterestProduct::from_str("SAVINGS|ACT/365|*:1.5,1000:0.5").is_err());
+        assert!(InterestProduct::from_str("|ACT/365|*:1.5").is_err());
+        assert!(parse_products(" ; ").is_err());
+        assert_eq!(
+            parse_products("A|ACT/365|*:1;B|30/360|*:2").unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn runs_accrue_every_completed_day() {
+        let clock = clock_at(2024, 3, 3, 10);
+        let days: Vec<_> = days_to_accrue(&clock, date(2024, 2, 27)).collect();
+        assert_eq!(
+            days,
+            [
+                date(2024, 2, 28),
+                date(2024, 2, 29),
+                date(2024, 3, 1),
+                date(2024, 3, 2)
+            ]
+        );
+
+        // Today is never accrued, so a second run on the same day does nothing
+        assert_eq!(days_to_accrue(&clock, date(2024, 3, 2)).count(), 0);
+    }
+
+    #[test]
+    fn month_end_capitalizes_whole_cents_and_carries_the_rest() {
+        let product = product("SAVINGS|ACT/365|1000:0.5,10000:1.0,*:1.5");
+        let clock = clock_at(2024, 3, 1, 0);
+
+        let mut accrued = 0.0;
+        let mut capitalized = Vec::new();
+        for day in days_to_accrue(&clock, date(2024, 1, 31)) {
+            accrued += product.daily_interest(10000.0, day);
+            if is_month_end(day) {
+                let posted = posted_interest(accrued);
+                capitalized.push((day, posted));
+                accrued -= posted;
+            }
+        }
+
+        // 95 a year over the 29 days of February
+        assert_eq!(capitalized, [(date(2024, 2, 29), 7.54)]);
+        assert_close(accrued, 95.0 * 29.0 / 365.0 - 7.54);
+    }
+
+    #[test]
+    fn month_ends() {
+        assert!(is_month_end(date(2024, 2, 29)));
+        assert!(!is_month_end(date(2023, 2, 27)));
+        assert!(is_month_end(date(2023, 2, 28)));
+        assert!(is_month_end(date(2023, 12, 31)));
+        assert!(!is_month_end(date(2023, 4, 29)));
+    }
+}
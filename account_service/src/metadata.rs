@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use mongodb::bson::{doc, Bson, Document};
+
+use crate::account_service::account::UpdateAccountRequest;
+
+// Account metadata that customers can change themselves. Settings are free
+// form string values stored under the account's `settings` document.
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_SETTINGS: usize = 32;
+const MAX_SETTING_KEY_LENGTH: usize = 32;
+const MAX_SETTING_VALUE_LENGTH: usize = 256;
+
+pub fn account_settings(account: &Document) -> HashMap<String, String> {
+    account
+        .get_document("settings")
+        .map(|settings| {
+            settings
+                .iter()
+                .filter_map(|(key, value)| match value {
+                    Bson::String(value) => Some((key.clone(), value.clone())),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Keys become field names inside `settings`, so they are kept to a safe charset
+fn is_setting_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_SETTING_KEY_LENGTH
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+pub fn validate_update(req: &UpdateAccountRequest) -> Result<(), String> {
+    if req.account_name.trim().len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "Account name cannot be longer than {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    if req.nickname.trim().len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "Nickname cannot be longer than {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    if req.settings.len() > MAX_SETTINGS {
+        return Err(format!("At most {} settings can be updated", MAX_SETTINGS));
+    }
+    for (key, value) in &req.settings {
+        if !is_setting_key(key) {
+            return Err(format!("Invalid setting name: {}", key));
+        }
+        if value.len() > MAX_SETTING_VALUE_LENGTH {
+            return Err(format!(
+                "Setting {} cannot be longer than {} characters",
+                key, MAX_SETTING_VALUE_LENGTH
+            ));
+        }
+    }
+    if req.account_name.trim().is_empty()
+        && req.nickname.trim().is_empty()
+        && req.settings.is_empty()
+    {
+        return Err("Nothing to update".to_string());
+    }
+
+    Ok(())
+}
+
+pub fn metadata_update(req: &UpdateAccountRequest) -> Document {
+    let mut set = doc! {};
+    let mut unset = doc! {};
+
+    if !req.account_name.trim().is_empty() {
+        set.insert("account_name", req.account_name.trim());
+    }
+    if !req.nickname.trim().is_empty() {
+        set.insert("nickname", req.nickname.trim());
+    }
+    for (key, value) in &req.settings {
+        if value.is_empty() {
+            unset.insert(format!("settings.{}", key), "");
+        } else {
+            set.insert(format!("settings.{}", key), value);
+        }
+    }
+
+    let mut update = doc! {};
+    if !set.is_empty() {
+        update.insert("$set", set);
+    }
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+    update
+}
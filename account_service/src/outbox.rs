@@ -0,0 +1,32 @@
+use mongodb::bson::{oid::ObjectId, Document};
+use mongodb::{Client, Database};
+
+use crate::account_service::MyAccountService;
+use bank_common::outbox::{self, OutboxConfig, OutboxService};
+
+// Events published by this service. They are recorded and relayed by
+// bank_common::outbox.
+pub const ACCOUNT_CREATED: &str = "AccountCreated";
+pub const HOLD_CAPTURED: &str = "HoldCaptured";
+
+const SOURCE: &str = "account";
+
+pub fn new_event(event_type: &str, aggregate_id: ObjectId, data: Document) -> Document {
+    outbox::new_event(SOURCE, event_type, aggregate_id, data)
+}
+
+impl OutboxService for MyAccountService {
+    const SOURCE: &'static str = SOURCE;
+
+    fn outbox_client(&self) -> &Client {
+        &self.client
+    }
+
+    fn outbox_db(&self) -> &Database {
+        &self.db
+    }
+
+    fn outbox_config(&self) -> &OutboxConfig {
+        &self.outbox_config
+    }
+}
@@ -0,0 +1,294 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use futures::stream::TryStreamExt;
+use log::info;
+use tonic::Status;
+
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Document},
+    Collection,
+};
+
+use bank_common::overdraft::{self, overdraft_limit};
+
+use crate::account_service::account::SetOverdraftLimitRequest;
+use crate::account_service::MyAccountService;
+use crate::interest::{format_date, is_duplicate_key, is_month_end, parse_date};
+
+// Overdraft interest always uses ACT/365 on the overdrawn amount, and its
+// daily accruals are stored next to savings accruals under this product name.
+pub const OVERDRAFT_PRODUCT: &str = "OVERDRAFT";
+const OVERDRAFT_DAY_COUNT: &str = "ACT/365";
+
+impl MyAccountService {
+    pub async fn set_overdraft_limit(
+        &self,
+        req: &SetOverdraftLimitRequest,
+    ) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        if req.overdraft_limit < 0.0 || req.overdraft_rate < 0.0 {
+            return Err(Status::invalid_argument(
+                "Overdraft limit and rate cannot be negative",
+            ));
+        }
+
+        let account_id = ObjectId::parse_str(&req.account_id)
+            .map_err(|_| Status::invalid_argument("Invalid account id"))?;
+
+        let account = accounts_collection
+            .find_one(doc! { "_id": account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        if account.get_str("account_type") != Ok("CHECKING") {
+            return Err(Status::failed_precondition(
+                "Overdrafts are only available on checking accounts",
+            ));
+        }
+
+        let overdraft_rate = if req.overdraft_rate > 0.0 {
+            req.overdraft_rate
+        } else {
+            self.interest_config.overdraft_rate
+        };
+
+        let mut update = doc! {
+            "overdraft_limit": req.overdraft_limit,
+            "overdraft_rate": overdraft_rate,
+            "overdraft_updated_by": &req.updated_by,
+            "overdraft_updated_at": bson::DateTime::from_millis(self.clock.now().timestamp_millis()),
+        };
+        // Overdraft interest accrues from the day the first limit is arranged
+        if account.get_str("overdraft_accrued_through").is_err() {
+            update.insert("overdraft_accrued_through", self.interest_start_date());
+            update.insert("accrued_overdraft_interest", 0.0);
+        }
+
+        accounts_collection
+            .update_one(doc! { "_id": account_id }, doc! { "$set": update }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update account: {}", e)))?;
+
+        info!(
+            "Overdraft limit of account {} set to {} at {}% by {}",
+            account_id, req.overdraft_limit, overdraft_rate, req.updated_by
+        );
+
+        let updated_account = accounts_collection
+            .find_one(doc! { "_id": account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        // Lowering the limit can leave the account past its new overdraft
+        let balance = updated_account.get_f64("balance").unwrap();
+        if balance < -req.overdraft_limit && balance >= -overdraft_limit(&account) {
+            let alert = format!(
+                "has exceeded its new arranged overdraft of {} (balance {:.2})",
+                req.overdraft_limit, balance
+            );
+            overdraft::send_overdraft_alert(&self.db, &updated_account, &alert).await?;
+        }
+
+        Ok(updated_account)
+    }
+
+    // Accrues overdraft interest for every completed day since the last run
+    // and charges it at the end of each month, like savings interest.
+    pub async fn accrue_overdraft_interest(&self) -> Result<(), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let today = self.clock.now().date_naive();
+
+        let overdraft_accounts: Vec<Document> = accounts_collection
+            .find(
+                doc! {
+                    "account_type": "CHECKING",
+                    "overdraft_accrued_through": { "$exists": true },
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get accounts: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get accounts: {}", e)))?;
+
+        for account in overdraft_accounts {
+            let account_id = account.get_object_id("_id").unwrap();
+            let mut date = parse_date(account.get_str("overdraft_accrued_through").unwrap())
+                .map_err(Status::internal)?;
+
+            while date + Duration::days(1) < today {
+                if !self.accrue_overdraft_day(account_id, date).await? {
+                    // Another instance is accruing this account
+                    break;
+                }
+                date += Duration::days(1);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn accrue_overdraft_day(
+        &self,
+        account_id: ObjectId,
+        previous_date: NaiveDate,
+    ) -> Result<bool, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let interest_accruals_collection: Collection<Document> =
+            self.db.collection("interest_accruals");
+        let date = previous_date + Duration::days(1);
+
+        let account_filter = doc! {
+            "_id": account_id,
+            "overdraft_accrued_through": format_date(previous_date),
+        };
+        let account = match accounts_collection
+            .find_one(account_filter.clone(), None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+        {
+            Some(account) => account,
+            None => return Ok(false),
+        };
+
+        let balance = account.get_f64("balance").unwrap();
+        let rate = account
+            .get_f64("overdraft_rate")
+            .unwrap_or(self.interest_config.overdraft_rate);
+        let amount = if balance < 0.0 {
+            -balance * rate / 100.0 / 365.0
+        } else {
+            0.0
+        };
+
+        if amount > 0.0 {
+            // Overdraft interest is owed by the customer, so it is stored as a
+            // negative accrual
+            let accrual = doc! {
+                "account_id": account_id,
+                "date": format_date(date),
+                "balance": balance,
+                "amount": -amount,
+                "interest_product": OVERDRAFT_PRODUCT,
+                "day_count": OVERDRAFT_DAY_COUNT,
+                "capitalized": false,
+                "created_at": bson::DateTime::from_millis(self.clock.now().timestamp_millis()),
+            };
+
+            if let Err(e) = interest_accruals_collection.insert_one(accrual, None).await {
+                if !is_duplicate_key(&e) {
+                    return Err(Status::internal(format!(
+                        "Failed to create interest accrual: {}",
+                        e
+                    )));
+                }
+            }
+        }
+
+        let update = doc! {
+            "$set": { "overdraft_accrued_through": format_date(date) },
+            "$inc": { "accrued_overdraft_interest": amount },
+        };
+        let update_result = accounts_collection
+            .update_one(account_filter, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update account: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Ok(false);
+        }
+
+        if is_month_end(date) {
+            self.charge_overdraft_interest(account_id, date).await?;
+        }
+
+        Ok(true)
+    }
+
+    // Debits the overdraft interest accrued during the month. Only whole cents
+    // are charged; the remainder is carried over to the next month.
+    async fn charge_overdraft_interest(
+        &self,
+        account_id: ObjectId,
+        month_end: NaiveDate,
+    ) -> Result<(), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+        let interest_accruals_collection: Collection<Document> =
+            self.db.collection("interest_accruals");
+
+        let account = accounts_collection
+            .find_one(doc! { "_id": account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        let accrued_interest = account
+            .get_f64("accrued_overdraft_interest")
+            .unwrap_or_default();
+        let charged_interest = (accrued_interest * 100.0).floor() / 100.0;
+
+        if charged_interest > 0.0 {
+            let filter = doc! {
+                "_id": account_id,
+                "accrued_overdraft_interest": accrued_interest,
+            };
+            let update = doc! {
+                "$inc": {
+                    "balance": -charged_interest,
+                    "accrued_overdraft_interest": -charged_interest,
+                }
+            };
+            let update_result = accounts_collection
+                .update_one(filter, update, None)
+                .await
+                .map_err(|e| {
+                    Status::internal(format!("Failed to charge overdraft interest: {}", e))
+                })?;
+            if update_result.modified_count == 0 {
+                return Ok(());
+            }
+
+            let transaction = doc! {
+                "account_id": account_id,
+                "amount": -charged_interest,
+                "type": "Interest",
+                "period": month_end.format("%Y-%m").to_string(),
+                "timestamp": bson::DateTime::from_millis(self.clock.now().timestamp_millis()),
+            };
+            transactions_collection
+                .insert_one(transaction, None)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?;
+
+            info!(
+                "Charged {} of overdraft interest on account {} for {}",
+                charged_interest,
+                account_id,
+                month_end.format("%Y-%m")
+            );
+
+            let balance = account.get_f64("balance").unwrap();
+            overdraft::notify_overdraft(&self.db, &account, balance, balance - charged_interest)
+                .await?;
+        }
+
+        let month_start = month_end.with_day(1).unwrap();
+        interest_accruals_collection
+            .update_many(
+                doc! {
+                    "account_id": account_id,
+                    "interest_product": OVERDRAFT_PRODUCT,
+                    "date": { "$gte": format_date(month_start), "$lte": format_date(month_end) },
+                },
+                doc! { "$set": { "capitalized": true } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update interest accruals: {}", e)))?;
+
+        Ok(())
+    }
+}
@@ -24,5 +24,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build_client(true)
         .compile(&["proto/historical_service.proto"], &["proto/"])?;
 
+    tonic_build::configure()
+        .build_server(false)
+        .build_client(true)
+        .compile(&["proto/notification_service.proto"], &["proto/"])?;
+
+    tonic_build::configure()
+        .build_server(false)
+        .build_client(true)
+        .compile(&["proto/health.proto"], &["proto/"])?;
+
     Ok(())
 }
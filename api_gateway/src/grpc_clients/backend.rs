@@ -0,0 +1,558 @@
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use log::{error, info, warn};
+use serde_json::json;
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::Sender;
+use tonic::{transport::Endpoint, Status};
+use tower::discover::Change;
+
+use super::{
+    health_grpc_client::{check_instance, InstanceHealth},
+    registry::ServiceRegistry,
+    resilience::{unavailable, BreakerState, CircuitBreaker},
+};
+
+// Where the instances of a backend are found: a comma separated list of
+// `host:port` addresses, `dns://host:port` to balance over every address
+// the name resolves to, or `registry://<service>` for the instances
+// registered by the service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Targets {
+    Static(Vec<String>),
+    Dns(String),
+    Registry(String),
+}
+
+impl Targets {
+    pub fn parse(targets: &str) -> Result<Self, String> {
+        if let Some(name) = targets.trim().strip_prefix("dns://") {
+            if !name.contains(':') {
+                return Err(format!("{} has no port", targets));
+            }
+            return Ok(Targets::Dns(name.to_string()));
+        }
+        if let Some(service) = targets.trim().strip_prefix("registry://") {
+            if service.is_empty() {
+                return Err(format!("{} has no service", targets));
+            }
+            return Ok(Targets::Registry(service.to_string()));
+        }
+
+        let addresses: Vec<String> = targets
+            .split(',')
+            .map(|address| address.trim().to_string())
+            .filter(|address| !address.is_empty())
+            .collect();
+        if addresses.is_empty() {
+            return Err("No address given".to_string());
+        }
+        Ok(Targets::Static(addresses))
+    }
+
+    async fn resolve(
+        &self,
+        registry: Option<&dyn ServiceRegistry>,
+    ) -> Result<BTreeSet<String>, String> {
+        match self {
+            Targets::Static(addresses) => Ok(addresses.iter().cloned().collect()),
+            Targets::Dns(name) => tokio::net::lookup_host(name.as_str())
+                .await
+                .map(|addresses| addresses.map(|address| address.to_string()).collect())
+                .map_err(|e| format!("Failed to resolve {}: {}", name, e)),
+            Targets::Registry(service) => match registry {
+                Some(registry) => registry.instances(service).await,
+                None => Err(format!("No registry to find the {} service in", service)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BalancingPolicy {
+    pub check_interval: Duration,
+    pub connect_timeout: Duration,
+    pub dns_refresh: Duration,
+    pub ejection_failures: u32,
+    pub ejection_duration: Duration,
+    pub registry: Option<Arc<dyn ServiceRegistry>>,
+}
+
+#[derive(Debug)]
+struct Instance {
+    endpoint: Endpoint,
+    // Whether the balancer sends calls to the instance
+    in_rotation: bool,
+    consecutive_failures: u32,
+    ejections: u32,
+    ejected_until: Option<Instant>,
+    ejected_until_timestamp: Option<DateTime<Utc>>,
+    last_checked: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    // As reported by the instance on its last successful check
+    health: InstanceHealth,
+}
+
+#[derive(Debug, Default)]
+struct Instances {
+    instances: BTreeMap<String, Instance>,
+    // False until the first check has completed
+    checked: bool,
+    resolved_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+// A gRPC service the gateway depends on, which may run as several instances.
+// Calls are spread over the instances in rotation by the balance channel of
+// tonic (power of two choices). A monitor checks every instance in the
+// background, ejects the ones that keep failing and puts them back once they
+// can be reached again.
+#[derive(Debug, Clone)]
+pub struct Backend {
+    name: String,
+    targets: Targets,
+    breaker: CircuitBreaker,
+    policy: BalancingPolicy,
+    changes: Sender<Change<String, Endpoint>>,
+    instances: Arc<Mutex<Instances>>,
+}
+
+impl Backend {
+    pub fn new(
+        name: &str,
+        targets: Targets,
+        breaker: CircuitBreaker,
+        policy: BalancingPolicy,
+        changes: Sender<Change<String, Endpoint>>,
+    ) -> Self {
+        let backend = Self {
+            name: name.to_string(),
+            targets,
+            breaker,
+            policy,
+            changes,
+            instances: Arc::new(Mutex::new(Instances::default())),
+        };
+
+        // Static instances take calls straight away, before their first check
+        if let Targets::Static(addresses) = &backend.targets {
+            let mut instances = backend.instances.lock().unwrap();
+            for address in addresses {
+                if let Some(mut instance) = backend.new_instance(address) {
+                    instance.in_rotation = backend
+                        .send_change(Change::Insert(address.clone(), instance.endpoint.clone()));
+                    instances.instances.insert(address.clone(), instance);
+                }
+            }
+        }
+        backend
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    pub fn check_interval(&self) -> Duration {
+        self.policy.check_interval
+    }
+
+    // Whether an instance in rotation passed its last check
+    fn any_available(instances: &Instances) -> bool {
+        instances
+            .instances
+            .values()
+            .any(|instance| instance.in_rotation && instance.consecutive_failures == 0)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        let instances = self.instances.lock().unwrap();
+        instances.checked
+            && Self::any_available(&instances)
+            && self.breaker.state() != BreakerState::Open
+    }
+
+    // Rejects calls while no instance of the backend can be reached
+    pub(crate) fn check_reachable(&self) -> Result<(), Box<Status>> {
+        let instances = self.instances.lock().unwrap();
+        if instances.checked && !Self::any_available(&instances) {
+            return Err(Box::new(unavailable(
+                format!(
+                    "The {} service is unreachable: {}",
+                    self.name,
+                    instances.last_error.as_deref().unwrap_or_default()
+                ),
+                self.policy.check_interval,
+            )));
+        }
+        Ok(())
+    }
+
+    fn new_instance(&self, address: &str) -> Option<Instance> {
+        let endpoint = match Endpoint::from_shared(format!("http://{}", address)) {
+            Ok(endpoint) => endpoint.connect_timeout(self.policy.connect_timeout),
+            Err(e) => {
+                error!(
+                    "Invalid address {} of the {} service: {}",
+                    address, self.name, e
+                );
+                return None;
+            }
+        };
+
+        Some(Instance {
+            endpoint,
+            in_rotation: false,
+            consecutive_failures: 0,
+            ejections: 0,
+            ejected_until: None,
+            ejected_until_timestamp: None,
+            last_checked: None,
+            last_error: None,
+            health: InstanceHealth::default(),
+        })
+    }
+
+    // The balancer only reads changes when calls are made, so they are
+    // queued without waiting. A change that does not fit is sent again on
+    // the next check.
+    fn send_change(&self, change: Change<String, Endpoint>) -> bool {
+        match self.changes.try_send(change) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Failed to update the instances of {}: {}", self.name, e);
+                false
+            }
+        }
+    }
+
+    async fn refresh_targets(&self) {
+        // The registry is read on every check so that the instances joining
+        // or leaving are seen straight away
+        let refresh = match self.targets {
+            Targets::Registry(_) => Duration::ZERO,
+            _ => self.policy.dns_refresh,
+        };
+        let due = self
+            .instances
+            .lock()
+            .unwrap()
+            .resolved_at
+            .is_none_or(|resolved_at| resolved_at.elapsed() >= refresh);
+        if !due {
+            return;
+        }
+
+        let addresses = match self.targets.resolve(self.policy.registry.as_deref()).await {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                error!("{}", e);
+                self.instances.lock().unwrap().last_error = Some(e);
+                return;
+            }
+        };
+
+        let mut instances = self.instances.lock().unwrap();
+        instances.resolved_at = Some(Instant::now());
+        if addresses.is_empty() {
+            instances.last_error = Some(format!(
+                "No instance of the {} service was found",
+                self.name
+            ));
+        }
+
+        let gone: Vec<String> = instances
+            .instances
+            .keys()
+            .filter(|address| !addresses.contains(*address))
+            .cloned()
+            .collect();
+        for address in gone {
+            info!("Instance {} of the {} service is gone", address, self.name);
+            if let Some(instance) = instances.instances.remove(&address) {
+                if instance.in_rotation {
+                    self.send_change(Change::Remove(address));
+                }
+            }
+        }
+        for address in addresses {
+            if let Entry::Vacant(entry) = instances.instances.entry(address) {
+                if let Some(instance) = self.new_instance(entry.key()) {
+                    info!(
+                        "Found instance {} of the {} service",
+                        entry.key(),
+                        self.name
+                    );
+                    entry.insert(instance);
+                }
+            }
+        }
+    }
+
+    fn record_check(&self, address: &str, result: Result<InstanceHealth, String>) {
+        let mut instances = self.instances.lock().unwrap();
+        let Some(instance) = instances.instances.get_mut(address) else {
+            return;
+        };
+        instance.last_checked = Some(Utc::now());
+
+        // Instances that answer but cannot serve, e.g. without their
+        // database, are taken out of rotation like unreachable ones
+        let result = match result {
+            Ok(health) if health.is_serving() => {
+                instance.health = health;
+                Ok(())
+            }
+            Ok(health) => {
+                let status = health.status.clone().unwrap_or_default();
+                instance.health = health;
+                Err(format!("The instance is {}", status))
+            }
+            Err(e) => {
+                instance.health = InstanceHealth::default();
+                Err(e)
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                instance.consecutive_failures = 0;
+                let ejected = instance
+                    .ejected_until
+                    .is_some_and(|ejected_until| Instant::now() < ejected_until);
+                if !instance.in_rotation && !ejected {
+                    instance.in_rotation = self.send_change(Change::Insert(
+                        address.to_string(),
+                        instance.endpoint.clone(),
+                    ));
+                    if instance.in_rotation {
+                        info!(
+                            "✅ Connection to the {} gRPC service at {} is successful!",
+                            self.name, address
+                        );
+                        instance.ejected_until = None;
+                        instance.ejected_until_timestamp = None;
+                    }
+                }
+            }
+            Err(e) => {
+                instance.consecutive_failures += 1;
+                instance.last_error = Some(e.clone());
+                if instance.in_rotation
+                    && instance.consecutive_failures >= self.policy.ejection_failures
+                    && self.send_change(Change::Remove(address.to_string()))
+                {
+                    // Instances that keep failing stay out for longer
+                    instance.ejections += 1;
+                    let ejection = self.policy.ejection_duration * instance.ejections.min(10);
+                    instance.in_rotation = false;
+                    instance.ejected_until = Some(Instant::now() + ejection);
+                    instance.ejected_until_timestamp = chrono::Duration::from_std(ejection)
+                        .ok()
+                        .map(|ejection| Utc::now() + ejection);
+                    error!(
+                        "❌ Failed to connect to the {} gRPC service at {}, ejected for {:?}: {}",
+                        self.name, address, ejection, e
+                    );
+                } else if !instance.in_rotation && instance.consecutive_failures == 1 {
+                    error!(
+                        "❌ Failed to connect to the {} gRPC service at {}: {}",
+                        self.name, address, e
+                    );
+                }
+                instances.last_error = Some(e);
+            }
+        }
+    }
+
+    async fn check(&self) {
+        self.refresh_targets().await;
+
+        let endpoints: Vec<(String, Endpoint)> = self
+            .instances
+            .lock()
+            .unwrap()
+            .instances
+            .iter()
+            .map(|(address, instance)| (address.clone(), instance.endpoint.clone()))
+            .collect();
+        let timeout = self.policy.connect_timeout;
+        let results = join_all(
+            endpoints
+                .iter()
+                .map(|(_, endpoint)| check_instance(endpoint, timeout)),
+        )
+        .await;
+
+        for ((address, _), result) in endpoints.iter().zip(results) {
+            self.record_check(address, result);
+        }
+        self.instances.lock().unwrap().checked = true;
+    }
+
+    pub async fn run_monitor(self) {
+        if let (Targets::Registry(service), Some(registry)) = (&self.targets, &self.policy.registry)
+        {
+            info!(
+                "Finding the instances of the {} service in the {} registry",
+                service,
+                registry.name()
+            );
+        }
+
+        let mut interval = tokio::time::interval(self.policy.check_interval);
+        loop {
+            interval.tick().await;
+            self.check().await;
+        }
+    }
+
+    // Status of every dependency reported by the instances. A dependency is
+    // SERVING while it is on all of them.
+    fn dependencies(instances: &Instances) -> BTreeMap<String, String> {
+        let mut dependencies: BTreeMap<String, String> = BTreeMap::new();
+        for instance in instances.instances.values() {
+            for (name, status) in &instance.health.dependencies {
+                match dependencies.entry(name.clone()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(status.clone());
+                    }
+                    Entry::Occupied(mut entry) => {
+                        if status != "SERVING" {
+                            entry.insert(status.clone());
+                        }
+                    }
+                }
+            }
+        }
+        dependencies
+    }
+
+    // DOWN while not ready, and DEGRADED while some instance or dependency
+    // is failing or the circuit breaker is not closed
+    fn state_of(&self, instances: &Instances) -> &'static str {
+        let ready = instances.checked
+            && Self::any_available(instances)
+            && self.breaker.state() != BreakerState::Open;
+        if !ready {
+            return "DOWN";
+        }
+
+        let degraded = self.breaker.state() != BreakerState::Closed
+            || instances
+                .instances
+                .values()
+                .any(|instance| !instance.in_rotation || instance.consecutive_failures > 0)
+            || Self::dependencies(instances)
+                .values()
+                .any(|status| status != "SERVING");
+        if degraded {
+            "DEGRADED"
+        } else {
+            "UP"
+        }
+    }
+
+    pub fn state(&self) -> &'static str {
+        self.state_of(&self.instances.lock().unwrap())
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let instances = self.instances.lock().unwrap();
+        let ready = instances.checked
+            && Self::any_available(&instances)
+            && self.breaker.state() != BreakerState::Open;
+        let instances_json: Vec<serde_json::Value> = instances
+            .instances
+            .iter()
+            .map(|(address, instance)| {
+                json!({
+                    "address": address,
+                    "in_rotation": instance.in_rotation,
+                    "consecutive_failures": instance.consecutive_failures,
+                    "ejections": instance.ejections,
+                    "ejected_until_timestamp": instance
+                        .ejected_until_timestamp
+                        .map(|ejected_until| ejected_until.to_rfc3339()),
+                    "last_checked_timestamp": instance
+                        .last_checked
+                        .map(|checked| checked.to_rfc3339()),
+                    "last_error": instance.last_error,
+                    "health": instance.health.status,
+                    "dependencies": instance.health.dependencies
+                })
+            })
+            .collect();
+
+        json!({
+            "backend": self.name,
+            "ready": ready,
+            "state": self.state_of(&instances),
+            "dependencies": Self::dependencies(&instances),
+            "circuit_breaker": self.breaker.state().to_string(),
+            "instances": instances_json,
+            "last_error": instances.last_error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::registry::InMemoryRegistry;
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(
+            Targets::parse("account-1:50052, account-2:50052").unwrap(),
+            Targets::Static(vec![
+                "account-1:50052".to_string(),
+                "account-2:50052".to_string()
+            ])
+        );
+        assert_eq!(
+            Targets::parse("dns://account:50052").unwrap(),
+            Targets::Dns("account:50052".to_string())
+        );
+        assert_eq!(
+            Targets::parse("registry://account").unwrap(),
+            Targets::Registry("account".to_string())
+        );
+        assert!(Targets::parse("dns://account").is_err());
+        assert!(Targets::parse("registry://").is_err());
+        assert!(Targets::parse(" , ").is_err());
+    }
+
+    #[tokio::test]
+    async fn registry_targets_follow_the_registered_instances() {
+        let registry = InMemoryRegistry::default();
+        let targets = Targets::parse("registry://account").unwrap();
+
+        registry.register("account", "account-1:50052");
+        registry.register("account", "account-2:50052");
+        registry.register("deposit", "deposit-1:50053");
+        assert_eq!(
+            targets.resolve(Some(&registry)).await.unwrap(),
+            BTreeSet::from(["account-1:50052".to_string(), "account-2:50052".to_string()])
+        );
+
+        registry.deregister("account", "account-1:50052");
+        assert_eq!(
+            targets.resolve(Some(&registry)).await.unwrap(),
+            BTreeSet::from(["account-2:50052".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn registry_targets_need_a_registry() {
+        let targets = Targets::parse("registry://account").unwrap();
+        assert!(targets.resolve(None).await.is_err());
+    }
+}
@@ -0,0 +1,103 @@
+pub mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+use health::{
+    health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
+use std::{collections::BTreeMap, time::Duration};
+use tonic::{
+    transport::{Channel, Endpoint},
+    Code, Response, Status,
+};
+
+use super::resilience::describe_error;
+
+// Set by the services on the health of the whole server
+const DEPENDENCIES_HEADER: &str = "x-health-dependencies";
+
+// Health of an instance as reported by the standard gRPC health protocol
+#[derive(Debug, Clone, Default)]
+pub struct InstanceHealth {
+    // None for instances that do not serve the health protocol
+    pub status: Option<String>,
+    pub dependencies: BTreeMap<String, String>,
+}
+
+impl InstanceHealth {
+    pub fn is_serving(&self) -> bool {
+        self.status
+            .as_deref()
+            .is_none_or(|status| status == "SERVING")
+    }
+}
+
+fn status_name(status: i32) -> &'static str {
+    match ServingStatus::from_i32(status) {
+        Some(ServingStatus::Serving) => "SERVING",
+        Some(ServingStatus::NotServing) => "NOT_SERVING",
+        Some(ServingStatus::ServiceUnknown) => "SERVICE_UNKNOWN",
+        _ => "UNKNOWN",
+    }
+}
+
+async fn check(
+    client: &mut HealthClient<Channel>,
+    service: &str,
+    timeout: Duration,
+) -> Result<Response<health::HealthCheckResponse>, Status> {
+    let request = HealthCheckRequest {
+        service: service.to_string(),
+    };
+    match tokio::time::timeout(timeout, client.check(request)).await {
+        Ok(result) => result,
+        Err(_) => Err(Status::deadline_exceeded(format!(
+            "No answer to the health check within {:?}",
+            timeout
+        ))),
+    }
+}
+
+// Connects to one instance of a backend and checks the whole server, then
+// every dependency it names
+pub async fn check_instance(
+    endpoint: &Endpoint,
+    timeout: Duration,
+) -> Result<InstanceHealth, String> {
+    let channel = endpoint.connect().await.map_err(|e| describe_error(&e))?;
+    let mut client = HealthClient::new(channel);
+
+    let response = match check(&mut client, "", timeout).await {
+        Ok(response) => response,
+        // Reachable, but without health checking
+        Err(e) if e.code() == Code::Unimplemented => return Ok(InstanceHealth::default()),
+        Err(e) => return Err(format!("Health check failed: {}", e.message())),
+    };
+
+    let names: Vec<String> = response
+        .metadata()
+        .get(DEPENDENCIES_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|names| {
+            names
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut dependencies = BTreeMap::new();
+    for name in names {
+        let status = match check(&mut client, &name, timeout).await {
+            Ok(response) => status_name(response.get_ref().status),
+            Err(_) => "UNKNOWN",
+        };
+        dependencies.insert(name, status.to_string());
+    }
+
+    Ok(InstanceHealth {
+        status: Some(status_name(response.get_ref().status).to_string()),
+        dependencies,
+    })
+}
@@ -3,19 +3,15 @@ pub mod historical {
 }
 
 use historical::historical_service_client::HistoricalServiceClient;
-use tonic::transport::Channel;
+use super::backend::Backend;
+use super::resilience::{GrpcChannel, ResiliencePolicy};
 
-pub async fn get_historical_grpc_client(
+pub fn get_historical_grpc_client(
     uri: String,
-) -> Result<HistoricalServiceClient<Channel>, Box<dyn std::error::Error>> {
-    let grpc_uri = format!("http://{}", uri); 
-    let static_uri = Box::leak(grpc_uri.into_boxed_str());
-
-    let channel = tonic::transport::Channel::from_static(static_uri)
-        .connect()
-        .await?;
-
+    policy: &ResiliencePolicy,
+) -> Result<(HistoricalServiceClient<GrpcChannel>, Backend), Box<dyn std::error::Error>> {
+    let (channel, backend) = policy.connect_lazy("historical", &uri)?;
     let client = HistoricalServiceClient::new(channel);
 
-    Ok(client)
+    Ok((client, backend))
 }
@@ -3,3 +3,8 @@ pub mod account_grpc_client;
 pub mod deposit_grpc_client;
 pub mod withdrawal_grpc_client;
 pub mod historical_grpc_client;
+pub mod notification_grpc_client;
+pub mod health_grpc_client;
+pub mod backend;
+pub mod registry;
+pub mod resilience;
@@ -0,0 +1,17 @@
+pub mod notification {
+    tonic::include_proto!("notification");
+}
+
+use notification::notification_service_client::NotificationServiceClient;
+use super::backend::Backend;
+use super::resilience::{GrpcChannel, ResiliencePolicy};
+
+pub fn get_notification_grpc_client(
+    uri: String,
+    policy: &ResiliencePolicy,
+) -> Result<(NotificationServiceClient<GrpcChannel>, Backend), Box<dyn std::error::Error>> {
+    let (channel, backend) = policy.connect_lazy("notification", &uri)?;
+    let client = NotificationServiceClient::new(channel);
+
+    Ok((client, backend))
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Where the services announce their instances. A backend configured as
+// `registry://<service>` sends its calls to the instances found here.
+#[tonic::async_trait]
+pub trait ServiceRegistry: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    // Addresses of the instances of the service that are still sending
+    // heartbeats
+    async fn instances(&self, service: &str) -> Result<BTreeSet<String>, String>;
+}
+
+// Reads the registrations the services write under
+// `<dir>/<service>/<instance_id>.json`. Instances whose last heartbeat is
+// older than the ttl are considered gone, as they stopped without
+// deregistering.
+#[derive(Debug, Clone)]
+pub struct FileRegistry {
+    pub dir: PathBuf,
+    pub ttl: Duration,
+}
+
+#[tonic::async_trait]
+impl ServiceRegistry for FileRegistry {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn instances(&self, service: &str) -> Result<BTreeSet<String>, String> {
+        let service_dir = self.dir.join(service);
+        let mut entries = match tokio::fs::read_dir(&service_dir).await {
+            Ok(entries) => entries,
+            // No instance has registered yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", service_dir.display(), e)),
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as u64)
+            .unwrap_or_default();
+        let ttl = self.ttl.as_millis() as u64;
+
+        let mut addresses = BTreeSet::new();
+        loop {
+            let entry = match entries.next_entry().await {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(e) => return Err(format!("Failed to read {}: {}", service_dir.display(), e)),
+            };
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+
+            // A registration removed while the directory is read is skipped
+            let Ok(contents) = tokio::fs::read_to_string(&path).await else {
+                continue;
+            };
+            let Ok(registration) = serde_json::from_str::<serde_json::Value>(&contents) else {
+                continue;
+            };
+            let heartbeat_at = registration["heartbeat_at"].as_u64().unwrap_or_default();
+            if now.saturating_sub(heartbeat_at) > ttl {
+                continue;
+            }
+            if let Some(address) = registration["address"].as_str() {
+                addresses.insert(address.to_string());
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+// Keeps the instances in the process, so that backends can be resolved from
+// the registry in tests without files
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct InMemoryRegistry {
+    instances: std::sync::Mutex<std::collections::BTreeMap<String, BTreeSet<String>>>,
+}
+
+#[cfg(test)]
+impl InMemoryRegistry {
+    pub fn register(&self, service: &str, address: &str) {
+        self.instances
+            .lock()
+            .unwrap()
+            .entry(service.to_string())
+            .or_default()
+            .insert(address.to_string());
+    }
+
+    pub fn deregister(&self, service: &str, address: &str) {
+        if let Some(addresses) = self.instances.lock().unwrap().get_mut(service) {
+            addresses.remove(address);
+        }
+    }
+}
+
+#[cfg(test)]
+#[tonic::async_trait]
+impl ServiceRegistry for InMemoryRegistry {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    async fn instances(&self, service: &str) -> Result<BTreeSet<String>, String> {
+        Ok(self
+            .instances
+            .lock()
+            .unwrap()
+            .get(service)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    #[tokio::test]
+    async fn file_registry_skips_instances_without_recent_heartbeats() {
+        let registry = FileRegistry {
+            dir: std::env::temp_dir().join(format!("gateway-registry-{:x}", now_millis())),
+            ttl: Duration::from_secs(15),
+        };
+        let service_dir = registry.dir.join("account");
+        std::fs::create_dir_all(&service_dir).unwrap();
+        for (instance, address, heartbeat_at) in [
+            ("account-1", "account-1:50052", now_millis()),
+            ("account-2", "account-2:50052", now_millis() - 60_000),
+        ] {
+            let entry = serde_json::json!({ "address": address, "heartbeat_at": heartbeat_at });
+            std::fs::write(
+                service_dir.join(format!("{}.json", instance)),
+                entry.to_string(),
+            )
+            .unwrap();
+        }
+        // Left behind by a registration being written
+        std::fs::write(service_dir.join("account-3.json.tmp"), "{").unwrap();
+
+        let instances = registry.instances("account").await.unwrap();
+        assert_eq!(instances, BTreeSet::from(["account-1:50052".to_string()]));
+        assert!(registry.instances("deposit").await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(&registry.dir).unwrap();
+    }
+}
@@ -0,0 +1,629 @@
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use http_body::Body as _;
+use log::{info, warn};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tonic::{
+    body::BoxBody,
+    codegen::http::{self, HeaderValue, Request, Response},
+    metadata::MetadataValue,
+    transport::{Body, Channel},
+    Code, Status,
+};
+use tower::{Layer, Service, ServiceBuilder, ServiceExt};
+
+use super::{
+    backend::{Backend, BalancingPolicy, Targets},
+    registry::{FileRegistry, ServiceRegistry},
+};
+use crate::models::config::Config;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, BoxError>> + Send>>;
+
+// Every gRPC client of the gateway talks to its backend through this stack:
+// bounded retries for idempotent calls, a circuit breaker per backend and a
+// deadline per attempt
+pub type GrpcChannel = RetryService<CircuitBreakerService<DeadlineService<Channel>>>;
+
+// Changes to the instances of a backend waiting to be read by its balancer
+const BALANCE_CHANNEL_CAPACITY: usize = 64;
+
+// Read-only RPCs, which are safe to send again when an attempt fails
+const IDEMPOTENT_PREFIXES: [&str; 4] = ["Get", "List", "Check", "Preview"];
+
+// RPCs that are expected to take longer than the default deadline
+const SLOW_RPCS: [(&str, u64); 3] = [
+    ("SubmitPaymentBatch", 60_000),
+    ("GetAccountStatement", 30_000),
+    ("RebuildProjection", 300_000),
+];
+
+// Metadata telling callers when an unavailable backend is worth trying again
+const RETRY_AFTER: &str = "retry-after";
+
+pub fn unavailable(message: String, retry_after: Duration) -> Status {
+    let mut status = Status::unavailable(message);
+    status.metadata_mut().insert(
+        RETRY_AFTER,
+        MetadataValue::from(retry_after.as_secs().max(1)),
+    );
+    status
+}
+
+// Seconds after which a call rejected as unavailable may be retried
+pub fn retry_after(status: &Status) -> Option<u64> {
+    status
+        .metadata()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+// Transport errors only say what failed in their sources
+pub fn describe_error(e: &(dyn std::error::Error + 'static)) -> String {
+    let mut description = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        let cause = e.to_string();
+        if !description.contains(&cause) {
+            description = format!("{}: {}", description, cause);
+        }
+        source = e.source();
+    }
+    description
+}
+
+// Method name of a gRPC path such as `/account.AccountService/GetAccount`
+fn method_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn is_idempotent(path: &str) -> bool {
+    let method = method_name(path);
+    IDEMPOTENT_PREFIXES
+        .iter()
+        .any(|prefix| method.starts_with(prefix))
+}
+
+// Status of a trailers-only response, which is how servers answer with an
+// error before sending any message
+fn response_code(response: &Response<Body>) -> Option<Code> {
+    response
+        .headers()
+        .get("grpc-status")
+        .and_then(|status| status.to_str().ok())
+        .and_then(|status| status.parse::<i32>().ok())
+        .map(Code::from_i32)
+}
+
+// Errors that say something about the health of the backend, as opposed to
+// errors about the request itself
+fn is_backend_failure(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::Internal | Code::Unknown
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct ResiliencePolicy {
+    pub default_deadline: Duration,
+    pub deadlines: HashMap<String, Duration>,
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+    pub failure_threshold: u32,
+    pub open_duration: Duration,
+    pub balancing: BalancingPolicy,
+}
+
+impl ResiliencePolicy {
+    pub fn from_config(config: &Config) -> Self {
+        let mut deadlines: HashMap<String, Duration> = SLOW_RPCS
+            .iter()
+            .map(|(method, ms)| (method.to_string(), Duration::from_millis(*ms)))
+            .collect();
+        for (method, ms) in &config.grpc_deadlines {
+            deadlines.insert(method.clone(), Duration::from_millis(*ms));
+        }
+
+        let registry: Option<Arc<dyn ServiceRegistry>> = match config.registry.as_deref() {
+            Some("file") => Some(Arc::new(FileRegistry {
+                dir: config.registry_dir.clone().into(),
+                ttl: Duration::from_secs(config.registry_ttl_secs),
+            })),
+            _ => None,
+        };
+
+        Self {
+            default_deadline: Duration::from_millis(config.grpc_deadline_ms),
+            deadlines,
+            max_retries: config.grpc_max_retries,
+            retry_backoff: Duration::from_millis(config.grpc_retry_backoff_ms),
+            failure_threshold: config.circuit_breaker_failures.max(1),
+            open_duration: Duration::from_secs(config.circuit_breaker_open_secs),
+            balancing: BalancingPolicy {
+                check_interval: Duration::from_secs(config.backend_check_secs.max(1)),
+                connect_timeout: Duration::from_millis(config.grpc_deadline_ms),
+                dns_refresh: Duration::from_secs(config.dns_refresh_secs),
+                ejection_failures: config.outlier_ejection_failures.max(1),
+                ejection_duration: Duration::from_secs(config.outlier_ejection_secs),
+                registry,
+            },
+        }
+    }
+
+    fn deadline(&self, path: &str) -> Duration {
+        self.deadlines
+            .get(method_name(path))
+            .copied()
+            .unwrap_or(self.default_deadline)
+    }
+
+    // Creates a channel balancing calls over the instances of a backend,
+    // wrapped in the middleware stack. Instances are connected lazily, so this
+    // never waits for the backend.
+    pub fn connect_lazy(
+        &self,
+        name: &str,
+        targets: &str,
+    ) -> Result<(GrpcChannel, Backend), Box<dyn std::error::Error>> {
+        let targets = Targets::parse(targets)?;
+        if let (Targets::Registry(service), None) = (&targets, &self.balancing.registry) {
+            return Err(
+                format!("REGISTRY must be set to find the {} service in it", service).into(),
+            );
+        }
+        let (channel, changes) = Channel::balance_channel(BALANCE_CHANNEL_CAPACITY);
+
+        let breaker = CircuitBreaker::new(name, self.failure_threshold, self.open_duration);
+        let backend = Backend::new(name, targets, breaker, self.balancing.clone(), changes);
+
+        let policy = Arc::new(self.clone());
+        let service = ServiceBuilder::new()
+            .layer(RetryLayer {
+                policy: policy.clone(),
+                backend: backend.clone(),
+            })
+            .layer(CircuitBreakerLayer {
+                backend: backend.clone(),
+            })
+            .layer(DeadlineLayer { policy })
+            .service(channel);
+
+        Ok((service, backend))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            BreakerState::Closed => "CLOSED",
+            BreakerState::Open => "OPEN",
+            BreakerState::HalfOpen => "HALF_OPEN",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+#[derive(Debug)]
+struct BreakerStatus {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    opened_timestamp: Option<DateTime<Utc>>,
+    probe_in_flight: bool,
+    rejected_requests: u64,
+    last_error: Option<String>,
+}
+
+// Opens after `failure_threshold` consecutive backend failures and rejects
+// calls until `open_duration` has passed. A single probe is then let through
+// (half-open): its success closes the breaker, its failure opens it again.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    backend: String,
+    failure_threshold: u32,
+    open_duration: Duration,
+    status: Arc<Mutex<BreakerStatus>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(backend: &str, failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            backend: backend.to_string(),
+            failure_threshold,
+            open_duration,
+            status: Arc::new(Mutex::new(BreakerStatus {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                opened_timestamp: None,
+                probe_in_flight: false,
+                rejected_requests: 0,
+                last_error: None,
+            })),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.status.lock().unwrap().state
+    }
+
+    // Lets the call through, returning whether it is the half-open probe
+    fn acquire(&self) -> Result<bool, Box<Status>> {
+        let mut status = self.status.lock().unwrap();
+
+        if status.state == BreakerState::Open
+            && status
+                .opened_at
+                .is_none_or(|opened_at| opened_at.elapsed() >= self.open_duration)
+        {
+            info!("Circuit breaker for {} is half-open", self.backend);
+            status.state = BreakerState::HalfOpen;
+            status.probe_in_flight = false;
+        }
+
+        match status.state {
+            BreakerState::Closed => Ok(false),
+            BreakerState::HalfOpen if !status.probe_in_flight => {
+                status.probe_in_flight = true;
+                Ok(true)
+            }
+            _ => {
+                status.rejected_requests += 1;
+                let retry_after = status
+                    .opened_at
+                    .map(|opened_at| self.open_duration.saturating_sub(opened_at.elapsed()))
+                    .unwrap_or(self.open_duration);
+                Err(Box::new(unavailable(
+                    format!(
+                        "The {} service is unavailable, its circuit breaker is open",
+                        self.backend
+                    ),
+                    retry_after,
+                )))
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        let mut status = self.status.lock().unwrap();
+        if status.state != BreakerState::Closed {
+            info!("Circuit breaker for {} is closed", self.backend);
+        }
+        status.state = BreakerState::Closed;
+        status.consecutive_failures = 0;
+        status.opened_at = None;
+        status.opened_timestamp = None;
+        status.probe_in_flight = false;
+    }
+
+    fn record_failure(&self, error: String) {
+        let mut status = self.status.lock().unwrap();
+        status.consecutive_failures += 1;
+        status.last_error = Some(error);
+
+        let trips = match status.state {
+            BreakerState::Closed => status.consecutive_failures >= self.failure_threshold,
+            BreakerState::HalfOpen => true,
+            BreakerState::Open => false,
+        };
+        if trips {
+            warn!(
+                "Circuit breaker for {} is open after {} failure(s): {}",
+                self.backend,
+                status.consecutive_failures,
+                status.last_error.as_deref().unwrap_or_default()
+            );
+            status.state = BreakerState::Open;
+            status.opened_at = Some(Instant::now());
+            status.opened_timestamp = Some(Utc::now());
+        }
+        status.probe_in_flight = false;
+    }
+
+    // A probe that was dropped before completing says nothing about the
+    // backend, so another call may probe it
+    fn release_probe(&self) {
+        self.status.lock().unwrap().probe_in_flight = false;
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let status = self.status.lock().unwrap();
+        json!({
+            "backend": self.backend,
+            "state": status.state.to_string(),
+            "consecutive_failures": status.consecutive_failures,
+            "failure_threshold": self.failure_threshold,
+            "open_secs": self.open_duration.as_secs(),
+            "opened_timestamp": status.opened_timestamp.map(|opened| opened.to_rfc3339()),
+            "rejected_requests": status.rejected_requests,
+            "last_error": status.last_error
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadlineLayer {
+    policy: Arc<ResiliencePolicy>,
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeadlineService {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+// Bounds every attempt by the deadline of its RPC, which is also sent to the
+// backend in the `grpc-timeout` header
+#[derive(Debug, Clone)]
+pub struct DeadlineService<S> {
+    inner: S,
+    policy: Arc<ResiliencePolicy>,
+}
+
+impl<S> Service<Request<BoxBody>> for DeadlineService<S>
+where
+    S: Service<Request<BoxBody>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: Request<BoxBody>) -> Self::Future {
+        let path = request.uri().path().to_string();
+        let deadline = self.policy.deadline(&path);
+        if let Ok(timeout) = HeaderValue::from_str(&format!("{}m", deadline.as_millis())) {
+            request.headers_mut().insert("grpc-timeout", timeout);
+        }
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let call = async move { inner.oneshot(request).await.map_err(Into::into) };
+            match tokio::time::timeout(deadline, call).await {
+                Ok(result) => result,
+                Err(_) => Err(Box::new(Status::deadline_exceeded(format!(
+                    "{} timed out after {:?}",
+                    path, deadline
+                ))) as BoxError),
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerLayer {
+    backend: Backend,
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            inner,
+            backend: self.backend.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerService<S> {
+    inner: S,
+    backend: Backend,
+}
+
+struct ProbeGuard(Option<CircuitBreaker>);
+
+impl Drop for ProbeGuard {
+    fn drop(&mut self) {
+        if let Some(breaker) = self.0.take() {
+            breaker.release_probe();
+        }
+    }
+}
+
+impl<S> Service<Request<BoxBody>> for CircuitBreakerService<S>
+where
+    S: Service<Request<BoxBody>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        let inner = self.inner.clone();
+        let backend = self.backend.clone();
+
+        Box::pin(async move {
+            backend
+                .check_reachable()
+                .map_err(|status| status as BoxError)?;
+            let breaker = backend.breaker();
+            let probe = breaker.acquire().map_err(|status| status as BoxError)?;
+            let mut guard = ProbeGuard(probe.then(|| breaker.clone()));
+
+            let result = inner.oneshot(request).await.map_err(Into::into);
+            guard.0 = None;
+            match &result {
+                Ok(response) => match response_code(response) {
+                    Some(code) if is_backend_failure(code) => {
+                        breaker.record_failure(format!("{:?}", code))
+                    }
+                    _ => breaker.record_success(),
+                },
+                Err(e) => breaker.record_failure(e.to_string()),
+            }
+            result
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryLayer {
+    policy: Arc<ResiliencePolicy>,
+    backend: Backend,
+}
+
+impl<S> Layer<S> for RetryLayer {
+    type Service = RetryService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryService {
+            inner,
+            policy: self.policy.clone(),
+            backend: self.backend.clone(),
+        }
+    }
+}
+
+// Sends idempotent calls again when the backend could not be reached or
+// answered `Unavailable`. Errors raised by the gateway itself, such as an
+// open breaker or an expired deadline, are not retried. Channel errors are
+// reported as `Unavailable`.
+#[derive(Debug, Clone)]
+pub struct RetryService<S> {
+    inner: S,
+    policy: Arc<ResiliencePolicy>,
+    backend: Backend,
+}
+
+fn is_retryable(result: &Result<Response<Body>, BoxError>) -> bool {
+    match result {
+        Ok(response) => response_code(response) == Some(Code::Unavailable),
+        Err(e) => !e.is::<Status>(),
+    }
+}
+
+async fn buffer_body(mut body: BoxBody) -> Result<Bytes, BoxError> {
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        buffer.extend_from_slice(&chunk?);
+    }
+    Ok(buffer.freeze())
+}
+
+fn buffered_request(parts: &http::request::Parts, body: Bytes) -> Request<BoxBody> {
+    let mut request = Request::new(
+        http_body::Full::new(body)
+            .map_err(|never| match never {})
+            .boxed_unsync(),
+    );
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+async fn send_with_retries<S>(
+    inner: S,
+    request: Request<BoxBody>,
+    policy: &ResiliencePolicy,
+) -> Result<Response<Body>, BoxError>
+where
+    S: Service<Request<BoxBody>, Response = Response<Body>> + Clone,
+    S::Error: Into<BoxError>,
+{
+    let (parts, body) = request.into_parts();
+    let body = buffer_body(body).await?;
+
+    let mut attempt = 0;
+    loop {
+        let request = buffered_request(&parts, body.clone());
+        let result = inner.clone().oneshot(request).await.map_err(Into::into);
+        if attempt >= policy.max_retries || !is_retryable(&result) {
+            return result;
+        }
+
+        attempt += 1;
+        warn!(
+            "Retrying {} (attempt {} of {})",
+            parts.uri.path(),
+            attempt,
+            policy.max_retries
+        );
+        tokio::time::sleep(policy.retry_backoff * attempt).await;
+    }
+}
+
+impl<S> Service<Request<BoxBody>> for RetryService<S>
+where
+    S: Service<Request<BoxBody>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        let inner = self.inner.clone();
+        let policy = self.policy.clone();
+        let backend = self.backend.clone();
+
+        Box::pin(async move {
+            let result = if policy.max_retries == 0 || !is_idempotent(request.uri().path()) {
+                inner.oneshot(request).await.map_err(Into::into)
+            } else {
+                send_with_retries(inner, request, &policy).await
+            };
+
+            // Errors that were not raised by the stack come from the channel,
+            // which could not reach the backend
+            result.map_err(|e| {
+                if e.is::<Status>() {
+                    return e;
+                }
+                Box::new(unavailable(
+                    format!(
+                        "The {} service is unreachable: {}",
+                        backend.name(),
+                        describe_error(e.as_ref())
+                    ),
+                    backend.check_interval(),
+                )) as BoxError
+            })
+        })
+    }
+}
@@ -3,19 +3,15 @@ pub mod user_service {
 }
 
 use user_service::{user_service_client::UserServiceClient};
-use tonic::transport::Channel;
+use super::backend::Backend;
+use super::resilience::{GrpcChannel, ResiliencePolicy};
 
-pub async fn get_user_grpc_client(
+pub fn get_user_grpc_client(
     uri: String,
-) -> Result<UserServiceClient<Channel>, Box<dyn std::error::Error>> {
-    let grpc_uri = format!("http://{}", uri); 
-    let static_uri = Box::leak(grpc_uri.into_boxed_str());
-
-    let channel = tonic::transport::Channel::from_static(static_uri)
-        .connect()
-        .await?;
-
+    policy: &ResiliencePolicy,
+) -> Result<(UserServiceClient<GrpcChannel>, Backend), Box<dyn std::error::Error>> {
+    let (channel, backend) = policy.connect_lazy("user", &uri)?;
     let client = UserServiceClient::new(channel);
 
-    Ok(client)
+    Ok((client, backend))
 }
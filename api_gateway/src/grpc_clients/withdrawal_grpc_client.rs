@@ -3,19 +3,15 @@ pub mod withdrawal {
 }
 
 use withdrawal::withdrawal_service_client::WithdrawalServiceClient;
-use tonic::transport::Channel;
+use super::backend::Backend;
+use super::resilience::{GrpcChannel, ResiliencePolicy};
 
-pub async fn get_withdrawal_grpc_client(
+pub fn get_withdrawal_grpc_client(
     uri: String,
-) -> Result<WithdrawalServiceClient<Channel>, Box<dyn std::error::Error>> {
-    let grpc_uri = format!("http://{}", uri); 
-    let static_uri = Box::leak(grpc_uri.into_boxed_str());
-
-    let channel = tonic::transport::Channel::from_static(static_uri)
-        .connect()
-        .await?;
-
+    policy: &ResiliencePolicy,
+) -> Result<(WithdrawalServiceClient<GrpcChannel>, Backend), Box<dyn std::error::Error>> {
+    let (channel, backend) = policy.connect_lazy("withdrawal", &uri)?;
     let client = WithdrawalServiceClient::new(channel);
 
-    Ok(client)
+    Ok((client, backend))
 }
@@ -1,19 +1,45 @@
 use crate::{
     grpc_clients::account_grpc_client::account::{
-        AccountType, CreateAccountRequest, GetAccountRequest, UpdateAccountRequest, GetUserAccountsRequest,
+        AccountType, CreateAccountRequest, GetAccountByNumberRequest, GetAccountRequest,
+        GetInterestAccrualsRequest, UpdateAccountRequest, GetUserAccountsRequest,
+        SetOverdraftLimitRequest, PlaceHoldRequest, CaptureHoldRequest, ReleaseHoldRequest,
+        ListHoldsRequest, Hold, AdjustBalanceRequest, CloseAccountRequest,
     },
+    handlers::approval_handlers::account_operation_to_json,
+    handlers::healt_handler::backend_unavailable,
     jwt_auth,
     models::{
         account::{Account, AccountType as AccountTypeModel},
+        account_closure_request::AccountClosureRequest,
         account_update_request::UpdateAccountRequestModel,
+        balance_adjustment_request::BalanceAdjustmentRequest,
+        capture_hold_request::CaptureHoldRequest as CaptureHoldRequestModel,
+        hold_query::HoldQuery,
+        hold_request::HoldRequest,
+        interest_query::InterestQuery,
+        overdraft_request::OverdraftRequest,
     },
     AppState
 };
 
-use actix_web::{get, post, put, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, post, put, web, HttpResponse, Responder};
 use log::{error, info};
 use serde_json::json;
 
+fn hold_to_json(hold: Hold) -> serde_json::Value {
+    serde_json::json!({
+        "hold_id": hold.hold_id,
+        "account_id": hold.account_id,
+        "amount": hold.amount,
+        "captured_amount": hold.captured_amount,
+        "status": hold.status,
+        "reference": hold.reference,
+        "description": hold.description,
+        "created_timestamp": hold.created_timestamp,
+        "expires_timestamp": hold.expires_timestamp
+    })
+}
+
 #[get("healthchecker")]
 async fn health_checker_handler(_: jwt_auth::JwtMiddleware) -> impl Responder {
     const MESSAGE: &str = "JWT Authentication in Rust using Actix-web and Mongodb";
@@ -22,15 +48,13 @@ async fn health_checker_handler(_: jwt_auth::JwtMiddleware) -> impl Responder {
 
 #[post("create")]
 async fn create_account_handler(
-    req: HttpRequest,
     body: web::Json<Account>,
     data: web::Data<AppState>,
-    _: jwt_auth::JwtMiddleware,
+    auth: jwt_auth::JwtMiddleware,
 ) -> impl Responder {
     info!("Creating new account with name: {}", body.account_name);
 
-    let ext = req.extensions();
-    let user_id = ext.get::<uuid::Uuid>().unwrap();
+    let user_id = auth.user_id;
 
     let mut grpc_client = data.account_grpc_client.clone();
 
@@ -44,20 +68,24 @@ async fn create_account_handler(
             user_id: user_id.to_string(),
             account_type: account_type as i32,
             account_name: body.account_name.clone(),
+            interest_product: body.interest_product.clone().unwrap_or_default(),
+            currency: body.currency.clone().unwrap_or_default(),
         }))
         .await;
 
     match result {
         Ok(response) => {
-            let account_id = response.into_inner().account_id;
-            info!("Account created with ID: {}", account_id);
+            let created = response.into_inner();
+            info!("Account created with ID: {}", created.account_id);
             let account_response = serde_json::json!({"status": "success","data": serde_json::json!({
                 "account": {
-                    "id": account_id
+                    "id": created.account_id,
+                    "account_number": created.account_number
                 }
             })});
             HttpResponse::Ok().json(account_response)
         }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
         Err(e) => {
             error!("Error creating account: {:?}", e);
             HttpResponse::InternalServerError()
@@ -76,25 +104,50 @@ async fn get_account_handler(
 
     let mut grpc_client = data.account_grpc_client.clone();
 
+    // The path accepts either the account id or the account number
     let account_id = account.into_inner();
-    let result = grpc_client
-        .get_account(tonic::Request::new(GetAccountRequest {
-            account_id: account_id.clone(),
-        }))
-        .await;
+    let is_object_id = account_id.len() == 24 && account_id.chars().all(|c| c.is_ascii_hexdigit());
+    let result = if is_object_id {
+        grpc_client
+            .get_account(tonic::Request::new(GetAccountRequest {
+                account_id: account_id.clone(),
+            }))
+            .await
+            .map(|response| response.into_inner().account)
+    } else {
+        grpc_client
+            .get_account_by_number(tonic::Request::new(GetAccountByNumberRequest {
+                account_number: account_id.clone(),
+            }))
+            .await
+            .map(|response| response.into_inner().account)
+    };
 
     match result {
-        Ok(response) => {
-            let account = response.into_inner().account.unwrap();
+        Ok(account) => {
+            let account = account.unwrap();
             let account_response = serde_json::json!({"status": "success","account": serde_json::json!({
                 "id": account.account_id,
                 "user_id": account.user_id,
                 "account_type": account.account_type,
                 "account_name": account.account_name,
-                "balance": account.balance
+                "account_number": account.account_number,
+                "interest_product": account.interest_product,
+                "accrued_interest": account.accrued_interest,
+                "overdraft_limit": account.overdraft_limit,
+                "overdraft_rate": account.overdraft_rate,
+                "accrued_overdraft_interest": account.accrued_overdraft_interest,
+                "currency": account.currency,
+                "nickname": account.nickname,
+                "settings": account.settings,
+                "balance": account.balance,
+                "available_balance": account.available_balance,
+                "held_amount": account.held_amount,
+                "closed": account.closed
             })});
             HttpResponse::Ok().json(account_response)
         }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
         Err(e) => {
             error!("Error getting account: {:?}", e);
             HttpResponse::InternalServerError()
@@ -105,12 +158,10 @@ async fn get_account_handler(
 
 #[get("accounts")]
 async fn get_accounts_handler(
-    req: HttpRequest,
     data: web::Data<AppState>,
-    _: jwt_auth::JwtMiddleware,
+    auth: jwt_auth::JwtMiddleware,
 ) -> impl Responder {
-    let ext = req.extensions();
-    let user_id = ext.get::<uuid::Uuid>().unwrap();
+    let user_id = auth.user_id;
 
     info!("Getting account with user ID: {}", user_id);
 
@@ -118,7 +169,7 @@ async fn get_accounts_handler(
 
     let result = grpc_client
         .get_user_accounts(tonic::Request::new(GetUserAccountsRequest {
-            user_id: user_id.to_string().clone(),
+            user_id: user_id.to_string(),
         }))
         .await;
 
@@ -136,7 +187,19 @@ async fn get_accounts_handler(
                     "user_id": account.user_id,
                     "account_type": account.account_type,
                     "balance": account.balance,
-                    "account_name": account.account_name
+                    "available_balance": account.available_balance,
+                    "held_amount": account.held_amount,
+                    "account_name": account.account_name,
+                    "account_number": account.account_number,
+                    "interest_product": account.interest_product,
+                    "accrued_interest": account.accrued_interest,
+                    "overdraft_limit": account.overdraft_limit,
+                    "overdraft_rate": account.overdraft_rate,
+                    "accrued_overdraft_interest": account.accrued_overdraft_interest,
+                    "currency": account.currency,
+                    "nickname": account.nickname,
+                    "settings": account.settings,
+                    "closed": account.closed
                 })
             }).collect::<Vec<serde_json::Value>>());
             
@@ -145,6 +208,7 @@ async fn get_accounts_handler(
                 "accounts": accounts_json
             }))
         }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
         Err(e) => {
             error!("Error getting accounts: {:?}", e);
             HttpResponse::InternalServerError()
@@ -157,7 +221,7 @@ async fn get_accounts_handler(
 async fn update_account_handler(
     account: web::Json<UpdateAccountRequestModel>,
     data: web::Data<AppState>,
-    _: jwt_auth::JwtMiddleware,
+    auth: jwt_auth::JwtMiddleware,
 ) -> impl Responder {
     info!("Updating account with ID: {}", account.account_id);
     let mut grpc_client = data.account_grpc_client.clone();
@@ -165,7 +229,10 @@ async fn update_account_handler(
     let result = grpc_client
         .update_account(UpdateAccountRequest {
             account_id: account.account_id.clone(),
-            balance: account.balance.clone(),
+            account_name: account.account_name.clone().unwrap_or_default(),
+            nickname: account.nickname.clone().unwrap_or_default(),
+            settings: account.settings.clone().unwrap_or_default(),
+            user_id: auth.user_id.to_string(),
         })
         .await;
 
@@ -178,10 +245,20 @@ async fn update_account_handler(
                 "account_name": account.account_name,
                 "account_type": account.account_type,
                 "account_name": account.account_name,
+                "account_number": account.account_number,
+                "nickname": account.nickname,
+                "settings": account.settings,
                 "balance": account.balance
             })});
             HttpResponse::Ok().json(account_response)
         }
+        Err(e) if e.code() == tonic::Code::InvalidArgument => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
         Err(e) => {
             error!("Error updating account: {:?}", e);
             HttpResponse::InternalServerError()
@@ -190,12 +267,427 @@ async fn update_account_handler(
     }
 }
 
+#[get("{account_id}/interest")]
+async fn get_interest_accruals_handler(
+    account: web::Path<String>,
+    query: web::Query<InterestQuery>,
+    data: web::Data<AppState>,
+    _: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let account_id = account.into_inner();
+    info!("Getting interest accruals for account: {}", account_id);
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .get_interest_accruals(tonic::Request::new(GetInterestAccrualsRequest {
+            account_id,
+            from_date: query.from.clone().unwrap_or_default(),
+            to_date: query.to.clone().unwrap_or_default(),
+        }))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let interest = response.into_inner();
+            let accruals: Vec<serde_json::Value> = interest
+                .accruals
+                .into_iter()
+                .map(|accrual| {
+                    serde_json::json!({
+                        "date": accrual.date,
+                        "balance": accrual.balance,
+                        "amount": accrual.amount,
+                        "day_count": accrual.day_count,
+                        "capitalized": accrual.capitalized
+                    })
+                })
+                .collect();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "interest_product": interest.interest_product,
+                "accrued_interest": interest.accrued_interest,
+                "accruals": accruals
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error getting interest accruals: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[put("{account_id}/overdraft")]
+async fn set_overdraft_limit_handler(
+    account: web::Path<String>,
+    body: web::Json<OverdraftRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let account_id = account.into_inner();
+    let user_id = auth.user_id.to_string();
+
+    if !data.env.admin_user_ids.contains(&user_id) {
+        error!("User {} is not allowed to manage overdraft limits", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only administrators can manage overdraft limits"
+        }));
+    }
+
+    info!(
+        "Setting overdraft limit of account {} to {}",
+        account_id, body.overdraft_limit
+    );
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .set_overdraft_limit(tonic::Request::new(SetOverdraftLimitRequest {
+            account_id,
+            overdraft_limit: body.overdraft_limit,
+            overdraft_rate: body.overdraft_rate.unwrap_or_default(),
+            updated_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let account = response.into_inner().account.unwrap();
+            HttpResponse::Ok().json(serde_json::json!({"status": "success","account": serde_json::json!({
+                "id": account.account_id,
+                "account_number": account.account_number,
+                "balance": account.balance,
+                "overdraft_limit": account.overdraft_limit,
+                "overdraft_rate": account.overdraft_rate,
+                "accrued_overdraft_interest": account.accrued_overdraft_interest,
+                "currency": account.currency
+            })}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error setting overdraft limit: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[post("{account_id}/holds")]
+async fn place_hold_handler(
+    account: web::Path<String>,
+    body: web::Json<HoldRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let account_id = account.into_inner();
+    let user_id = auth.user_id.to_string();
+    info!("Placing hold of {} on account {}", body.amount, account_id);
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .place_hold(tonic::Request::new(PlaceHoldRequest {
+            account_id,
+            amount: body.amount,
+            reference: body.reference.clone().unwrap_or_default(),
+            description: body.description.clone().unwrap_or_default(),
+            expires_in_seconds: body.expires_in_seconds.unwrap_or_default(),
+            requester_roles: data.env.roles(&user_id),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => match response.into_inner().hold {
+            Some(hold) => HttpResponse::Ok()
+                .json(serde_json::json!({"status": "success", "hold": hold_to_json(hold)})),
+            None => HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": "Missing hold"})),
+        },
+        Err(e) if matches!(
+            e.code(),
+            tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition
+        ) => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::NotFound => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error placing hold: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[get("{account_id}/holds")]
+async fn list_holds_handler(
+    account: web::Path<String>,
+    query: web::Query<HoldQuery>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let account_id = account.into_inner();
+    let user_id = auth.user_id.to_string();
+    info!("Getting holds of account {}", account_id);
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .list_holds(tonic::Request::new(ListHoldsRequest {
+            account_id,
+            active_only: query.active_only.unwrap_or_default(),
+            requester_roles: data.env.roles(&user_id),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let holds: Vec<serde_json::Value> = response
+                .into_inner()
+                .holds
+                .into_iter()
+                .map(hold_to_json)
+                .collect();
+            HttpResponse::Ok().json(serde_json::json!({"status": "success", "holds": holds}))
+        }
+        Err(e) if e.code() == tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::NotFound => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error getting holds: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[post("holds/{hold_id}/capture")]
+async fn capture_hold_handler(
+    hold: web::Path<String>,
+    body: web::Json<CaptureHoldRequestModel>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let hold_id = hold.into_inner();
+    let user_id = auth.user_id.to_string();
+    info!("Capturing hold {}", hold_id);
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .capture_hold(tonic::Request::new(CaptureHoldRequest {
+            hold_id,
+            amount: body.amount.unwrap_or_default(),
+            requester_roles: data.env.roles(&user_id),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let captured = response.into_inner();
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "transaction_id": captured.transaction_id,
+                "hold": captured.hold.map(hold_to_json)
+            }))
+        }
+        Err(e) if matches!(
+            e.code(),
+            tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition
+        ) => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::NotFound => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error capturing hold: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[post("holds/{hold_id}/release")]
+async fn release_hold_handler(
+    hold: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let hold_id = hold.into_inner();
+    let user_id = auth.user_id.to_string();
+    info!("Releasing hold {}", hold_id);
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .release_hold(tonic::Request::new(ReleaseHoldRequest {
+            hold_id,
+            requester_roles: data.env.roles(&user_id),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "hold": response.into_inner().hold.map(hold_to_json)
+        })),
+        Err(e) if matches!(
+            e.code(),
+            tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition
+        ) => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::NotFound => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error releasing hold: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[post("{account_id}/adjustments")]
+async fn adjust_balance_handler(
+    account: web::Path<String>,
+    body: web::Json<BalanceAdjustmentRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let account_id = account.into_inner();
+    let user_id = auth.user_id.to_string();
+
+    if !data.env.admin_user_ids.contains(&user_id) {
+        error!("User {} is not allowed to adjust balances", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only administrators can adjust balances"
+        }));
+    }
+
+    info!(
+        "Requesting balance adjustment of {} on account {}",
+        body.amount, account_id
+    );
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .adjust_balance(tonic::Request::new(AdjustBalanceRequest {
+            account_id,
+            amount: body.amount,
+            reason: body.reason.clone(),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => HttpResponse::Accepted().json(serde_json::json!({
+            "status": "pending_approval",
+            "operation": response.into_inner().operation.map(account_operation_to_json)
+        })),
+        Err(e) if matches!(
+            e.code(),
+            tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition
+        ) => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error requesting balance adjustment: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[post("{account_id}/close")]
+async fn close_account_handler(
+    account: web::Path<String>,
+    body: web::Json<AccountClosureRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let account_id = account.into_inner();
+    let user_id = auth.user_id.to_string();
+
+    info!("Requesting closure of account {} by user {}", account_id, user_id);
+
+    let mut grpc_client = data.account_grpc_client.clone();
+
+    let result = grpc_client
+        .close_account(tonic::Request::new(CloseAccountRequest {
+            account_id,
+            reason: body.reason.clone(),
+            requester_roles: data.env.roles(&user_id),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => HttpResponse::Accepted().json(serde_json::json!({
+            "status": "pending_approval",
+            "operation": response.into_inner().operation.map(account_operation_to_json)
+        })),
+        Err(e) if matches!(
+            e.code(),
+            tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition
+        ) => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error requesting account closure: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
 pub fn config(conf: &mut web::ServiceConfig) {
     let scope = web::scope("/api/account")
         .service(health_checker_handler)
         .service(create_account_handler)
         .service(update_account_handler)
         .service(get_accounts_handler)
+        .service(get_interest_accruals_handler)
+        .service(set_overdraft_limit_handler)
+        .service(place_hold_handler)
+        .service(list_holds_handler)
+        .service(capture_hold_handler)
+        .service(release_hold_handler)
+        .service(adjust_balance_handler)
+        .service(close_account_handler)
         .service(get_account_handler);
     conf.service(scope);
 }
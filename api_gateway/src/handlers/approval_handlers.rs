@@ -0,0 +1,252 @@
+use crate::{
+    grpc_clients::account_grpc_client::account::{
+        ListPendingOperationsRequest as AccountOperationsRequest,
+        PendingOperation as AccountOperation, ReviewOperationRequest as AccountReviewRequest,
+    },
+    grpc_clients::deposit_grpc_client::deposit::{
+        ListPendingOperationsRequest as DepositOperationsRequest,
+        PendingOperation as DepositOperation, ReviewOperationRequest as DepositReviewRequest,
+    },
+    handlers::healt_handler::backend_unavailable,
+    jwt_auth,
+    models::{operation_query::OperationQuery, operation_review_request::OperationReviewRequest},
+    AppState
+};
+
+use actix_web::{get, post, web, HttpResponse, Responder};
+use log::{error, info};
+use serde_json::json;
+
+// Every service owning privileged operations generates its own PendingOperation
+// type, with the same fields
+macro_rules! operation_to_json {
+    ($service:expr, $operation:expr) => {{
+        let operation = $operation;
+        serde_json::json!({
+            "service": $service,
+            "operation_id": operation.operation_id,
+            "kind": operation.kind,
+            "summary": operation.summary,
+            "status": operation.status,
+            "requested_by": operation.requested_by,
+            "required_role": operation.required_role,
+            "reviewed_by": operation.reviewed_by,
+            "review_comment": operation.review_comment,
+            "result": operation.result,
+            "error": operation.error,
+            "created_timestamp": operation.created_timestamp,
+            "expires_timestamp": operation.expires_timestamp,
+            "reviewed_timestamp": operation.reviewed_timestamp,
+            "history": operation.history.into_iter().map(|event| serde_json::json!({
+                "action": event.action,
+                "user_id": event.user_id,
+                "comment": event.comment,
+                "timestamp": event.timestamp
+            })).collect::<Vec<serde_json::Value>>()
+        })
+    }};
+}
+
+pub fn account_operation_to_json(operation: AccountOperation) -> serde_json::Value {
+    operation_to_json!("account", operation)
+}
+
+pub fn deposit_operation_to_json(operation: DepositOperation) -> serde_json::Value {
+    operation_to_json!("deposit", operation)
+}
+
+fn operation_error(e: tonic::Status) -> HttpResponse {
+    match e.code() {
+        tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::NotFound => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::Unavailable => backend_unavailable(&e),
+        _ => {
+            error!("Error handling pending operation: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[get("")]
+async fn list_operations_handler(
+    query: web::Query<OperationQuery>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let user_id = auth.user_id.to_string();
+
+    if data.env.roles(&user_id).is_empty() {
+        error!("User {} is not allowed to list pending operations", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only bank staff can list pending operations"
+        }));
+    }
+
+    let service = query.service.clone().unwrap_or_default();
+    if !matches!(service.as_str(), "" | "account" | "deposit") {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "fail",
+            "message": format!("Unknown service: {}", service)
+        }));
+    }
+    let status = query.status.clone().unwrap_or_default();
+    let kind = query.kind.clone().unwrap_or_default();
+
+    info!("Listing pending operations for user {}", user_id);
+
+    let mut operations = Vec::new();
+
+    if service.is_empty() || service == "account" {
+        let mut grpc_client = data.account_grpc_client.clone();
+        match grpc_client
+            .list_pending_operations(tonic::Request::new(AccountOperationsRequest {
+                status: status.clone(),
+                kind: kind.clone(),
+            }))
+            .await
+        {
+            Ok(response) => operations.extend(
+                response.into_inner().operations.into_iter().map(account_operation_to_json),
+            ),
+            Err(e) => return operation_error(e),
+        }
+    }
+
+    if service.is_empty() || service == "deposit" {
+        let mut grpc_client = data.deposit_grpc_client.clone();
+        match grpc_client
+            .list_pending_operations(tonic::Request::new(DepositOperationsRequest {
+                status,
+                kind,
+            }))
+            .await
+        {
+            Ok(response) => operations.extend(
+                response.into_inner().operations.into_iter().map(deposit_operation_to_json),
+            ),
+            Err(e) => return operation_error(e),
+        }
+    }
+
+    operations.sort_by_key(|operation| std::cmp::Reverse(operation["created_timestamp"].as_i64()));
+
+    HttpResponse::Ok().json(json!({"status": "success", "operations": operations}))
+}
+
+async fn review_operation(
+    service: String,
+    operation_id: String,
+    approve: bool,
+    body: OperationReviewRequest,
+    data: web::Data<AppState>,
+    user_id: String,
+) -> HttpResponse {
+    info!(
+        "Reviewing {} operation {} (approve: {}) by user {}",
+        service, operation_id, approve, user_id
+    );
+
+    let reviewer_roles = data.env.roles(&user_id);
+    let comment = body.comment.unwrap_or_default();
+
+    let result = match service.as_str() {
+        "account" => {
+            let mut grpc_client = data.account_grpc_client.clone();
+            let request = tonic::Request::new(AccountReviewRequest {
+                operation_id,
+                reviewed_by: user_id,
+                reviewer_roles,
+                comment,
+            });
+            let response = if approve {
+                grpc_client.approve_operation(request).await
+            } else {
+                grpc_client.reject_operation(request).await
+            };
+            response.map(|response| response.into_inner().operation.map(account_operation_to_json))
+        }
+        "deposit" => {
+            let mut grpc_client = data.deposit_grpc_client.clone();
+            let request = tonic::Request::new(DepositReviewRequest {
+                operation_id,
+                reviewed_by: user_id,
+                reviewer_roles,
+                comment,
+            });
+            let response = if approve {
+                grpc_client.approve_operation(request).await
+            } else {
+                grpc_client.reject_operation(request).await
+            };
+            response.map(|response| response.into_inner().operation.map(deposit_operation_to_json))
+        }
+        _ => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "fail",
+                "message": format!("Unknown service: {}", service)
+            }))
+        }
+    };
+
+    match result {
+        Ok(operation) => {
+            HttpResponse::Ok().json(json!({"status": "success", "operation": operation}))
+        }
+        Err(e) => operation_error(e),
+    }
+}
+
+#[post("{service}/{operation_id}/approve")]
+async fn approve_operation_handler(
+    path: web::Path<(String, String)>,
+    body: web::Json<OperationReviewRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let (service, operation_id) = path.into_inner();
+    review_operation(
+        service,
+        operation_id,
+        true,
+        body.into_inner(),
+        data,
+        auth.user_id.to_string(),
+    )
+    .await
+}
+
+#[post("{service}/{operation_id}/reject")]
+async fn reject_operation_handler(
+    path: web::Path<(String, String)>,
+    body: web::Json<OperationReviewRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let (service, operation_id) = path.into_inner();
+    review_operation(
+        service,
+        operation_id,
+        false,
+        body.into_inner(),
+        data,
+        auth.user_id.to_string(),
+    )
+    .await
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    let scope = web::scope("/api/bank/approvals")
+        .service(list_operations_handler)
+        .service(approve_operation_handler)
+        .service(reject_operation_handler);
+    conf.service(scope);
+}
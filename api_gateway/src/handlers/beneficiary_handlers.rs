@@ -0,0 +1,284 @@
+use crate::{
+    grpc_clients::deposit_grpc_client::deposit::{
+        AddBeneficiaryRequest, Beneficiary, ConfirmBeneficiaryRequest, DeleteBeneficiaryRequest,
+        GetBeneficiaryRequest, ListBeneficiariesRequest, UpdateBeneficiaryRequest,
+    },
+    handlers::healt_handler::backend_unavailable,
+    jwt_auth,
+    models::{
+        beneficiary_request::BeneficiaryRequest,
+        beneficiary_update_request::BeneficiaryUpdateRequest,
+    },
+    AppState
+};
+
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use log::{error, info};
+
+fn beneficiary_to_json(beneficiary: Beneficiary) -> serde_json::Value {
+    serde_json::json!({
+        "beneficiary_id": beneficiary.beneficiary_id,
+        "name": beneficiary.name,
+        "account_id": beneficiary.account_id,
+        "nickname": beneficiary.nickname,
+        "verification_status": beneficiary.verification_status,
+        "created_timestamp": beneficiary.created_timestamp,
+        "cooling_off_until_timestamp": beneficiary.cooling_off_until_timestamp,
+        "cooling_off_remaining_limit": beneficiary.cooling_off_remaining_limit,
+        "name_mismatch_confirmed": beneficiary.name_mismatch_confirmed
+    })
+}
+
+#[post("")]
+async fn add_beneficiary_handler(
+    body: web::Json<BeneficiaryRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!(
+        "Adding beneficiary for account: {} to user: {}",
+        body.account_id, auth.user_id
+    );
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let add_request = AddBeneficiaryRequest {
+        user_id: auth.user_id.to_string(),
+        name: body.name.clone(),
+        account_id: body.account_id.clone(),
+        nickname: body.nickname.clone().unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .add_beneficiary(tonic::Request::new(add_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let beneficiary = response.into_inner().beneficiary.unwrap();
+            info!("Beneficiary added: {}", beneficiary.beneficiary_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "beneficiary": beneficiary_to_json(beneficiary)
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error adding beneficiary: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[get("")]
+async fn list_beneficiaries_handler(
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!("Getting beneficiaries for user: {}", auth.user_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let list_request = ListBeneficiariesRequest {
+        user_id: auth.user_id.to_string(),
+    };
+
+    let result = grpc_client
+        .list_beneficiaries(tonic::Request::new(list_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let beneficiaries: Vec<serde_json::Value> = response
+                .into_inner()
+                .beneficiaries
+                .into_iter()
+                .map(beneficiary_to_json)
+                .collect();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "beneficiaries": beneficiaries
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error getting beneficiaries: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[get("{beneficiary_id}")]
+async fn get_beneficiary_handler(
+    beneficiary: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let beneficiary_id = beneficiary.into_inner();
+    info!("Getting beneficiary with ID: {}", beneficiary_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let get_request = GetBeneficiaryRequest {
+        user_id: auth.user_id.to_string(),
+        beneficiary_id,
+    };
+
+    let result = grpc_client
+        .get_beneficiary(tonic::Request::new(get_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let beneficiary = response.into_inner().beneficiary.unwrap();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "beneficiary": beneficiary_to_json(beneficiary)
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error getting beneficiary: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[put("{beneficiary_id}")]
+async fn update_beneficiary_handler(
+    beneficiary: web::Path<String>,
+    body: web::Json<BeneficiaryUpdateRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let beneficiary_id = beneficiary.into_inner();
+    info!("Updating beneficiary with ID: {}", beneficiary_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let update_request = UpdateBeneficiaryRequest {
+        user_id: auth.user_id.to_string(),
+        beneficiary_id,
+        name: body.name.clone().unwrap_or_default(),
+        nickname: body.nickname.clone().unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .update_beneficiary(tonic::Request::new(update_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let beneficiary = response.into_inner().beneficiary.unwrap();
+            info!("Beneficiary updated: {}", beneficiary.beneficiary_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "beneficiary": beneficiary_to_json(beneficiary)
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error updating beneficiary: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[delete("{beneficiary_id}")]
+async fn delete_beneficiary_handler(
+    beneficiary: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let beneficiary_id = beneficiary.into_inner();
+    info!("Deleting beneficiary with ID: {}", beneficiary_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let delete_request = DeleteBeneficiaryRequest {
+        user_id: auth.user_id.to_string(),
+        beneficiary_id,
+    };
+
+    let result = grpc_client
+        .delete_beneficiary(tonic::Request::new(delete_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let status = response.into_inner().success;
+
+            HttpResponse::Ok().json(serde_json::json!({ "status": status }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error deleting beneficiary: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+// Accepts paying a beneficiary whose name does not match the account holder
+#[post("{beneficiary_id}/confirm")]
+async fn confirm_beneficiary_handler(
+    beneficiary: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let beneficiary_id = beneficiary.into_inner();
+    info!("Confirming beneficiary with ID: {}", beneficiary_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let confirm_request = ConfirmBeneficiaryRequest {
+        user_id: auth.user_id.to_string(),
+        beneficiary_id,
+    };
+
+    let result = grpc_client
+        .confirm_beneficiary(tonic::Request::new(confirm_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let beneficiary = response.into_inner().beneficiary.unwrap();
+            info!("Beneficiary confirmed: {}", beneficiary.beneficiary_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "beneficiary": beneficiary_to_json(beneficiary)
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::FailedPrecondition => HttpResponse::BadRequest()
+            .json(serde_json::json!({"status": "fail", "message": e.message()})),
+        Err(e) if e.code() == tonic::Code::NotFound => HttpResponse::NotFound()
+            .json(serde_json::json!({"status": "fail", "message": e.message()})),
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error confirming beneficiary: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    let scope = web::scope("/api/bank/beneficiaries")
+        .service(add_beneficiary_handler)
+        .service(list_beneficiaries_handler)
+        .service(get_beneficiary_handler)
+        .service(update_beneficiary_handler)
+        .service(delete_beneficiary_handler)
+        .service(confirm_beneficiary_handler);
+
+    conf.service(scope);
+}
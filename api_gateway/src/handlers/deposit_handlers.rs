@@ -1,10 +1,24 @@
 use crate::{
-    grpc_clients::deposit_grpc_client::deposit::MakeDepositRequest, jwt_auth,
-    models::deposit_request::DepositRequest, 
+    grpc_clients::deposit_grpc_client::deposit::{
+        GetPaymentBatchRequest, GetPaymentStatusReportRequest, GetTransferLimitsRequest,
+        MakeDepositRequest, PaymentBatch, PaymentFileFormat, PreviewTransferFeeRequest,
+        QuoteTransferRequest,
+        ReverseFeeRequest, ReverseTransactionRequest, SetTransferLimitsRequest, SubmitPaymentBatchRequest, TransactionLimits,
+    },
+    handlers::approval_handlers::deposit_operation_to_json,
+    handlers::healt_handler::{backend_unavailable, grpc_error},
+    jwt_auth,
+    models::{
+        deposit_request::DepositRequest, fee_preview_query::FeePreviewQuery,
+        fee_reversal_request::FeeReversalRequest, limits_query::LimitsQuery,
+        limits_request::LimitsRequest, payment_batch_query::PaymentBatchQuery,
+        transfer_quote_request::TransferQuoteRequest,
+        transaction_reversal_request::TransactionReversalRequest,
+    },
     AppState
 };
 
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{get, post, put, web, HttpResponse, Responder};
 use log::{error, info};
 use serde_json::json;
 
@@ -18,20 +32,40 @@ async fn health_checker_handler(_: jwt_auth::JwtMiddleware) -> impl Responder {
 async fn deposit_handler(
     body: web::Json<DepositRequest>,
     data: web::Data<AppState>,
-    _: jwt_auth::JwtMiddleware,
+    auth: jwt_auth::JwtMiddleware,
 ) -> impl Responder {
+    if body.to_account_id.is_some() == body.beneficiary_id.is_some() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "fail",
+            "message": "Exactly one of to_account_id or beneficiary_id is required"
+        }));
+    }
+
+    // Agent deposits skip limits, fees and the balance check, and need approval
+    if body.is_bank_agent && data.env.roles(&auth.user_id.to_string()).is_empty() {
+        error!("User {} is not a bank agent", auth.user_id);
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "status": "fail",
+            "message": "Only bank agents can make agent deposits"
+        }));
+    }
+
     info!(
-        "Depositing amount: {} from account: {} to account: {}",
-        body.amount, body.from_account_id, body.to_account_id
+        "Depositing amount: {} from account: {} to account: {:?} (beneficiary: {:?})",
+        body.amount, body.from_account_id, body.to_account_id, body.beneficiary_id
     );
 
     let mut grpc_client = data.deposit_grpc_client.clone();
 
     let deposit_request = MakeDepositRequest {
         from_account_id: body.from_account_id.clone(),
-        to_account_id: body.to_account_id.clone(),
+        to_account_id: body.to_account_id.clone().unwrap_or_default(),
         amount: body.amount,
         is_bank_agent: body.is_bank_agent,
+        beneficiary_id: body.beneficiary_id.clone().unwrap_or_default(),
+        user_id: auth.user_id.to_string(),
+        currency: body.currency.clone().unwrap_or_default(),
+        fx_quote_id: body.fx_quote_id.clone().unwrap_or_default(),
     };
 
     let result = grpc_client
@@ -40,25 +74,490 @@ async fn deposit_handler(
 
     match result {
         Ok(response) => {
-            let status = response.into_inner().success;
+            let response = response.into_inner();
 
-            info!("Deposit successful: {}", status);
-            let deposit_response = serde_json::json!({ "status": status });
+            if let Some(operation) = response.operation {
+                info!("Agent deposit {} awaiting approval", operation.operation_id);
+                return HttpResponse::Accepted().json(serde_json::json!({
+                    "status": "pending_approval",
+                    "operation": deposit_operation_to_json(operation)
+                }));
+            }
+
+            info!("Deposit successful: {}", response.success);
+            let deposit_response = serde_json::json!({
+                "status": response.success,
+                "fee": response.fee,
+                "converted_amount": response.converted_amount,
+                "fx_rate": response.fx_rate
+            });
 
             HttpResponse::Ok().json(deposit_response)
         }
+        Err(e) => grpc_error("during deposit", e),
+    }
+}
+
+#[get("fee")]
+async fn preview_transfer_fee_handler(
+    query: web::Query<FeePreviewQuery>,
+    data: web::Data<AppState>,
+    _: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!(
+        "Previewing transfer fee for account: {} and amount: {}",
+        query.account_id, query.amount
+    );
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let preview_request = PreviewTransferFeeRequest {
+        from_account_id: query.account_id.clone(),
+        amount: query.amount,
+        is_bank_agent: query.is_bank_agent.unwrap_or(false),
+    };
+
+    let result = grpc_client
+        .preview_transfer_fee(tonic::Request::new(preview_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let preview = response.into_inner();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "fee": preview.fee,
+                "rule_name": preview.rule_name,
+                "free_operations_remaining": preview.free_operations_remaining
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error previewing transfer fee: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[post("fees/{fee_id}/reverse")]
+async fn reverse_fee_handler(
+    fee: web::Path<String>,
+    body: web::Json<FeeReversalRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let fee_id = fee.into_inner();
+    let user_id = auth.user_id.to_string();
+
+    // Bank agents and administrators can reverse fees
+    if data.env.roles(&user_id).is_empty() {
+        error!("User {} is not allowed to reverse fees", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only bank agents can reverse fees"
+        }));
+    }
+
+    info!("Reversing fee {} requested by user: {}", fee_id, user_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let reverse_request = ReverseFeeRequest {
+        fee_id,
+        reason: body.reason.clone(),
+        reversed_by: user_id,
+    };
+
+    let result = grpc_client
+        .reverse_fee(tonic::Request::new(reverse_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let reversal = response.into_inner();
+            info!("Fee {} reversed", reversal.fee_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "fee_id": reversal.fee_id,
+                "amount": reversal.amount
+            }))
+        }
+        Err(e) if matches!(
+            e.code(),
+            tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition
+        ) => {
+            HttpResponse::BadRequest()
+                .json(serde_json::json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::NotFound => HttpResponse::NotFound()
+            .json(serde_json::json!({"status": "fail", "message": e.message()})),
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
         Err(e) => {
-            error!("Error during deposit: {:?}", e);
+            error!("Error reversing fee: {:?}", e);
             HttpResponse::InternalServerError()
                 .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
         }
     }
 }
 
+#[post("transactions/{transaction_id}/reverse")]
+async fn reverse_transaction_handler(
+    transaction: web::Path<String>,
+    body: web::Json<TransactionReversalRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let transaction_id = transaction.into_inner();
+    let user_id = auth.user_id.to_string();
+
+    if data.env.roles(&user_id).is_empty() {
+        error!("User {} is not allowed to reverse transactions", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only bank agents can reverse transactions"
+        }));
+    }
+
+    info!(
+        "Reversing transaction {} ({}) requested by user: {}",
+        transaction_id, body.reason_code, user_id
+    );
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let reverse_request = ReverseTransactionRequest {
+        transaction_id,
+        reason_code: body.reason_code.clone(),
+        note: body.note.clone().unwrap_or_default(),
+        reversed_by: user_id,
+    };
+
+    let result = grpc_client
+        .reverse_transaction(tonic::Request::new(reverse_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let reversal = response.into_inner();
+            info!("Transaction reversed as {}", reversal.reversal_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "reversal_id": reversal.reversal_id,
+                "reversal_transaction_ids": reversal.reversal_transaction_ids,
+                "amount": reversal.amount
+            }))
+        }
+        Err(e) if matches!(
+            e.code(),
+            tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition
+        ) => {
+            HttpResponse::BadRequest()
+                .json(serde_json::json!({"status": "fail", "message": e.message()}))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error reversing transaction: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[post("quote")]
+async fn quote_transfer_handler(
+    body: web::Json<TransferQuoteRequest>,
+    data: web::Data<AppState>,
+    _: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!(
+        "Quoting transfer of {} from account: {} to account: {}",
+        body.amount, body.from_account_id, body.to_account_id
+    );
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let quote_request = QuoteTransferRequest {
+        from_account_id: body.from_account_id.clone(),
+        to_account_id: body.to_account_id.clone(),
+        amount: body.amount,
+    };
+
+    let result = grpc_client
+        .quote_transfer(tonic::Request::new(quote_request))
+        .await;
+
+    match result {
+        Ok(response) => match response.into_inner().quote {
+            Some(quote) => HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "quote_id": quote.quote_id,
+                "from_currency": quote.from_currency,
+                "to_currency": quote.to_currency,
+                "amount": quote.amount,
+                "converted_amount": quote.converted_amount,
+                "mid_rate": quote.mid_rate,
+                "rate": quote.rate,
+                "spread": quote.spread,
+                "expires_timestamp": quote.expires_timestamp
+            })),
+            None => HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": "Missing FX quote"})),
+        },
+        Err(e) => grpc_error("quoting transfer", e),
+    }
+}
+
+fn payment_batch_to_json(batch: PaymentBatch) -> serde_json::Value {
+    let items: Vec<serde_json::Value> = batch
+        .items
+        .into_iter()
+        .map(|item| {
+            serde_json::json!({
+                "end_to_end_id": item.end_to_end_id,
+                "payment_information_id": item.payment_information_id,
+                "from_account_id": item.from_account_id,
+                "to_account_id": item.to_account_id,
+                "amount": item.amount,
+                "status": item.status,
+                "reason_code": item.reason_code,
+                "reason": item.reason
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "batch_id": batch.batch_id,
+        "message_id": batch.message_id,
+        "format": batch.format,
+        "status": batch.status,
+        "total_items": batch.total_items,
+        "executed_items": batch.executed_items,
+        "rejected_items": batch.rejected_items,
+        "control_sum": batch.control_sum,
+        "items": items
+    })
+}
+
+#[post("batch")]
+async fn submit_payment_batch_handler(
+    query: web::Query<PaymentBatchQuery>,
+    body: String,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!(
+        "Received payment file {} from user: {}",
+        query.file_name, auth.user_id
+    );
+
+    let format = match query.format.as_deref().unwrap_or("pain001") {
+        "pain001" => PaymentFileFormat::Pain001,
+        "csv" => PaymentFileFormat::Csv,
+        other => {
+            error!("Unsupported payment file format: {}", other);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "fail",
+                "message": "format must be either pain001 or csv"
+            }));
+        }
+    };
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let submit_request = SubmitPaymentBatchRequest {
+        file_name: query.file_name.clone(),
+        format: format as i32,
+        content: body,
+        submitted_by: auth.user_id.to_string(),
+    };
+
+    let result = grpc_client
+        .submit_payment_batch(tonic::Request::new(submit_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let batch = response.into_inner().batch.unwrap();
+            info!("Payment batch created: {}", batch.batch_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "batch": payment_batch_to_json(batch)
+            }))
+        }
+        Err(e) => grpc_error("submitting payment batch", e),
+    }
+}
+
+#[get("batch/{batch_id}")]
+async fn get_payment_batch_handler(
+    batch: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let batch_id = batch.into_inner();
+    let user_id = auth.user_id.to_string();
+    info!("Getting payment batch with ID: {}", batch_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let result = grpc_client
+        .get_payment_batch(tonic::Request::new(GetPaymentBatchRequest {
+            batch_id,
+            requester_roles: data.env.roles(&user_id),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let batch = response.into_inner().batch.unwrap();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "batch": payment_batch_to_json(batch)
+            }))
+        }
+        Err(e) => grpc_error("getting payment batch", e),
+    }
+}
+
+#[get("batch/{batch_id}/report")]
+async fn get_payment_status_report_handler(
+    batch: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let batch_id = batch.into_inner();
+    let user_id = auth.user_id.to_string();
+    info!("Getting payment status report for batch: {}", batch_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let result = grpc_client
+        .get_payment_status_report(tonic::Request::new(GetPaymentStatusReportRequest {
+            batch_id,
+            requester_roles: data.env.roles(&user_id),
+            requested_by: user_id,
+        }))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let report = response.into_inner();
+            info!("Payment status report generated: {}", report.message_id);
+
+            HttpResponse::Ok()
+                .content_type("application/xml")
+                .body(report.document)
+        }
+        Err(e) => grpc_error("getting payment status report", e),
+    }
+}
+
+fn transaction_limits_to_json(limits: TransactionLimits) -> serde_json::Value {
+    serde_json::json!({
+        "channel": limits.channel,
+        "per_transaction_limit": limits.per_transaction_limit,
+        "daily_limit": limits.daily_limit,
+        "monthly_limit": limits.monthly_limit,
+        "daily_used": limits.daily_used,
+        "monthly_used": limits.monthly_used,
+        "daily_remaining": limits.daily_remaining,
+        "monthly_remaining": limits.monthly_remaining,
+        "max_per_transaction_limit": limits.max_per_transaction_limit,
+        "max_daily_limit": limits.max_daily_limit,
+        "max_monthly_limit": limits.max_monthly_limit
+    })
+}
+
+#[get("limits")]
+async fn get_transfer_limits_handler(
+    query: web::Query<LimitsQuery>,
+    data: web::Data<AppState>,
+    _: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!("Getting transfer limits for account: {}", query.account_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let limits_request = GetTransferLimitsRequest {
+        account_id: query.account_id.clone(),
+        channel: query.channel.clone().unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .get_transfer_limits(tonic::Request::new(limits_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let limits: Vec<serde_json::Value> = response
+                .into_inner()
+                .limits
+                .into_iter()
+                .map(transaction_limits_to_json)
+                .collect();
+
+            HttpResponse::Ok().json(serde_json::json!({"status": "success", "limits": limits}))
+        }
+        Err(e) => grpc_error("getting transfer limits", e),
+    }
+}
+
+#[put("limits")]
+async fn set_transfer_limits_handler(
+    body: web::Json<LimitsRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!(
+        "Setting transfer limits for account: {} requested by user: {}",
+        body.account_id, auth.user_id
+    );
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let limits_request = SetTransferLimitsRequest {
+        user_id: auth.user_id.to_string(),
+        account_id: body.account_id.clone(),
+        channel: body.channel.clone().unwrap_or_default(),
+        per_transaction_limit: body.per_transaction_limit.unwrap_or_default(),
+        daily_limit: body.daily_limit.unwrap_or_default(),
+        monthly_limit: body.monthly_limit.unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .set_transfer_limits(tonic::Request::new(limits_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let limits = response.into_inner().limits.map(transaction_limits_to_json);
+
+            HttpResponse::Ok().json(serde_json::json!({"status": "success", "limits": limits}))
+        }
+        Err(e) => grpc_error("setting transfer limits", e),
+    }
+}
+
 pub fn config(conf: &mut web::ServiceConfig) {
     let scope = web::scope("/api/bank/deposit")
         .service(health_checker_handler)
-        .service(deposit_handler);
+        .service(deposit_handler)
+        .service(preview_transfer_fee_handler)
+        .service(quote_transfer_handler)
+        .service(reverse_fee_handler)
+        .service(reverse_transaction_handler)
+        .service(get_transfer_limits_handler)
+        .service(set_transfer_limits_handler)
+        .service(submit_payment_batch_handler)
+        .service(get_payment_batch_handler)
+        .service(get_payment_status_report_handler);
 
     conf.service(scope);
 }
@@ -1,11 +1,138 @@
 use actix_web::{
-    get, HttpResponse, Responder
+    get, web, HttpResponse, Responder
 };
 
+use log::error;
 use serde_json::json;
 
+use crate::{
+    grpc_clients::resilience::{retry_after, BreakerState},
+    AppState,
+};
+
+// Response for calls rejected because their backend is unavailable. Clients
+// are told when to try again with the `Retry-After` header.
+pub fn backend_unavailable(e: &tonic::Status) -> HttpResponse {
+    let retry_after = retry_after(e).unwrap_or(5);
+    HttpResponse::ServiceUnavailable()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(json!({"status": "error", "message": e.message()}))
+}
+
+// Response for a failed call to a backend. Rejections are returned to the
+// client with the message of the service; anything else is an internal error.
+pub fn grpc_error(action: &str, e: tonic::Status) -> HttpResponse {
+    match e.code() {
+        tonic::Code::InvalidArgument => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::NotFound => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::AlreadyExists => {
+            HttpResponse::Conflict().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::FailedPrecondition => HttpResponse::UnprocessableEntity()
+            .json(json!({"status": "fail", "message": e.message()})),
+        tonic::Code::Unavailable => backend_unavailable(&e),
+        _ => {
+            error!("Error {}: {:?}", action, e);
+            HttpResponse::InternalServerError()
+                .json(json!({"status": "error", "message": format!("{:?}", e)}))
+        }
+    }
+}
+
 #[get("healthchecker")]
 async fn health_checker_handler() -> impl Responder {
     const MESSAGE: &str = "JWT Authentication in Rust using Actix-web and Mongodb";
     HttpResponse::Ok().json(json!({"status": "success", "message": MESSAGE}))
 }
+
+// State of the circuit breaker of every backend. The gateway is degraded
+// while any of them is not closed.
+#[get("api/health/circuit-breakers")]
+async fn circuit_breakers_handler(data: web::Data<AppState>) -> impl Responder {
+    let breakers: Vec<serde_json::Value> = data
+        .backends
+        .iter()
+        .map(|backend| backend.breaker().to_json())
+        .collect();
+    let degraded = data
+        .backends
+        .iter()
+        .any(|backend| backend.breaker().state() != BreakerState::Closed);
+
+    HttpResponse::Ok().json(json!({"status": "success", "data": {
+        "state": if degraded { "DEGRADED" } else { "UP" },
+        "circuit_breakers": breakers
+    }}))
+}
+
+// The gateway is live as long as it can answer, whatever the state of the
+// backends
+#[get("api/health/live")]
+async fn liveness_handler() -> impl Responder {
+    HttpResponse::Ok().json(json!({"status": "success", "data": {"state": "UP"}}))
+}
+
+// The gateway is ready while at least one backend can be reached, and
+// degraded while some of them cannot
+#[get("api/health/ready")]
+async fn readiness_handler(data: web::Data<AppState>) -> impl Responder {
+    let backends: Vec<serde_json::Value> =
+        data.backends.iter().map(|backend| backend.to_json()).collect();
+    let ready = data
+        .backends
+        .iter()
+        .filter(|backend| backend.is_ready())
+        .count();
+
+    if ready == 0 {
+        let retry_after = data
+            .backends
+            .iter()
+            .map(|backend| backend.check_interval().as_secs())
+            .min()
+            .unwrap_or(5);
+        return HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .json(json!({"status": "error", "data": {"state": "DOWN", "backends": backends}}));
+    }
+
+    let state = if ready == data.backends.len() { "UP" } else { "DEGRADED" };
+    HttpResponse::Ok().json(json!({"status": "success", "data": {
+        "state": state,
+        "backends": backends
+    }}))
+}
+
+// Health of every backend as reported by its instances over the standard gRPC
+// health protocol, down to the dependencies of each service
+#[get("api/health")]
+async fn health_handler(data: web::Data<AppState>) -> impl Responder {
+    let backends: Vec<serde_json::Value> =
+        data.backends.iter().map(|backend| backend.to_json()).collect();
+    let states: Vec<&str> = data.backends.iter().map(|backend| backend.state()).collect();
+
+    if states.iter().all(|state| *state == "DOWN") {
+        let retry_after = data
+            .backends
+            .iter()
+            .map(|backend| backend.check_interval().as_secs())
+            .min()
+            .unwrap_or(5);
+        return HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .json(json!({"status": "error", "data": {"state": "DOWN", "backends": backends}}));
+    }
+
+    let state = if states.iter().all(|state| *state == "UP") { "UP" } else { "DEGRADED" };
+    HttpResponse::Ok().json(json!({"status": "success", "data": {
+        "state": state,
+        "backends": backends
+    }}))
+}
@@ -1,12 +1,33 @@
 use crate::{
-    grpc_clients::historical_grpc_client::historical::GetTransactionHistoryRequest, jwt_auth,
+    grpc_clients::historical_grpc_client::historical::{
+        GetAccountStatementRequest, GetProjectionStatusRequest, GetTransactionHistoryRequest,
+        ProjectionStatus, RebuildProjectionRequest, StatementFormat,
+    },
+    handlers::healt_handler::backend_unavailable,
+    jwt_auth,
+    models::statement_query::StatementQuery,
     AppState
 };
 
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::{get, post, web, HttpResponse, Responder};
 use log::{error, info};
 use serde_json::json;
 
+fn projection_status_to_json(status: Option<ProjectionStatus>) -> serde_json::Value {
+    let status = status.unwrap_or_default();
+    json!({
+        "source": status.source,
+        "applied_events": status.applied_events,
+        "duplicate_events": status.duplicate_events,
+        "pending_events": status.pending_events,
+        "last_event_id": status.last_event_id,
+        "last_event_timestamp": status.last_event_timestamp,
+        "last_applied_timestamp": status.last_applied_timestamp,
+        "lag_ms": status.lag_ms,
+        "rebuilt_timestamp": status.rebuilt_timestamp
+    })
+}
+
 #[get("healthchecker")]
 async fn health_checker_handler() -> impl Responder {
     const MESSAGE: &str = "JWT Authentication in Rust using Actix-web and Mongodb";
@@ -51,7 +72,11 @@ async fn get_transaction_history_handler(
                         "transaction_id": transaction.transaction_id,
                         "account_id": transaction.account_id,
                         "transaction_type": transaction.transaction_type,
-                        "amount": transaction.amount
+                        "amount": transaction.amount,
+                        "reversal": transaction.reversal,
+                        "reversal_of": transaction.reversal_of,
+                        "reversal_transaction_id": transaction.reversal_transaction_id,
+                        "reversal_reason_code": transaction.reversal_reason_code
                     })
                 })
                 .collect();
@@ -61,6 +86,7 @@ async fn get_transaction_history_handler(
                 "data": serde_json::json!({ "transactions": transactions_json })
             }))
         }
+        Err(err) if err.code() == tonic::Code::Unavailable => backend_unavailable(&err),
         Err(err) => {
             error!("Error retrieving transaction history: {:?}", err);
             HttpResponse::InternalServerError().json(serde_json::json!({
@@ -71,10 +97,146 @@ async fn get_transaction_history_handler(
     }
 }
 
+#[get("statements/{account_id}")]
+async fn get_account_statement_handler(
+    account: web::Path<String>,
+    query: web::Query<StatementQuery>,
+    data: web::Data<AppState>,
+    _: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let account_id = account.into_inner();
+    info!("Received statement request for account: {}", account_id);
+
+    let format = match query.format.as_deref().unwrap_or("camt053") {
+        "camt053" => StatementFormat::Camt053,
+        "camt052" => StatementFormat::Camt052,
+        other => {
+            error!("Unsupported statement format: {}", other);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "fail",
+                "message": "format must be either camt053 or camt052"
+            }));
+        }
+    };
+
+    let mut grpc_client = data.historical_grpc_client.clone();
+
+    let get_account_statement_request = GetAccountStatementRequest {
+        account_id,
+        format: format as i32,
+        from_timestamp: query.from.unwrap_or_default(),
+        to_timestamp: query.to.unwrap_or_default(),
+    };
+
+    let statement_result = grpc_client
+        .get_account_statement(tonic::Request::new(get_account_statement_request))
+        .await;
+
+    match statement_result {
+        Ok(response) => {
+            let statement = response.into_inner();
+            info!("Statement generated: {}", statement.message_id);
+
+            HttpResponse::Ok()
+                .content_type("application/xml")
+                .body(statement.document)
+        }
+        Err(err) if err.code() == tonic::Code::Unavailable => backend_unavailable(&err),
+        Err(err) => {
+            error!("Error generating statement: {:?}", err);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "message": format!("{:?}", err)
+            }))
+        }
+    }
+}
+
+#[get("projection")]
+async fn get_projection_status_handler(
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let user_id = auth.user_id.to_string();
+
+    if data.env.roles(&user_id).is_empty() {
+        error!("User {} is not allowed to see the projection status", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only bank staff can see the projection status"
+        }));
+    }
+
+    let mut grpc_client = data.historical_grpc_client.clone();
+
+    match grpc_client
+        .get_projection_status(tonic::Request::new(GetProjectionStatusRequest {}))
+        .await
+    {
+        Ok(response) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "projection": projection_status_to_json(response.into_inner().status)
+        })),
+        Err(err) if err.code() == tonic::Code::Unavailable => backend_unavailable(&err),
+        Err(err) => {
+            error!("Error retrieving projection status: {:?}", err);
+            HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", err)
+            }))
+        }
+    }
+}
+
+#[post("projection/rebuild")]
+async fn rebuild_projection_handler(
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let user_id = auth.user_id.to_string();
+
+    if !data.env.roles(&user_id).contains(&"ADMIN".to_string()) {
+        error!("User {} is not allowed to rebuild the projection", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only administrators can rebuild the transaction history"
+        }));
+    }
+
+    info!("Rebuilding the transaction history, requested by user {}", user_id);
+
+    let mut grpc_client = data.historical_grpc_client.clone();
+
+    match grpc_client
+        .rebuild_projection(tonic::Request::new(RebuildProjectionRequest {}))
+        .await
+    {
+        Ok(response) => {
+            let response = response.into_inner();
+            HttpResponse::Ok().json(json!({
+                "status": "success",
+                "replayed_events": response.replayed_events,
+                "projection": projection_status_to_json(response.status)
+            }))
+        }
+        Err(err) if err.code() == tonic::Code::Unavailable => backend_unavailable(&err),
+        Err(err) => {
+            error!("Error rebuilding the transaction history: {:?}", err);
+            HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", err)
+            }))
+        }
+    }
+}
+
 pub fn config(conf: &mut web::ServiceConfig) {
     let scope = web::scope("/api/history")
         .service(health_checker_handler)
-        .service(get_transaction_history_handler);
+        .service(get_transaction_history_handler)
+        .service(get_account_statement_handler)
+        .service(get_projection_status_handler)
+        .service(rebuild_projection_handler);
 
     conf.service(scope);
 }
@@ -4,3 +4,7 @@ pub mod account_handlers;
 pub mod deposit_handlers;
 pub mod withdrawal_handlers;
 pub mod historical_handler;
+pub mod standing_order_handlers;
+pub mod beneficiary_handlers;
+pub mod approval_handlers;
+pub mod notification_handlers;
@@ -0,0 +1,271 @@
+use crate::{
+    grpc_clients::notification_grpc_client::notification::{
+        ContactDetails, DeliveryStatus, GetContactDetailsRequest, GetNotificationRequest,
+        ListNotificationsRequest, Notification, NotificationType, SendNotificationRequest,
+        SetContactDetailsRequest,
+    },
+    handlers::healt_handler::backend_unavailable,
+    jwt_auth,
+    models::{
+        contact_details_request::ContactDetailsRequest, notification_query::NotificationQuery,
+        notification_request::NotificationRequest,
+    },
+    AppState
+};
+
+use actix_web::{get, post, put, web, HttpResponse, Responder};
+use log::{error, info};
+use serde_json::json;
+
+fn notification_type_name(notification_type: i32) -> &'static str {
+    match NotificationType::from_i32(notification_type) {
+        Some(NotificationType::Sms) => "SMS",
+        Some(NotificationType::PushNotification) => "PUSH_NOTIFICATION",
+        _ => "EMAIL",
+    }
+}
+
+fn delivery_status_name(status: i32) -> &'static str {
+    match DeliveryStatus::from_i32(status) {
+        Some(DeliveryStatus::Sent) => "SENT",
+        Some(DeliveryStatus::Failed) => "FAILED",
+        _ => "PENDING",
+    }
+}
+
+fn notification_to_json(notification: Notification) -> serde_json::Value {
+    json!({
+        "notification_id": notification.notification_id,
+        "customer_id": notification.customer_id,
+        "notification_type": notification_type_name(notification.notification_type),
+        "subject": notification.subject,
+        "message": notification.message,
+        "status": delivery_status_name(notification.status),
+        "attempts": notification.attempts,
+        "last_error": notification.last_error,
+        "channel": notification.channel,
+        "created_timestamp": notification.created_timestamp,
+        "sent_timestamp": notification.sent_timestamp,
+        "next_attempt_timestamp": notification.next_attempt_timestamp
+    })
+}
+
+fn contact_details_to_json(contact_details: Option<ContactDetails>) -> serde_json::Value {
+    let contact_details = contact_details.unwrap_or_default();
+    json!({
+        "email": contact_details.email,
+        "phone_number": contact_details.phone_number,
+        "push_token": contact_details.push_token
+    })
+}
+
+fn notification_error(e: tonic::Status) -> HttpResponse {
+    match e.code() {
+        tonic::Code::InvalidArgument => {
+            HttpResponse::BadRequest().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::NotFound => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": e.message()}))
+        }
+        tonic::Code::Unavailable => backend_unavailable(&e),
+        _ => {
+            error!("Error handling notification request: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({ "status": "error", "message": format!("{:?}", e) }))
+        }
+    }
+}
+
+#[get("")]
+async fn list_notifications_handler(
+    query: web::Query<NotificationQuery>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let user_id = auth.user_id.to_string();
+    info!("Listing notifications for user {}", user_id);
+
+    let mut grpc_client = data.notification_grpc_client.clone();
+    let request = ListNotificationsRequest {
+        customer_id: user_id,
+        status: query.status.clone().unwrap_or_default(),
+        limit: query.limit.unwrap_or_default(),
+    };
+
+    match grpc_client
+        .list_notifications(tonic::Request::new(request))
+        .await
+    {
+        Ok(response) => {
+            let notifications: Vec<serde_json::Value> = response
+                .into_inner()
+                .notifications
+                .into_iter()
+                .map(notification_to_json)
+                .collect();
+            HttpResponse::Ok().json(json!({"status": "success", "notifications": notifications}))
+        }
+        Err(e) => notification_error(e),
+    }
+}
+
+#[get("contacts")]
+async fn get_contact_details_handler(
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let mut grpc_client = data.notification_grpc_client.clone();
+    let request = GetContactDetailsRequest {
+        customer_id: auth.user_id.to_string(),
+    };
+
+    match grpc_client
+        .get_contact_details(tonic::Request::new(request))
+        .await
+    {
+        Ok(response) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "contact_details": contact_details_to_json(response.into_inner().contact_details)
+        })),
+        Err(e) => notification_error(e),
+    }
+}
+
+#[put("contacts")]
+async fn set_contact_details_handler(
+    body: web::Json<ContactDetailsRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let user_id = auth.user_id.to_string();
+    info!("Updating notification contact details for user {}", user_id);
+
+    let body = body.into_inner();
+    let mut grpc_client = data.notification_grpc_client.clone();
+    let request = SetContactDetailsRequest {
+        customer_id: user_id,
+        contact_details: Some(ContactDetails {
+            email: body.email.unwrap_or_default(),
+            phone_number: body.phone_number.unwrap_or_default(),
+            push_token: body.push_token.unwrap_or_default(),
+        }),
+    };
+
+    match grpc_client
+        .set_contact_details(tonic::Request::new(request))
+        .await
+    {
+        Ok(response) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "contact_details": contact_details_to_json(response.into_inner().contact_details)
+        })),
+        Err(e) => notification_error(e),
+    }
+}
+
+#[get("{notification_id}")]
+async fn get_notification_handler(
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let user_id = auth.user_id.to_string();
+    let mut grpc_client = data.notification_grpc_client.clone();
+    let request = GetNotificationRequest {
+        notification_id: path.into_inner(),
+    };
+
+    match grpc_client.get_notification(tonic::Request::new(request)).await {
+        Ok(response) => match response.into_inner().notification {
+            // Customers only see their own notifications, staff see every one
+            Some(notification)
+                if notification.customer_id == user_id
+                    || !data.env.roles(&user_id).is_empty() =>
+            {
+                HttpResponse::Ok().json(json!({
+                    "status": "success",
+                    "notification": notification_to_json(notification)
+                }))
+            }
+            _ => HttpResponse::NotFound()
+                .json(json!({"status": "fail", "message": "Notification not found"})),
+        },
+        Err(e) => notification_error(e),
+    }
+}
+
+#[post("")]
+async fn send_notification_handler(
+    body: web::Json<NotificationRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let user_id = auth.user_id.to_string();
+
+    if data.env.roles(&user_id).is_empty() {
+        error!("User {} is not allowed to send notifications", user_id);
+        return HttpResponse::Forbidden().json(json!({
+            "status": "fail",
+            "message": "Only bank staff can send notifications"
+        }));
+    }
+
+    let body = body.into_inner();
+    let notification_type = match body.notification_type.as_deref().unwrap_or("EMAIL") {
+        "EMAIL" => NotificationType::Email,
+        "SMS" => NotificationType::Sms,
+        "PUSH_NOTIFICATION" => NotificationType::PushNotification,
+        other => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "fail",
+                "message": format!("Unknown notification type: {}", other)
+            }));
+        }
+    };
+
+    info!(
+        "Sending notification to customer {} by user {}",
+        body.customer_id, user_id
+    );
+
+    let mut grpc_client = data.notification_grpc_client.clone();
+    let request = SendNotificationRequest {
+        customer_id: body.customer_id,
+        notification_type: notification_type as i32,
+        message: body.message.unwrap_or_default(),
+        template: body.template.unwrap_or_default(),
+        template_data: body.template_data.unwrap_or_default(),
+    };
+
+    match grpc_client
+        .send_notification(tonic::Request::new(request))
+        .await
+    {
+        Ok(response) => {
+            let response = response.into_inner();
+            let delivery_status = delivery_status_name(response.status);
+            let body = json!({
+                "status": if response.success { "success" } else { "fail" },
+                "notification_id": response.notification_id,
+                "delivery_status": delivery_status
+            });
+            // Deliveries that failed on the first attempt are retried in the background
+            if delivery_status == "PENDING" {
+                HttpResponse::Accepted().json(body)
+            } else {
+                HttpResponse::Ok().json(body)
+            }
+        }
+        Err(e) => notification_error(e),
+    }
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    let scope = web::scope("/api/notifications")
+        .service(list_notifications_handler)
+        .service(send_notification_handler)
+        .service(get_contact_details_handler)
+        .service(set_contact_details_handler)
+        .service(get_notification_handler);
+    conf.service(scope);
+}
@@ -0,0 +1,185 @@
+use crate::{
+    grpc_clients::deposit_grpc_client::deposit::{
+        CancelStandingOrderRequest, CreateStandingOrderRequest, ListStandingOrdersRequest,
+        StandingOrder, StandingOrderFrequency,
+    },
+    handlers::healt_handler::backend_unavailable,
+    jwt_auth,
+    models::{
+        standing_order_query::StandingOrderQuery, standing_order_request::StandingOrderRequest,
+    },
+    AppState
+};
+
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use log::{error, info};
+
+fn standing_order_to_json(standing_order: StandingOrder) -> serde_json::Value {
+    serde_json::json!({
+        "standing_order_id": standing_order.standing_order_id,
+        "from_account_id": standing_order.from_account_id,
+        "to_account_id": standing_order.to_account_id,
+        "amount": standing_order.amount,
+        "frequency": standing_order.frequency,
+        "status": standing_order.status,
+        "start_timestamp": standing_order.start_timestamp,
+        "end_timestamp": standing_order.end_timestamp,
+        "next_execution_timestamp": standing_order.next_execution_timestamp,
+        "executions": standing_order.executions,
+        "failure_count": standing_order.failure_count,
+        "last_error": standing_order.last_error,
+        "reference": standing_order.reference
+    })
+}
+
+#[post("")]
+async fn create_standing_order_handler(
+    body: web::Json<StandingOrderRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!(
+        "Creating {} standing order of {} from account: {} to account: {}",
+        body.frequency, body.amount, body.from_account_id, body.to_account_id
+    );
+
+    let frequency = match body.frequency.as_str() {
+        "once" => StandingOrderFrequency::Once,
+        "daily" => StandingOrderFrequency::Daily,
+        "weekly" => StandingOrderFrequency::Weekly,
+        "monthly" => StandingOrderFrequency::Monthly,
+        "end_of_month" => StandingOrderFrequency::EndOfMonth,
+        other => {
+            error!("Unsupported standing order frequency: {}", other);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "fail",
+                "message": "frequency must be one of once, daily, weekly, monthly or end_of_month"
+            }));
+        }
+    };
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let create_request = CreateStandingOrderRequest {
+        user_id: auth.user_id.to_string(),
+        from_account_id: body.from_account_id.clone(),
+        to_account_id: body.to_account_id.clone(),
+        amount: body.amount,
+        frequency: frequency as i32,
+        start_timestamp: body.start_timestamp.unwrap_or_default(),
+        end_timestamp: body.end_timestamp.unwrap_or_default(),
+        reference: body.reference.clone().unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .create_standing_order(tonic::Request::new(create_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let standing_order = response.into_inner().standing_order.unwrap();
+            info!("Standing order created: {}", standing_order.standing_order_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "standing_order": standing_order_to_json(standing_order)
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error creating standing order: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[get("")]
+async fn list_standing_orders_handler(
+    query: web::Query<StandingOrderQuery>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!("Getting standing orders for user: {}", auth.user_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let list_request = ListStandingOrdersRequest {
+        user_id: auth.user_id.to_string(),
+        account_id: query.account_id.clone().unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .list_standing_orders(tonic::Request::new(list_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let standing_orders: Vec<serde_json::Value> = response
+                .into_inner()
+                .standing_orders
+                .into_iter()
+                .map(standing_order_to_json)
+                .collect();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "standing_orders": standing_orders
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error getting standing orders: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+#[delete("{standing_order_id}")]
+async fn cancel_standing_order_handler(
+    standing_order: web::Path<String>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    let standing_order_id = standing_order.into_inner();
+    info!("Cancelling standing order with ID: {}", standing_order_id);
+
+    let mut grpc_client = data.deposit_grpc_client.clone();
+
+    let cancel_request = CancelStandingOrderRequest {
+        user_id: auth.user_id.to_string(),
+        standing_order_id,
+    };
+
+    let result = grpc_client
+        .cancel_standing_order(tonic::Request::new(cancel_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let standing_order = response.into_inner().standing_order.unwrap();
+            info!("Standing order cancelled: {}", standing_order.standing_order_id);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "standing_order": standing_order_to_json(standing_order)
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
+        Err(e) => {
+            error!("Error cancelling standing order: {:?}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
+        }
+    }
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    let scope = web::scope("/api/bank/standing-orders")
+        .service(create_standing_order_handler)
+        .service(list_standing_orders_handler)
+        .service(cancel_standing_order_handler);
+
+    conf.service(scope);
+}
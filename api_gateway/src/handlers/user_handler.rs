@@ -3,6 +3,7 @@ use crate::{
     grpc_clients::user_grpc_client::user_service::{
         CreateUserRequest, GetUserByIdRequest, GetUserByUserNameRequest,
     },
+    handlers::healt_handler::backend_unavailable,
     jwt_auth,
     models::{
         login_user::LoginUserSchema, registrer_user::RegisterUserSchema, token_claims::TokenClaims,
@@ -12,7 +13,7 @@ use crate::{
 
 use actix_web::{
     cookie::{time::Duration as ActixWebDuration, Cookie},
-    get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder,
+    get, post, web, HttpResponse, Responder,
 };
 use chrono::{prelude::Utc, Duration};
 use jsonwebtoken::{encode, EncodingKey, Header};
@@ -58,6 +59,7 @@ async fn register_user_handler(
 
             HttpResponse::Ok().json(user_response)
         }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
         Err(e) => {
             error!("Error during user registration: {:?}", e);
             HttpResponse::InternalServerError()
@@ -85,6 +87,9 @@ async fn login_user_handler(
 
     let user = match query_result {
         Ok(response) => response.into_inner(),
+        Err(err) if err.code() == tonic::Code::Unavailable => {
+            return backend_unavailable(&err);
+        }
         Err(err) => {
             error!("Error during login: {}", err);
             return HttpResponse::BadRequest()
@@ -92,7 +97,7 @@ async fn login_user_handler(
         }
     };
 
-    if !(body.password == user.password) {
+    if body.password != user.password {
         error!("Invalid email or password");
         return HttpResponse::BadRequest()
             .json(json!({"status": "fail", "message": "Invalid email or password"}));
@@ -157,13 +162,11 @@ async fn logout_handler(_: jwt_auth::JwtMiddleware) -> impl Responder {
 
 #[get("/users/me")]
 async fn get_me_handler(
-    req: HttpRequest,
     data: web::Data<AppState>,
-    _: jwt_auth::JwtMiddleware,
+    auth: jwt_auth::JwtMiddleware,
 ) -> impl Responder {
     info!("User data request received");
-    let ext = req.extensions();
-    let user_id = ext.get::<uuid::Uuid>().unwrap();
+    let user_id = auth.user_id;
 
     let mut grpc_client = data.user_grpc_client.clone();
 
@@ -171,7 +174,7 @@ async fn get_me_handler(
         id: user_id.to_string(),
     };
 
-    info!("User ID: {}", user_id.to_string());
+    info!("User ID: {}", user_id);
 
     let user_result = grpc_client
         .get_user_by_id(tonic::Request::new(get_user_request))
@@ -179,6 +182,9 @@ async fn get_me_handler(
 
     let user = match user_result {
         Ok(response) => response.into_inner(),
+        Err(err) if err.code() == tonic::Code::Unavailable => {
+            return backend_unavailable(&err);
+        }
         Err(err) => {
             error!("Error fetching user data: {}", err);
             return HttpResponse::InternalServerError()
@@ -1,10 +1,18 @@
 use crate::{
-    grpc_clients::withdrawal_grpc_client::withdrawal::MakeWithdrawalRequest, jwt_auth,
-    models::withdrawal_request::WithdrawalRequest, 
+    grpc_clients::withdrawal_grpc_client::withdrawal::{
+        GetWithdrawalLimitsRequest, MakeWithdrawalRequest, PreviewWithdrawalFeeRequest,
+        SetWithdrawalLimitsRequest, TransactionLimits,
+    },
+    handlers::healt_handler::{backend_unavailable, grpc_error},
+    jwt_auth,
+    models::{
+        fee_preview_query::FeePreviewQuery, limits_query::LimitsQuery,
+        limits_request::LimitsRequest, withdrawal_request::WithdrawalRequest,
+    },
     AppState
 };
 
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{get, post, put, web, HttpResponse, Responder};
 use log::{error, info};
 use serde_json::json;
 
@@ -29,6 +37,7 @@ async fn withdraw_handler(
     let withdrawal_request = MakeWithdrawalRequest {
         account_id: body.account_id.clone(),
         amount: body.amount,
+        currency: body.currency.clone().unwrap_or_default(),
     };
 
     let result = grpc_client
@@ -37,24 +46,153 @@ async fn withdraw_handler(
 
     match result {
         Ok(response) => {
-            let transaction_id = response.into_inner().transaction_id;
-            info!("Withdrawal successful, transaction_id: {}", transaction_id);
-            let withdrawal_response =
-                serde_json::json!({"status": "success", "transaction_id": transaction_id});
+            let response = response.into_inner();
+            info!("Withdrawal successful, transaction_id: {}", response.transaction_id);
+            let withdrawal_response = serde_json::json!({
+                "status": "success",
+                "transaction_id": response.transaction_id,
+                "fee": response.fee
+            });
             HttpResponse::Ok().json(withdrawal_response)
         }
+        Err(e) => grpc_error("processing withdrawal", e),
+    }
+}
+
+#[get("fee")]
+async fn preview_withdrawal_fee_handler(
+    query: web::Query<FeePreviewQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    info!(
+        "Previewing withdrawal fee for account: {} and amount: {}",
+        query.account_id, query.amount
+    );
+
+    let mut grpc_client = data.withdrawal_grpc_client.clone();
+
+    let preview_request = PreviewWithdrawalFeeRequest {
+        account_id: query.account_id.clone(),
+        amount: query.amount,
+    };
+
+    let result = grpc_client
+        .preview_withdrawal_fee(tonic::Request::new(preview_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let preview = response.into_inner();
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "fee": preview.fee,
+                "rule_name": preview.rule_name,
+                "free_operations_remaining": preview.free_operations_remaining
+            }))
+        }
+        Err(e) if e.code() == tonic::Code::Unavailable => backend_unavailable(&e),
         Err(e) => {
-            error!("Error processing withdrawal: {:?}", e);
+            error!("Error previewing withdrawal fee: {:?}", e);
             HttpResponse::InternalServerError()
                 .json(serde_json::json!({"status": "error","message": format!("{:?}", e)}))
         }
     }
 }
 
+fn transaction_limits_to_json(limits: TransactionLimits) -> serde_json::Value {
+    serde_json::json!({
+        "channel": limits.channel,
+        "per_transaction_limit": limits.per_transaction_limit,
+        "daily_limit": limits.daily_limit,
+        "monthly_limit": limits.monthly_limit,
+        "daily_used": limits.daily_used,
+        "monthly_used": limits.monthly_used,
+        "daily_remaining": limits.daily_remaining,
+        "monthly_remaining": limits.monthly_remaining,
+        "max_per_transaction_limit": limits.max_per_transaction_limit,
+        "max_daily_limit": limits.max_daily_limit,
+        "max_monthly_limit": limits.max_monthly_limit
+    })
+}
+
+#[get("limits")]
+async fn get_withdrawal_limits_handler(
+    query: web::Query<LimitsQuery>,
+    data: web::Data<AppState>,
+    _: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!("Getting withdrawal limits for account: {}", query.account_id);
+
+    let mut grpc_client = data.withdrawal_grpc_client.clone();
+
+    let limits_request = GetWithdrawalLimitsRequest {
+        account_id: query.account_id.clone(),
+        channel: query.channel.clone().unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .get_withdrawal_limits(tonic::Request::new(limits_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let limits: Vec<serde_json::Value> = response
+                .into_inner()
+                .limits
+                .into_iter()
+                .map(transaction_limits_to_json)
+                .collect();
+
+            HttpResponse::Ok().json(serde_json::json!({"status": "success", "limits": limits}))
+        }
+        Err(e) => grpc_error("getting withdrawal limits", e),
+    }
+}
+
+#[put("limits")]
+async fn set_withdrawal_limits_handler(
+    body: web::Json<LimitsRequest>,
+    data: web::Data<AppState>,
+    auth: jwt_auth::JwtMiddleware,
+) -> impl Responder {
+    info!(
+        "Setting withdrawal limits for account: {} requested by user: {}",
+        body.account_id, auth.user_id
+    );
+
+    let mut grpc_client = data.withdrawal_grpc_client.clone();
+
+    let limits_request = SetWithdrawalLimitsRequest {
+        user_id: auth.user_id.to_string(),
+        account_id: body.account_id.clone(),
+        channel: body.channel.clone().unwrap_or_default(),
+        per_transaction_limit: body.per_transaction_limit.unwrap_or_default(),
+        daily_limit: body.daily_limit.unwrap_or_default(),
+        monthly_limit: body.monthly_limit.unwrap_or_default(),
+    };
+
+    let result = grpc_client
+        .set_withdrawal_limits(tonic::Request::new(limits_request))
+        .await;
+
+    match result {
+        Ok(response) => {
+            let limits = response.into_inner().limits.map(transaction_limits_to_json);
+
+            HttpResponse::Ok().json(serde_json::json!({"status": "success", "limits": limits}))
+        }
+        Err(e) => grpc_error("setting withdrawal limits", e),
+    }
+}
+
 pub fn config(conf: &mut web::ServiceConfig) {
     let scope = web::scope("/api/bank/withdraw")
         .service(health_checker_handler)
-        .service(withdraw_handler);
+        .service(withdraw_handler)
+        .service(preview_withdrawal_fee_handler)
+        .service(get_withdrawal_limits_handler)
+        .service(set_withdrawal_limits_handler);
 
     conf.service(scope);
 }
@@ -7,14 +7,14 @@ use actix_cors::Cors;
 use actix_web::{http::header, web, App, HttpServer, middleware::Logger};
 use dotenv::dotenv;
 use env_logger::{Builder, Env};
-use log::{error, info};
-use std::time::Duration;
-use tokio;
-use tonic::transport::Channel;
+use log::info;
 
 use crate::models::config::Config;
 
 use crate::{
+    grpc_clients::backend::Backend,
+    grpc_clients::resilience::{GrpcChannel, ResiliencePolicy},
+
     grpc_clients::user_grpc_client::get_user_grpc_client,
     grpc_clients::user_grpc_client::user_service::user_service_client::UserServiceClient,
 
@@ -29,16 +29,21 @@ use crate::{
     
     grpc_clients::historical_grpc_client::get_historical_grpc_client,
     grpc_clients::historical_grpc_client::historical::historical_service_client::HistoricalServiceClient,
+
+    grpc_clients::notification_grpc_client::get_notification_grpc_client,
+    grpc_clients::notification_grpc_client::notification::notification_service_client::NotificationServiceClient,
 };
 
 
 pub struct AppState {
     env: Config,
-    user_grpc_client: UserServiceClient<Channel>,
-    account_grpc_client: AccountServiceClient<Channel>,
-    deposit_grpc_client: DepositServiceClient<Channel>,
-    withdrawal_grpc_client: WithdrawalServiceClient<Channel>,
-    historical_grpc_client: HistoricalServiceClient<Channel>
+    user_grpc_client: UserServiceClient<GrpcChannel>,
+    account_grpc_client: AccountServiceClient<GrpcChannel>,
+    deposit_grpc_client: DepositServiceClient<GrpcChannel>,
+    withdrawal_grpc_client: WithdrawalServiceClient<GrpcChannel>,
+    historical_grpc_client: HistoricalServiceClient<GrpcChannel>,
+    notification_grpc_client: NotificationServiceClient<GrpcChannel>,
+    backends: Vec<Backend>
 }
 
 #[actix_web::main]
@@ -48,88 +53,40 @@ async fn main() -> std::io::Result<()> {
     Builder::from_env(Env::default().default_filter_or("info")).init();
 
     let config = Config::init();
-
-    let user_grpc_client = loop {
-        match get_user_grpc_client(config.user_grpc_uri.clone()).await {
-            Ok(client) => {
-                info!("✅ Connection to the user gRPC service is successful!");
-                break client;
-            }
-            Err(err) => {
-                error!("❌ Failed to connect to the user gRPC service: {:?}", err);
-                info!("Retrying in 3 seconds...");
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-        };
-    };
-
-    let account_grpc_client = loop {
-        match get_account_grpc_client(config.account_grpc_uri.clone()).await {
-            Ok(client) => {
-                info!("✅ Connection to the account gRPC service is successful!");
-                break client;
-            }
-            Err(err) => {
-                error!(
-                    "❌ Failed to connect to the account gRPC service: {:?}",
-                    err
-                );
-                info!("Retrying in 3 seconds...");
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-        };
-    };
-
-    let deposit_grpc_client = loop {
-        match get_deposit_grpc_client(config.deposit_grpc_uri.clone()).await {
-            Ok(client) => {
-                info!("✅ Connection to the deposit gRPC service is successful!");
-                break client;
-            }
-            Err(err) => {
-                error!(
-                    "❌ Failed to connect to the deposit gRPC service: {:?}",
-                    err
-                );
-                info!("Retrying in 3 seconds...");
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-        };
-    };
-
-    let withdrawal_grpc_client = loop {
-        match get_withdrawal_grpc_client(config.withdrawal_grpc_uri.clone()).await {
-            Ok(client) => {
-                info!("✅ Connection to the withdrawal gRPC service is successful!");
-                break client;
-            }
-            Err(err) => {
-                error!(
-                    "❌ Failed to connect to the withdrawal gRPC service: {:?}",
-                    err
-                );
-                info!("Retrying in 3 seconds...");
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-        };
-    };
-
-    let historical_grpc_client = loop {
-        match get_historical_grpc_client(config.historical_grpc_uri.clone()).await {
-            Ok(client) => {
-                info!("✅ Connection to the historical gRPC service is successful!");
-                break client;
-            }
-            Err(err) => {
-                error!(
-                    "❌ Failed to connect to the historical gRPC service: {:?}",
-                    err
-                );
-                info!("Retrying in 3 seconds...");
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-        };
-    };
+    let policy = ResiliencePolicy::from_config(&config);
+
+    let (user_grpc_client, user_backend) =
+        get_user_grpc_client(config.user_grpc_uri.clone(), &policy)
+            .expect("USER_GRPC_SERVICE_URL must be a valid address");
+    let (account_grpc_client, account_backend) =
+        get_account_grpc_client(config.account_grpc_uri.clone(), &policy)
+            .expect("ACCOUNT_GRPC_SERVICE_URL must be a valid address");
+    let (deposit_grpc_client, deposit_backend) =
+        get_deposit_grpc_client(config.deposit_grpc_uri.clone(), &policy)
+            .expect("DEPOSIT_GRPC_SERVICE_URL must be a valid address");
+    let (withdrawal_grpc_client, withdrawal_backend) =
+        get_withdrawal_grpc_client(config.withdrawal_grpc_uri.clone(), &policy)
+            .expect("WITHDRAWAL_GRPC_SERVICE_URL must be a valid address");
+    let (historical_grpc_client, historical_backend) =
+        get_historical_grpc_client(config.historical_grpc_uri.clone(), &policy)
+            .expect("HISTORICAL_GRPC_SERVICE_URL must be a valid address");
+    let (notification_grpc_client, notification_backend) =
+        get_notification_grpc_client(config.notification_grpc_uri.clone(), &policy)
+            .expect("NOTIFICATION_GRPC_SERVICE_URL must be a valid address");
+
+    // Channels connect lazily, so the gateway starts serving straight away and
+    // reports backends that are down until they can be reached
+    let backends = vec![
+        user_backend,
+        account_backend,
+        deposit_backend,
+        withdrawal_backend,
+        historical_backend,
+        notification_backend,
+    ];
+    for backend in &backends {
+        tokio::spawn(backend.clone().run_monitor());
+    }
 
     info!("✅ Server started successfully");
 
@@ -147,12 +104,12 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(move || {
         // Configure CORS options.
         // - Allow requests from "http://localhost:3000"
-        // - Allow GET and POST methods
+        // - Allow GET, POST, PUT and DELETE methods
         // - Allow certain headers: Content-Type, Authorization, and Accept
         // - Support credentials, like cookies, for cross-origin requests
         let cors = Cors::default()
             .allowed_origin("http://localhost:4200")
-            .allowed_methods(vec!["GET", "POST", "OPTIONS"])
+            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
             .allowed_headers(vec![
                 header::CONTENT_TYPE,
                 header::AUTHORIZATION,
@@ -170,14 +127,24 @@ async fn main() -> std::io::Result<()> {
                 deposit_grpc_client: deposit_grpc_client.clone(),
                 withdrawal_grpc_client: withdrawal_grpc_client.clone(),
                 historical_grpc_client: historical_grpc_client.clone(),
+                notification_grpc_client: notification_grpc_client.clone(),
+                backends: backends.clone(),
             }))
             // Register handlers for various routes and resources.
             .service(handlers::healt_handler::health_checker_handler)
+            .service(handlers::healt_handler::circuit_breakers_handler)
+            .service(handlers::healt_handler::liveness_handler)
+            .service(handlers::healt_handler::readiness_handler)
+            .service(handlers::healt_handler::health_handler)
             .configure(handlers::user_handler::config)
             .configure(handlers::account_handlers::config)
             .configure(handlers::deposit_handlers::config)
             .configure(handlers::withdrawal_handlers::config)
             .configure(handlers::historical_handler::config)
+            .configure(handlers::standing_order_handlers::config)
+            .configure(handlers::beneficiary_handlers::config)
+            .configure(handlers::approval_handlers::config)
+            .configure(handlers::notification_handlers::config)
             // Apply CORS middleware.
             .wrap(cors)
             // Apply logging middleware.
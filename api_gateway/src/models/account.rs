@@ -3,7 +3,9 @@ use serde::{Serialize, Deserialize};
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Account {
     pub account_type: AccountType,
-    pub account_name: String
+    pub account_name: String,
+    pub interest_product: Option<String>,
+    pub currency: Option<String>
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
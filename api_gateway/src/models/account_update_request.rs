@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateAccountRequestModel {
     pub account_id: String,
-    pub balance: f64,
+    pub account_name: Option<String>,
+    pub nickname: Option<String>,
+    pub settings: Option<HashMap<String, String>>,
 }
@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BalanceAdjustmentRequest {
+    pub amount: f64,
+    pub reason: String,
+}
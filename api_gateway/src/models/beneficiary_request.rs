@@ -0,0 +1,8 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BeneficiaryRequest {
+    pub name: String,
+    pub account_id: String,
+    pub nickname: Option<String>
+}
@@ -0,0 +1,7 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BeneficiaryUpdateRequest {
+    pub name: Option<String>,
+    pub nickname: Option<String>
+}
@@ -1,13 +1,38 @@
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub jwt_secret: String,
+    #[allow(dead_code)]
     pub jwt_expires_in: String,
+    #[allow(dead_code)]
     pub jwt_maxage: i32,
     pub user_grpc_uri: String,
     pub account_grpc_uri: String,
     pub deposit_grpc_uri: String,
     pub withdrawal_grpc_uri: String,
     pub historical_grpc_uri: String,
+    pub notification_grpc_uri: String,
+    pub admin_user_ids: Vec<String>,
+    pub agent_user_ids: Vec<String>,
+    pub grpc_deadline_ms: u64,
+    pub grpc_deadlines: Vec<(String, u64)>,
+    pub grpc_max_retries: u32,
+    pub grpc_retry_backoff_ms: u64,
+    pub circuit_breaker_failures: u32,
+    pub circuit_breaker_open_secs: u64,
+    pub backend_check_secs: u64,
+    pub dns_refresh_secs: u64,
+    pub outlier_ejection_failures: u32,
+    pub outlier_ejection_secs: u64,
+    pub registry: Option<String>,
+    pub registry_dir: String,
+    pub registry_ttl_secs: u64,
 }
 
 impl Config {
@@ -20,6 +45,40 @@ impl Config {
         let deposit_grpc_uri = std::env::var("DEPOSIT_GRPC_SERVICE_URL").expect("DEPOSIT_GRPC_SERVICE_URL must be set");
         let withdrawal_grpc_uri = std::env::var("WITHDRAWAL_GRPC_SERVICE_URL").expect("WITHDRAWAL_GRPC_SERVICE_URL must be set");
         let historical_grpc_uri = std::env::var("HISTORICAL_GRPC_SERVICE_URL").expect("HISTORICAL_GRPC_SERVICE_URL must be set");
+        let notification_grpc_uri = std::env::var("NOTIFICATION_GRPC_SERVICE_URL").expect("NOTIFICATION_GRPC_SERVICE_URL must be set");
+        // Comma separated ids of the users allowed to use the admin endpoints
+        let admin_user_ids = std::env::var("ADMIN_USER_IDS")
+            .map(|ids| ids.split(',').map(|id| id.trim().to_string()).filter(|id| !id.is_empty()).collect())
+            .unwrap_or_default();
+        // Comma separated ids of the bank agents, who can make agent deposits
+        let agent_user_ids = std::env::var("AGENT_USER_IDS")
+            .map(|ids| ids.split(',').map(|id| id.trim().to_string()).filter(|id| !id.is_empty()).collect())
+            .unwrap_or_default();
+        // Deadline of every gRPC call, unless overridden per method with
+        // comma separated `Method=ms` entries in GRPC_DEADLINES
+        let grpc_deadline_ms = env_number("GRPC_DEADLINE_MS", 5000);
+        let grpc_deadlines = std::env::var("GRPC_DEADLINES")
+            .map(|deadlines| deadlines.split(',').filter_map(|deadline| {
+                let (method, ms) = deadline.split_once('=')?;
+                Some((method.trim().to_string(), ms.trim().parse().ok()?))
+            }).collect())
+            .unwrap_or_default();
+        let grpc_max_retries = env_number("GRPC_MAX_RETRIES", 2);
+        let grpc_retry_backoff_ms = env_number("GRPC_RETRY_BACKOFF_MS", 100);
+        let circuit_breaker_failures = env_number("CIRCUIT_BREAKER_FAILURES", 5);
+        let circuit_breaker_open_secs = env_number("CIRCUIT_BREAKER_OPEN_SECS", 30);
+        let backend_check_secs = env_number("BACKEND_CHECK_INTERVAL_SECS", 5);
+        let dns_refresh_secs = env_number("DNS_REFRESH_SECS", 30);
+        let outlier_ejection_failures = env_number("OUTLIER_EJECTION_FAILURES", 2);
+        let outlier_ejection_secs = env_number("OUTLIER_EJECTION_SECS", 30);
+        // Registry the services announce themselves to, read by the backends
+        // configured as `registry://<service>`. Only `file` is supported.
+        let registry = std::env::var("REGISTRY").ok().filter(|registry| registry != "none");
+        if let Some(registry) = &registry {
+            assert!(registry == "file", "Unknown REGISTRY {}", registry);
+        }
+        let registry_dir = std::env::var("REGISTRY_DIR").unwrap_or_else(|_| "/registry".to_string());
+        let registry_ttl_secs = env_number("REGISTRY_TTL_SECS", 15);
         
         Config {
             jwt_secret,
@@ -29,7 +88,35 @@ impl Config {
             account_grpc_uri,
             deposit_grpc_uri,
             withdrawal_grpc_uri,
-            historical_grpc_uri
+            historical_grpc_uri,
+            notification_grpc_uri,
+            admin_user_ids,
+            agent_user_ids,
+            grpc_deadline_ms,
+            grpc_deadlines,
+            grpc_max_retries,
+            grpc_retry_backoff_ms,
+            circuit_breaker_failures,
+            circuit_breaker_open_secs,
+            backend_check_secs,
+            dns_refresh_secs,
+            outlier_ejection_failures,
+            outlier_ejection_secs,
+            registry,
+            registry_dir,
+            registry_ttl_secs
+        }
+    }
+
+    // Roles checked by the services when privileged operations are requested or approved
+    pub fn roles(&self, user_id: &str) -> Vec<String> {
+        let mut roles = Vec::new();
+        if self.admin_user_ids.iter().any(|id| id == user_id) {
+            roles.push("ADMIN".to_string());
+        }
+        if self.agent_user_ids.iter().any(|id| id == user_id) {
+            roles.push("AGENT".to_string());
         }
+        roles
     }
 }
@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ContactDetailsRequest {
+    pub email: Option<String>,
+    pub phone_number: Option<String>,
+    pub push_token: Option<String>,
+}
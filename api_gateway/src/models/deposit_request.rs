@@ -3,8 +3,11 @@ use serde::{Serialize, Deserialize};
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DepositRequest {
     pub from_account_id: String,
-    pub to_account_id: String,
+    pub to_account_id: Option<String>,
+    pub beneficiary_id: Option<String>,
     pub amount: f64,
-    pub is_bank_agent: bool
+    pub is_bank_agent: bool,
+    pub currency: Option<String>,
+    pub fx_quote_id: Option<String>,
 }
  
\ No newline at end of file
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct FeePreviewQuery {
+    pub account_id: String,
+    pub amount: f64,
+    pub is_bank_agent: Option<bool>,
+}
@@ -0,0 +1,6 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct HoldQuery {
+    pub active_only: Option<bool>,
+}
@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HoldRequest {
+    pub amount: f64,
+    pub reference: Option<String>,
+    pub description: Option<String>,
+    pub expires_in_seconds: Option<i64>,
+}
@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct InterestQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
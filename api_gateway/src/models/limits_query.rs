@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct LimitsQuery {
+    pub account_id: String,
+    pub channel: Option<String>,
+}
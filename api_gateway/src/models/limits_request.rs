@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LimitsRequest {
+    pub account_id: String,
+    pub channel: Option<String>,
+    pub per_transaction_limit: Option<f64>,
+    pub daily_limit: Option<f64>,
+    pub monthly_limit: Option<f64>,
+}
@@ -5,4 +5,28 @@ pub mod account;
 pub mod account_update_request;
 pub mod deposit_request;
 pub mod withdrawal_request;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod statement_query;
+pub mod payment_batch_query;
+pub mod standing_order_request;
+pub mod standing_order_query;
+pub mod beneficiary_request;
+pub mod beneficiary_update_request;
+pub mod interest_query;
+pub mod fee_preview_query;
+pub mod fee_reversal_request;
+pub mod overdraft_request;
+pub mod limits_query;
+pub mod limits_request;
+pub mod transfer_quote_request;
+pub mod hold_request;
+pub mod capture_hold_request;
+pub mod hold_query;
+pub mod transaction_reversal_request;
+pub mod balance_adjustment_request;
+pub mod operation_review_request;
+pub mod operation_query;
+pub mod account_closure_request;
+pub mod notification_query;
+pub mod notification_request;
+pub mod contact_details_request;
\ No newline at end of file
@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
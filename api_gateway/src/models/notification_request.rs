@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NotificationRequest {
+    pub customer_id: String,
+    pub notification_type: Option<String>,
+    pub message: Option<String>,
+    pub template: Option<String>,
+    pub template_data: Option<HashMap<String, String>>,
+}
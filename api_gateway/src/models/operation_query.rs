@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OperationQuery {
+    pub service: Option<String>,
+    pub status: Option<String>,
+    pub kind: Option<String>,
+}
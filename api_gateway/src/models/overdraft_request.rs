@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OverdraftRequest {
+    pub overdraft_limit: f64,
+    pub overdraft_rate: Option<f64>,
+}
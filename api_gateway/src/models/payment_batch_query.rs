@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct PaymentBatchQuery {
+    pub format: Option<String>,
+    pub file_name: String,
+}
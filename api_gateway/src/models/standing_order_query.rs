@@ -0,0 +1,6 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct StandingOrderQuery {
+    pub account_id: Option<String>,
+}
@@ -0,0 +1,12 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StandingOrderRequest {
+    pub from_account_id: String,
+    pub to_account_id: String,
+    pub amount: f64,
+    pub frequency: String,
+    pub start_timestamp: Option<i64>,
+    pub end_timestamp: Option<i64>,
+    pub reference: Option<String>
+}
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct StatementQuery {
+    pub format: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
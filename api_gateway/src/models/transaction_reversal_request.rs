@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TransactionReversalRequest {
+    pub reason_code: String,
+    pub note: Option<String>,
+}
@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TransferQuoteRequest {
+    pub from_account_id: String,
+    pub to_account_id: String,
+    pub amount: f64,
+}
@@ -4,4 +4,5 @@ use serde::{Serialize, Deserialize};
 pub struct WithdrawalRequest {
     pub account_id: String,
     pub amount: f64,
+    pub currency: Option<String>,
 }
@@ -0,0 +1,16 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // google.rpc error details, as published by googleapis
+    tonic_build::configure()
+        .build_server(false)
+        .build_client(false)
+        .compile(&["proto/google/rpc/error_details.proto"], &["proto/"])?;
+    // grpc.health.v1, served by every service
+    tonic_build::configure()
+        .build_server(true)
+        .build_client(false)
+        .file_descriptor_set_path(
+            std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("health_descriptor.bin"),
+        )
+        .compile(&["proto/health.proto"], &["proto/"])?;
+    Ok(())
+}
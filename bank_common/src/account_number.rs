@@ -0,0 +1,143 @@
+use std::str::FromStr;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    Collection, Database,
+};
+
+// Account numbers are IBAN-compatible: country code, two mod-97 check digits
+// and a BBAN.
+pub const MAX_ACCOUNT_NUMBER_LENGTH: usize = 34;
+
+// ISO 7064 MOD 97-10 over the alphanumeric string, where letters count as
+// two digits (A = 10 ... Z = 35).
+fn mod97(value: &str) -> u32 {
+    value.chars().fold(0, |remainder, c| match c.to_digit(36) {
+        Some(digit) if digit >= 10 => (remainder * 100 + digit) % 97,
+        Some(digit) => (remainder * 10 + digit) % 97,
+        None => remainder,
+    })
+}
+
+pub fn check_digits(country_code: &str, bban: &str) -> String {
+    let rearranged = format!("{}{}00", bban, country_code);
+    format!("{:02}", 98 - mod97(&rearranged))
+}
+
+// Removes the spaces used when account numbers are printed in groups of four
+pub fn normalize(account_number: &str) -> String {
+    account_number
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+pub fn is_valid(account_number: &str) -> bool {
+    let account_number = normalize(account_number);
+    let bytes = account_number.as_bytes();
+
+    if bytes.len() < 5 || bytes.len() > MAX_ACCOUNT_NUMBER_LENGTH {
+        return false;
+    }
+    if !bytes[..2].iter().all(u8::is_ascii_uppercase)
+        || !bytes[2..4].iter().all(u8::is_ascii_digit)
+        || !bytes.iter().all(u8::is_ascii_alphanumeric)
+    {
+        return false;
+    }
+
+    let rearranged = format!("{}{}", &account_number[4..], &account_number[..4]);
+    mod97(&rearranged) == 1
+}
+
+// Accounts can be referenced either by their id or by their IBAN-style
+// account number (spaces allowed, case insensitive).
+pub async fn resolve_account_id(db: &Database, account: &str) -> Result<ObjectId, Status> {
+    if let Ok(object_id) = ObjectId::from_str(account) {
+        return Ok(object_id);
+    }
+
+    let accounts_collection: Collection<Document> = db.collection("accounts");
+    let account_doc = accounts_collection
+        .find_one(doc! { "account_number": normalize(account) }, None)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+        .ok_or_else(|| Status::not_found(format!("Account {} not found", account)))?;
+
+    Ok(account_doc.get_object_id("_id").unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Examples published in the IBAN registry
+    const KNOWN_IBANS: [&str; 5] = [
+        "GB82 WEST 1234 5698 7654 32",
+        "DE89 3704 0044 0532 0130 00",
+        "FR14 2004 1010 0505 0001 3M02 606",
+        "NL91 ABNA 0417 1643 00",
+        "BE71 0961 2345 6769",
+    ];
+
+    #[test]
+    fn mod97_of_a_rearranged_iban_is_one() {
+        assert_eq!(mod97("WEST12345698765432GB82"), 1);
+        assert_eq!(mod97("3214282912345698765432161182"), 1);
+        assert_eq!(mod97("WEST12345698765432GB00"), 16);
+    }
+
+    #[test]
+    fn check_digits_match_known_ibans() {
+        assert_eq!(check_digits("GB", "WEST12345698765432"), "82");
+        assert_eq!(check_digits("DE", "370400440532013000"), "89");
+        assert_eq!(check_digits("FR", "20041010050500013M02606"), "14");
+        assert_eq!(check_digits("NL", "ABNA0417164300"), "91");
+        assert_eq!(check_digits("BE", "096123456769"), "71");
+    }
+
+    #[test]
+    fn check_digits_are_zero_padded() {
+        let check = check_digits("GB", "WEST00000000000000");
+        assert_eq!(check.len(), 2);
+        assert!(is_valid(&format!("GB{}WEST00000000000000", check)));
+    }
+
+    #[test]
+    fn known_ibans_are_valid() {
+        for iban in KNOWN_IBANS {
+            assert!(is_valid(iban), "{}", iban);
+            assert!(is_valid(&normalize(iban)), "{}", iban);
+            assert!(is_valid(&iban.to_lowercase()), "{}", iban);
+        }
+    }
+
+    #[test]
+    fn altered_ibans_are_invalid() {
+        // Wrong check digits, a changed digit and two swapped digits
+        assert!(!is_valid("GB83WEST12345698765432"));
+        assert!(!is_valid("GB82WEST12345698765433"));
+        assert!(!is_valid("GB82WEST12345698765423"));
+        assert!(!is_valid("DE89370400440532013001"));
+    }
+
+    #[test]
+    fn malformed_ibans_are_invalid() {
+        assert!(!is_valid(""));
+        assert!(!is_valid("GB82"));
+        assert!(!is_valid("1282WEST12345698765432"));
+        assert!(!is_valid("GBX2WEST12345698765432"));
+        assert!(!is_valid("GB82-WEST-1234-5698-7654-32"));
+        assert!(!is_valid(&format!("GB82{}", "1".repeat(31))));
+    }
+
+    #[test]
+    fn normalize_removes_spaces_and_uppercases() {
+        assert_eq!(
+            normalize(" gb82 west 1234\t5698 7654 32 "),
+            "GB82WEST12345698765432"
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use log::{error, info};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Collection, Database,
+};
+
+// Maker-checker approvals for privileged operations. Instead of being executed
+// right away, a privileged request is stored in `pending_operations`, a
+// collection shared by every service owning such operations. A user other than
+// the requester, holding the operation's required role, then approves or
+// rejects it, and the owning service executes approved operations. Every step
+// is appended to the operation's history.
+//
+// Services are only reachable through the gateway, which authenticates the
+// caller and derives their roles from its own configuration; the roles sent in
+// requests are trusted as is.
+const SYSTEM_USER: &str = "system";
+
+pub const ADMIN_ROLE: &str = "ADMIN";
+
+#[derive(Debug, Clone)]
+pub struct ApprovalConfig {
+    pub ttl: Duration,
+    pub expiry_interval: std::time::Duration,
+}
+
+// Same values as the OperationStatus enum of the service protos
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationStatus {
+    PendingApproval = 0,
+    Approved = 1,
+    Executed = 2,
+    Rejected = 3,
+    Expired = 4,
+    Failed = 5,
+}
+
+impl Display for OperationStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let operation_status_str = match self {
+            OperationStatus::PendingApproval => "PENDING_APPROVAL",
+            OperationStatus::Approved => "APPROVED",
+            OperationStatus::Executed => "EXECUTED",
+            OperationStatus::Rejected => "REJECTED",
+            OperationStatus::Expired => "EXPIRED",
+            OperationStatus::Failed => "FAILED",
+        };
+
+        write!(f, "{}", operation_status_str)
+    }
+}
+
+impl FromStr for OperationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PENDING_APPROVAL" => Ok(OperationStatus::PendingApproval),
+            "APPROVED" => Ok(OperationStatus::Approved),
+            "EXECUTED" => Ok(OperationStatus::Executed),
+            "REJECTED" => Ok(OperationStatus::Rejected),
+            "EXPIRED" => Ok(OperationStatus::Expired),
+            "FAILED" => Ok(OperationStatus::Failed),
+            _ => Err(format!("Invalid operation status: {}", s)),
+        }
+    }
+}
+
+// A privileged request to be stored for approval; `payload` holds whatever
+// the owning service needs to execute it
+pub struct NewOperation<'a> {
+    pub kind: &'a str,
+    pub summary: String,
+    pub payload: Document,
+    pub requested_by: &'a str,
+    pub required_role: &'a str,
+}
+
+pub struct Review<'a> {
+    pub operation_id: &'a str,
+    pub reviewed_by: &'a str,
+    pub reviewer_roles: &'a [String],
+    pub comment: &'a str,
+}
+
+fn history_event(action: &str, user_id: &str, comment: &str) -> Document {
+    doc! {
+        "action": action,
+        "user_id": user_id,
+        "comment": comment,
+        "timestamp": DateTime::now(),
+    }
+}
+
+fn validate_review(review: &Review) -> Result<ObjectId, String> {
+    if review.reviewed_by.is_empty() {
+        return Err("The reviewer is required".to_string());
+    }
+    ObjectId::parse_str(review.operation_id).map_err(|_| "Invalid operation id".to_string())
+}
+
+// Reviewers must be someone other than the requester and hold the role the
+// operation requires
+fn check_reviewer(operation: &Document, review: &Review) -> Result<(), String> {
+    if operation.get_str("requested_by") == Ok(review.reviewed_by) {
+        return Err("Operations must be reviewed by someone other than the requester".to_string());
+    }
+    let required_role = operation.get_str("required_role").unwrap_or_default();
+    if !review
+        .reviewer_roles
+        .iter()
+        .any(|role| role == required_role)
+    {
+        return Err(format!(
+            "Reviewing this operation requires the {} role",
+            required_role
+        ));
+    }
+    Ok(())
+}
+
+// Implemented by every service owning privileged operations, which only has to
+// say where its operations are stored and how to execute approved ones
+#[tonic::async_trait]
+pub trait ApprovalService: Send + Sync + Sized + 'static {
+    // Operations are stored with the name of the service owning them
+    const SERVICE: &'static str;
+
+    fn approval_db(&self) -> &Database;
+
+    fn approval_config(&self) -> &ApprovalConfig;
+
+    // Runs an approved operation owned by this service and returns its result
+    async fn execute_operation(&self, operation: &Document) -> Result<Document, Status>;
+
+    fn operations_collection(&self) -> Collection<Document> {
+        self.approval_db().collection("pending_operations")
+    }
+
+    async fn get_operation(&self, operation_id: ObjectId) -> Result<Document, Status> {
+        self.operations_collection()
+            .find_one(doc! { "_id": operation_id, "service": Self::SERVICE }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get operation: {}", e)))?
+            .ok_or_else(|| Status::not_found("Operation not found"))
+    }
+
+    async fn request_operation(&self, operation: NewOperation<'_>) -> Result<Document, Status> {
+        let now = Utc::now();
+        let expires_at = now + self.approval_config().ttl;
+
+        let pending_operation = doc! {
+            "service": Self::SERVICE,
+            "kind": operation.kind,
+            "summary": &operation.summary,
+            "payload": operation.payload,
+            "status": OperationStatus::PendingApproval.to_string(),
+            "requested_by": operation.requested_by,
+            "required_role": operation.required_role,
+            "created_at": DateTime::from_millis(now.timestamp_millis()),
+            "expires_at": DateTime::from_millis(expires_at.timestamp_millis()),
+            "history": [history_event("REQUESTED", operation.requested_by, &operation.summary)],
+        };
+        let operation_id = self
+            .operations_collection()
+            .insert_one(pending_operation, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create operation: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap();
+
+        info!(
+            "Operation {} ({}) requested by {}: {}",
+            operation_id, operation.kind, operation.requested_by, operation.summary
+        );
+
+        self.get_operation(operation_id).await
+    }
+
+    // An empty status or kind lists operations with any of them
+    async fn list_operations(&self, status: &str, kind: &str) -> Result<Vec<Document>, Status> {
+        let mut filter = doc! { "service": Self::SERVICE };
+        if !status.is_empty() {
+            OperationStatus::from_str(status).map_err(Status::invalid_argument)?;
+            filter.insert("status", status);
+        }
+        if !kind.is_empty() {
+            filter.insert("kind", kind);
+        }
+
+        self.operations_collection()
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! { "created_at": -1 })
+                    .build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get operations: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get operations: {}", e)))
+    }
+
+    // Moves a pending operation to `status` on behalf of a reviewer who is
+    // allowed to decide on it. Only one review can succeed.
+    async fn decide_operation(
+        &self,
+        review: &Review<'_>,
+        status: OperationStatus,
+    ) -> Result<Document, Status> {
+        let operation_id = validate_review(review).map_err(Status::invalid_argument)?;
+        let operation = self.get_operation(operation_id).await?;
+        check_reviewer(&operation, review).map_err(Status::permission_denied)?;
+
+        let review_event = history_event(&status.to_string(), review.reviewed_by, review.comment);
+        self.operations_collection()
+            .find_one_and_update(
+                doc! {
+                    "_id": operation_id,
+                    "status": OperationStatus::PendingApproval.to_string(),
+                    "expires_at": { "$gt": DateTime::now() },
+                },
+                doc! {
+                    "$set": {
+                        "status": status.to_string(),
+                        "reviewed_by": review.reviewed_by,
+                        "review_comment": review.comment,
+                        "reviewed_at": DateTime::now(),
+                    },
+                    "$push": { "history": review_event },
+                },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to review operation: {}", e)))?
+            .ok_or_else(|| match operation.get_str("status") {
+                Ok("PENDING_APPROVAL") => Status::failed_precondition("Operation has expired"),
+                status => Status::failed_precondition(format!(
+                    "Operation is {}",
+                    status.unwrap_or_default()
+                )),
+            })
+    }
+
+    // Approved operations are executed straight away; the outcome, or the
+    // reason it failed, is recorded on the operation
+    async fn review_operation(
+        &self,
+        review: &Review<'_>,
+        approve: bool,
+    ) -> Result<Document, Status> {
+        if !approve {
+            let operation = self
+                .decide_operation(review, OperationStatus::Rejected)
+                .await?;
+            info!(
+                "Operation {} rejected by {}",
+                review.operation_id, review.reviewed_by
+            );
+            return Ok(operation);
+        }
+
+        let operation = self
+            .decide_operation(review, OperationStatus::Approved)
+            .await?;
+        let operation_id = operation.get_object_id("_id").unwrap();
+
+        let update = match self.execute_operation(&operation).await {
+            Ok(result) => {
+                info!(
+                    "Operation {} approved by {} and executed",
+                    operation_id, review.reviewed_by
+                );
+                doc! {
+                    "$set": {
+                        "status": OperationStatus::Executed.to_string(),
+                        "result": result,
+                    },
+                    "$push": { "history": history_event("EXECUTED", SYSTEM_USER, "") },
+                }
+            }
+            Err(status) => {
+                error!(
+                    "Failed to execute operation {}: {}",
+                    operation_id,
+                    status.message()
+                );
+                doc! {
+                    "$set": {
+                        "status": OperationStatus::Failed.to_string(),
+                        "error": status.message(),
+                    },
+                    "$push": { "history": history_event("FAILED", SYSTEM_USER, status.message()) },
+                }
+            }
+        };
+        self.operations_collection()
+            .update_one(doc! { "_id": operation_id }, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update operation: {}", e)))?;
+
+        self.get_operation(operation_id).await
+    }
+
+    async fn expire_operations(&self) -> Result<(), Status> {
+        let update_result = self
+            .operations_collection()
+            .update_many(
+                doc! {
+                    "service": Self::SERVICE,
+                    "status": OperationStatus::PendingApproval.to_string(),
+                    "expires_at": { "$lte": DateTime::now() },
+                },
+                doc! {
+                    "$set": { "status": OperationStatus::Expired.to_string() },
+                    "$push": { "history": history_event("EXPIRED", SYSTEM_USER, "") },
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to expire operations: {}", e)))?;
+
+        if update_result.modified_count > 0 {
+            info!(
+                "{} pending operations expired",
+                update_result.modified_count
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn run_approval_expiry(self) {
+        info!(
+            "Approval expiry started, running every {:?}",
+            self.approval_config().expiry_interval
+        );
+
+        let mut interval = tokio::time::interval(self.approval_config().expiry_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.expire_operations().await {
+                error!("Failed to expire operations: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation() -> Document {
+        doc! { "requested_by": "maker", "required_role": ADMIN_ROLE }
+    }
+
+    fn review<'a>(reviewed_by: &'a str, reviewer_roles: &'a [String]) -> Review<'a> {
+        Review {
+            operation_id: "0123456789abcdef01234567",
+            reviewed_by,
+            reviewer_roles,
+            comment: "",
+        }
+    }
+
+    #[test]
+    fn requesters_cannot_review_their_own_operations() {
+        let roles = vec![ADMIN_ROLE.to_string()];
+        assert!(check_reviewer(&operation(), &review("maker", &roles)).is_err());
+        assert!(check_reviewer(&operation(), &review("checker", &roles)).is_ok());
+    }
+
+    #[test]
+    fn reviewers_need_the_required_role() {
+        let roles = vec!["AGENT".to_string()];
+        assert_eq!(
+            check_reviewer(&operation(), &review("checker", &roles)),
+            Err("Reviewing this operation requires the ADMIN role".to_string())
+        );
+        assert!(check_reviewer(&operation(), &review("checker", &[])).is_err());
+    }
+
+    #[test]
+    fn reviews_need_a_reviewer_and_a_valid_operation_id() {
+        assert!(validate_review(&review("", &[])).is_err());
+        let mut invalid = review("checker", &[]);
+        invalid.operation_id = "42";
+        assert!(validate_review(&invalid).is_err());
+        assert!(validate_review(&review("checker", &[])).is_ok());
+    }
+
+    #[test]
+    fn statuses_round_trip_through_their_names() {
+        for status in [
+            OperationStatus::PendingApproval,
+            OperationStatus::Approved,
+            OperationStatus::Executed,
+            OperationStatus::Rejected,
+            OperationStatus::Expired,
+            OperationStatus::Failed,
+        ] {
+            assert_eq!(OperationStatus::from_str(&status.to_string()), Ok(status));
+        }
+        assert!(OperationStatus::from_str("PENDING").is_err());
+    }
+}
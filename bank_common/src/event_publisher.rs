@@ -0,0 +1,203 @@
+use lapin::{
+    options::{BasicPublishOptions, ConfirmSelectOptions, ExchangeDeclareOptions},
+    publisher_confirm::Confirmation,
+    types::FieldTable,
+    BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind,
+};
+use log::info;
+use std::{fmt::Debug, sync::Arc};
+use tokio::sync::Mutex;
+
+use crate::health::Dependency;
+
+// A domain event as it leaves the outbox. `event_id` is stable across
+// redeliveries, so consumers use it to drop duplicates.
+#[derive(Debug, Clone)]
+pub struct OutboxEvent {
+    pub event_id: String,
+    pub event_type: String,
+    pub source: String,
+    pub aggregate_id: String,
+    pub occurred_at: i64,
+    pub data: serde_json::Value,
+}
+
+impl OutboxEvent {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "event_id": self.event_id,
+            "event_type": self.event_type,
+            "source": self.source,
+            "aggregate_id": self.aggregate_id,
+            "occurred_at": self.occurred_at,
+            "data": self.data,
+        })
+    }
+}
+
+// Delivers events to the bus. Publishing is at least once: an event is
+// published again when the outbox could not record that it was sent.
+#[tonic::async_trait]
+pub trait EventPublisher: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), String>;
+
+    // Whether events can be published right now
+    async fn check(&self) -> Result<(), String>;
+}
+
+// Publishes to a durable topic exchange, routed by event type, and waits for
+// the broker to confirm each message
+#[derive(Debug)]
+pub struct AmqpPublisher {
+    pub url: String,
+    pub exchange: String,
+    // The connection is kept alongside its channel so that it stays open
+    channel: Mutex<Option<(Connection, Channel)>>,
+}
+
+impl AmqpPublisher {
+    pub fn new(url: &str, exchange: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            exchange: exchange.to_string(),
+            channel: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<(Connection, Channel), lapin::Error> {
+        let connection = Connection::connect(&self.url, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
+        channel
+            .exchange_declare(
+                &self.exchange,
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        info!("Connected to the event exchange {}", self.exchange);
+        Ok((connection, channel))
+    }
+
+    async fn ensure_connected(
+        &self,
+        channel: &mut Option<(Connection, Channel)>,
+    ) -> Result<(), String> {
+        if !channel
+            .as_ref()
+            .is_some_and(|(_, channel)| channel.status().connected())
+        {
+            *channel = Some(
+                self.connect()
+                    .await
+                    .map_err(|e| format!("Failed to connect to {}: {}", self.exchange, e))?,
+            );
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl EventPublisher for AmqpPublisher {
+    fn name(&self) -> &str {
+        "amqp"
+    }
+
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), String> {
+        let mut channel = self.channel.lock().await;
+        self.ensure_connected(&mut channel).await?;
+
+        let properties = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_message_id(event.event_id.as_str().into())
+            .with_type(event.event_type.as_str().into())
+            .with_delivery_mode(2);
+        let result = async {
+            channel
+                .as_ref()
+                .unwrap()
+                .1
+                .basic_publish(
+                    &self.exchange,
+                    &event.event_type,
+                    BasicPublishOptions::default(),
+                    event.to_json().to_string().as_bytes(),
+                    properties,
+                )
+                .await?
+                .await
+        }
+        .await;
+
+        match result {
+            Ok(Confirmation::Nack(_)) => Err(format!("Event {} was rejected", event.event_id)),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // Reconnect on the next event
+                *channel = None;
+                Err(format!("Failed to publish event {}: {}", event.event_id, e))
+            }
+        }
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let mut channel = self.channel.lock().await;
+        self.ensure_connected(&mut channel).await
+    }
+}
+
+const IN_MEMORY_EVENTS: usize = 1000;
+
+// Stand-in bus for running a service on its own: the latest events are kept
+// in memory and logged
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryPublisher {
+    events: Arc<Mutex<Vec<OutboxEvent>>>,
+}
+
+#[tonic::async_trait]
+impl EventPublisher for InMemoryPublisher {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), String> {
+        info!("Event published: {}", event.to_json());
+        let mut events = self.events.lock().await;
+        if events.len() == IN_MEMORY_EVENTS {
+            events.remove(0);
+        }
+        events.push(event.clone());
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Events wait in the outbox while the bus is down, so the services keep
+// serving their calls
+#[tonic::async_trait]
+impl Dependency for dyn EventPublisher {
+    fn name(&self) -> &str {
+        EventPublisher::name(self)
+    }
+
+    fn critical(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        EventPublisher::check(self).await
+    }
+}
@@ -0,0 +1,524 @@
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use log::{info, warn};
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
+    ClientSession, Collection, Database,
+};
+
+// Rules-based fee engine evaluated on every money movement. Rules live in the
+// `fee_rules` collection and select the operations they apply to.
+pub const OPERATION_TRANSFER: &str = "TRANSFER";
+pub const OPERATION_WITHDRAWAL: &str = "WITHDRAWAL";
+
+#[derive(Debug, Clone)]
+pub enum FeeCalculation {
+    Flat(f64),
+    Percentage {
+        rate: f64,
+        min_fee: Option<f64>,
+        max_fee: Option<f64>,
+    },
+    // (upper bound of the amount, fee) pairs in ascending order
+    Tiered(Vec<(Option<f64>, f64)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeRule {
+    pub name: String,
+    pub operation: String,
+    pub account_type: Option<String>,
+    pub calculation: FeeCalculation,
+    pub free_per_month: i32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FeeQuote {
+    pub fee: f64,
+    pub rule_name: String,
+    pub free_operations_remaining: i32,
+}
+
+fn get_number(document: &Document, key: &str) -> Option<f64> {
+    match document.get(key) {
+        Some(Bson::Double(value)) => Some(*value),
+        Some(Bson::Int32(value)) => Some(*value as f64),
+        Some(Bson::Int64(value)) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn round_to_cents(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+impl FeeRule {
+    pub fn from_document(rule: &Document) -> Result<FeeRule, String> {
+        let name = rule.get_str("name").unwrap_or_default().to_string();
+        let operation = rule
+            .get_str("operation")
+            .map_err(|_| format!("Fee rule {} has no operation", name))?
+            .to_string();
+        let account_type = rule.get_str("account_type").ok().map(str::to_string);
+
+        let calculation = match rule.get_str("fee_type").unwrap_or_default() {
+            "FLAT" => FeeCalculation::Flat(
+                get_number(rule, "amount")
+                    .ok_or_else(|| format!("Flat fee rule {} has no amount", name))?,
+            ),
+            "PERCENTAGE" => FeeCalculation::Percentage {
+                rate: get_number(rule, "rate")
+                    .ok_or_else(|| format!("Percentage fee rule {} has no rate", name))?,
+                min_fee: get_number(rule, "min_fee"),
+                max_fee: get_number(rule, "max_fee"),
+            },
+            "TIERED" => {
+                let tiers = rule
+                    .get_array("tiers")
+                    .map_err(|_| format!("Tiered fee rule {} has no tiers", name))?
+                    .iter()
+                    .map(|tier| match tier {
+                        Bson::Document(tier) => Ok((
+                            get_number(tier, "up_to"),
+                            get_number(tier, "fee")
+                                .ok_or_else(|| format!("Tier without fee in rule {}", name))?,
+                        )),
+                        _ => Err(format!("Invalid tier in rule {}", name)),
+                    })
+                    .collect::<Result<Vec<(Option<f64>, f64)>, String>>()?;
+                FeeCalculation::Tiered(tiers)
+            }
+            other => return Err(format!("Invalid fee type {} in rule {}", other, name)),
+        };
+
+        Ok(FeeRule {
+            name,
+            operation,
+            account_type,
+            calculation,
+            free_per_month: get_number(rule, "free_per_month").unwrap_or_default() as i32,
+        })
+    }
+
+    pub fn fee_for(&self, amount: f64) -> f64 {
+        let fee = match &self.calculation {
+            FeeCalculation::Flat(fee) => *fee,
+            FeeCalculation::Percentage {
+                rate,
+                min_fee,
+                max_fee,
+            } => {
+                let fee = amount * rate / 100.0;
+                let fee = min_fee.map_or(fee, |min_fee| fee.max(min_fee));
+                max_fee.map_or(fee, |max_fee| fee.min(max_fee))
+            }
+            // Amounts above the last bounded tier pay the fee of the last tier
+            FeeCalculation::Tiered(tiers) => tiers
+                .iter()
+                .find(|(up_to, _)| up_to.is_none_or(|up_to| amount <= up_to))
+                .or_else(|| tiers.last())
+                .map(|(_, fee)| *fee)
+                .unwrap_or_default(),
+        };
+
+        round_to_cents(fee.max(0.0))
+    }
+}
+
+pub async fn load_rules(db: &Database) -> Result<Vec<FeeRule>, Status> {
+    let rules_collection: Collection<Document> = db.collection("fee_rules");
+
+    let rule_documents: Vec<Document> = rules_collection
+        .find(doc! { "active": { "$ne": false } }, None)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get fee rules: {}", e)))?
+        .try_collect()
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get fee rules: {}", e)))?;
+
+    let mut rules = Vec::new();
+    for rule in rule_documents {
+        match FeeRule::from_document(&rule) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => warn!("Ignoring invalid fee rule: {}", e),
+        }
+    }
+
+    Ok(rules)
+}
+
+// Rules for the specific account type take precedence over generic ones
+pub fn select_rule<'a>(
+    rules: &'a [FeeRule],
+    operation: &str,
+    account_type: &str,
+) -> Option<&'a FeeRule> {
+    let mut candidates = rules.iter().filter(|rule| rule.operation == operation);
+
+    candidates
+        .clone()
+        .find(|rule| rule.account_type.as_deref() == Some(account_type))
+        .or_else(|| candidates.find(|rule| rule.account_type.is_none()))
+}
+
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+fn usage_filter(account_id: ObjectId, operation: &str) -> Document {
+    doc! {
+        "account_id": account_id,
+        "operation": operation,
+        "month": current_month(),
+    }
+}
+
+fn quote_for_usage(rule: &FeeRule, used_this_month: i32, amount: f64) -> FeeQuote {
+    if used_this_month < rule.free_per_month {
+        return FeeQuote {
+            fee: 0.0,
+            rule_name: rule.name.clone(),
+            free_operations_remaining: rule.free_per_month - used_this_month,
+        };
+    }
+
+    FeeQuote {
+        fee: rule.fee_for(amount),
+        rule_name: rule.name.clone(),
+        free_operations_remaining: 0,
+    }
+}
+
+pub async fn quote_fee(
+    db: &Database,
+    account: &Document,
+    operation: &str,
+    amount: f64,
+) -> Result<FeeQuote, Status> {
+    let rules = load_rules(db).await?;
+    let account_type = account.get_str("account_type").unwrap_or_default();
+
+    let rule = match select_rule(&rules, operation, account_type) {
+        Some(rule) => rule,
+        None => return Ok(FeeQuote::default()),
+    };
+
+    let usage_collection: Collection<Document> = db.collection("fee_usage");
+    let used_this_month = usage_collection
+        .find_one(
+            usage_filter(account.get_object_id("_id").unwrap(), operation),
+            None,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get fee usage: {}", e)))?
+        .and_then(|usage| usage.get_i32("count").ok())
+        .unwrap_or_default();
+
+    Ok(quote_for_usage(rule, used_this_month, amount))
+}
+
+// Quotes the fee inside the transaction that charges it, so that the free
+// quota read here and the usage counted by `record_usage` in the same
+// session cannot be raced by another operation
+pub async fn quote_fee_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    account: &Document,
+    operation: &str,
+    amount: f64,
+) -> Result<FeeQuote, Status> {
+    let rules = load_rules(db).await?;
+    let account_type = account.get_str("account_type").unwrap_or_default();
+
+    let rule = match select_rule(&rules, operation, account_type) {
+        Some(rule) => rule,
+        None => return Ok(FeeQuote::default()),
+    };
+
+    let usage_collection: Collection<Document> = db.collection("fee_usage");
+    let used_this_month = usage_collection
+        .find_one_with_session(
+            usage_filter(account.get_object_id("_id").unwrap(), operation),
+            None,
+            session,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get fee usage: {}", e)))?
+        .and_then(|usage| usage.get_i32("count").ok())
+        .unwrap_or_default();
+
+    Ok(quote_for_usage(rule, used_this_month, amount))
+}
+
+// Counts an executed operation against the monthly free quota
+pub async fn record_usage(
+    db: &Database,
+    session: &mut ClientSession,
+    account_id: ObjectId,
+    operation: &str,
+) -> Result<(), Status> {
+    let usage_collection: Collection<Document> = db.collection("fee_usage");
+
+    usage_collection
+        .update_one_with_session(
+            usage_filter(account_id, operation),
+            doc! { "$inc": { "count": 1 } },
+            UpdateOptions::builder().upsert(true).build(),
+            session,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to update fee usage: {}", e)))?;
+
+    Ok(())
+}
+
+// Gives back the free operation counted for an operation that was rolled back
+pub async fn release_usage(
+    db: &Database,
+    session: &mut ClientSession,
+    account_id: ObjectId,
+    operation: &str,
+) -> Result<(), Status> {
+    let usage_collection: Collection<Document> = db.collection("fee_usage");
+
+    let mut filter = usage_filter(account_id, operation);
+    filter.insert("count", doc! { "$gt": 0 });
+    usage_collection
+        .update_one_with_session(filter, doc! { "$inc": { "count": -1 } }, None, session)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to update fee usage: {}", e)))?;
+
+    Ok(())
+}
+
+// Fees are credited to the fee-income account, so a service charging them
+// refuses to start when that account does not exist.
+pub async fn check_fee_income_account(
+    db: &Database,
+    fee_income_account_id: ObjectId,
+) -> Result<(), Status> {
+    let accounts_collection: Collection<Document> = db.collection("accounts");
+
+    accounts_collection
+        .find_one(doc! { "_id": fee_income_account_id }, None)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get fee income account: {}", e)))?
+        .ok_or_else(|| {
+            Status::failed_precondition(format!(
+                "Fee income account {} not found",
+                fee_income_account_id
+            ))
+        })?;
+
+    Ok(())
+}
+
+// Debits the fee from the customer account and credits the fee-income
+// account, recording both legs as `Fee` transactions next to the movement
+// that caused them. Everything is written in the caller's transaction, so the
+// fee commits together with the movement it is charged for.
+pub async fn post_fee(
+    db: &Database,
+    session: &mut ClientSession,
+    fee_income_account_id: ObjectId,
+    account_id: ObjectId,
+    operation: &str,
+    quote: &FeeQuote,
+    reference: &str,
+) -> Result<Option<ObjectId>, Status> {
+    if quote.fee <= 0.0 {
+        return Ok(None);
+    }
+
+    let accounts_collection: Collection<Document> = db.collection("accounts");
+    let transactions_collection: Collection<Document> = db.collection("transactions");
+    let fees_collection: Collection<Document> = db.collection("fees");
+
+    accounts_collection
+        .update_one_with_session(
+            doc! { "_id": account_id },
+            doc! { "$inc": { "balance": -quote.fee } },
+            None,
+            &mut *session,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to charge fee: {}", e)))?;
+    accounts_collection
+        .update_one_with_session(
+            doc! { "_id": fee_income_account_id },
+            doc! { "$inc": { "balance": quote.fee } },
+            None,
+            &mut *session,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to credit fee income: {}", e)))?;
+
+    let fee_record = doc! {
+        "account_id": account_id,
+        "fee_income_account_id": fee_income_account_id,
+        "operation": operation,
+        "amount": quote.fee,
+        "rule_name": &quote.rule_name,
+        "reference": reference,
+        "status": "CHARGED",
+        "created_at": DateTime::now(),
+    };
+    let fee_id = fees_collection
+        .insert_one_with_session(fee_record, None, &mut *session)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to record fee: {}", e)))?
+        .inserted_id
+        .as_object_id()
+        .ok_or_else(|| Status::internal("Failed to record fee: missing inserted_id"))?;
+
+    let timestamp = DateTime::now();
+    let fee_transactions = vec![
+        doc! {
+            "from_account_id": account_id,
+            "to_account_id": fee_income_account_id,
+            "amount": -quote.fee,
+            "type": "Fee",
+            "account_id": account_id,
+            "fee_id": fee_id,
+            "timestamp": timestamp,
+        },
+        doc! {
+            "from_account_id": account_id,
+            "to_account_id": fee_income_account_id,
+            "amount": quote.fee,
+            "type": "Fee",
+            "account_id": fee_income_account_id,
+            "fee_id": fee_id,
+            "timestamp": timestamp,
+        },
+    ];
+    transactions_collection
+        .insert_many_with_session(fee_transactions, None, session)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to create fee transactions: {}", e)))?;
+
+    info!(
+        "Charged fee {} of {} to account {} ({})",
+        fee_id, quote.fee, account_id, quote.rule_name
+    );
+
+    Ok(Some(fee_id))
+}
+
+// Refunds a charged fee by posting the opposite ledger entries in the
+// caller's transaction. A fee can only be reversed once, and records the agent
+// who reversed it.
+pub async fn reverse_fee(
+    db: &Database,
+    session: &mut ClientSession,
+    fee_id: &str,
+    reason: &str,
+    reversed_by: &str,
+) -> Result<Document, Status> {
+    let fees_collection: Collection<Document> = db.collection("fees");
+    let accounts_collection: Collection<Document> = db.collection("accounts");
+    let transactions_collection: Collection<Document> = db.collection("transactions");
+
+    let fee_object_id = ObjectId::parse_str(fee_id)
+        .map_err(|_| Status::invalid_argument(format!("Invalid fee id: {}", fee_id)))?;
+
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+
+    let fee = fees_collection
+        .find_one_and_update_with_session(
+            doc! { "_id": fee_object_id, "status": "CHARGED" },
+            doc! {
+                "$set": {
+                    "status": "REVERSED",
+                    "reversal_reason": reason,
+                    "reversed_by": reversed_by,
+                    "reversed_at": DateTime::now(),
+                }
+            },
+            options,
+            &mut *session,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to reverse fee: {}", e)))?;
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let exists = fees_collection
+                .find_one_with_session(doc! { "_id": fee_object_id }, None, &mut *session)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to get fee: {}", e)))?
+                .is_some();
+            return Err(if exists {
+                Status::failed_precondition("Fee has already been reversed")
+            } else {
+                Status::not_found("Fee not found")
+            });
+        }
+    };
+
+    let account_id = fee.get_object_id("account_id").unwrap();
+    let fee_income_account_id = fee.get_object_id("fee_income_account_id").unwrap();
+    let amount = fee.get_f64("amount").unwrap();
+
+    accounts_collection
+        .update_one_with_session(
+            doc! { "_id": fee_income_account_id },
+            doc! { "$inc": { "balance": -amount } },
+            None,
+            &mut *session,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to debit fee income: {}", e)))?;
+    accounts_collection
+        .update_one_with_session(
+            doc! { "_id": account_id },
+            doc! { "$inc": { "balance": amount } },
+            None,
+            &mut *session,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to refund fee: {}", e)))?;
+
+    let timestamp = DateTime::now();
+    let reversal_transactions = vec![
+        doc! {
+            "from_account_id": fee_income_account_id,
+            "to_account_id": account_id,
+            "amount": amount,
+            "type": "Fee",
+            "account_id": account_id,
+            "fee_id": fee_object_id,
+            "reversal": true,
+            "reversed_by": reversed_by,
+            "timestamp": timestamp,
+        },
+        doc! {
+            "from_account_id": fee_income_account_id,
+            "to_account_id": account_id,
+            "amount": -amount,
+            "type": "Fee",
+            "account_id": fee_income_account_id,
+            "fee_id": fee_object_id,
+            "reversal": true,
+            "reversed_by": reversed_by,
+            "timestamp": timestamp,
+        },
+    ];
+    transactions_collection
+        .insert_many_with_session(reversal_transactions, None, session)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to create fee transactions: {}", e)))?;
+
+    info!(
+        "Reversed fee {} of {} for account {} by {}: {}",
+        fee_id, amount, account_id, reversed_by, reason
+    );
+
+    Ok(fee)
+}
@@ -0,0 +1,314 @@
+use futures::Stream;
+use log::{error, info};
+use std::{collections::HashMap, pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+// Registered with the server reflection of every service, next to its own
+// protos
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("health_descriptor");
+use proto::{
+    health_check_response::ServingStatus,
+    health_server::{Health, HealthServer},
+    HealthCheckRequest, HealthCheckResponse,
+};
+
+// Something the service needs to serve its calls, such as its database. Every
+// dependency is reported under its name by the health service.
+#[tonic::async_trait]
+pub trait Dependency: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Whether the service cannot serve its calls without the dependency. The
+    // others are reported but leave the service SERVING.
+    fn critical(&self) -> bool {
+        true
+    }
+
+    async fn check(&self) -> Result<(), String>;
+}
+
+// Serves the standard `grpc.health.v1.Health` protocol. The service, and the
+// empty name that stands for the whole server, are SERVING while every
+// critical dependency passes its last check.
+#[derive(Clone)]
+pub struct HealthService {
+    service: String,
+    dependencies: Vec<Arc<dyn Dependency>>,
+    interval: Duration,
+    timeout: Duration,
+    statuses: Arc<watch::Sender<HashMap<String, ServingStatus>>>,
+}
+
+impl HealthService {
+    pub fn new(
+        service: &str,
+        dependencies: Vec<Arc<dyn Dependency>>,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Self {
+        let (statuses, _) = watch::channel(HashMap::new());
+        Self {
+            service: service.to_string(),
+            dependencies,
+            interval,
+            timeout,
+            statuses: Arc::new(statuses),
+        }
+    }
+
+    pub fn server(&self) -> HealthServer<Self> {
+        HealthServer::new(self.clone())
+    }
+
+    async fn check_dependency(&self, dependency: &dyn Dependency) -> Result<(), String> {
+        match tokio::time::timeout(self.timeout, dependency.check()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("No answer within {:?}", self.timeout)),
+        }
+    }
+
+    pub async fn check(&self) {
+        let mut statuses = HashMap::new();
+        for dependency in &self.dependencies {
+            let result = self.check_dependency(dependency.as_ref()).await;
+            let previous = self.statuses.borrow().get(dependency.name()).copied();
+            let status = match result {
+                Ok(()) => {
+                    if previous == Some(ServingStatus::NotServing) {
+                        info!("✅ {} is available again", dependency.name());
+                    }
+                    ServingStatus::Serving
+                }
+                Err(e) => {
+                    if previous != Some(ServingStatus::NotServing) {
+                        error!("❌ Health check of {} failed: {}", dependency.name(), e);
+                    }
+                    ServingStatus::NotServing
+                }
+            };
+            statuses.insert(dependency.name().to_string(), status);
+        }
+
+        let serving = self
+            .dependencies
+            .iter()
+            .filter(|dependency| dependency.critical())
+            .all(|dependency| statuses.get(dependency.name()) == Some(&ServingStatus::Serving));
+        let status = if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        statuses.insert(String::new(), status);
+        statuses.insert(self.service.clone(), status);
+        self.statuses.send_replace(statuses);
+    }
+
+    pub async fn run_checks(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            self.check().await;
+        }
+    }
+}
+
+// Dependencies are usually shared with the parts of the service using them
+#[tonic::async_trait]
+impl<T: Dependency + ?Sized> Dependency for Arc<T> {
+    fn name(&self) -> &str {
+        self.as_ref().name()
+    }
+
+    fn critical(&self) -> bool {
+        self.as_ref().critical()
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.as_ref().check().await
+    }
+}
+
+const DEPENDENCIES_HEADER: &str = "x-health-dependencies";
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        let status = match self.statuses.borrow().get(&service) {
+            Some(status) => *status,
+            None => return Err(Status::not_found(format!("Unknown service: {}", service))),
+        };
+
+        let mut response = Response::new(HealthCheckResponse {
+            status: status as i32,
+        });
+        // Names the dependencies of the server, so that clients can check
+        // each of them
+        if service.is_empty() {
+            let dependencies: Vec<&str> = self
+                .dependencies
+                .iter()
+                .map(|dependency| dependency.name())
+                .collect();
+            if let Ok(value) = dependencies.join(",").parse() {
+                response.metadata_mut().insert(DEPENDENCIES_HEADER, value);
+            }
+        }
+        Ok(response)
+    }
+
+    type WatchStream = WatchStream;
+
+    // Sends the status of the service straight away, then every time it
+    // changes
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let receiver = self.statuses.subscribe();
+
+        let stream = futures::stream::unfold(
+            (receiver, service, None),
+            |(mut receiver, service, last)| async move {
+                loop {
+                    let status = receiver
+                        .borrow_and_update()
+                        .get(&service)
+                        .copied()
+                        .unwrap_or(ServingStatus::ServiceUnknown);
+                    if last != Some(status) {
+                        let response = HealthCheckResponse {
+                            status: status as i32,
+                        };
+                        return Some((Ok(response), (receiver, service, Some(status))));
+                    }
+                    // Waits for the next check, ending the stream once the
+                    // server stops
+                    receiver.changed().await.ok()?;
+                }
+            },
+        );
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDependency {
+        name: &'static str,
+        critical: bool,
+        available: bool,
+    }
+
+    #[tonic::async_trait]
+    impl Dependency for TestDependency {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn critical(&self) -> bool {
+            self.critical
+        }
+
+        async fn check(&self) -> Result<(), String> {
+            if self.available {
+                Ok(())
+            } else {
+                Err(format!("{} is down", self.name))
+            }
+        }
+    }
+
+    fn health_service(mongodb: bool, amqp: bool) -> HealthService {
+        let dependencies: Vec<Arc<dyn Dependency>> = vec![
+            Arc::new(TestDependency {
+                name: "mongodb",
+                critical: true,
+                available: mongodb,
+            }),
+            Arc::new(TestDependency {
+                name: "amqp",
+                critical: false,
+                available: amqp,
+            }),
+        ];
+        HealthService::new(
+            "account.AccountService",
+            dependencies,
+            Duration::from_secs(10),
+            Duration::from_secs(1),
+        )
+    }
+
+    async fn status(health: &HealthService, service: &str) -> Result<i32, Status> {
+        let request = Request::new(HealthCheckRequest {
+            service: service.to_string(),
+        });
+        Health::check(health, request)
+            .await
+            .map(|response| response.into_inner().status)
+    }
+
+    #[tokio::test]
+    async fn failing_dependencies_that_are_not_critical_leave_the_server_serving() {
+        let health = health_service(true, false);
+        health.check().await;
+
+        assert_eq!(
+            status(&health, "").await.unwrap(),
+            ServingStatus::Serving as i32
+        );
+        assert_eq!(
+            status(&health, "account.AccountService").await.unwrap(),
+            ServingStatus::Serving as i32
+        );
+        assert_eq!(
+            status(&health, "amqp").await.unwrap(),
+            ServingStatus::NotServing as i32
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_critical_dependencies_take_the_server_out_of_service() {
+        let health = health_service(false, true);
+        health.check().await;
+
+        assert_eq!(
+            status(&health, "").await.unwrap(),
+            ServingStatus::NotServing as i32
+        );
+        assert_eq!(
+            status(&health, "unknown").await.unwrap_err().code(),
+            tonic::Code::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn the_server_names_its_dependencies() {
+        let health = health_service(true, true);
+        health.check().await;
+
+        let request = Request::new(HealthCheckRequest {
+            service: String::new(),
+        });
+        let response = Health::check(&health, request).await.unwrap();
+        assert_eq!(
+            response.metadata().get(DEPENDENCIES_HEADER).unwrap(),
+            "mongodb,amqp"
+        );
+    }
+}
@@ -0,0 +1,11 @@
+// Code shared by the bank services
+pub mod account_number;
+pub mod approval;
+pub mod event_publisher;
+pub mod fee_engine;
+pub mod health;
+pub mod limits;
+pub mod outbox;
+pub mod overdraft;
+pub mod registry;
+pub mod validation;
@@ -0,0 +1,481 @@
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    options::UpdateOptions,
+    Collection, Database,
+};
+
+// Transaction limits. The bank sets maximums per operation, channel and
+// account type in the `transaction_limits` collection, and customers can lower
+// them for each of their accounts in `user_transaction_limits`.
+// deposit_service enforces them for transfers and withdrawal_service for
+// withdrawals.
+pub const OPERATION_TRANSFER: &str = "TRANSFER";
+pub const OPERATION_WITHDRAWAL: &str = "WITHDRAWAL";
+
+pub const CHANNELS: [&str; 3] = [ONLINE_CHANNEL, ATM_CHANNEL, "BRANCH"];
+// Clients never choose the channel of a movement: calls coming through the
+// gateway are ONLINE, and only withdrawals reserved by a terminal are ATM.
+pub const ONLINE_CHANNEL: &str = "ONLINE";
+pub const ATM_CHANNEL: &str = "ATM";
+const DEFAULT_CHANNEL: &str = ONLINE_CHANNEL;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub per_transaction: Option<f64>,
+    pub daily: Option<f64>,
+    pub monthly: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LimitUsage {
+    pub daily: f64,
+    pub monthly: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LimitStatus {
+    pub channel: String,
+    pub maximum: Limits,
+    pub effective: Limits,
+    pub usage: LimitUsage,
+}
+
+// Limits of zero or less, as well as missing ones, mean "no limit"
+fn get_limit(document: &Document, key: &str) -> Option<f64> {
+    let value = match document.get(key) {
+        Some(Bson::Double(value)) => *value,
+        Some(Bson::Int32(value)) => *value as f64,
+        Some(Bson::Int64(value)) => *value as f64,
+        _ => return None,
+    };
+    Some(value).filter(|value| *value > 0.0)
+}
+
+fn tighter(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn exceeds(limit: Option<f64>, maximum: Option<f64>) -> bool {
+    match (limit, maximum) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(limit), Some(maximum)) => limit > maximum,
+    }
+}
+
+impl Limits {
+    pub fn new(per_transaction: f64, daily: f64, monthly: f64) -> Limits {
+        Limits {
+            per_transaction: Some(per_transaction).filter(|value| *value > 0.0),
+            daily: Some(daily).filter(|value| *value > 0.0),
+            monthly: Some(monthly).filter(|value| *value > 0.0),
+        }
+    }
+
+    pub fn from_document(document: &Document) -> Limits {
+        Limits {
+            per_transaction: get_limit(document, "per_transaction"),
+            daily: get_limit(document, "daily"),
+            monthly: get_limit(document, "monthly"),
+        }
+    }
+
+    pub fn tighten(&self, other: &Limits) -> Limits {
+        Limits {
+            per_transaction: tighter(self.per_transaction, other.per_transaction),
+            daily: tighter(self.daily, other.daily),
+            monthly: tighter(self.monthly, other.monthly),
+        }
+    }
+
+    pub fn within(&self, maximum: &Limits) -> bool {
+        !exceeds(self.per_transaction, maximum.per_transaction)
+            && !exceeds(self.daily, maximum.daily)
+            && !exceeds(self.monthly, maximum.monthly)
+    }
+}
+
+impl LimitStatus {
+    pub fn daily_remaining(&self) -> Option<f64> {
+        self.effective
+            .daily
+            .map(|daily| (daily - self.usage.daily).max(0.0))
+    }
+
+    pub fn monthly_remaining(&self) -> Option<f64> {
+        self.effective
+            .monthly
+            .map(|monthly| (monthly - self.usage.monthly).max(0.0))
+    }
+
+    pub fn check(&self, amount: f64) -> Result<(), String> {
+        if let Some(limit) = self.effective.per_transaction {
+            if amount > limit {
+                return Err(format!(
+                    "Amount {} exceeds the {} per-transaction limit of {}",
+                    amount, self.channel, limit
+                ));
+            }
+        }
+        if let Some(remaining) = self.daily_remaining() {
+            if amount > remaining {
+                return Err(format!(
+                    "Amount {} exceeds the remaining {} daily limit of {}",
+                    amount, self.channel, remaining
+                ));
+            }
+        }
+        if let Some(remaining) = self.monthly_remaining() {
+            if amount > remaining {
+                return Err(format!(
+                    "Amount {} exceeds the remaining {} monthly limit of {}",
+                    amount, self.channel, remaining
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn normalize_channel(channel: &str) -> Result<String, String> {
+    if channel.is_empty() {
+        return Ok(DEFAULT_CHANNEL.to_string());
+    }
+
+    let channel = channel.to_uppercase();
+    if CHANNELS.contains(&channel.as_str()) {
+        Ok(channel)
+    } else {
+        Err(format!("Invalid channel: {}", channel))
+    }
+}
+
+fn periods(at: chrono::DateTime<Utc>) -> (String, String) {
+    (
+        at.format("%Y-%m-%d").to_string(),
+        at.format("%Y-%m").to_string(),
+    )
+}
+
+fn current_periods() -> (String, String) {
+    periods(Utc::now())
+}
+
+// The most specific bank limit wins: a rule for the channel beats a rule for
+// the account type, which beats a generic rule for the operation.
+pub async fn bank_limits(
+    db: &Database,
+    operation: &str,
+    channel: &str,
+    account_type: &str,
+) -> Result<Limits, Status> {
+    let limits_collection: Collection<Document> = db.collection("transaction_limits");
+
+    let rules: Vec<Document> = limits_collection
+        .find(doc! { "operation": operation }, None)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get transaction limits: {}", e)))?
+        .try_collect()
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get transaction limits: {}", e)))?;
+
+    let best_rule = rules
+        .iter()
+        .filter_map(|rule| {
+            let channel_score = match rule.get_str("channel") {
+                Ok(rule_channel) if rule_channel == channel => 2,
+                Ok(_) => return None,
+                Err(_) => 0,
+            };
+            let account_type_score = match rule.get_str("account_type") {
+                Ok(rule_account_type) if rule_account_type == account_type => 1,
+                Ok(_) => return None,
+                Err(_) => 0,
+            };
+            Some((channel_score + account_type_score, rule))
+        })
+        .max_by_key(|(score, _)| *score);
+
+    Ok(best_rule
+        .map(|(_, rule)| Limits::from_document(rule))
+        .unwrap_or_default())
+}
+
+// Limits chosen by the customer for this account
+pub async fn user_limits(
+    db: &Database,
+    account_id: ObjectId,
+    operation: &str,
+    channel: &str,
+) -> Result<Limits, Status> {
+    let user_limits_collection: Collection<Document> = db.collection("user_transaction_limits");
+
+    let filter = doc! {
+        "account_id": account_id,
+        "operation": operation,
+        "channel": channel,
+    };
+
+    let user_limit = user_limits_collection
+        .find_one(filter, None)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get user limits: {}", e)))?;
+
+    Ok(user_limit
+        .map(|user_limit| Limits::from_document(&user_limit))
+        .unwrap_or_default())
+}
+
+pub async fn limit_usage(
+    db: &Database,
+    account_id: ObjectId,
+    operation: &str,
+    channel: &str,
+) -> Result<LimitUsage, Status> {
+    let usage_collection: Collection<Document> = db.collection("limit_usage");
+    let (day, month) = current_periods();
+
+    let mut usage = LimitUsage::default();
+    let mut cursor = usage_collection
+        .find(
+            doc! {
+                "account_id": account_id,
+                "operation": operation,
+                "channel": channel,
+                "period": { "$in": [&day, &month] },
+            },
+            None,
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get limit usage: {}", e)))?;
+
+    while let Some(period_usage) = cursor
+        .try_next()
+        .await
+        .map_err(|e| Status::internal(format!("Failed to get limit usage: {}", e)))?
+    {
+        let amount = period_usage.get_f64("amount").unwrap_or_default();
+        if period_usage.get_str("period") == Ok(day.as_str()) {
+            usage.daily = amount;
+        } else {
+            usage.monthly = amount;
+        }
+    }
+
+    Ok(usage)
+}
+
+pub async fn limit_status(
+    db: &Database,
+    account: &Document,
+    operation: &str,
+    channel: &str,
+) -> Result<LimitStatus, Status> {
+    let account_id = account.get_object_id("_id").unwrap();
+    let account_type = account.get_str("account_type").unwrap_or_default();
+
+    let maximum = bank_limits(db, operation, channel, account_type).await?;
+    let effective = maximum.tighten(&user_limits(db, account_id, operation, channel).await?);
+    let usage = limit_usage(db, account_id, operation, channel).await?;
+
+    Ok(LimitStatus {
+        channel: channel.to_string(),
+        maximum,
+        effective,
+        usage,
+    })
+}
+
+pub async fn record_usage(
+    db: &Database,
+    account_id: ObjectId,
+    operation: &str,
+    channel: &str,
+    amount: f64,
+) -> Result<(), Status> {
+    add_usage(
+        db,
+        account_id,
+        operation,
+        channel,
+        amount,
+        current_periods(),
+    )
+    .await
+}
+
+// Gives back usage recorded at `recorded_at`, to the day and month it was
+// counted in
+pub async fn release_usage(
+    db: &Database,
+    account_id: ObjectId,
+    operation: &str,
+    channel: &str,
+    amount: f64,
+    recorded_at: DateTime,
+) -> Result<(), Status> {
+    let recorded_at = chrono::DateTime::<Utc>::from(recorded_at.to_system_time());
+    add_usage(
+        db,
+        account_id,
+        operation,
+        channel,
+        -amount,
+        periods(recorded_at),
+    )
+    .await
+}
+
+async fn add_usage(
+    db: &Database,
+    account_id: ObjectId,
+    operation: &str,
+    channel: &str,
+    amount: f64,
+    (day, month): (String, String),
+) -> Result<(), Status> {
+    let usage_collection: Collection<Document> = db.collection("limit_usage");
+
+    for period in [day, month] {
+        usage_collection
+            .update_one(
+                doc! {
+                    "account_id": account_id,
+                    "operation": operation,
+                    "channel": channel,
+                    "period": period,
+                },
+                doc! { "$inc": { "amount": amount } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update limit usage: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+// Stores the customer's own limits for one of their accounts. They can never
+// be above the bank maximums.
+pub async fn set_user_limits(
+    db: &Database,
+    user_id: &str,
+    account: &Document,
+    operation: &str,
+    channel: &str,
+    limits: Limits,
+) -> Result<(), Status> {
+    let user_limits_collection: Collection<Document> = db.collection("user_transaction_limits");
+
+    if account.get_str("user_id") != Ok(user_id) {
+        return Err(Status::permission_denied("Account does not belong to user"));
+    }
+
+    let maximum = bank_limits(
+        db,
+        operation,
+        channel,
+        account.get_str("account_type").unwrap_or_default(),
+    )
+    .await?;
+    if !limits.within(&maximum) {
+        return Err(Status::invalid_argument(format!(
+            "Limits cannot exceed the bank maximums for {}",
+            channel
+        )));
+    }
+
+    user_limits_collection
+        .update_one(
+            doc! {
+                "account_id": account.get_object_id("_id").unwrap(),
+                "operation": operation,
+                "channel": channel,
+            },
+            doc! {
+                "$set": {
+                    "user_id": user_id,
+                    "per_transaction": limits.per_transaction,
+                    "daily": limits.daily,
+                    "monthly": limits.monthly,
+                    "updated_at": DateTime::now(),
+                }
+            },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(|e| Status::internal(format!("Failed to update user limits: {}", e)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn status(effective: Limits, usage: LimitUsage) -> LimitStatus {
+        LimitStatus {
+            channel: ONLINE_CHANNEL.to_string(),
+            maximum: Limits::default(),
+            effective,
+            usage,
+        }
+    }
+
+    #[test]
+    fn customer_limits_only_tighten_the_bank_maximums() {
+        let maximum = Limits::new(1000.0, 2000.0, 0.0);
+        let customer = Limits::new(500.0, 0.0, 3000.0);
+
+        let effective = maximum.tighten(&customer);
+        assert_eq!(effective.per_transaction, Some(500.0));
+        assert_eq!(effective.daily, Some(2000.0));
+        assert_eq!(effective.monthly, Some(3000.0));
+
+        assert!(customer.within(&Limits::new(1000.0, 0.0, 0.0)));
+        assert!(!Limits::new(0.0, 0.0, 0.0).within(&maximum));
+        assert!(!Limits::new(1500.0, 0.0, 0.0).within(&maximum));
+    }
+
+    #[test]
+    fn amounts_are_checked_against_the_remaining_limits() {
+        let usage = LimitUsage {
+            daily: 150.0,
+            monthly: 900.0,
+        };
+        let status = status(Limits::new(100.0, 200.0, 1000.0), usage);
+
+        assert!(status.check(50.0).is_ok());
+        assert!(status.check(120.0).unwrap_err().contains("per-transaction"));
+        assert!(status.check(60.0).unwrap_err().contains("daily"));
+        assert_eq!(status.daily_remaining(), Some(50.0));
+        assert_eq!(status.monthly_remaining(), Some(100.0));
+
+        let unlimited = self::status(Limits::default(), usage);
+        assert!(unlimited.check(1_000_000.0).is_ok());
+    }
+
+    #[test]
+    fn usage_is_counted_per_day_and_month() {
+        let at = Utc.with_ymd_and_hms(2024, 2, 29, 23, 59, 0).unwrap();
+        assert_eq!(
+            periods(at),
+            ("2024-02-29".to_string(), "2024-02".to_string())
+        );
+    }
+
+    #[test]
+    fn channels_default_to_online() {
+        assert_eq!(normalize_channel("").unwrap(), ONLINE_CHANNEL);
+        assert_eq!(normalize_channel("atm").unwrap(), ATM_CHANNEL);
+        assert!(normalize_channel("PHONE").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use futures::stream::TryStreamExt;
+use log::{error, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    error::UNKNOWN_TRANSACTION_COMMIT_RESULT,
+    options::FindOptions,
+    Client, ClientSession, Collection, Database,
+};
+
+use crate::event_publisher::{EventPublisher, OutboxEvent};
+
+// Domain events are written to the `outbox` collection in the same MongoDB
+// transaction as the state change they describe, and published to the event
+// bus afterwards by the relay. Transactions need MongoDB to run as a replica
+// set.
+const OUTBOX: &str = "outbox";
+
+// A commit whose outcome is unknown is retried this many times in total,
+// waiting twice as long before each new attempt
+const COMMIT_MAX_ATTEMPTS: u32 = 5;
+const COMMIT_RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
+fn commit_retry_delay(attempt: u32) -> Duration {
+    COMMIT_RETRY_BASE_DELAY * 2u32.pow(attempt - 1)
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    pub publisher: Arc<dyn EventPublisher>,
+    pub relay_interval: std::time::Duration,
+    pub batch_size: i64,
+}
+
+pub fn new_event(
+    source: &str,
+    event_type: &str,
+    aggregate_id: ObjectId,
+    data: Document,
+) -> Document {
+    doc! {
+        "event_type": event_type,
+        "source": source,
+        "aggregate_id": aggregate_id.to_hex(),
+        "data": data,
+        "occurred_at": DateTime::now(),
+        "published": false,
+        "attempts": 0,
+    }
+}
+
+fn event_from_document(event: Document) -> OutboxEvent {
+    OutboxEvent {
+        event_id: event.get_object_id("_id").unwrap().to_hex(),
+        event_type: event.get_str("event_type").unwrap_or_default().to_string(),
+        source: event.get_str("source").unwrap_or_default().to_string(),
+        aggregate_id: event
+            .get_str("aggregate_id")
+            .unwrap_or_default()
+            .to_string(),
+        occurred_at: event
+            .get_datetime("occurred_at")
+            .map(|occurred_at| occurred_at.timestamp_millis())
+            .unwrap_or_default(),
+        data: Bson::Document(event.get_document("data").cloned().unwrap_or_default())
+            .into_relaxed_extjson(),
+    }
+}
+
+// Implemented by every service publishing domain events, which only has to
+// say where its events are stored and where they are published
+#[tonic::async_trait]
+pub trait OutboxService: Send + Sync + Sized + 'static {
+    // Every event is recorded with the name of the service it comes from
+    const SOURCE: &'static str;
+
+    fn outbox_client(&self) -> &Client;
+
+    fn outbox_db(&self) -> &Database;
+
+    fn outbox_config(&self) -> &OutboxConfig;
+
+    async fn start_transaction(&self) -> Result<ClientSession, Status> {
+        let mut session = self
+            .outbox_client()
+            .start_session(None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to start session: {}", e)))?;
+        session
+            .start_transaction(None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to start transaction: {}", e)))?;
+        Ok(session)
+    }
+
+    async fn commit_transaction(&self, session: &mut ClientSession) -> Result<(), Status> {
+        let mut attempt = 1;
+        loop {
+            match session.commit_transaction().await {
+                Ok(()) => return Ok(()),
+                // Committing again is safe when the outcome of the commit is unknown
+                Err(e)
+                    if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT)
+                        && attempt < COMMIT_MAX_ATTEMPTS =>
+                {
+                    let delay = commit_retry_delay(attempt);
+                    warn!("Retrying transaction commit in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(Status::internal(format!(
+                        "Failed to commit transaction: {}",
+                        e
+                    )))
+                }
+            }
+        }
+    }
+
+    async fn record_events(
+        &self,
+        session: &mut ClientSession,
+        events: Vec<Document>,
+    ) -> Result<(), Status> {
+        let outbox_collection: Collection<Document> = self.outbox_db().collection(OUTBOX);
+
+        outbox_collection
+            .insert_many_with_session(events, None, session)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to record events: {}", e)))?;
+
+        Ok(())
+    }
+
+    // Publishes unpublished events oldest first, stopping at the first failure
+    // so that events of this service are published in order
+    async fn relay_events(&self) -> Result<usize, Status> {
+        let outbox_collection: Collection<Document> = self.outbox_db().collection(OUTBOX);
+        let publisher = &self.outbox_config().publisher;
+
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .limit(self.outbox_config().batch_size)
+            .build();
+        let events: Vec<Document> = outbox_collection
+            .find(doc! { "source": Self::SOURCE, "published": false }, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get outbox events: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get outbox events: {}", e)))?;
+
+        let mut published = 0;
+        for event in events {
+            let event_id = event.get_object_id("_id").unwrap();
+            let outbox_event = event_from_document(event);
+
+            if let Err(e) = publisher.publish(&outbox_event).await {
+                outbox_collection
+                    .update_one(
+                        doc! { "_id": event_id },
+                        doc! { "$inc": { "attempts": 1 }, "$set": { "last_error": &e } },
+                        None,
+                    )
+                    .await
+                    .map_err(|e| Status::internal(format!("Failed to update event: {}", e)))?;
+                error!(
+                    "Failed to publish {} event {}: {}",
+                    outbox_event.event_type, event_id, e
+                );
+                break;
+            }
+
+            outbox_collection
+                .update_one(
+                    doc! { "_id": event_id },
+                    doc! {
+                        "$inc": { "attempts": 1 },
+                        "$set": { "published": true, "published_at": DateTime::now() },
+                        "$unset": { "last_error": "" },
+                    },
+                    None,
+                )
+                .await
+                .map_err(|e| Status::internal(format!("Failed to update event: {}", e)))?;
+            published += 1;
+        }
+
+        Ok(published)
+    }
+
+    async fn run_outbox_relay(self) {
+        info!(
+            "Outbox relay started, publishing to {} every {:?}",
+            self.outbox_config().publisher.name(),
+            self.outbox_config().relay_interval
+        );
+
+        let mut interval = tokio::time::interval(self.outbox_config().relay_interval);
+        loop {
+            interval.tick().await;
+            match self.relay_events().await {
+                Ok(0) => {}
+                Ok(published) => info!("Published {} event(s)", published),
+                Err(e) => error!("Failed to relay events: {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_retries_back_off_exponentially() {
+        assert_eq!(commit_retry_delay(1), Duration::from_millis(50));
+        assert_eq!(commit_retry_delay(2), Duration::from_millis(100));
+        assert_eq!(
+            commit_retry_delay(COMMIT_MAX_ATTEMPTS - 1),
+            Duration::from_millis(400)
+        );
+    }
+
+    #[test]
+    fn events_leave_the_outbox_with_their_id_and_relaxed_json_data() {
+        let event_id = ObjectId::new();
+        let aggregate_id = ObjectId::new();
+        let mut event = new_event(
+            "deposit",
+            "FundsDeposited",
+            aggregate_id,
+            doc! { "amount": 12.5, "timestamp": 1_700_000_000_000i64 },
+        );
+        event.insert("_id", event_id);
+
+        let outbox_event = event_from_document(event);
+        assert_eq!(outbox_event.event_id, event_id.to_hex());
+        assert_eq!(outbox_event.source, "deposit");
+        assert_eq!(outbox_event.aggregate_id, aggregate_id.to_hex());
+        assert_eq!(
+            outbox_event.data,
+            serde_json::json!({ "amount": 12.5, "timestamp": 1_700_000_000_000i64 })
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    Collection, Database,
+};
+
+// Arranged overdrafts are only available on checking accounts. Limits are
+// managed by account_service; every service booking movements enforces them
+// and raises the overdraft alerts for the movements it books.
+pub fn overdraft_limit(account: &Document) -> f64 {
+    if account.get_str("account_type") == Ok("CHECKING") {
+        account.get_f64("overdraft_limit").unwrap_or_default()
+    } else {
+        0.0
+    }
+}
+
+// Funds that can be debited: the balance plus the arranged overdraft, less
+// the amount reserved by pending holds
+pub fn available_funds(account: &Document) -> f64 {
+    account.get_f64("balance").unwrap() + overdraft_limit(account)
+        - account.get_f64("held_amount").unwrap_or_default()
+}
+
+// The `$expr` of an update filter matching the account only while its
+// available funds, computed like `available_funds`, cover the amount. Debits
+// guarded by it cannot be raced by another movement between the balance
+// check and the update.
+pub fn covers_expr(amount: f64) -> Document {
+    doc! {
+        "$gte": [
+            {
+                "$subtract": [
+                    {
+                        "$add": [
+                            "$balance",
+                            {
+                                "$cond": [
+                                    { "$eq": ["$account_type", "CHECKING"] },
+                                    { "$ifNull": ["$overdraft_limit", 0.0] },
+                                    0.0,
+                                ]
+                            },
+                        ]
+                    },
+                    { "$ifNull": ["$held_amount", 0.0] },
+                ]
+            },
+            amount,
+        ]
+    }
+}
+
+// Alerts are raised on transitions only: when the balance first drops below
+// zero and when it first goes past the arranged limit.
+pub fn overdraft_alert(previous_balance: f64, new_balance: f64, limit: f64) -> Option<String> {
+    if new_balance < -limit && previous_balance >= -limit {
+        Some(format!(
+            "has exceeded its arranged overdraft of {} (balance {:.2})",
+            limit, new_balance
+        ))
+    } else if new_balance < 0.0 && previous_balance >= 0.0 {
+        Some(format!("is now overdrawn (balance {:.2})", new_balance))
+    } else {
+        None
+    }
+}
+
+// Raises the alert for a movement that took the balance from
+// `previous_balance` to `new_balance`, if it crossed a threshold
+pub async fn notify_overdraft(
+    db: &Database,
+    account: &Document,
+    previous_balance: f64,
+    new_balance: f64,
+) -> Result<(), Status> {
+    match overdraft_alert(previous_balance, new_balance, overdraft_limit(account)) {
+        Some(alert) => send_overdraft_alert(db, account, &alert).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn send_overdraft_alert(
+    db: &Database,
+    account: &Document,
+    alert: &str,
+) -> Result<(), Status> {
+    let notifications_collection: Collection<Document> = db.collection("notifications");
+
+    let notification = doc! {
+        "customer_id": account.get_str("user_id").unwrap_or_default(),
+        "notification_type": "EMAIL",
+        "message": format!(
+            "Your account {} {}",
+            account.get_str("account_number").unwrap_or_default(),
+            alert
+        ),
+        "status": "PENDING",
+        "created_at": DateTime::now(),
+    };
+
+    notifications_collection
+        .insert_one(notification, None)
+        .await
+        .map_err(|e| Status::internal(format!("Failed to create notification: {}", e)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_checking_accounts_have_an_overdraft() {
+        let checking =
+            doc! { "account_type": "CHECKING", "overdraft_limit": 500.0, "balance": 100.0 };
+        let savings =
+            doc! { "account_type": "SAVINGS", "overdraft_limit": 500.0, "balance": 100.0 };
+        assert_eq!(overdraft_limit(&checking), 500.0);
+        assert_eq!(overdraft_limit(&savings), 0.0);
+        assert_eq!(available_funds(&checking), 600.0);
+        assert_eq!(available_funds(&savings), 100.0);
+    }
+
+    #[test]
+    fn holds_reduce_the_available_funds() {
+        let account = doc! {
+            "account_type": "CHECKING",
+            "overdraft_limit": 500.0,
+            "balance": 100.0,
+            "held_amount": 250.0,
+        };
+        assert_eq!(available_funds(&account), 350.0);
+    }
+
+    #[test]
+    fn alerts_are_raised_on_transitions_only() {
+        assert!(overdraft_alert(100.0, 50.0, 500.0).is_none());
+        assert!(overdraft_alert(100.0, -50.0, 500.0)
+            .unwrap()
+            .starts_with("is now overdrawn"));
+        assert!(overdraft_alert(-50.0, -80.0, 500.0).is_none());
+        assert!(overdraft_alert(-50.0, -600.0, 500.0)
+            .unwrap()
+            .starts_with("has exceeded"));
+        assert!(overdraft_alert(-600.0, -700.0, 500.0).is_none());
+        // Going straight past the limit reports the limit
+        assert!(overdraft_alert(100.0, -600.0, 500.0)
+            .unwrap()
+            .starts_with("has exceeded"));
+    }
+}
@@ -0,0 +1,278 @@
+use log::{error, info, warn};
+use std::{
+    env,
+    fmt::Debug,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Every service announces its instances to the registry, from which the API
+// gateway learns where to send its calls
+
+// An instance of a service as announced to the registry
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub service: String,
+    pub instance_id: String,
+    pub address: String,
+    pub registered_at: u64,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// Registering again renews the registration, which expires when the instance
+// stops sending heartbeats
+#[tonic::async_trait]
+pub trait Registry: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn register(&self, registration: &Registration) -> Result<(), String>;
+
+    async fn deregister(&self, registration: &Registration) -> Result<(), String>;
+}
+
+// Registrations are files in a directory shared with the gateway, one per
+// instance under `<dir>/<service>/<instance_id>.json`
+#[derive(Debug, Clone)]
+pub struct FileRegistry {
+    pub dir: PathBuf,
+}
+
+impl FileRegistry {
+    fn path(&self, registration: &Registration) -> PathBuf {
+        self.dir
+            .join(&registration.service)
+            .join(format!("{}.json", registration.instance_id))
+    }
+}
+
+#[tonic::async_trait]
+impl Registry for FileRegistry {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn register(&self, registration: &Registration) -> Result<(), String> {
+        let path = self.path(registration);
+        let service_dir = self.dir.join(&registration.service);
+        tokio::fs::create_dir_all(&service_dir)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", service_dir.display(), e))?;
+
+        let entry = serde_json::json!({
+            "service": registration.service,
+            "instance_id": registration.instance_id,
+            "address": registration.address,
+            "registered_at": registration.registered_at,
+            "heartbeat_at": now_millis(),
+        });
+
+        // Written aside and renamed so that readers never see a partial file
+        let temporary = path.with_extension("json.tmp");
+        tokio::fs::write(&temporary, entry.to_string())
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", temporary.display(), e))?;
+        tokio::fs::rename(&temporary, &path)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    async fn deregister(&self, registration: &Registration) -> Result<(), String> {
+        let path = self.path(registration);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+}
+
+// Keeps the registration in the process, for running the service on its own
+// with the gateway pointed at static addresses
+#[derive(Debug, Default)]
+pub struct LocalRegistry {
+    registrations: Mutex<Vec<(Registration, u64)>>,
+}
+
+#[tonic::async_trait]
+impl Registry for LocalRegistry {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    async fn register(&self, registration: &Registration) -> Result<(), String> {
+        let mut registrations = self.registrations.lock().unwrap();
+        registrations.retain(|(registered, _)| registered.instance_id != registration.instance_id);
+        registrations.push((registration.clone(), now_millis()));
+        Ok(())
+    }
+
+    async fn deregister(&self, registration: &Registration) -> Result<(), String> {
+        self.registrations
+            .lock()
+            .unwrap()
+            .retain(|(registered, _)| registered.instance_id != registration.instance_id);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Registrar {
+    registry: Arc<dyn Registry>,
+    registration: Registration,
+    heartbeat: Duration,
+}
+
+impl Registrar {
+    // Reads REGISTRY (`local` or `file`), REGISTRY_DIR, REGISTRY_HEARTBEAT_SECS
+    // and SERVICE_ADVERTISE_ADDRESS, the address other services reach this
+    // instance at. It defaults to the host name and the port of the server.
+    pub fn from_env(service: &str, addr: &SocketAddr) -> Result<Self, String> {
+        let registry: Arc<dyn Registry> = match env::var("REGISTRY")
+            .unwrap_or_else(|_| "local".to_string())
+            .as_str()
+        {
+            "file" => Arc::new(FileRegistry {
+                dir: PathBuf::from(
+                    env::var("REGISTRY_DIR").unwrap_or_else(|_| "/registry".to_string()),
+                ),
+            }),
+            "local" => Arc::new(LocalRegistry::default()),
+            other => return Err(format!("Unknown registry: {}", other)),
+        };
+
+        let address = env::var("SERVICE_ADVERTISE_ADDRESS").unwrap_or_else(|_| {
+            let host = if addr.ip().is_unspecified() {
+                env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string())
+            } else {
+                addr.ip().to_string()
+            };
+            format!("{}:{}", host, addr.port())
+        });
+        let heartbeat = env::var("REGISTRY_HEARTBEAT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(5);
+
+        Ok(Self {
+            registry,
+            registration: Registration {
+                service: service.to_string(),
+                instance_id: format!("{}-{}-{:x}", service, std::process::id(), now_millis()),
+                address,
+                registered_at: now_millis(),
+            },
+            heartbeat: Duration::from_secs(heartbeat),
+        })
+    }
+
+    pub async fn run_heartbeat(self) {
+        info!(
+            "Registering {} at {} with the {} registry",
+            self.registration.instance_id,
+            self.registration.address,
+            self.registry.name()
+        );
+
+        let mut interval = tokio::time::interval(self.heartbeat);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.registry.register(&self.registration).await {
+                error!(
+                    "Failed to register {}: {}",
+                    self.registration.instance_id, e
+                );
+            }
+        }
+    }
+
+    // Resolves once the process is asked to stop, after removing the
+    // registration so that the gateway stops sending calls to this instance
+    pub async fn shutdown_signal(self) {
+        let terminate = async {
+            #[cfg(unix)]
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(mut terminate) => {
+                    terminate.recv().await;
+                }
+                Err(e) => {
+                    warn!("Failed to listen for SIGTERM: {}", e);
+                    std::future::pending::<()>().await;
+                }
+            }
+            #[cfg(not(unix))]
+            std::future::pending::<()>().await;
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate => {}
+        }
+
+        info!("Deregistering {}", self.registration.instance_id);
+        if let Err(e) = self.registry.deregister(&self.registration).await {
+            error!(
+                "Failed to deregister {}: {}",
+                self.registration.instance_id, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration() -> Registration {
+        Registration {
+            service: "account".to_string(),
+            instance_id: format!("account-{:x}", now_millis()),
+            address: "account-1:50052".to_string(),
+            registered_at: now_millis(),
+        }
+    }
+
+    #[tokio::test]
+    async fn file_registrations_are_written_and_removed() {
+        let registry = FileRegistry {
+            dir: env::temp_dir().join(format!(
+                "registry-{}-{:x}",
+                std::process::id(),
+                now_millis()
+            )),
+        };
+        let registration = registration();
+
+        registry.register(&registration).await.unwrap();
+        let contents = std::fs::read_to_string(registry.path(&registration)).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(entry["address"], "account-1:50052");
+        assert!(entry["heartbeat_at"].as_u64().unwrap() >= registration.registered_at);
+
+        registry.deregister(&registration).await.unwrap();
+        assert!(!registry.path(&registration).exists());
+        // Deregistering twice is harmless
+        registry.deregister(&registration).await.unwrap();
+
+        std::fs::remove_dir_all(&registry.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn registering_again_replaces_the_local_registration() {
+        let registry = LocalRegistry::default();
+        let registration = registration();
+
+        registry.register(&registration).await.unwrap();
+        registry.register(&registration).await.unwrap();
+        assert_eq!(registry.registrations.lock().unwrap().len(), 1);
+
+        registry.deregister(&registration).await.unwrap();
+        assert!(registry.registrations.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,329 @@
+use mongodb::bson::oid::ObjectId;
+use prost::Message;
+use tonic::{Code, Status};
+
+use crate::account_number::{normalize, MAX_ACCOUNT_NUMBER_LENGTH};
+
+pub mod rpc {
+    tonic::include_proto!("google.rpc");
+}
+use rpc::{bad_request::FieldViolation, BadRequest};
+
+// Validation of the money paths. Problems are collected per field and
+// returned as a single `InvalidArgument` status whose details carry a
+// `google.rpc.BadRequest`, so clients can point at the offending input.
+pub const MAX_DECIMAL_PLACES: i32 = 2;
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+#[derive(Debug, Clone)]
+pub struct ValidationPolicy {
+    // Currency of the accounts opened before currencies existed
+    pub default_currency: String,
+    pub supported_currencies: Vec<String>,
+}
+
+pub fn validate_amount(amount: f64) -> Result<(), String> {
+    if !amount.is_finite() {
+        return Err("Amount must be a finite number".to_string());
+    }
+    if amount <= 0.0 {
+        return Err("Amount must be greater than zero".to_string());
+    }
+
+    // The tolerance grows with the amount, as large amounts cannot be
+    // represented to the cent exactly
+    let scaled = amount * 10f64.powi(MAX_DECIMAL_PLACES);
+    let tolerance = (scaled * 4.0 * f64::EPSILON).max(1e-6);
+    if (scaled.round() - scaled).abs() > tolerance {
+        return Err(format!(
+            "Amount cannot have more than {} decimal places",
+            MAX_DECIMAL_PLACES
+        ));
+    }
+    // Rules out positive amounts that round to nothing
+    if scaled.round() < 1.0 {
+        return Err("Amount must be greater than zero".to_string());
+    }
+
+    Ok(())
+}
+
+// Accounts are referenced by id or by IBAN-style account number
+pub fn validate_account_reference(account: &str) -> Result<(), String> {
+    if account.trim().is_empty() {
+        return Err("Account is required".to_string());
+    }
+    if ObjectId::parse_str(account).is_ok() {
+        return Ok(());
+    }
+
+    let account_number = normalize(account);
+    let bytes = account_number.as_bytes();
+    if bytes.len() < 5
+        || bytes.len() > MAX_ACCOUNT_NUMBER_LENGTH
+        || !bytes[..2].iter().all(u8::is_ascii_uppercase)
+        || !bytes[2..4].iter().all(u8::is_ascii_digit)
+        || !bytes.iter().all(u8::is_ascii_alphanumeric)
+    {
+        return Err(format!("Invalid account id or number: {}", account));
+    }
+
+    Ok(())
+}
+
+// Status for a single invalid field found after the request was validated
+pub fn invalid_field(field: &str, description: &str) -> Status {
+    let mut validator = Validator::new();
+    validator.violation(field, description);
+    validator.error().unwrap()
+}
+
+#[derive(Debug, Default)]
+pub struct Validator {
+    violations: Vec<(String, String)>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    pub fn violation(&mut self, field: &str, description: impl Into<String>) -> &mut Validator {
+        self.violations
+            .push((field.to_string(), description.into()));
+        self
+    }
+
+    fn check(&mut self, field: &str, result: Result<(), String>) -> &mut Validator {
+        if let Err(description) = result {
+            self.violation(field, description);
+        }
+        self
+    }
+
+    pub fn amount(&mut self, field: &str, amount: f64) -> &mut Validator {
+        self.check(field, validate_amount(amount))
+    }
+
+    pub fn account(&mut self, field: &str, account: &str) -> &mut Validator {
+        self.check(field, validate_account_reference(account))
+    }
+
+    // An empty currency means the currency the accounts are held in
+    pub fn currency(
+        &mut self,
+        field: &str,
+        currency: &str,
+        policy: &ValidationPolicy,
+    ) -> &mut Validator {
+        if !currency.is_empty()
+            && !policy
+                .supported_currencies
+                .iter()
+                .any(|supported| supported.eq_ignore_ascii_case(currency))
+        {
+            self.violation(field, format!("Unsupported currency: {}", currency));
+        }
+        self
+    }
+
+    pub fn different_accounts(&mut self, field: &str, from: &str, to: &str) -> &mut Validator {
+        if !from.is_empty() && normalize(from) == normalize(to) {
+            self.violation(field, "Cannot transfer to the same account");
+        }
+        self
+    }
+
+    // The InvalidArgument status for the collected violations, if any
+    pub fn error(&self) -> Option<Status> {
+        if self.violations.is_empty() {
+            return None;
+        }
+
+        let message = self
+            .violations
+            .iter()
+            .map(|(field, description)| format!("{}: {}", field, description))
+            .collect::<Vec<String>>()
+            .join("; ");
+
+        let bad_request = BadRequest {
+            field_violations: self
+                .violations
+                .iter()
+                .map(|(field, description)| FieldViolation {
+                    field: field.clone(),
+                    description: description.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        // google.rpc.Status, sent in the `grpc-status-details-bin` trailer
+        let details = tonic_types::Status {
+            code: Code::InvalidArgument as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: BAD_REQUEST_TYPE_URL.to_string(),
+                value: bad_request.encode_to_vec(),
+            }],
+        };
+
+        Some(Status::with_details(
+            Code::InvalidArgument,
+            message,
+            details.encode_to_vec().into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Fields reported in the google.rpc.BadRequest details of the status
+    fn violated_fields(validator: &Validator) -> Vec<String> {
+        let status = match validator.error() {
+            Some(status) => status,
+            None => return Vec::new(),
+        };
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let details = tonic_types::Status::decode(status.details()).unwrap();
+        assert_eq!(details.code, Code::InvalidArgument as i32);
+        assert_eq!(details.message, status.message());
+        assert_eq!(details.details.len(), 1);
+        assert_eq!(details.details[0].type_url, BAD_REQUEST_TYPE_URL);
+
+        BadRequest::decode(details.details[0].value.as_slice())
+            .unwrap()
+            .field_violations
+            .into_iter()
+            .map(|violation| violation.field)
+            .collect()
+    }
+
+    fn amount_violations(amount: f64) -> Vec<String> {
+        violated_fields(Validator::new().amount("amount", amount))
+    }
+
+    fn policy() -> ValidationPolicy {
+        ValidationPolicy {
+            default_currency: "EUR".to_string(),
+            supported_currencies: vec!["EUR".to_string(), "USD".to_string()],
+        }
+    }
+
+    fn object_id() -> impl Strategy<Value = String> {
+        "[0-9a-f]{24}"
+    }
+
+    fn account_number() -> impl Strategy<Value = String> {
+        "[A-Z]{2}[0-9]{2}[A-Z0-9]{4,30}"
+    }
+
+    // The same account number as typed by a person: grouped and in any case
+    fn typed(account_number: &str, lowercase: bool) -> String {
+        let grouped = account_number
+            .as_bytes()
+            .chunks(4)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap())
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if lowercase {
+            grouped.to_lowercase()
+        } else {
+            grouped
+        }
+    }
+
+    #[test]
+    fn non_finite_amounts_are_rejected() {
+        for amount in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(amount_violations(amount), ["amount"]);
+        }
+    }
+
+    #[test]
+    fn violations_are_reported_together() {
+        let fields = violated_fields(
+            Validator::new()
+                .account("from_account_id", "")
+                .account("to_account_id", "not an account")
+                .amount("amount", f64::NAN)
+                .currency("currency", "XXX", &policy()),
+        );
+        assert_eq!(
+            fields,
+            ["from_account_id", "to_account_id", "amount", "currency"]
+        );
+    }
+
+    #[test]
+    fn invalid_field_carries_a_bad_request() {
+        let status = invalid_field("fx_quote_id", "Invalid FX quote id");
+        let details = tonic_types::Status::decode(status.details()).unwrap();
+        let bad_request = BadRequest::decode(details.details[0].value.as_slice()).unwrap();
+        assert_eq!(bad_request.field_violations[0].field, "fx_quote_id");
+        assert_eq!(
+            bad_request.field_violations[0].description,
+            "Invalid FX quote id"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn whole_cents_are_accepted(cents in 1u64..100_000_000_000) {
+            prop_assert!(amount_violations(cents as f64 / 100.0).is_empty());
+        }
+
+        #[test]
+        fn negative_and_zero_amounts_are_rejected(cents in 0u64..100_000_000_000) {
+            prop_assert_eq!(amount_violations(-(cents as f64) / 100.0), ["amount"]);
+        }
+
+        #[test]
+        fn fractions_of_cents_are_rejected(cents in 0u64..1_000_000_000, mills in 1u64..10) {
+            let amount = (cents * 10 + mills) as f64 / 1000.0;
+            prop_assert_eq!(amount_violations(amount), ["amount"]);
+        }
+
+        #[test]
+        fn any_negative_or_tiny_amount_is_rejected(amount in prop::num::f64::ANY) {
+            prop_assume!(!(amount.is_finite() && amount >= 0.01));
+            prop_assert_eq!(amount_violations(amount), ["amount"]);
+        }
+
+        #[test]
+        fn account_references_are_accepted(id in object_id(), number in account_number()) {
+            prop_assert!(validate_account_reference(&id).is_ok());
+            prop_assert!(validate_account_reference(&number).is_ok());
+            prop_assert!(validate_account_reference(&typed(&number, true)).is_ok());
+        }
+
+        #[test]
+        fn self_transfers_are_rejected(number in account_number(), lowercase in any::<bool>()) {
+            let fields = violated_fields(Validator::new().different_accounts(
+                "to_account_id",
+                &number,
+                &typed(&number, lowercase),
+            ));
+            prop_assert_eq!(fields, ["to_account_id"]);
+        }
+
+        #[test]
+        fn self_transfers_by_id_are_rejected(id in object_id()) {
+            let fields =
+                violated_fields(Validator::new().different_accounts("to_account_id", &id, &id));
+            prop_assert_eq!(fields, ["to_account_id"]);
+        }
+
+        #[test]
+        fn transfers_between_accounts_are_accepted(from in account_number(), to in account_number()) {
+            prop_assume!(normalize(&from) != normalize(&to));
+            let fields =
+                violated_fields(Validator::new().different_accounts("to_account_id", &from, &to));
+            prop_assert!(fields.is_empty());
+        }
+    }
+}
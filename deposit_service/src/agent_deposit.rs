@@ -0,0 +1,85 @@
+use tonic::Status;
+
+use mongodb::bson::{doc, Document};
+
+use crate::deposit_service::deposit::MakeDepositRequest;
+use crate::deposit_service::MyDepositService;
+use bank_common::approval::{ApprovalService, NewOperation, ADMIN_ROLE};
+use bank_common::validation;
+
+// Deposits made by bank agents skip customer limits, fees and the balance
+// check, so they are kept as pending operations and only transferred once a
+// user other than the agent approves them.
+pub const AGENT_DEPOSIT: &str = "AGENT_DEPOSIT";
+
+impl MyDepositService {
+    pub async fn request_agent_deposit(
+        &self,
+        req: &MakeDepositRequest,
+    ) -> Result<Document, Status> {
+        if !req.beneficiary_id.is_empty() {
+            return Err(validation::invalid_field(
+                "beneficiary_id",
+                "Agent deposits must be made to an account, not a beneficiary",
+            ));
+        }
+        if req.user_id.is_empty() {
+            return Err(validation::invalid_field(
+                "user_id",
+                "The agent making the deposit is required",
+            ));
+        }
+
+        // Accounts are resolved now so approvers see exactly which accounts are used
+        let from_account_doc = self.get_account(&req.from_account_id).await?;
+        let to_account_doc = self.get_account(&req.to_account_id).await?;
+
+        self.request_operation(NewOperation {
+            kind: AGENT_DEPOSIT,
+            summary: format!(
+                "Agent deposit of {:.2} {} from account {} to account {}",
+                req.amount,
+                self.account_currency(&from_account_doc),
+                from_account_doc
+                    .get_str("account_number")
+                    .unwrap_or_default(),
+                to_account_doc.get_str("account_number").unwrap_or_default()
+            ),
+            payload: doc! {
+                "from_account_id": from_account_doc.get_object_id("_id").unwrap(),
+                "to_account_id": to_account_doc.get_object_id("_id").unwrap(),
+                "amount": req.amount,
+                "currency": &req.currency,
+                "fx_quote_id": &req.fx_quote_id,
+            },
+            requested_by: &req.user_id,
+            required_role: ADMIN_ROLE,
+        })
+        .await
+    }
+
+    pub async fn apply_agent_deposit(&self, operation: &Document) -> Result<Document, Status> {
+        let payload = operation.get_document("payload").unwrap();
+
+        let req = MakeDepositRequest {
+            from_account_id: payload.get_object_id("from_account_id").unwrap().to_hex(),
+            to_account_id: payload.get_object_id("to_account_id").unwrap().to_hex(),
+            amount: payload.get_f64("amount").unwrap(),
+            is_bank_agent: true,
+            beneficiary_id: String::new(),
+            user_id: operation.get_str("requested_by").unwrap().to_string(),
+            currency: payload.get_str("currency").unwrap_or_default().to_string(),
+            fx_quote_id: payload
+                .get_str("fx_quote_id")
+                .unwrap_or_default()
+                .to_string(),
+        };
+        let (fee, conversion) = self.transfer(&req).await?;
+
+        Ok(doc! {
+            "fee": fee.to_string(),
+            "converted_amount": conversion.converted_amount.to_string(),
+            "fx_rate": conversion.rate.to_string(),
+        })
+    }
+}
@@ -0,0 +1,456 @@
+use chrono::{Duration, SecondsFormat, TimeZone, Utc};
+use futures::stream::TryStreamExt;
+use log::{error, info, warn};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::FindOptions,
+    Collection,
+};
+
+use crate::deposit_service::deposit::{
+    AddBeneficiaryRequest, Beneficiary, BeneficiaryVerificationStatus, UpdateBeneficiaryRequest,
+};
+use crate::deposit_service::MyDepositService;
+
+// Newly added beneficiaries can only receive up to `cooling_off_limit` in
+// total until `cooling_off_period` has passed since they were added.
+#[derive(Debug, Clone)]
+pub struct BeneficiaryPolicy {
+    pub cooling_off_period: Duration,
+    pub cooling_off_limit: f64,
+}
+
+impl Display for BeneficiaryVerificationStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let status_str = match self {
+            BeneficiaryVerificationStatus::Unverified => "UNVERIFIED",
+            BeneficiaryVerificationStatus::Verified => "VERIFIED",
+            BeneficiaryVerificationStatus::NameMismatch => "NAME_MISMATCH",
+        };
+
+        write!(f, "{}", status_str)
+    }
+}
+
+impl FromStr for BeneficiaryVerificationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "UNVERIFIED" => Ok(BeneficiaryVerificationStatus::Unverified),
+            "VERIFIED" => Ok(BeneficiaryVerificationStatus::Verified),
+            "NAME_MISMATCH" => Ok(BeneficiaryVerificationStatus::NameMismatch),
+            _ => Err(format!("Invalid beneficiary verification status: {}", s)),
+        }
+    }
+}
+
+// Lowercases and strips punctuation and repeated whitespace so that
+// "J. Smith " and "j smith" are considered the same name.
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Confirmation of payee: the name given by the customer is checked against
+// the name held on the target account.
+fn verify_name(name: &str, account: &Document) -> BeneficiaryVerificationStatus {
+    match account.get_str("account_name") {
+        Ok(account_name) if !account_name.trim().is_empty() => {
+            if normalize_name(account_name) == normalize_name(name) {
+                BeneficiaryVerificationStatus::Verified
+            } else {
+                BeneficiaryVerificationStatus::NameMismatch
+            }
+        }
+        _ => BeneficiaryVerificationStatus::Unverified,
+    }
+}
+
+fn beneficiary_from_document(
+    beneficiary: &Document,
+    policy: &BeneficiaryPolicy,
+) -> Result<Beneficiary, String> {
+    let verification_status = BeneficiaryVerificationStatus::from_str(
+        beneficiary.get_str("verification_status").unwrap(),
+    )?;
+    let cooling_off_until = beneficiary.get_datetime("cooling_off_until").unwrap();
+
+    let cooling_off_remaining_limit =
+        if cooling_off_until.timestamp_millis() > Utc::now().timestamp_millis() {
+            (policy.cooling_off_limit - beneficiary.get_f64("cooling_off_transferred").unwrap())
+                .max(0.0)
+        } else {
+            0.0
+        };
+
+    Ok(Beneficiary {
+        beneficiary_id: beneficiary.get_object_id("_id").unwrap().to_hex(),
+        user_id: beneficiary.get_str("user_id").unwrap().to_string(),
+        name: beneficiary.get_str("name").unwrap().to_string(),
+        account_id: beneficiary.get_str("account_id").unwrap().to_string(),
+        nickname: beneficiary.get_str("nickname").unwrap().to_string(),
+        verification_status: verification_status as i32,
+        created_timestamp: beneficiary
+            .get_datetime("created_at")
+            .unwrap()
+            .timestamp_millis(),
+        cooling_off_until_timestamp: cooling_off_until.timestamp_millis(),
+        cooling_off_remaining_limit,
+        name_mismatch_confirmed: beneficiary
+            .get_bool("name_mismatch_confirmed")
+            .unwrap_or(false),
+    })
+}
+
+impl MyDepositService {
+    fn beneficiaries_collection(&self) -> Collection<Document> {
+        self.db.collection("beneficiaries")
+    }
+
+    async fn get_account_document(&self, account_id: &str) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let object_id = self.resolve_account_id(account_id).await?;
+
+        accounts_collection
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))
+    }
+
+    async fn get_beneficiary_document(
+        &self,
+        user_id: &str,
+        beneficiary_id: &str,
+    ) -> Result<Document, Status> {
+        let object_id = ObjectId::from_str(beneficiary_id)
+            .map_err(|_| Status::invalid_argument("Invalid beneficiary id"))?;
+
+        self.beneficiaries_collection()
+            .find_one(doc! { "_id": object_id, "user_id": user_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get beneficiary: {}", e)))?
+            .ok_or_else(|| Status::not_found("Beneficiary not found"))
+    }
+
+    pub async fn add_beneficiary_for_user(
+        &self,
+        req: AddBeneficiaryRequest,
+    ) -> Result<Beneficiary, Status> {
+        if req.name.trim().is_empty() {
+            return Err(Status::invalid_argument("Beneficiary name is required"));
+        }
+
+        let account = self.get_account_document(&req.account_id).await?;
+        let account_id = account.get_object_id("_id").unwrap().to_hex();
+        if account.get_str("user_id").unwrap() == req.user_id {
+            return Err(Status::invalid_argument(
+                "Your own accounts cannot be added as beneficiaries",
+            ));
+        }
+
+        let existing = self
+            .beneficiaries_collection()
+            .find_one(
+                doc! { "user_id": &req.user_id, "account_id": &account_id },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get beneficiary: {}", e)))?;
+        if existing.is_some() {
+            return Err(Status::already_exists(
+                "A beneficiary for this account already exists",
+            ));
+        }
+
+        let verification_status = verify_name(&req.name, &account);
+        if verification_status == BeneficiaryVerificationStatus::NameMismatch {
+            warn!(
+                "Beneficiary name for account {} does not match the account holder, payments are blocked until it is confirmed",
+                account_id
+            );
+        }
+
+        let now = Utc::now();
+        let cooling_off_until = now + self.beneficiary_policy.cooling_off_period;
+
+        let new_beneficiary = doc! {
+            "user_id": &req.user_id,
+            "name": req.name.trim(),
+            "account_id": &account_id,
+            "nickname": req.nickname.trim(),
+            "verification_status": verification_status.to_string(),
+            "created_at": DateTime::from_millis(now.timestamp_millis()),
+            "cooling_off_until": DateTime::from_millis(cooling_off_until.timestamp_millis()),
+            "cooling_off_transferred": 0.0,
+            "name_mismatch_confirmed": false,
+        };
+
+        let insert_result = self
+            .beneficiaries_collection()
+            .insert_one(new_beneficiary, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create beneficiary: {}", e)))?;
+        let beneficiary_id = insert_result
+            .inserted_id
+            .as_object_id()
+            .ok_or_else(|| Status::internal("Failed to create beneficiary: missing inserted_id"))?
+            .to_hex();
+
+        info!(
+            "Beneficiary {} added for user {} ({})",
+            beneficiary_id, req.user_id, verification_status
+        );
+
+        self.get_beneficiary_for_user(&req.user_id, &beneficiary_id)
+            .await
+    }
+
+    pub async fn list_beneficiaries_for_user(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<Beneficiary>, Status> {
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! {
+            "name": 1,
+        });
+
+        let mut cursor = self
+            .beneficiaries_collection()
+            .find(doc! { "user_id": user_id }, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get beneficiaries: {}", e)))?;
+
+        let mut beneficiaries = Vec::new();
+        while let Some(result) = cursor
+            .try_next()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get beneficiaries: {}", e)))?
+        {
+            beneficiaries.push(
+                beneficiary_from_document(&result, &self.beneficiary_policy)
+                    .map_err(Status::internal)?,
+            );
+        }
+
+        Ok(beneficiaries)
+    }
+
+    pub async fn get_beneficiary_for_user(
+        &self,
+        user_id: &str,
+        beneficiary_id: &str,
+    ) -> Result<Beneficiary, Status> {
+        let beneficiary = self
+            .get_beneficiary_document(user_id, beneficiary_id)
+            .await?;
+
+        beneficiary_from_document(&beneficiary, &self.beneficiary_policy).map_err(Status::internal)
+    }
+
+    // Only the name and nickname can be changed. Pointing a beneficiary at a
+    // different account would bypass the cooling-off period, so that requires
+    // deleting it and adding a new one.
+    pub async fn update_beneficiary_for_user(
+        &self,
+        req: UpdateBeneficiaryRequest,
+    ) -> Result<Beneficiary, Status> {
+        let beneficiary = self
+            .get_beneficiary_document(&req.user_id, &req.beneficiary_id)
+            .await?;
+
+        let mut update = doc! {};
+        if !req.nickname.trim().is_empty() {
+            update.insert("nickname", req.nickname.trim());
+        }
+        if !req.name.trim().is_empty() {
+            let account = self
+                .get_account_document(beneficiary.get_str("account_id").unwrap())
+                .await?;
+            update.insert("name", req.name.trim());
+            update.insert(
+                "verification_status",
+                verify_name(&req.name, &account).to_string(),
+            );
+            // A new name has to be confirmed again if it does not match either
+            update.insert("name_mismatch_confirmed", false);
+        }
+
+        if !update.is_empty() {
+            self.beneficiaries_collection()
+                .update_one(
+                    doc! { "_id": beneficiary.get_object_id("_id").unwrap() },
+                    doc! { "$set": update },
+                    None,
+                )
+                .await
+                .map_err(|e| Status::internal(format!("Failed to update beneficiary: {}", e)))?;
+
+            info!("Beneficiary {} updated", req.beneficiary_id);
+        }
+
+        self.get_beneficiary_for_user(&req.user_id, &req.beneficiary_id)
+            .await
+    }
+
+    // The customer accepts paying a beneficiary whose name does not match the
+    // account holder, which is otherwise refused
+    pub async fn confirm_beneficiary_for_user(
+        &self,
+        user_id: &str,
+        beneficiary_id: &str,
+    ) -> Result<Beneficiary, Status> {
+        let beneficiary = self
+            .get_beneficiary_document(user_id, beneficiary_id)
+            .await?;
+        if beneficiary.get_str("verification_status").unwrap()
+            != BeneficiaryVerificationStatus::NameMismatch.to_string()
+        {
+            return Err(Status::failed_precondition(
+                "Only beneficiaries whose name does not match the account holder need to be confirmed",
+            ));
+        }
+
+        self.beneficiaries_collection()
+            .update_one(
+                doc! { "_id": beneficiary.get_object_id("_id").unwrap() },
+                doc! { "$set": { "name_mismatch_confirmed": true } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update beneficiary: {}", e)))?;
+
+        info!(
+            "Beneficiary {} confirmed by user {} despite the name mismatch",
+            beneficiary_id, user_id
+        );
+
+        self.get_beneficiary_for_user(user_id, beneficiary_id).await
+    }
+
+    pub async fn delete_beneficiary_for_user(
+        &self,
+        user_id: &str,
+        beneficiary_id: &str,
+    ) -> Result<(), Status> {
+        let object_id = ObjectId::from_str(beneficiary_id)
+            .map_err(|_| Status::invalid_argument("Invalid beneficiary id"))?;
+
+        let delete_result = self
+            .beneficiaries_collection()
+            .delete_one(doc! { "_id": object_id, "user_id": user_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to delete beneficiary: {}", e)))?;
+        if delete_result.deleted_count == 0 {
+            return Err(Status::not_found("Beneficiary not found"));
+        }
+
+        info!("Beneficiary {} deleted", beneficiary_id);
+
+        Ok(())
+    }
+
+    // Resolves the target account of a transfer by beneficiary. Beneficiaries
+    // whose name does not match the account holder are refused until the
+    // customer confirms them. While the beneficiary is in its cooling-off
+    // period the amount is reserved against the cooling-off limit; the
+    // reservation is a conditional update so concurrent transfers cannot
+    // exceed the limit together.
+    pub async fn reserve_beneficiary_transfer(
+        &self,
+        user_id: &str,
+        beneficiary_id: &str,
+        amount: f64,
+    ) -> Result<(String, bool), Status> {
+        let beneficiary = self
+            .get_beneficiary_document(user_id, beneficiary_id)
+            .await?;
+        let account_id = beneficiary.get_str("account_id").unwrap().to_string();
+
+        if beneficiary.get_str("verification_status").unwrap()
+            == BeneficiaryVerificationStatus::NameMismatch.to_string()
+            && !beneficiary
+                .get_bool("name_mismatch_confirmed")
+                .unwrap_or(false)
+        {
+            error!(
+                "Transfer to unconfirmed beneficiary {} with a name mismatch refused",
+                beneficiary_id
+            );
+            return Err(Status::failed_precondition(
+                "The beneficiary name does not match the account holder, confirm the beneficiary before paying it",
+            ));
+        }
+
+        let cooling_off_until = beneficiary.get_datetime("cooling_off_until").unwrap();
+        if cooling_off_until.timestamp_millis() <= Utc::now().timestamp_millis() {
+            return Ok((account_id, false));
+        }
+
+        let filter = doc! {
+            "_id": beneficiary.get_object_id("_id").unwrap(),
+            "cooling_off_transferred": {
+                "$lte": self.beneficiary_policy.cooling_off_limit - amount,
+            },
+        };
+        let update = doc! {
+            "$inc": {
+                "cooling_off_transferred": amount,
+            }
+        };
+
+        let update_result = self
+            .beneficiaries_collection()
+            .update_one(filter, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update beneficiary: {}", e)))?;
+        if update_result.modified_count == 0 {
+            error!(
+                "Transfer of {} to beneficiary {} exceeds the cooling-off limit",
+                amount, beneficiary_id
+            );
+            return Err(Status::failed_precondition(format!(
+                "Newly added beneficiaries can receive at most {:.2} until {}",
+                self.beneficiary_policy.cooling_off_limit,
+                Utc.timestamp_millis_opt(cooling_off_until.timestamp_millis())
+                    .unwrap()
+                    .to_rfc3339_opts(SecondsFormat::Secs, true)
+            )));
+        }
+
+        Ok((account_id, true))
+    }
+
+    pub async fn release_beneficiary_transfer(
+        &self,
+        beneficiary_id: &str,
+        amount: f64,
+    ) -> Result<(), Status> {
+        let object_id = ObjectId::from_str(beneficiary_id)
+            .map_err(|_| Status::invalid_argument("Invalid beneficiary id"))?;
+
+        self.beneficiaries_collection()
+            .update_one(
+                doc! { "_id": object_id },
+                doc! { "$inc": { "cooling_off_transferred": -amount } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update beneficiary: {}", e)))?;
+
+        Ok(())
+    }
+}
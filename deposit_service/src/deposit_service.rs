@@ -1,5 +1,5 @@
 use log::{error, info};
-use std::{str::FromStr, sync::Arc};
+use std::{collections::HashMap, sync::Arc};
 use tonic::{Request, Response, Status};
 
 use mongodb::{
@@ -12,135 +12,253 @@ pub mod deposit {
     tonic::include_proto!("deposit");
 }
 
+use crate::agent_deposit::AGENT_DEPOSIT;
+use crate::approval;
+use crate::beneficiary::BeneficiaryPolicy;
+use crate::fx::{Conversion, FxConfig};
+use crate::pain002;
+use crate::saga::{self, SagaConfig, SagaDefinition};
+use crate::transfer_saga::{self, TRANSFER_SAGA};
+use bank_common::account_number;
+use bank_common::approval::{ApprovalConfig, ApprovalService};
+use bank_common::fee_engine::{self, FeeQuote, OPERATION_TRANSFER};
+use bank_common::health::Dependency;
+use bank_common::limits::{self, LimitStatus, Limits};
+use bank_common::outbox::{OutboxConfig, OutboxService};
+use bank_common::overdraft;
+use bank_common::validation::{self, ValidationPolicy, Validator};
+
 use deposit::deposit_service_server::DepositService;
 use deposit::{
-    CheckAccountBalanceRequest, CheckAccountBalanceResponse, MakeDepositRequest,
-    MakeDepositResponse,
+    AddBeneficiaryRequest, AddBeneficiaryResponse, CancelStandingOrderRequest,
+    CancelStandingOrderResponse, CheckAccountBalanceRequest, CheckAccountBalanceResponse,
+    ConfirmBeneficiaryRequest, ConfirmBeneficiaryResponse, CreateStandingOrderRequest,
+    CreateStandingOrderResponse, DeleteBeneficiaryRequest, DeleteBeneficiaryResponse,
+    GetBeneficiaryRequest, GetBeneficiaryResponse, GetPaymentBatchRequest, GetPaymentBatchResponse,
+    GetPaymentStatusReportRequest, GetPaymentStatusReportResponse, GetTransferLimitsRequest,
+    GetTransferLimitsResponse, ListBeneficiariesRequest, ListBeneficiariesResponse,
+    ListPendingOperationsRequest, ListPendingOperationsResponse, ListStandingOrdersRequest,
+    ListStandingOrdersResponse, MakeDepositRequest, MakeDepositResponse, PreviewTransferFeeRequest,
+    PreviewTransferFeeResponse, QuoteTransferRequest, QuoteTransferResponse, ReverseFeeRequest,
+    ReverseFeeResponse, ReverseTransactionRequest, ReverseTransactionResponse,
+    ReviewOperationRequest, ReviewOperationResponse, SetTransferLimitsRequest,
+    SetTransferLimitsResponse, SubmitPaymentBatchRequest, SubmitPaymentBatchResponse,
+    TransactionLimits, UpdateBeneficiaryRequest, UpdateBeneficiaryResponse,
 };
 
+fn transaction_limits(status: &LimitStatus) -> TransactionLimits {
+    TransactionLimits {
+        channel: status.channel.clone(),
+        per_transaction_limit: status.effective.per_transaction.unwrap_or_default(),
+        daily_limit: status.effective.daily.unwrap_or_default(),
+        monthly_limit: status.effective.monthly.unwrap_or_default(),
+        daily_used: status.usage.daily,
+        monthly_used: status.usage.monthly,
+        daily_remaining: status.daily_remaining().unwrap_or_default(),
+        monthly_remaining: status.monthly_remaining().unwrap_or_default(),
+        max_per_transaction_limit: status.maximum.per_transaction.unwrap_or_default(),
+        max_daily_limit: status.maximum.daily.unwrap_or_default(),
+        max_monthly_limit: status.maximum.monthly.unwrap_or_default(),
+    }
+}
+
+fn is_closed(account: &Document) -> bool {
+    account.get_str("status") == Ok("CLOSED")
+}
+
 #[derive(Debug, Clone)]
 pub struct MyDepositService {
-    db: Arc<mongodb::Database>,
+    pub(crate) client: Client,
+    pub(crate) db: Arc<mongodb::Database>,
+    pub(crate) beneficiary_policy: BeneficiaryPolicy,
+    pub(crate) fee_income_account_id: Option<ObjectId>,
+    pub(crate) validation_policy: ValidationPolicy,
+    pub(crate) fx_config: FxConfig,
+    pub(crate) approval_config: ApprovalConfig,
+    pub(crate) outbox_config: OutboxConfig,
+    pub(crate) saga_config: SagaConfig,
+    // The sagas this service runs and resumes, by type
+    pub(crate) saga_definitions: HashMap<&'static str, SagaDefinition>,
 }
 
 impl MyDepositService {
-    pub async fn new(uri: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        uri: &str,
+        beneficiary_policy: BeneficiaryPolicy,
+        fee_income_account_id: Option<ObjectId>,
+        validation_policy: ValidationPolicy,
+        fx_config: FxConfig,
+        approval_config: ApprovalConfig,
+        outbox_config: OutboxConfig,
+        saga_config: SagaConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let client_options = ClientOptions::parse(uri).await?;
         let client = Client::with_options(client_options)?;
         let db = client.database("bank");
-        Ok(Self { db: Arc::new(db) })
+        Ok(Self {
+            client,
+            db: Arc::new(db),
+            beneficiary_policy,
+            fee_income_account_id,
+            validation_policy,
+            fx_config,
+            approval_config,
+            outbox_config,
+            saga_definitions: saga::definitions(&saga_config.policy),
+            saga_config,
+        })
     }
 
     pub async fn test_connection(&self) -> Result<(), mongodb::error::Error> {
         let _ = self.db.run_command(doc! { "ping": 1 }, None).await?;
         Ok(())
     }
-}
 
-#[tonic::async_trait]
-impl DepositService for MyDepositService {
-    async fn make_deposit(
+    // Accounts can be referenced either by their id or by their IBAN-style
+    // account number
+    pub async fn resolve_account_id(&self, account: &str) -> Result<ObjectId, Status> {
+        account_number::resolve_account_id(&self.db, account).await
+    }
+
+    pub async fn get_account(&self, account: &str) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let object_id = self.resolve_account_id(account).await?;
+
+        accounts_collection
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))
+    }
+
+    pub async fn check_fee_income_account(&self) -> Result<(), Status> {
+        match self.fee_income_account_id {
+            Some(fee_income_account_id) => {
+                fee_engine::check_fee_income_account(&self.db, fee_income_account_id).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Fees are only charged when a fee-income account is configured. Transfers
+    // made by bank agents are never charged.
+    pub async fn quote_transfer_fee(
         &self,
-        request: Request<MakeDepositRequest>,
-    ) -> Result<Response<MakeDepositResponse>, Status> {
-        let req = request.into_inner();
+        from_account_doc: &Document,
+        amount: f64,
+        is_bank_agent: bool,
+    ) -> Result<FeeQuote, Status> {
+        if self.fee_income_account_id.is_none() || is_bank_agent {
+            return Ok(FeeQuote::default());
+        }
+
+        fee_engine::quote_fee(&self.db, from_account_doc, OPERATION_TRANSFER, amount).await
+    }
+
+    // Moves `amount` between two accounts and records both legs of the
+    // transfer, charging the transfer fee on top. Shared by single deposits
+    // and bulk payment batches. Transfers between accounts held in different
+    // currencies are converted, and the fee and conversion used are returned.
+    pub async fn transfer(&self, req: &MakeDepositRequest) -> Result<(f64, Conversion), Status> {
         let accounts_collection: Collection<Document> = self.db.collection("accounts");
 
-        let from_account_id = ObjectId::from_str(&req.from_account_id)
-            .map_err(|_| Status::invalid_argument("Invalid from account id"))?;
-        let to_account_id = ObjectId::from_str(&req.to_account_id)
-            .map_err(|_| Status::invalid_argument("Invalid to account id"))?;
+        let from_account_id = self.resolve_account_id(&req.from_account_id).await?;
+        let to_account_id = self.resolve_account_id(&req.to_account_id).await?;
+
+        // Catches an id and an account number, or a beneficiary, naming the same account
+        if from_account_id == to_account_id {
+            return Err(validation::invalid_field(
+                "to_account_id",
+                "Cannot transfer to the same account",
+            ));
+        }
 
         let from_filter = doc! {
-            "_id": from_account_id.clone()
+            "_id": from_account_id
         };
         let to_filter = doc! {
-            "_id": to_account_id.clone()
+            "_id": to_account_id
         };
 
         let from_account_doc_option = accounts_collection
-            .find_one(from_filter.clone(), None)
+            .find_one(from_filter, None)
             .await
             .map_err(|e| Status::internal(format!("Failed to get from account: {}", e)))?;
 
         let to_account_doc_option = accounts_collection
-            .find_one(to_filter.clone(), None)
+            .find_one(to_filter, None)
             .await
             .map_err(|e| Status::internal(format!("Failed to get to account: {}", e)))?;
 
         if let (Some(from_account_doc), Some(to_account_doc)) =
             (from_account_doc_option, to_account_doc_option)
         {
-            if req.is_bank_agent || from_account_doc.get_f64("balance").unwrap() >= req.amount {
-                let new_from_balance = from_account_doc.get_f64("balance").unwrap() - req.amount;
-                let new_to_balance = to_account_doc.get_f64("balance").unwrap() + req.amount;
+            if is_closed(&from_account_doc) || is_closed(&to_account_doc) {
+                return Err(Status::failed_precondition("Account is closed"));
+            }
 
-                let from_update = doc! {
-                    "$set": {
-                        "balance": new_from_balance,
-                    }
-                };
-                let to_update = doc! {
-                    "$set": {
-                        "balance": new_to_balance,
-                    }
-                };
-
-                // Update the account balances
-                accounts_collection
-                    .update_one(from_filter, from_update, None)
-                    .await
-                    .map_err(|e| {
-                        Status::internal(format!("Failed to update from account balance: {}", e))
-                    })?;
-                accounts_collection
-                    .update_one(to_filter, to_update, None)
-                    .await
-                    .map_err(|e| {
-                        Status::internal(format!("Failedto update to account balance: {}", e))
-                    })?;
-                // Record the transaction
-
-                let new_transaction_deposit = doc! {
-                    "from_account_id": from_account_id,
-                    "to_account_id": to_account_id,
-                    "amount": req.amount,
-                    "type": "Deposit",
-                    "account_id": to_account_id
-                };
-
-                let new_transaction_withdrawal = doc! {
-                    "from_account_id": from_account_id,
-                    "to_account_id": to_account_id,
-                    "amount": -req.amount,
-                    "type": "Withdrawal",
-                    "account_id": to_account_id
-                };
-
-                let transactions_collection: Collection<Document> =
-                    self.db.collection("transactions");
-                let _insert_result = transactions_collection
-                    .insert_one(new_transaction_deposit, None)
-                    .await
-                    .map_err(|e| {
-                        Status::internal(format!("Failed to create transaction: {}", e))
-                    })?;
-
-                let _insert_result = transactions_collection
-                    .insert_one(new_transaction_withdrawal, None)
-                    .await
-                    .map_err(|e| {
-                        Status::internal(format!("Failed to create transaction: {}", e))
-                    })?;
-
-                info!(
-                    "Deposit of {} made from account {} to account {}",
-                    req.amount, req.from_account_id, req.to_account_id
-                );
+            // Transfers made by bank agents are not subject to customer limits
+            let channel = limits::ONLINE_CHANNEL;
+            if !req.is_bank_agent {
+                let limit_status = limits::limit_status(
+                    &self.db,
+                    &from_account_doc,
+                    limits::OPERATION_TRANSFER,
+                    channel,
+                )
+                .await?;
+                limit_status.check(req.amount).map_err(|reason| {
+                    error!("{}", reason);
+                    Status::failed_precondition(reason)
+                })?;
+            }
+
+            // Amounts, fees and limits are always in the currency of the debited account
+            if !req.currency.is_empty()
+                && !req
+                    .currency
+                    .eq_ignore_ascii_case(self.account_currency(&from_account_doc))
+            {
+                return Err(validation::invalid_field(
+                    "currency",
+                    &format!(
+                        "Transfers are made in the currency of the debited account ({})",
+                        self.account_currency(&from_account_doc)
+                    ),
+                ));
+            }
+
+            let fee_quote = self
+                .quote_transfer_fee(&from_account_doc, req.amount, req.is_bank_agent)
+                .await?;
+            let conversion = self
+                .conversion(
+                    &from_account_doc,
+                    &to_account_doc,
+                    req.amount,
+                    &req.fx_quote_id,
+                )
+                .await?;
 
-                let response = MakeDepositResponse { success: true };
+            if req.is_bank_agent
+                || overdraft::available_funds(&from_account_doc) >= req.amount + fee_quote.fee
+            {
+                // The transfer is booked by a saga, which rolls back the steps
+                // already taken when a later one fails
+                let payload = transfer_saga::transfer_payload(
+                    req,
+                    from_account_id,
+                    to_account_id,
+                    channel,
+                    from_account_doc.get_f64("balance").unwrap(),
+                    &fee_quote,
+                    &conversion,
+                );
+                self.run_saga(TRANSFER_SAGA, payload).await?;
 
-                Ok(Response::new(response))
+                Ok((fee_quote.fee, conversion))
             } else {
                 error!("Insufficient balance or not a bank agent for deposit");
                 Err(Status::failed_precondition(
@@ -152,6 +270,110 @@ impl DepositService for MyDepositService {
             Err(Status::not_found("Account not found"))
         }
     }
+}
+
+#[tonic::async_trait]
+impl ApprovalService for MyDepositService {
+    const SERVICE: &'static str = "deposit";
+
+    fn approval_db(&self) -> &mongodb::Database {
+        &self.db
+    }
+
+    fn approval_config(&self) -> &ApprovalConfig {
+        &self.approval_config
+    }
+
+    async fn execute_operation(&self, operation: &Document) -> Result<Document, Status> {
+        match operation.get_str("kind").unwrap() {
+            AGENT_DEPOSIT => self.apply_agent_deposit(operation).await,
+            kind => Err(Status::internal(format!(
+                "Unknown operation kind: {}",
+                kind
+            ))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Dependency for MyDepositService {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.test_connection()
+            .await
+            .map_err(|e| format!("Failed to ping MongoDB: {}", e))
+    }
+}
+
+#[tonic::async_trait]
+impl DepositService for MyDepositService {
+    async fn make_deposit(
+        &self,
+        request: Request<MakeDepositRequest>,
+    ) -> Result<Response<MakeDepositResponse>, Status> {
+        let mut req = request.into_inner();
+
+        let mut validator = Validator::new();
+        validator
+            .account("from_account_id", &req.from_account_id)
+            .amount("amount", req.amount)
+            .currency("currency", &req.currency, &self.validation_policy);
+        if req.beneficiary_id.is_empty() {
+            validator
+                .account("to_account_id", &req.to_account_id)
+                .different_accounts("to_account_id", &req.from_account_id, &req.to_account_id);
+        } else if !req.to_account_id.is_empty() {
+            validator.violation("to_account_id", "Cannot be combined with beneficiary_id");
+        }
+        if let Some(status) = validator.error() {
+            return Err(status);
+        }
+
+        if req.is_bank_agent {
+            let operation = self.request_agent_deposit(&req).await?;
+
+            let response = MakeDepositResponse {
+                success: false,
+                fee: 0.0,
+                converted_amount: 0.0,
+                fx_rate: 0.0,
+                operation: Some(approval::operation_to_proto(&operation)),
+            };
+            return Ok(Response::new(response));
+        }
+
+        let (fee, conversion) = if req.beneficiary_id.is_empty() {
+            self.transfer(&req).await?
+        } else {
+            let (to_account_id, reserved) = self
+                .reserve_beneficiary_transfer(&req.user_id, &req.beneficiary_id, req.amount)
+                .await?;
+            req.to_account_id = to_account_id;
+
+            match self.transfer(&req).await {
+                Ok(transfer) => transfer,
+                Err(status) => {
+                    if reserved {
+                        self.release_beneficiary_transfer(&req.beneficiary_id, req.amount)
+                            .await?;
+                    }
+                    return Err(status);
+                }
+            }
+        };
+
+        let response = MakeDepositResponse {
+            success: true,
+            fee,
+            converted_amount: conversion.converted_amount,
+            fx_rate: conversion.rate,
+            operation: None,
+        };
+        Ok(Response::new(response))
+    }
 
     async fn check_account_balance(
         &self,
@@ -160,10 +382,7 @@ impl DepositService for MyDepositService {
         let req = request.into_inner();
         let accounts_collection: Collection<Document> = self.db.collection("accounts");
 
-        let object_id = match ObjectId::from_str(&req.account_id) {
-            Ok(oid) => oid,
-            Err(_) => return Err(Status::invalid_argument("Invalid account id")),
-        };
+        let object_id = self.resolve_account_id(&req.account_id).await?;
 
         let filter = doc! {
             "_id": object_id,
@@ -190,4 +409,410 @@ impl DepositService for MyDepositService {
             Err(Status::not_found("Account not found"))
         }
     }
+
+    async fn submit_payment_batch(
+        &self,
+        request: Request<SubmitPaymentBatchRequest>,
+    ) -> Result<Response<SubmitPaymentBatchResponse>, Status> {
+        let req = request.into_inner();
+
+        let batch = self.submit_batch(req).await?;
+
+        let response = SubmitPaymentBatchResponse { batch: Some(batch) };
+        Ok(Response::new(response))
+    }
+
+    async fn get_payment_batch(
+        &self,
+        request: Request<GetPaymentBatchRequest>,
+    ) -> Result<Response<GetPaymentBatchResponse>, Status> {
+        let req = request.into_inner();
+
+        let batch = self
+            .get_batch_requested_by(&req.batch_id, &req.requested_by, &req.requester_roles)
+            .await?;
+
+        let response = GetPaymentBatchResponse { batch: Some(batch) };
+        Ok(Response::new(response))
+    }
+
+    async fn get_payment_status_report(
+        &self,
+        request: Request<GetPaymentStatusReportRequest>,
+    ) -> Result<Response<GetPaymentStatusReportResponse>, Status> {
+        let req = request.into_inner();
+
+        let batch = self
+            .get_batch_requested_by(&req.batch_id, &req.requested_by, &req.requester_roles)
+            .await?;
+        let message_id = format!("PAIN002{}", ObjectId::new().to_hex());
+        let document = pain002::render(&message_id, &batch);
+
+        info!(
+            "Generated payment status report {} for batch {}",
+            message_id, req.batch_id
+        );
+
+        let response = GetPaymentStatusReportResponse {
+            message_id,
+            document,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn create_standing_order(
+        &self,
+        request: Request<CreateStandingOrderRequest>,
+    ) -> Result<Response<CreateStandingOrderResponse>, Status> {
+        let req = request.into_inner();
+
+        let standing_order = self.create_standing_order_for_user(req).await?;
+
+        let response = CreateStandingOrderResponse {
+            standing_order: Some(standing_order),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_standing_orders(
+        &self,
+        request: Request<ListStandingOrdersRequest>,
+    ) -> Result<Response<ListStandingOrdersResponse>, Status> {
+        let req = request.into_inner();
+
+        let standing_orders = self
+            .list_standing_orders_for_user(&req.user_id, &req.account_id)
+            .await?;
+
+        let response = ListStandingOrdersResponse { standing_orders };
+        Ok(Response::new(response))
+    }
+
+    async fn cancel_standing_order(
+        &self,
+        request: Request<CancelStandingOrderRequest>,
+    ) -> Result<Response<CancelStandingOrderResponse>, Status> {
+        let req = request.into_inner();
+
+        let standing_order = self
+            .cancel_standing_order_for_user(&req.user_id, &req.standing_order_id)
+            .await?;
+
+        let response = CancelStandingOrderResponse {
+            standing_order: Some(standing_order),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn add_beneficiary(
+        &self,
+        request: Request<AddBeneficiaryRequest>,
+    ) -> Result<Response<AddBeneficiaryResponse>, Status> {
+        let req = request.into_inner();
+
+        let beneficiary = self.add_beneficiary_for_user(req).await?;
+
+        let response = AddBeneficiaryResponse {
+            beneficiary: Some(beneficiary),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_beneficiaries(
+        &self,
+        request: Request<ListBeneficiariesRequest>,
+    ) -> Result<Response<ListBeneficiariesResponse>, Status> {
+        let req = request.into_inner();
+
+        let beneficiaries = self.list_beneficiaries_for_user(&req.user_id).await?;
+
+        let response = ListBeneficiariesResponse { beneficiaries };
+        Ok(Response::new(response))
+    }
+
+    async fn get_beneficiary(
+        &self,
+        request: Request<GetBeneficiaryRequest>,
+    ) -> Result<Response<GetBeneficiaryResponse>, Status> {
+        let req = request.into_inner();
+
+        let beneficiary = self
+            .get_beneficiary_for_user(&req.user_id, &req.beneficiary_id)
+            .await?;
+
+        let response = GetBeneficiaryResponse {
+            beneficiary: Some(beneficiary),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn update_beneficiary(
+        &self,
+        request: Request<UpdateBeneficiaryRequest>,
+    ) -> Result<Response<UpdateBeneficiaryResponse>, Status> {
+        let req = request.into_inner();
+
+        let beneficiary = self.update_beneficiary_for_user(req).await?;
+
+        let response = UpdateBeneficiaryResponse {
+            beneficiary: Some(beneficiary),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn delete_beneficiary(
+        &self,
+        request: Request<DeleteBeneficiaryRequest>,
+    ) -> Result<Response<DeleteBeneficiaryResponse>, Status> {
+        let req = request.into_inner();
+
+        self.delete_beneficiary_for_user(&req.user_id, &req.beneficiary_id)
+            .await?;
+
+        let response = DeleteBeneficiaryResponse { success: true };
+        Ok(Response::new(response))
+    }
+
+    async fn confirm_beneficiary(
+        &self,
+        request: Request<ConfirmBeneficiaryRequest>,
+    ) -> Result<Response<ConfirmBeneficiaryResponse>, Status> {
+        let req = request.into_inner();
+
+        let beneficiary = self
+            .confirm_beneficiary_for_user(&req.user_id, &req.beneficiary_id)
+            .await?;
+
+        let response = ConfirmBeneficiaryResponse {
+            beneficiary: Some(beneficiary),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn preview_transfer_fee(
+        &self,
+        request: Request<PreviewTransferFeeRequest>,
+    ) -> Result<Response<PreviewTransferFeeResponse>, Status> {
+        let req = request.into_inner();
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        if let Some(status) = Validator::new()
+            .account("from_account_id", &req.from_account_id)
+            .amount("amount", req.amount)
+            .error()
+        {
+            return Err(status);
+        }
+
+        let object_id = self.resolve_account_id(&req.from_account_id).await?;
+
+        let account_doc = accounts_collection
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        let quote = self
+            .quote_transfer_fee(&account_doc, req.amount, req.is_bank_agent)
+            .await?;
+
+        let response = PreviewTransferFeeResponse {
+            fee: quote.fee,
+            rule_name: quote.rule_name,
+            free_operations_remaining: quote.free_operations_remaining,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn get_transfer_limits(
+        &self,
+        request: Request<GetTransferLimitsRequest>,
+    ) -> Result<Response<GetTransferLimitsResponse>, Status> {
+        let req = request.into_inner();
+
+        let account_doc = self.get_account(&req.account_id).await?;
+
+        // Without a channel the limits of every channel are returned
+        let channels = if req.channel.is_empty() {
+            limits::CHANNELS
+                .iter()
+                .map(|channel| channel.to_string())
+                .collect()
+        } else {
+            vec![limits::normalize_channel(&req.channel).map_err(Status::invalid_argument)?]
+        };
+
+        let mut transfer_limits = Vec::new();
+        for channel in channels {
+            let limit_status =
+                limits::limit_status(&self.db, &account_doc, limits::OPERATION_TRANSFER, &channel)
+                    .await?;
+            transfer_limits.push(transaction_limits(&limit_status));
+        }
+
+        let response = GetTransferLimitsResponse {
+            limits: transfer_limits,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn set_transfer_limits(
+        &self,
+        request: Request<SetTransferLimitsRequest>,
+    ) -> Result<Response<SetTransferLimitsResponse>, Status> {
+        let req = request.into_inner();
+
+        let account_doc = self.get_account(&req.account_id).await?;
+        let channel = limits::normalize_channel(&req.channel).map_err(Status::invalid_argument)?;
+
+        limits::set_user_limits(
+            &self.db,
+            &req.user_id,
+            &account_doc,
+            limits::OPERATION_TRANSFER,
+            &channel,
+            Limits::new(
+                req.per_transaction_limit,
+                req.daily_limit,
+                req.monthly_limit,
+            ),
+        )
+        .await?;
+
+        info!(
+            "Transfer limits for {} on account {} updated by user {}",
+            channel, req.account_id, req.user_id
+        );
+
+        let limit_status =
+            limits::limit_status(&self.db, &account_doc, limits::OPERATION_TRANSFER, &channel)
+                .await?;
+
+        let response = SetTransferLimitsResponse {
+            limits: Some(transaction_limits(&limit_status)),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn reverse_fee(
+        &self,
+        request: Request<ReverseFeeRequest>,
+    ) -> Result<Response<ReverseFeeResponse>, Status> {
+        let req = request.into_inner();
+
+        let mut validator = Validator::new();
+        if req.reversed_by.is_empty() {
+            validator.violation("reversed_by", "The agent reversing the fee is required");
+        }
+        if let Some(status) = validator.error() {
+            return Err(status);
+        }
+
+        // The refund and its ledger entries are written atomically
+        let mut session = self.start_transaction().await?;
+        let fee = fee_engine::reverse_fee(
+            &self.db,
+            &mut session,
+            &req.fee_id,
+            &req.reason,
+            &req.reversed_by,
+        )
+        .await?;
+        self.commit_transaction(&mut session).await?;
+
+        let response = ReverseFeeResponse {
+            fee_id: req.fee_id,
+            amount: fee.get_f64("amount").unwrap(),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn reverse_transaction(
+        &self,
+        request: Request<ReverseTransactionRequest>,
+    ) -> Result<Response<ReverseTransactionResponse>, Status> {
+        let req = request.into_inner();
+
+        let reversal = self.reverse_transaction(&req).await?;
+
+        let response = ReverseTransactionResponse {
+            reversal_id: reversal.reversal_id.to_hex(),
+            reversal_transaction_ids: reversal
+                .reversal_transaction_ids
+                .iter()
+                .map(|id| id.to_hex())
+                .collect(),
+            amount: reversal.amount,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn quote_transfer(
+        &self,
+        request: Request<QuoteTransferRequest>,
+    ) -> Result<Response<QuoteTransferResponse>, Status> {
+        let req = request.into_inner();
+
+        if let Some(status) = Validator::new()
+            .account("from_account_id", &req.from_account_id)
+            .account("to_account_id", &req.to_account_id)
+            .different_accounts("to_account_id", &req.from_account_id, &req.to_account_id)
+            .amount("amount", req.amount)
+            .error()
+        {
+            return Err(status);
+        }
+
+        let quote = self.quote_transfer(&req).await?;
+
+        let response = QuoteTransferResponse { quote: Some(quote) };
+        Ok(Response::new(response))
+    }
+
+    async fn list_pending_operations(
+        &self,
+        request: Request<ListPendingOperationsRequest>,
+    ) -> Result<Response<ListPendingOperationsResponse>, Status> {
+        let req = request.into_inner();
+
+        let operations = self.list_operations(&req.status, &req.kind).await?;
+
+        let response = ListPendingOperationsResponse {
+            operations: operations
+                .iter()
+                .map(approval::operation_to_proto)
+                .collect(),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn approve_operation(
+        &self,
+        request: Request<ReviewOperationRequest>,
+    ) -> Result<Response<ReviewOperationResponse>, Status> {
+        let req = request.into_inner();
+
+        let operation = self.review_operation(&approval::review(&req), true).await?;
+
+        let response = ReviewOperationResponse {
+            operation: Some(approval::operation_to_proto(&operation)),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn reject_operation(
+        &self,
+        request: Request<ReviewOperationRequest>,
+    ) -> Result<Response<ReviewOperationResponse>, Status> {
+        let req = request.into_inner();
+
+        let operation = self
+            .review_operation(&approval::review(&req), false)
+            .await?;
+
+        let response = ReviewOperationResponse {
+            operation: Some(approval::operation_to_proto(&operation)),
+        };
+        Ok(Response::new(response))
+    }
 }
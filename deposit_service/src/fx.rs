@@ -0,0 +1,430 @@
+use chrono::{Duration, Utc};
+use log::info;
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    ClientSession, Collection,
+};
+
+use crate::deposit_service::deposit::{FxQuote, QuoteTransferRequest};
+use crate::deposit_service::MyDepositService;
+use bank_common::validation;
+
+// Foreign exchange for transfers between accounts held in different
+// currencies. Providers give mid rates, the bank's spread is taken off them,
+// and quotes are honoured by transfers until they expire.
+const QUOTE_OPEN: &str = "OPEN";
+const QUOTE_USED: &str = "USED";
+
+pub trait FxRateProvider: Debug + Send + Sync {
+    fn mid_rate(&self, from_currency: &str, to_currency: &str) -> Result<f64, String>;
+}
+
+// Fixed rates read from a `from_currency,to_currency,rate` CSV file, for
+// offline use. Inverse pairs are derived, and crosses go through the base
+// currency so the same pair always gets the same rate.
+#[derive(Debug, Default)]
+pub struct StaticRateProvider {
+    base_currency: String,
+    rates: HashMap<(String, String), f64>,
+}
+
+impl StaticRateProvider {
+    pub fn from_csv(contents: &str, base_currency: &str) -> Result<StaticRateProvider, String> {
+        let mut reader = csv::Reader::from_reader(contents.as_bytes());
+        let mut rates = HashMap::new();
+
+        for record in reader.records() {
+            let record = record.map_err(|e| format!("Invalid FX rate file: {}", e))?;
+            let field = |index: usize| record.get(index).unwrap_or_default().trim();
+
+            let rate: f64 = field(2)
+                .parse()
+                .map_err(|_| format!("Invalid FX rate: {}", field(2)))?;
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(format!("Invalid FX rate: {}", rate));
+            }
+
+            rates.insert((field(0).to_uppercase(), field(1).to_uppercase()), rate);
+        }
+
+        Ok(StaticRateProvider {
+            base_currency: base_currency.to_uppercase(),
+            rates,
+        })
+    }
+
+    fn direct_rate(&self, from_currency: &str, to_currency: &str) -> Option<f64> {
+        if from_currency == to_currency {
+            return Some(1.0);
+        }
+
+        let pair = |from: &str, to: &str| (from.to_string(), to.to_string());
+        self.rates
+            .get(&pair(from_currency, to_currency))
+            .copied()
+            .or_else(|| {
+                self.rates
+                    .get(&pair(to_currency, from_currency))
+                    .map(|rate| 1.0 / rate)
+            })
+    }
+}
+
+impl FxRateProvider for StaticRateProvider {
+    fn mid_rate(&self, from_currency: &str, to_currency: &str) -> Result<f64, String> {
+        if let Some(rate) = self.direct_rate(from_currency, to_currency) {
+            return Ok(rate);
+        }
+
+        let base = self.base_currency.as_str();
+        self.direct_rate(from_currency, base)
+            .zip(self.direct_rate(base, to_currency))
+            .map(|(to_base, from_base)| to_base * from_base)
+            .ok_or_else(|| format!("No FX rate from {} to {}", from_currency, to_currency))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FxConfig {
+    pub provider: Arc<dyn FxRateProvider>,
+    // Percentage taken off the mid rate
+    pub spread: f64,
+    pub quote_ttl: Duration,
+}
+
+// How the amount debited from one account is credited to the other
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub from_currency: String,
+    pub to_currency: String,
+    pub rate: f64,
+    pub converted_amount: f64,
+    pub quote_id: Option<ObjectId>,
+}
+
+impl Conversion {
+    pub fn is_cross_currency(&self) -> bool {
+        self.from_currency != self.to_currency
+    }
+
+    // Each leg is booked in the currency of its account; converted legs also
+    // carry the rate that was used
+    pub fn leg_fields(&self, currency: &str) -> Document {
+        let mut fields = doc! { "currency": currency };
+        if self.is_cross_currency() {
+            fields.insert("fx_rate", self.rate);
+            if let Some(quote_id) = self.quote_id {
+                fields.insert("fx_quote_id", quote_id);
+            }
+        }
+        fields
+    }
+}
+
+fn round_to_cents(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// A quote is honoured while it is open and unexpired. One already used by
+// the given saga can be used by it again.
+fn check_quote(quote: &Document, saga_id: Option<ObjectId>, now: DateTime) -> Result<(), String> {
+    let used_by_saga = saga_id.is_some()
+        && quote.get_str("status") == Ok(QUOTE_USED)
+        && quote.get_object_id("saga_id").ok() == saga_id;
+    if used_by_saga {
+        return Ok(());
+    }
+
+    if quote.get_str("status") != Ok(QUOTE_OPEN) {
+        return Err("FX quote has already been used".to_string());
+    }
+    if quote
+        .get_datetime("expires_at")
+        .map_or(true, |expires_at| *expires_at <= now)
+    {
+        return Err("FX quote has expired".to_string());
+    }
+
+    Ok(())
+}
+
+impl MyDepositService {
+    fn fx_quotes_collection(&self) -> Collection<Document> {
+        self.db.collection("fx_quotes")
+    }
+
+    pub fn account_currency<'a>(&'a self, account: &'a Document) -> &'a str {
+        account
+            .get_str("currency")
+            .unwrap_or(&self.validation_policy.default_currency)
+    }
+
+    // Returns the mid rate and the rate with the spread applied
+    fn fx_rates(&self, from_currency: &str, to_currency: &str) -> Result<(f64, f64), String> {
+        if from_currency == to_currency {
+            return Ok((1.0, 1.0));
+        }
+
+        let mid_rate = self
+            .fx_config
+            .provider
+            .mid_rate(from_currency, to_currency)?;
+        Ok((mid_rate, mid_rate * (1.0 - self.fx_config.spread / 100.0)))
+    }
+
+    pub async fn quote_transfer(&self, req: &QuoteTransferRequest) -> Result<FxQuote, Status> {
+        let from_account = self.get_account(&req.from_account_id).await?;
+        let to_account = self.get_account(&req.to_account_id).await?;
+        let from_currency = self.account_currency(&from_account).to_string();
+        let to_currency = self.account_currency(&to_account).to_string();
+
+        let (mid_rate, rate) = self
+            .fx_rates(&from_currency, &to_currency)
+            .map_err(Status::failed_precondition)?;
+        let converted_amount = round_to_cents(req.amount * rate);
+        let expires_at = Utc::now() + self.fx_config.quote_ttl;
+
+        let quote = doc! {
+            "from_account_id": from_account.get_object_id("_id").unwrap(),
+            "to_account_id": to_account.get_object_id("_id").unwrap(),
+            "from_currency": &from_currency,
+            "to_currency": &to_currency,
+            "amount": req.amount,
+            "converted_amount": converted_amount,
+            "mid_rate": mid_rate,
+            "rate": rate,
+            "spread": self.fx_config.spread,
+            "status": QUOTE_OPEN,
+            "expires_at": DateTime::from_millis(expires_at.timestamp_millis()),
+            "created_at": DateTime::now(),
+        };
+
+        let quote_id = self
+            .fx_quotes_collection()
+            .insert_one(quote, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create FX quote: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .ok_or_else(|| Status::internal("Failed to create FX quote: missing inserted_id"))?;
+
+        info!(
+            "FX quote {} for {} {} to {} at {}",
+            quote_id, req.amount, from_currency, to_currency, rate
+        );
+
+        Ok(FxQuote {
+            quote_id: quote_id.to_hex(),
+            from_currency,
+            to_currency,
+            amount: req.amount,
+            converted_amount,
+            mid_rate,
+            rate,
+            spread: self.fx_config.spread,
+            expires_timestamp: expires_at.timestamp_millis(),
+        })
+    }
+
+    // Transfers naming a quote are converted at the quoted rate, and the others
+    // at the current rate
+    pub async fn conversion(
+        &self,
+        from_account: &Document,
+        to_account: &Document,
+        amount: f64,
+        fx_quote_id: &str,
+    ) -> Result<Conversion, Status> {
+        let from_currency = self.account_currency(from_account).to_string();
+        let to_currency = self.account_currency(to_account).to_string();
+
+        if fx_quote_id.is_empty() {
+            let (_, rate) = self
+                .fx_rates(&from_currency, &to_currency)
+                .map_err(Status::failed_precondition)?;
+            return Ok(Conversion {
+                from_currency,
+                to_currency,
+                rate,
+                converted_amount: round_to_cents(amount * rate),
+                quote_id: None,
+            });
+        }
+
+        let quote_id = ObjectId::parse_str(fx_quote_id)
+            .map_err(|_| validation::invalid_field("fx_quote_id", "Invalid FX quote id"))?;
+        let quote = self
+            .fx_quotes_collection()
+            .find_one(doc! { "_id": quote_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get FX quote: {}", e)))?
+            .ok_or_else(|| Status::not_found("FX quote not found"))?;
+
+        if quote.get_object_id("from_account_id") != from_account.get_object_id("_id")
+            || quote.get_object_id("to_account_id") != to_account.get_object_id("_id")
+            || quote.get_f64("amount") != Ok(amount)
+            || quote.get_str("to_currency") != Ok(to_currency.as_str())
+        {
+            return Err(validation::invalid_field(
+                "fx_quote_id",
+                "FX quote does not match the transfer",
+            ));
+        }
+        check_quote(&quote, None, DateTime::now()).map_err(Status::failed_precondition)?;
+
+        Ok(Conversion {
+            from_currency,
+            to_currency,
+            rate: quote.get_f64("rate").unwrap(),
+            converted_amount: quote.get_f64("converted_amount").unwrap(),
+            quote_id: Some(quote_id),
+        })
+    }
+
+    // A quote can only be executed once, and never after it expires. Using it
+    // again from the same transfer saga is a no-op. The quote is used in the
+    // transaction booking the transfer, so it is only taken if the transfer is.
+    pub async fn use_fx_quote(
+        &self,
+        quote_id: ObjectId,
+        saga_id: ObjectId,
+        session: &mut ClientSession,
+    ) -> Result<(), Status> {
+        let update_result = self
+            .fx_quotes_collection()
+            .update_one_with_session(
+                doc! {
+                    "_id": quote_id,
+                    "$or": [
+                        { "status": QUOTE_OPEN, "expires_at": { "$gt": DateTime::now() } },
+                        { "status": QUOTE_USED, "saga_id": saga_id },
+                    ],
+                },
+                doc! {
+                    "$set": { "status": QUOTE_USED, "used_at": DateTime::now(), "saga_id": saga_id }
+                },
+                None,
+                session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update FX quote: {}", e)))?;
+
+        if update_result.matched_count == 0 {
+            return Err(Status::failed_precondition(
+                "FX quote has expired or has already been used",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Hands back a quote used by a transfer that was rolled back. It can be
+    // used again until it expires.
+    pub async fn release_fx_quote(
+        &self,
+        quote_id: ObjectId,
+        saga_id: ObjectId,
+    ) -> Result<(), Status> {
+        self.fx_quotes_collection()
+            .update_one(
+                doc! { "_id": quote_id, "status": QUOTE_USED, "saga_id": saga_id },
+                doc! {
+                    "$set": { "status": QUOTE_OPEN },
+                    "$unset": { "used_at": "", "saga_id": "" },
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update FX quote: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: &str =
+        "from_currency,to_currency,rate\nEUR,USD,1.25\nEUR,GBP,0.80\nGBP,CHF,1.10\n";
+
+    fn assert_rate(provider: &StaticRateProvider, from: &str, to: &str, expected: f64) {
+        let rate = provider.mid_rate(from, to).unwrap();
+        assert!((rate - expected).abs() < 1e-9, "{}->{}: {}", from, to, rate);
+    }
+
+    fn quote(status: &str, expires_at: DateTime, saga_id: Option<ObjectId>) -> Document {
+        let mut quote = doc! { "status": status, "expires_at": expires_at };
+        if let Some(saga_id) = saga_id {
+            quote.insert("saga_id", saga_id);
+        }
+        quote
+    }
+
+    #[test]
+    fn direct_and_inverse_rates() {
+        let provider = StaticRateProvider::from_csv(RATES, "EUR").unwrap();
+        assert_rate(&provider, "EUR", "USD", 1.25);
+        assert_rate(&provider, "USD", "EUR", 0.8);
+        assert_rate(&provider, "GBP", "EUR", 1.25);
+        assert_rate(&provider, "USD", "USD", 1.0);
+    }
+
+    #[test]
+    fn crosses_go_through_the_base_currency() {
+        let provider = StaticRateProvider::from_csv(RATES, "eur").unwrap();
+        assert_rate(&provider, "USD", "GBP", 0.64);
+        assert_rate(&provider, "GBP", "USD", 1.5625);
+        // GBP,CHF is not reachable from the base currency
+        assert!(provider.mid_rate("EUR", "CHF").is_err());
+        assert!(provider.mid_rate("USD", "CHF").is_err());
+    }
+
+    #[test]
+    fn crosses_do_not_depend_on_other_pivots() {
+        let provider = StaticRateProvider::from_csv(RATES, "GBP").unwrap();
+        assert_rate(&provider, "EUR", "CHF", 0.88);
+        // EUR is not used as a pivot when GBP is the base currency
+        assert!(provider.mid_rate("USD", "CHF").is_err());
+        let unknown_base = StaticRateProvider::from_csv(RATES, "JPY").unwrap();
+        assert!(unknown_base.mid_rate("USD", "GBP").is_err());
+    }
+
+    #[test]
+    fn invalid_rates_are_rejected() {
+        assert!(
+            StaticRateProvider::from_csv("from_currency,to_currency,rate\nEUR,USD,0\n", "EUR")
+                .is_err()
+        );
+        assert!(
+            StaticRateProvider::from_csv("from_currency,to_currency,rate\nEUR,USD,x\n", "EUR")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn open_quotes_are_honoured_until_they_expire() {
+        let now = DateTime::now();
+        let later = DateTime::from_millis(now.timestamp_millis() + 1_000);
+        assert!(check_quote(&quote(QUOTE_OPEN, later, None), None, now).is_ok());
+
+        let expired = check_quote(&quote(QUOTE_OPEN, now, None), None, now).unwrap_err();
+        assert_eq!(expired, "FX quote has expired");
+    }
+
+    #[test]
+    fn used_quotes_are_only_reused_by_their_saga() {
+        let now = DateTime::now();
+        let later = DateTime::from_millis(now.timestamp_millis() + 1_000);
+        let saga_id = ObjectId::new();
+        let used = quote(QUOTE_USED, later, Some(saga_id));
+
+        assert!(check_quote(&used, Some(saga_id), now).is_ok());
+        let other_saga = check_quote(&used, Some(ObjectId::new()), now).unwrap_err();
+        assert_eq!(other_saga, "FX quote has already been used");
+        assert!(check_quote(&used, None, now).is_err());
+    }
+}
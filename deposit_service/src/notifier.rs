@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use tonic::{transport::Channel, Status};
+
+pub mod notification {
+    tonic::include_proto!("notification");
+}
+
+use notification::{
+    notification_service_client::NotificationServiceClient, NotificationType,
+    SendNotificationRequest,
+};
+
+// Sends customer notifications through notification_service, which renders
+// the template and retries the delivery in the background
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    client: Option<NotificationServiceClient<Channel>>,
+}
+
+impl Notifier {
+    // Connects on the first notification, so notification_service may start
+    // after this service
+    pub fn connect_lazy(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let channel = Channel::from_shared(url.to_string())?.connect_lazy();
+        Ok(Self {
+            client: Some(NotificationServiceClient::new(channel)),
+        })
+    }
+
+    // Returns the id of the notification, or None when notifications are
+    // disabled
+    pub async fn send_template(
+        &self,
+        customer_id: &str,
+        template: &str,
+        template_data: HashMap<String, String>,
+    ) -> Result<Option<String>, Status> {
+        let mut client = match &self.client {
+            Some(client) => client.clone(),
+            None => return Ok(None),
+        };
+
+        let request = SendNotificationRequest {
+            customer_id: customer_id.to_string(),
+            notification_type: NotificationType::Email as i32,
+            template: template.to_string(),
+            template_data,
+            ..Default::default()
+        };
+
+        let response = client.send_notification(request).await?;
+        Ok(Some(response.into_inner().notification_id))
+    }
+}
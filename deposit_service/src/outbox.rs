@@ -0,0 +1,33 @@
+use mongodb::bson::{oid::ObjectId, Document};
+use mongodb::{Client, Database};
+
+use crate::deposit_service::MyDepositService;
+use bank_common::outbox::{self, OutboxConfig, OutboxService};
+
+// Events published by this service. They are recorded and relayed by
+// bank_common::outbox.
+pub const FUNDS_DEPOSITED: &str = "FundsDeposited";
+pub const FUNDS_WITHDRAWN: &str = "FundsWithdrawn";
+pub const TRANSFER_COMPLETED: &str = "TransferCompleted";
+
+const SOURCE: &str = "deposit";
+
+pub fn new_event(event_type: &str, aggregate_id: ObjectId, data: Document) -> Document {
+    outbox::new_event(SOURCE, event_type, aggregate_id, data)
+}
+
+impl OutboxService for MyDepositService {
+    const SOURCE: &'static str = SOURCE;
+
+    fn outbox_client(&self) -> &Client {
+        &self.client
+    }
+
+    fn outbox_db(&self) -> &Database {
+        &self.db
+    }
+
+    fn outbox_config(&self) -> &OutboxConfig {
+        &self.outbox_config
+    }
+}
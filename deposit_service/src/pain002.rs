@@ -0,0 +1,127 @@
+use chrono::{SecondsFormat, Utc};
+use std::fmt::Write;
+
+use crate::deposit_service::deposit::{
+    PaymentBatch, PaymentBatchItem, PaymentBatchStatus, PaymentFileFormat, PaymentItemStatus,
+};
+
+// Renders an ISO 20022 pain.002 (CustomerPaymentStatusReport) describing the
+// group status of a payment batch and the status of every instruction in it.
+pub fn render(message_id: &str, batch: &PaymentBatch) -> String {
+    let mut xml = String::new();
+
+    let group_status = match PaymentBatchStatus::from_i32(batch.status) {
+        Some(PaymentBatchStatus::Completed) => "ACSC",
+        Some(PaymentBatchStatus::PartiallyCompleted) => "PART",
+        Some(PaymentBatchStatus::Rejected) => "RJCT",
+        Some(PaymentBatchStatus::Processing) | None => "PDNG",
+    };
+    let original_message_name = match PaymentFileFormat::from_i32(batch.format) {
+        Some(PaymentFileFormat::Pain001) => "pain.001.001.03",
+        _ => "CSV",
+    };
+
+    // Writing into a String never fails, so the fmt::Results are ignored.
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        xml,
+        r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.002.001.03">"#
+    );
+    let _ = writeln!(xml, "  <CstmrPmtStsRpt>");
+    let _ = writeln!(xml, "    <GrpHdr>");
+    let _ = writeln!(xml, "      <MsgId>{}</MsgId>", escape(message_id));
+    let _ = writeln!(
+        xml,
+        "      <CreDtTm>{}</CreDtTm>",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    let _ = writeln!(xml, "    </GrpHdr>");
+    let _ = writeln!(xml, "    <OrgnlGrpInfAndSts>");
+    let _ = writeln!(
+        xml,
+        "      <OrgnlMsgId>{}</OrgnlMsgId>",
+        escape(&batch.message_id)
+    );
+    let _ = writeln!(
+        xml,
+        "      <OrgnlMsgNmId>{}</OrgnlMsgNmId>",
+        original_message_name
+    );
+    let _ = writeln!(
+        xml,
+        "      <OrgnlNbOfTxs>{}</OrgnlNbOfTxs>",
+        batch.total_items
+    );
+    let _ = writeln!(
+        xml,
+        "      <OrgnlCtrlSum>{:.2}</OrgnlCtrlSum>",
+        batch.control_sum
+    );
+    let _ = writeln!(xml, "      <GrpSts>{}</GrpSts>", group_status);
+    let _ = writeln!(xml, "    </OrgnlGrpInfAndSts>");
+
+    // Items keep the order of the original file, so consecutive items with the
+    // same payment information id form one OrgnlPmtInfAndSts block.
+    let mut current_payment_information: Option<&str> = None;
+    for item in &batch.items {
+        if current_payment_information != Some(item.payment_information_id.as_str()) {
+            if current_payment_information.is_some() {
+                let _ = writeln!(xml, "    </OrgnlPmtInfAndSts>");
+            }
+            let _ = writeln!(xml, "    <OrgnlPmtInfAndSts>");
+            let _ = writeln!(
+                xml,
+                "      <OrgnlPmtInfId>{}</OrgnlPmtInfId>",
+                escape(&item.payment_information_id)
+            );
+            current_payment_information = Some(item.payment_information_id.as_str());
+        }
+        write_transaction_status(&mut xml, item);
+    }
+    if current_payment_information.is_some() {
+        let _ = writeln!(xml, "    </OrgnlPmtInfAndSts>");
+    }
+
+    let _ = writeln!(xml, "  </CstmrPmtStsRpt>");
+    let _ = writeln!(xml, "</Document>");
+
+    xml
+}
+
+fn write_transaction_status(xml: &mut String, item: &PaymentBatchItem) {
+    let status = match PaymentItemStatus::from_i32(item.status) {
+        Some(PaymentItemStatus::Executed) => "ACSC",
+        Some(PaymentItemStatus::Rejected) | Some(PaymentItemStatus::Failed) => "RJCT",
+        Some(PaymentItemStatus::Pending) | None => "PDNG",
+    };
+
+    let _ = writeln!(xml, "      <TxInfAndSts>");
+    let _ = writeln!(
+        xml,
+        "        <OrgnlEndToEndId>{}</OrgnlEndToEndId>",
+        escape(&item.end_to_end_id)
+    );
+    let _ = writeln!(xml, "        <TxSts>{}</TxSts>", status);
+    if !item.reason_code.is_empty() {
+        let _ = writeln!(xml, "        <StsRsnInf>");
+        let _ = writeln!(
+            xml,
+            "          <Rsn><Cd>{}</Cd></Rsn>",
+            escape(&item.reason_code)
+        );
+        // AddtlInf is a Max105Text
+        let reason: String = item.reason.chars().take(105).collect();
+        let _ = writeln!(xml, "          <AddtlInf>{}</AddtlInf>", escape(&reason));
+        let _ = writeln!(xml, "        </StsRsnInf>");
+    }
+    let _ = writeln!(xml, "      </TxInfAndSts>");
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,689 @@
+use futures::stream::TryStreamExt;
+use log::{error, info, warn};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use tonic::{Code, Status};
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{FindOptions, IndexOptions},
+    Collection, IndexModel,
+};
+
+use crate::deposit_service::deposit::{
+    MakeDepositRequest, PaymentBatch, PaymentBatchItem, PaymentBatchStatus, PaymentFileFormat,
+    PaymentItemStatus, SubmitPaymentBatchRequest,
+};
+use crate::deposit_service::MyDepositService;
+use crate::payment_file::{self, PaymentFile, PaymentInstruction};
+use bank_common::overdraft;
+use bank_common::validation;
+
+// ISO 20022 ExternalStatusReason1Code values used in item rejections
+const REASON_INCORRECT_ACCOUNT: &str = "AC01";
+const REASON_INSUFFICIENT_FUNDS: &str = "AM04";
+const REASON_DUPLICATION: &str = "AM05";
+const REASON_INVALID_AMOUNT: &str = "AM12";
+const REASON_TRANSACTION_FORBIDDEN: &str = "AG01";
+const REASON_NARRATIVE: &str = "NARR";
+
+impl Display for PaymentFileFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let format_str = match self {
+            PaymentFileFormat::Pain001 => "PAIN001",
+            PaymentFileFormat::Csv => "CSV",
+        };
+
+        write!(f, "{}", format_str)
+    }
+}
+
+impl FromStr for PaymentFileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PAIN001" => Ok(PaymentFileFormat::Pain001),
+            "CSV" => Ok(PaymentFileFormat::Csv),
+            _ => Err(format!("Invalid payment file format: {}", s)),
+        }
+    }
+}
+
+impl Display for PaymentBatchStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let status_str = match self {
+            PaymentBatchStatus::Processing => "PROCESSING",
+            PaymentBatchStatus::Completed => "COMPLETED",
+            PaymentBatchStatus::PartiallyCompleted => "PARTIALLY_COMPLETED",
+            PaymentBatchStatus::Rejected => "REJECTED",
+        };
+
+        write!(f, "{}", status_str)
+    }
+}
+
+impl FromStr for PaymentBatchStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PROCESSING" => Ok(PaymentBatchStatus::Processing),
+            "COMPLETED" => Ok(PaymentBatchStatus::Completed),
+            "PARTIALLY_COMPLETED" => Ok(PaymentBatchStatus::PartiallyCompleted),
+            "REJECTED" => Ok(PaymentBatchStatus::Rejected),
+            _ => Err(format!("Invalid payment batch status: {}", s)),
+        }
+    }
+}
+
+impl Display for PaymentItemStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let status_str = match self {
+            PaymentItemStatus::Pending => "PENDING",
+            PaymentItemStatus::Executed => "EXECUTED",
+            PaymentItemStatus::Rejected => "REJECTED",
+            PaymentItemStatus::Failed => "FAILED",
+        };
+
+        write!(f, "{}", status_str)
+    }
+}
+
+impl FromStr for PaymentItemStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PENDING" => Ok(PaymentItemStatus::Pending),
+            "EXECUTED" => Ok(PaymentItemStatus::Executed),
+            "REJECTED" => Ok(PaymentItemStatus::Rejected),
+            "FAILED" => Ok(PaymentItemStatus::Failed),
+            _ => Err(format!("Invalid payment item status: {}", s)),
+        }
+    }
+}
+
+struct ValidatedInstruction {
+    instruction: PaymentInstruction,
+    status: PaymentItemStatus,
+    reason_code: String,
+    reason: String,
+}
+
+impl ValidatedInstruction {
+    fn reject(&mut self, reason_code: &str, reason: String) {
+        self.status = PaymentItemStatus::Rejected;
+        self.reason_code = reason_code.to_string();
+        self.reason = reason;
+    }
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000
+    )
+}
+
+impl MyDepositService {
+    fn batches_collection(&self) -> Collection<Document> {
+        self.db.collection("payment_batches")
+    }
+
+    fn batch_items_collection(&self) -> Collection<Document> {
+        self.db.collection("payment_batch_items")
+    }
+
+    // A payment file can only be submitted once, even by concurrent requests
+    pub async fn ensure_batch_indexes(&self) -> Result<(), Status> {
+        let index = IndexModel::builder()
+            .keys(doc! { "message_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        self.batches_collection()
+            .create_index(index, None)
+            .await
+            .map_err(|e| {
+                Status::internal(format!("Failed to create payment batch index: {}", e))
+            })?;
+
+        Ok(())
+    }
+
+    // Parses and validates a bulk payment file, stores it as a batch and
+    // starts executing the accepted instructions in the background.
+    pub async fn submit_batch(
+        &self,
+        req: SubmitPaymentBatchRequest,
+    ) -> Result<PaymentBatch, Status> {
+        let format = PaymentFileFormat::from_i32(req.format)
+            .ok_or_else(|| Status::invalid_argument("Invalid payment file format"))?;
+
+        info!(
+            "Received {} payment file {} from {}",
+            format, req.file_name, req.submitted_by
+        );
+
+        let payment_file = match format {
+            PaymentFileFormat::Pain001 => payment_file::parse_pain001(&req.content),
+            PaymentFileFormat::Csv => payment_file::parse_csv(&req.file_name, &req.content),
+        }
+        .map_err(Status::invalid_argument)?;
+
+        check_file_totals(&payment_file).map_err(Status::invalid_argument)?;
+
+        let existing_batch = self
+            .batches_collection()
+            .find_one(doc! { "message_id": &payment_file.message_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get payment batch: {}", e)))?;
+        if existing_batch.is_some() {
+            error!("Duplicate payment file {}", payment_file.message_id);
+            return Err(Status::already_exists(format!(
+                "Payment file {} has already been submitted",
+                payment_file.message_id
+            )));
+        }
+
+        let validated = self
+            .validate_instructions(&req.submitted_by, payment_file.instructions)
+            .await?;
+        let rejected_items = validated
+            .iter()
+            .filter(|item| item.status == PaymentItemStatus::Rejected)
+            .count();
+        let status = if rejected_items == validated.len() {
+            PaymentBatchStatus::Rejected
+        } else {
+            PaymentBatchStatus::Processing
+        };
+        let control_sum: f64 = validated.iter().map(|item| item.instruction.amount).sum();
+
+        let new_batch = doc! {
+            "message_id": &payment_file.message_id,
+            "file_name": &req.file_name,
+            "format": format.to_string(),
+            "submitted_by": &req.submitted_by,
+            "status": status.to_string(),
+            "control_sum": control_sum,
+            "total_items": validated.len() as i32,
+            "executed_items": 0,
+            "rejected_items": rejected_items as i32,
+            "created_at": DateTime::now(),
+        };
+
+        let insert_result = self
+            .batches_collection()
+            .insert_one(new_batch, None)
+            .await
+            .map_err(|e| {
+                if is_duplicate_key(&e) {
+                    error!("Duplicate payment file {}", payment_file.message_id);
+                    Status::already_exists(format!(
+                        "Payment file {} has already been submitted",
+                        payment_file.message_id
+                    ))
+                } else {
+                    Status::internal(format!("Failed to create payment batch: {}", e))
+                }
+            })?;
+        let batch_id = insert_result.inserted_id.as_object_id().ok_or_else(|| {
+            Status::internal("Failed to create payment batch: missing inserted_id")
+        })?;
+
+        let items: Vec<Document> = validated
+            .iter()
+            .enumerate()
+            .map(|(sequence, item)| {
+                doc! {
+                    "batch_id": batch_id,
+                    "sequence": sequence as i32,
+                    "payment_information_id": &item.instruction.payment_information_id,
+                    "end_to_end_id": &item.instruction.end_to_end_id,
+                    "from_account_id": &item.instruction.from_account_id,
+                    "to_account_id": &item.instruction.to_account_id,
+                    "amount": item.instruction.amount,
+                    "status": item.status.to_string(),
+                    "reason_code": &item.reason_code,
+                    "reason": &item.reason,
+                }
+            })
+            .collect();
+
+        if !items.is_empty() {
+            self.batch_items_collection()
+                .insert_many(items, None)
+                .await
+                .map_err(|e| {
+                    Status::internal(format!("Failed to create payment batch items: {}", e))
+                })?;
+        }
+
+        info!(
+            "Payment batch {} created with {} items ({} rejected)",
+            batch_id,
+            validated.len(),
+            rejected_items
+        );
+
+        if status == PaymentBatchStatus::Processing {
+            let service = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = service.process_batch(batch_id).await {
+                    error!("Failed to process payment batch {}: {:?}", batch_id, e);
+                }
+            });
+        }
+
+        self.get_batch(&batch_id.to_hex()).await
+    }
+
+    // A batch is visible to the user who submitted it and to bank staff
+    pub async fn get_batch_requested_by(
+        &self,
+        batch_id: &str,
+        requested_by: &str,
+        requester_roles: &[String],
+    ) -> Result<PaymentBatch, Status> {
+        if requested_by.is_empty() {
+            return Err(Status::invalid_argument(
+                "The requester of the batch is required",
+            ));
+        }
+
+        let object_id = ObjectId::from_str(batch_id)
+            .map_err(|_| Status::invalid_argument("Invalid batch id"))?;
+
+        let batch_doc = self
+            .batches_collection()
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get payment batch: {}", e)))?
+            .ok_or_else(|| Status::not_found("Payment batch not found"))?;
+
+        if requester_roles.is_empty() && batch_doc.get_str("submitted_by") != Ok(requested_by) {
+            return Err(Status::permission_denied(
+                "Payment batch was submitted by another user",
+            ));
+        }
+
+        self.get_batch(batch_id).await
+    }
+
+    pub async fn get_batch(&self, batch_id: &str) -> Result<PaymentBatch, Status> {
+        let object_id = ObjectId::from_str(batch_id)
+            .map_err(|_| Status::invalid_argument("Invalid batch id"))?;
+
+        let batch_doc = self
+            .batches_collection()
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get payment batch: {}", e)))?
+            .ok_or_else(|| Status::not_found("Payment batch not found"))?;
+
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! {
+            "sequence": 1,
+        });
+
+        let mut cursor = self
+            .batch_items_collection()
+            .find(doc! { "batch_id": object_id }, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get payment batch items: {}", e)))?;
+
+        let mut items = Vec::new();
+        while let Some(result) = cursor
+            .try_next()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get payment batch items: {}", e)))?
+        {
+            let status = PaymentItemStatus::from_str(result.get_str("status").unwrap())
+                .map_err(Status::internal)?;
+            items.push(PaymentBatchItem {
+                end_to_end_id: result.get_str("end_to_end_id").unwrap().to_string(),
+                from_account_id: result.get_str("from_account_id").unwrap().to_string(),
+                to_account_id: result.get_str("to_account_id").unwrap().to_string(),
+                amount: result.get_f64("amount").unwrap(),
+                status: status as i32,
+                reason_code: result.get_str("reason_code").unwrap().to_string(),
+                reason: result.get_str("reason").unwrap().to_string(),
+                payment_information_id: result
+                    .get_str("payment_information_id")
+                    .unwrap()
+                    .to_string(),
+            });
+        }
+
+        let format = PaymentFileFormat::from_str(batch_doc.get_str("format").unwrap())
+            .map_err(Status::internal)?;
+        let status = PaymentBatchStatus::from_str(batch_doc.get_str("status").unwrap())
+            .map_err(Status::internal)?;
+
+        Ok(PaymentBatch {
+            batch_id: batch_id.to_string(),
+            message_id: batch_doc.get_str("message_id").unwrap().to_string(),
+            format: format as i32,
+            status: status as i32,
+            total_items: batch_doc.get_i32("total_items").unwrap(),
+            executed_items: batch_doc.get_i32("executed_items").unwrap(),
+            rejected_items: batch_doc.get_i32("rejected_items").unwrap(),
+            control_sum: batch_doc.get_f64("control_sum").unwrap(),
+            items,
+        })
+    }
+
+    // Rejects instructions with invalid amounts, unknown accounts, debtor
+    // accounts the submitter does not own, duplicate end-to-end ids (within
+    // the file or already submitted for the same debtor) and instructions the
+    // debtor cannot cover once the earlier instructions of the file have been
+    // applied.
+    async fn validate_instructions(
+        &self,
+        submitted_by: &str,
+        instructions: Vec<PaymentInstruction>,
+    ) -> Result<Vec<ValidatedInstruction>, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let mut balances: HashMap<ObjectId, Option<f64>> = HashMap::new();
+        let mut owners: HashMap<ObjectId, String> = HashMap::new();
+        let mut seen_end_to_end_ids = HashSet::new();
+        let mut validated = Vec::new();
+
+        for instruction in instructions {
+            let mut item = ValidatedInstruction {
+                instruction,
+                status: PaymentItemStatus::Pending,
+                reason_code: String::new(),
+                reason: String::new(),
+            };
+            let instruction = item.instruction.clone();
+
+            if let Err(reason) = validation::validate_amount(instruction.amount) {
+                item.reject(
+                    REASON_INVALID_AMOUNT,
+                    format!("Invalid amount {}: {}", instruction.amount, reason),
+                );
+                validated.push(item);
+                continue;
+            }
+
+            // pain.001 files usually carry account numbers rather than ids
+            let (from_account_id, to_account_id) = match (
+                self.resolve_account_id(&instruction.from_account_id).await,
+                self.resolve_account_id(&instruction.to_account_id).await,
+            ) {
+                (Ok(from), Ok(to)) if from == to => {
+                    item.reject(
+                        REASON_INCORRECT_ACCOUNT,
+                        "Debtor and creditor accounts are the same".to_string(),
+                    );
+                    validated.push(item);
+                    continue;
+                }
+                (Ok(from), Ok(to)) => (from, to),
+                (Err(status), _) | (_, Err(status)) if status.code() == Code::Internal => {
+                    return Err(status);
+                }
+                (Err(status), _) | (_, Err(status)) => {
+                    item.reject(REASON_INCORRECT_ACCOUNT, status.message().to_string());
+                    validated.push(item);
+                    continue;
+                }
+            };
+
+            if !seen_end_to_end_ids.insert((
+                instruction.from_account_id.clone(),
+                instruction.end_to_end_id.clone(),
+            )) {
+                item.reject(
+                    REASON_DUPLICATION,
+                    format!(
+                        "Duplicate end-to-end id {} in file",
+                        instruction.end_to_end_id
+                    ),
+                );
+                validated.push(item);
+                continue;
+            }
+
+            let previous_submission = self
+                .batch_items_collection()
+                .find_one(
+                    doc! {
+                        "from_account_id": &instruction.from_account_id,
+                        "end_to_end_id": &instruction.end_to_end_id,
+                        "status": { "$ne": PaymentItemStatus::Rejected.to_string() },
+                    },
+                    None,
+                )
+                .await
+                .map_err(|e| {
+                    Status::internal(format!("Failed to get payment batch items: {}", e))
+                })?;
+            if previous_submission.is_some() {
+                item.reject(
+                    REASON_DUPLICATION,
+                    format!(
+                        "End-to-end id {} has already been submitted",
+                        instruction.end_to_end_id
+                    ),
+                );
+                validated.push(item);
+                continue;
+            }
+
+            for account_id in [from_account_id, to_account_id] {
+                if let Entry::Vacant(entry) = balances.entry(account_id) {
+                    let account_doc = accounts_collection
+                        .find_one(doc! { "_id": account_id }, None)
+                        .await
+                        .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?;
+                    if let Some(account) = &account_doc {
+                        owners.insert(
+                            account_id,
+                            account.get_str("user_id").unwrap_or_default().to_string(),
+                        );
+                    }
+                    // Debtors can draw on their arranged overdraft
+                    entry.insert(account_doc.map(|account| overdraft::available_funds(&account)));
+                }
+            }
+
+            let from_balance = balances[&from_account_id];
+            let to_balance = balances[&to_account_id];
+            match (from_balance, to_balance) {
+                (None, _) => item.reject(
+                    REASON_INCORRECT_ACCOUNT,
+                    format!("Debtor account {} not found", instruction.from_account_id),
+                ),
+                (_, None) => item.reject(
+                    REASON_INCORRECT_ACCOUNT,
+                    format!("Creditor account {} not found", instruction.to_account_id),
+                ),
+                _ if owners.get(&from_account_id).map(String::as_str) != Some(submitted_by) => item
+                    .reject(
+                        REASON_TRANSACTION_FORBIDDEN,
+                        format!(
+                            "Debtor account {} does not belong to the submitter",
+                            instruction.from_account_id
+                        ),
+                    ),
+                (Some(from_balance), Some(_)) if from_balance < instruction.amount => item.reject(
+                    REASON_INSUFFICIENT_FUNDS,
+                    format!(
+                        "Insufficient balance in account {}",
+                        instruction.from_account_id
+                    ),
+                ),
+                (Some(from_balance), Some(to_balance)) => {
+                    balances.insert(from_account_id, Some(from_balance - instruction.amount));
+                    balances.insert(to_account_id, Some(to_balance + instruction.amount));
+                }
+            }
+
+            validated.push(item);
+        }
+
+        Ok(validated)
+    }
+
+    // Executes the pending items of a batch in file order through the regular
+    // transfer logic and records the outcome of each of them.
+    async fn process_batch(&self, batch_id: ObjectId) -> Result<(), Status> {
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! {
+            "sequence": 1,
+        });
+
+        let filter = doc! {
+            "batch_id": batch_id,
+            "status": PaymentItemStatus::Pending.to_string(),
+        };
+
+        let pending_items: Vec<Document> = self
+            .batch_items_collection()
+            .find(filter, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get payment batch items: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get payment batch items: {}", e)))?;
+
+        for item in pending_items {
+            let item_id = item.get_object_id("_id").unwrap();
+            let transfer_request = MakeDepositRequest {
+                from_account_id: item.get_str("from_account_id").unwrap().to_string(),
+                to_account_id: item.get_str("to_account_id").unwrap().to_string(),
+                amount: item.get_f64("amount").unwrap(),
+                is_bank_agent: false,
+                ..Default::default()
+            };
+
+            let update = match self.transfer(&transfer_request).await {
+                Ok(_) => doc! {
+                    "$set": {
+                        "status": PaymentItemStatus::Executed.to_string(),
+                        "executed_at": DateTime::now(),
+                    }
+                },
+                Err(status) => {
+                    warn!(
+                        "Payment batch {} item {} failed: {}",
+                        batch_id,
+                        item_id,
+                        status.message()
+                    );
+                    let reason_code = match status.code() {
+                        Code::FailedPrecondition => REASON_INSUFFICIENT_FUNDS,
+                        Code::NotFound | Code::InvalidArgument => REASON_INCORRECT_ACCOUNT,
+                        _ => REASON_NARRATIVE,
+                    };
+                    doc! {
+                        "$set": {
+                            "status": PaymentItemStatus::Failed.to_string(),
+                            "reason_code": reason_code,
+                            "reason": status.message(),
+                        }
+                    }
+                }
+            };
+
+            self.batch_items_collection()
+                .update_one(doc! { "_id": item_id }, update, None)
+                .await
+                .map_err(|e| {
+                    Status::internal(format!("Failed to update payment batch item: {}", e))
+                })?;
+        }
+
+        self.complete_batch(batch_id).await
+    }
+
+    async fn complete_batch(&self, batch_id: ObjectId) -> Result<(), Status> {
+        let items_collection = self.batch_items_collection();
+        let count = |status: PaymentItemStatus| {
+            items_collection.count_documents(
+                doc! { "batch_id": batch_id, "status": status.to_string() },
+                None,
+            )
+        };
+
+        let executed_items = count(PaymentItemStatus::Executed)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to count payment batch items: {}", e)))?;
+        let rejected_items = count(PaymentItemStatus::Rejected)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to count payment batch items: {}", e)))?
+            + count(PaymentItemStatus::Failed).await.map_err(|e| {
+                Status::internal(format!("Failed to count payment batch items: {}", e))
+            })?;
+
+        let status = if rejected_items == 0 {
+            PaymentBatchStatus::Completed
+        } else if executed_items == 0 {
+            PaymentBatchStatus::Rejected
+        } else {
+            PaymentBatchStatus::PartiallyCompleted
+        };
+
+        let update = doc! {
+            "$set": {
+                "status": status.to_string(),
+                "executed_items": executed_items as i32,
+                "rejected_items": rejected_items as i32,
+                "completed_at": DateTime::now(),
+            }
+        };
+
+        self.batches_collection()
+            .update_one(doc! { "_id": batch_id }, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update payment batch: {}", e)))?;
+
+        info!(
+            "Payment batch {} finished as {} ({} executed, {} rejected)",
+            batch_id, status, executed_items, rejected_items
+        );
+
+        Ok(())
+    }
+}
+
+fn check_file_totals(payment_file: &PaymentFile) -> Result<(), String> {
+    if payment_file.message_id.chars().count() > 35 {
+        return Err("Message identification must be at most 35 characters".to_string());
+    }
+
+    if payment_file.instructions.is_empty() {
+        return Err("Payment file contains no instructions".to_string());
+    }
+
+    if let Some(declared_count) = payment_file.declared_count {
+        if declared_count != payment_file.instructions.len() {
+            return Err(format!(
+                "NbOfTxs is {} but the file contains {} transactions",
+                declared_count,
+                payment_file.instructions.len()
+            ));
+        }
+    }
+
+    if let Some(control_sum) = payment_file.control_sum {
+        let total: f64 = payment_file.instructions.iter().map(|i| i.amount).sum();
+        if (control_sum - total).abs() > 0.005 {
+            return Err(format!(
+                "CtrlSum is {} but the transactions add up to {}",
+                control_sum, total
+            ));
+        }
+    }
+
+    Ok(())
+}
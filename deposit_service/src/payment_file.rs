@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+// A single credit transfer read from a bulk payment file.
+#[derive(Debug, Clone)]
+pub struct PaymentInstruction {
+    pub payment_information_id: String,
+    pub end_to_end_id: String,
+    pub from_account_id: String,
+    pub to_account_id: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentFile {
+    pub message_id: String,
+    pub declared_count: Option<usize>,
+    pub control_sum: Option<f64>,
+    pub instructions: Vec<PaymentInstruction>,
+}
+
+// Parses an ISO 20022 pain.001 (CustomerCreditTransferInitiation) document.
+// Account identifiers are read from either `Id/IBAN` or `Id/Othr/Id`.
+pub fn parse_pain001(content: &str) -> Result<PaymentFile, String> {
+    let document = roxmltree::Document::parse(content)
+        .map_err(|e| format!("Malformed pain.001 document: {}", e))?;
+
+    let initiation = child(document.root_element(), "CstmrCdtTrfInitn")
+        .ok_or_else(|| "Missing CstmrCdtTrfInitn element".to_string())?;
+    let group_header =
+        child(initiation, "GrpHdr").ok_or_else(|| "Missing GrpHdr element".to_string())?;
+
+    let message_id = child_text(group_header, "MsgId")
+        .ok_or_else(|| "Missing GrpHdr/MsgId element".to_string())?;
+    let declared_count = match child_text(group_header, "NbOfTxs") {
+        Some(value) => Some(
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid GrpHdr/NbOfTxs: {}", value))?,
+        ),
+        None => None,
+    };
+    let control_sum = match child_text(group_header, "CtrlSum") {
+        Some(value) => Some(parse_amount(&value)?),
+        None => None,
+    };
+
+    let mut instructions = Vec::new();
+    for payment_information in children(initiation, "PmtInf") {
+        let payment_information_id = child_text(payment_information, "PmtInfId")
+            .ok_or_else(|| "Missing PmtInf/PmtInfId element".to_string())?;
+        let from_account_id = child(payment_information, "DbtrAcct")
+            .and_then(account_identifier)
+            .ok_or_else(|| format!("Missing debtor account in {}", payment_information_id))?;
+
+        for transaction in children(payment_information, "CdtTrfTxInf") {
+            let end_to_end_id = child(transaction, "PmtId")
+                .and_then(|payment_id| child_text(payment_id, "EndToEndId"))
+                .ok_or_else(|| format!("Missing EndToEndId in {}", payment_information_id))?;
+            let amount = child(transaction, "Amt")
+                .and_then(|amount| child_text(amount, "InstdAmt"))
+                .ok_or_else(|| format!("Missing InstdAmt for {}", end_to_end_id))?;
+            let to_account_id = child(transaction, "CdtrAcct")
+                .and_then(account_identifier)
+                .ok_or_else(|| format!("Missing creditor account for {}", end_to_end_id))?;
+
+            instructions.push(PaymentInstruction {
+                payment_information_id: payment_information_id.clone(),
+                end_to_end_id,
+                from_account_id: from_account_id.clone(),
+                to_account_id,
+                amount: parse_amount(&amount)?,
+            });
+        }
+    }
+
+    Ok(PaymentFile {
+        message_id,
+        declared_count,
+        control_sum,
+        instructions,
+    })
+}
+
+// Parses a CSV payment file with the header
+// `end_to_end_id,from_account_id,to_account_id,amount`. CSV files carry no
+// message identification, so the uploaded file name is used instead.
+pub fn parse_csv(file_name: &str, content: &str) -> Result<PaymentFile, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Malformed CSV header: {}", e))?
+        .clone();
+    let columns: HashMap<&str, usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (name, index))
+        .collect();
+
+    let column = |name: &str| {
+        columns
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("Missing CSV column: {}", name))
+    };
+    let end_to_end_id_column = column("end_to_end_id")?;
+    let from_account_column = column("from_account_id")?;
+    let to_account_column = column("to_account_id")?;
+    let amount_column = column("amount")?;
+
+    let mut instructions = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("Malformed CSV record: {}", e))?;
+        let field = |index: usize| record.get(index).unwrap_or_default().to_string();
+
+        instructions.push(PaymentInstruction {
+            payment_information_id: "CSV".to_string(),
+            end_to_end_id: field(end_to_end_id_column),
+            from_account_id: field(from_account_column),
+            to_account_id: field(to_account_column),
+            amount: parse_amount(&field(amount_column))
+                .map_err(|e| format!("Line {}: {}", line + 2, e))?,
+        });
+    }
+
+    Ok(PaymentFile {
+        message_id: file_name.to_string(),
+        declared_count: None,
+        control_sum: None,
+        instructions,
+    })
+}
+
+fn parse_amount(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid amount: {}", value))
+}
+
+fn account_identifier(account: roxmltree::Node) -> Option<String> {
+    let id = child(account, "Id")?;
+    child_text(id, "IBAN").or_else(|| child(id, "Othr").and_then(|other| child_text(other, "Id")))
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.text())
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
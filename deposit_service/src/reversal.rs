@@ -0,0 +1,285 @@
+use log::info;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use crate::deposit_service::deposit::ReverseTransactionRequest;
+use crate::deposit_service::MyDepositService;
+use bank_common::outbox::OutboxService;
+use bank_common::overdraft;
+use bank_common::validation::Validator;
+
+// Deposits and withdrawals are never edited once booked. A reversal posts a
+// compensating entry of the opposite type for every leg of the original
+// transaction, links the entries both ways and keeps an audit record of who
+// reversed it and why. Fees charged on the original are refunded separately
+// with ReverseFee.
+pub const REASON_CODES: [(&str, &str); 6] = [
+    ("AM05", "Duplication"),
+    ("AC03", "Wrong account"),
+    ("AM09", "Wrong amount"),
+    ("FRAD", "Fraudulent origin"),
+    ("CUST", "Requested by customer"),
+    ("TECH", "Technical problem"),
+];
+
+pub struct Reversal {
+    pub reversal_id: ObjectId,
+    pub reversal_transaction_ids: Vec<ObjectId>,
+    pub amount: f64,
+}
+
+fn reason_description(reason_code: &str) -> Option<&'static str> {
+    REASON_CODES
+        .iter()
+        .find(|(code, _)| *code == reason_code)
+        .map(|(_, description)| *description)
+}
+
+// Reversing a deposit takes the money back out of the account, reversing a
+// withdrawal pays it back in
+fn reversal_entry(leg: &Document, reason_code: &str, reversed_by: &str) -> Document {
+    let amount = leg.get_f64("amount").unwrap().abs();
+    let (transaction_type, amount) = if leg.get_str("type") == Ok("Deposit") {
+        ("Withdrawal", -amount)
+    } else {
+        ("Deposit", amount)
+    };
+
+    let mut entry = doc! {
+        "account_id": leg.get_object_id("account_id").unwrap(),
+        "amount": amount,
+        "type": transaction_type,
+        "reversal": true,
+        "reversal_of": leg.get_object_id("_id").unwrap(),
+        "reversal_reason_code": reason_code,
+        "reversed_by": reversed_by,
+        "timestamp": DateTime::now(),
+    };
+    // The money goes back the way it came
+    if let (Ok(from_account_id), Ok(to_account_id)) = (
+        leg.get_object_id("from_account_id"),
+        leg.get_object_id("to_account_id"),
+    ) {
+        entry.insert("from_account_id", to_account_id);
+        entry.insert("to_account_id", from_account_id);
+    }
+    if let Ok(currency) = leg.get_str("currency") {
+        entry.insert("currency", currency);
+    }
+    entry
+}
+
+impl MyDepositService {
+    // The legs of a transfer are linked by the saga that booked them
+    async fn transaction_legs(&self, transaction: &Document) -> Result<Vec<Document>, Status> {
+        if transaction.get_object_id("from_account_id").is_err() {
+            return Ok(vec![transaction.clone()]);
+        }
+
+        let saga_id = transaction
+            .get_object_id("saga_id")
+            .map_err(|_| Status::failed_precondition("Transfer legs are not linked to a saga"))?;
+        Ok(self
+            .saga_legs(saga_id)
+            .await?
+            .into_iter()
+            .filter(|leg| matches!(leg.get_str("type"), Ok("Deposit") | Ok("Withdrawal")))
+            .collect())
+    }
+
+    pub async fn reverse_transaction(
+        &self,
+        req: &ReverseTransactionRequest,
+    ) -> Result<Reversal, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+        let reversals_collection: Collection<Document> =
+            self.db.collection("transaction_reversals");
+
+        let mut validator = Validator::new();
+        if ObjectId::parse_str(&req.transaction_id).is_err() {
+            validator.violation("transaction_id", "Invalid transaction id");
+        }
+        if reason_description(&req.reason_code).is_none() {
+            validator.violation(
+                "reason_code",
+                format!("Unknown reversal reason code: {}", req.reason_code),
+            );
+        }
+        if req.reversed_by.is_empty() {
+            validator.violation(
+                "reversed_by",
+                "The agent reversing the transaction is required",
+            );
+        }
+        if let Some(status) = validator.error() {
+            return Err(status);
+        }
+
+        let transaction_id = ObjectId::parse_str(&req.transaction_id).unwrap();
+        let transaction = transactions_collection
+            .find_one(doc! { "_id": transaction_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get transaction: {}", e)))?
+            .ok_or_else(|| Status::not_found("Transaction not found"))?;
+
+        if transaction.get_bool("reversal").unwrap_or_default() {
+            return Err(Status::failed_precondition(
+                "Reversal entries cannot be reversed",
+            ));
+        }
+        match transaction.get_str("type") {
+            Ok("Deposit") | Ok("Withdrawal") => {}
+            Ok("Fee") => {
+                return Err(Status::failed_precondition(
+                    "Fees are reversed with ReverseFee",
+                ))
+            }
+            _ => {
+                return Err(Status::failed_precondition(
+                    "Only deposits and withdrawals can be reversed",
+                ))
+            }
+        }
+
+        let legs = self.transaction_legs(&transaction).await?;
+
+        // The debited leg of a transfer is the one marked first, so a transfer
+        // cannot be reversed twice through either of its legs
+        let lock_id = legs
+            .iter()
+            .find(|leg| leg.get_str("type") == Ok("Withdrawal"))
+            .unwrap_or(&transaction)
+            .get_object_id("_id")
+            .unwrap();
+        let leg_ids: Vec<ObjectId> = legs
+            .iter()
+            .map(|leg| leg.get_object_id("_id").unwrap())
+            .collect();
+        let reversed = doc! {
+            "$set": {
+                "reversed_at": DateTime::now(),
+                "reversed_by": &req.reversed_by,
+                "reversal_reason_code": &req.reason_code,
+            }
+        };
+
+        // The claim, the compensating entries, their balance updates and the
+        // audit record are written atomically
+        let mut session = self.start_transaction().await?;
+
+        let claim_result = transactions_collection
+            .update_one_with_session(
+                doc! { "_id": lock_id, "reversed_at": { "$exists": false } },
+                reversed.clone(),
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to reverse transaction: {}", e)))?;
+        if claim_result.modified_count == 0 {
+            return Err(Status::failed_precondition(
+                "Transaction has already been reversed",
+            ));
+        }
+        transactions_collection
+            .update_many_with_session(
+                doc! { "_id": { "$in": leg_ids.clone() }, "reversed_at": { "$exists": false } },
+                reversed,
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to reverse transaction: {}", e)))?;
+
+        let mut reversal_transaction_ids = Vec::new();
+        let mut debited_accounts = Vec::new();
+        for leg in &legs {
+            let entry = reversal_entry(leg, &req.reason_code, &req.reversed_by);
+            let amount = entry.get_f64("amount").unwrap();
+            let account_id = leg.get_object_id("account_id").unwrap();
+
+            let account_doc = accounts_collection
+                .find_one_and_update_with_session(
+                    doc! { "_id": account_id },
+                    doc! { "$inc": { "balance": amount } },
+                    FindOneAndUpdateOptions::builder()
+                        .return_document(ReturnDocument::Before)
+                        .build(),
+                    &mut session,
+                )
+                .await
+                .map_err(|e| Status::internal(format!("Failed to update account balance: {}", e)))?
+                .ok_or_else(|| Status::not_found("Account not found"))?;
+
+            let reversal_transaction_id = transactions_collection
+                .insert_one_with_session(entry, None, &mut session)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?
+                .inserted_id
+                .as_object_id()
+                .unwrap();
+            transactions_collection
+                .update_one_with_session(
+                    doc! { "_id": leg.get_object_id("_id").unwrap() },
+                    doc! { "$set": { "reversal_transaction_id": reversal_transaction_id } },
+                    None,
+                    &mut session,
+                )
+                .await
+                .map_err(|e| Status::internal(format!("Failed to update transaction: {}", e)))?;
+            reversal_transaction_ids.push(reversal_transaction_id);
+
+            if amount < 0.0 {
+                debited_accounts.push((account_doc, amount));
+            }
+        }
+
+        let amount = transaction.get_f64("amount").unwrap().abs();
+        let reversal = doc! {
+            "transaction_id": transaction_id,
+            "transaction_ids": leg_ids,
+            "reversal_transaction_ids": reversal_transaction_ids.clone(),
+            "amount": amount,
+            "reason_code": &req.reason_code,
+            "reason": reason_description(&req.reason_code).unwrap_or_default(),
+            "note": &req.note,
+            "reversed_by": &req.reversed_by,
+            "created_at": DateTime::now(),
+        };
+        let reversal_id = reversals_collection
+            .insert_one_with_session(reversal, None, &mut session)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to record reversal: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap();
+
+        self.commit_transaction(&mut session).await?;
+
+        for (account_doc, amount) in debited_accounts {
+            let balance = account_doc.get_f64("balance").unwrap();
+            overdraft::notify_overdraft(&self.db, &account_doc, balance, balance + amount).await?;
+        }
+
+        info!(
+            "Reversed transaction {} ({} legs) for {} by {}: {}",
+            transaction_id,
+            legs.len(),
+            req.reason_code,
+            req.reversed_by,
+            req.note
+        );
+
+        Ok(Reversal {
+            reversal_id,
+            reversal_transaction_ids,
+            amount,
+        })
+    }
+}
@@ -0,0 +1,1031 @@
+use chrono::Utc;
+use log::{error, info, warn};
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
+use tonic::{Code, Status};
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use crate::deposit_service::MyDepositService;
+use crate::transfer_saga;
+
+// A saga runs a sequence of steps that each commit on their own. When a step
+// fails, the steps that ran before it are compensated in reverse order. The
+// state of every saga is kept in the `sagas` collection and updated after each
+// step, so that sagas interrupted by a restart are picked up again by the
+// recovery task once their lease expires.
+pub const SAGA_RUNNING: &str = "RUNNING";
+pub const SAGA_COMPENSATING: &str = "COMPENSATING";
+pub const SAGA_COMPLETED: &str = "COMPLETED";
+pub const SAGA_COMPENSATED: &str = "COMPENSATED";
+// The compensation failed as well, the saga needs manual attention
+pub const SAGA_FAILED: &str = "FAILED";
+
+const STEP_PENDING: &str = "PENDING";
+const STEP_DONE: &str = "DONE";
+const STEP_FAILED: &str = "FAILED";
+const STEP_COMPENSATED: &str = "COMPENSATED";
+
+#[derive(Debug, Clone)]
+pub struct StepPolicy {
+    pub timeout: Duration,
+    pub max_attempts: u32,
+    // Grows linearly with every failed attempt
+    pub retry_delay: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SagaConfig {
+    pub policy: StepPolicy,
+    // How long a saga stays with the instance running it. Every attempt of a
+    // step renews the lease, so it has to outlast a step timeout and the
+    // retry delays.
+    pub lease: chrono::Duration,
+    pub recovery_interval: Duration,
+}
+
+pub enum StepError {
+    // Retried until the step runs out of attempts. The step may or may not
+    // have taken effect.
+    Retryable(String),
+    // Fails the saga straight away. The step must not have taken effect.
+    Rejected(Box<Status>),
+}
+
+impl From<Status> for StepError {
+    fn from(status: Status) -> Self {
+        match status.code() {
+            Code::Internal | Code::Unavailable | Code::Unknown | Code::DeadlineExceeded => {
+                StepError::Retryable(status.message().to_string())
+            }
+            _ => StepError::Rejected(Box::new(status)),
+        }
+    }
+}
+
+// What a step gets to see of its saga: the payload it was started with and
+// the values returned by the steps that already ran
+#[derive(Debug, Clone)]
+pub struct Saga {
+    pub id: ObjectId,
+    pub created_at: DateTime,
+    pub payload: Document,
+    pub context: Document,
+}
+
+// Steps run again when a saga is resumed, so both actions must be
+// idempotent. A step that failed without a clear outcome is compensated too,
+// so compensations must also cope with a step that never took effect.
+#[tonic::async_trait]
+pub trait SagaStep: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    // Returns values to add to the saga context
+    async fn execute(&self, service: &MyDepositService, saga: &Saga)
+        -> Result<Document, StepError>;
+
+    async fn compensate(&self, _service: &MyDepositService, _saga: &Saga) -> Result<(), StepError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StepDefinition {
+    pub step: Arc<dyn SagaStep>,
+    pub policy: StepPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct SagaDefinition {
+    pub saga_type: &'static str,
+    pub steps: Vec<StepDefinition>,
+}
+
+enum StepOutcome {
+    Done(Document),
+    Rejected(Status),
+    Exhausted(String),
+}
+
+// What a saga does next, decided from the state persisted after its last
+// step alone, so that a resumed saga carries on where it stopped
+#[derive(Debug, PartialEq)]
+enum SagaAction {
+    Execute(usize),
+    Compensate(usize),
+    Complete,
+    FinishCompensation,
+    Stop,
+}
+
+fn next_action(status: &str, current_step: usize, steps: usize) -> SagaAction {
+    match status {
+        SAGA_RUNNING if current_step >= steps => SagaAction::Complete,
+        SAGA_RUNNING => SagaAction::Execute(current_step),
+        SAGA_COMPENSATING if current_step == 0 => SagaAction::FinishCompensation,
+        SAGA_COMPENSATING => SagaAction::Compensate(current_step - 1),
+        _ => SagaAction::Stop,
+    }
+}
+
+// The status and current step a saga moves to once a step has run or been
+// compensated. Steps that failed without a clear outcome are compensated
+// along with the steps before them, and a failed compensation leaves the
+// saga where it stopped.
+fn after_step(index: usize, compensate: bool, outcome: &StepOutcome) -> (&'static str, usize) {
+    match (compensate, outcome) {
+        (false, StepOutcome::Done(_)) => (SAGA_RUNNING, index + 1),
+        (false, StepOutcome::Rejected(_)) => (SAGA_COMPENSATING, index),
+        (false, StepOutcome::Exhausted(_)) => (SAGA_COMPENSATING, index + 1),
+        (true, StepOutcome::Done(_)) => (SAGA_COMPENSATING, index),
+        (true, _) => (SAGA_FAILED, index + 1),
+    }
+}
+
+pub fn definitions(policy: &StepPolicy) -> HashMap<&'static str, SagaDefinition> {
+    [transfer_saga::definition(policy)]
+        .into_iter()
+        .map(|definition| (definition.saga_type, definition))
+        .collect()
+}
+
+fn saga_error(saga: &Document) -> Status {
+    Status::new(
+        Code::from(saga.get_i32("error_code").unwrap_or(Code::Internal as i32)),
+        saga.get_str("error").unwrap_or("Saga failed").to_string(),
+    )
+}
+
+impl MyDepositService {
+    fn sagas_collection(&self) -> Collection<Document> {
+        self.db.collection("sagas")
+    }
+
+    fn saga_definition(&self, saga_type: &str) -> Option<SagaDefinition> {
+        self.saga_definitions.get(saga_type).cloned()
+    }
+
+    fn lease_expiry(&self) -> DateTime {
+        DateTime::from_millis((Utc::now() + self.saga_config.lease).timestamp_millis())
+    }
+
+    async fn get_saga(&self, saga_id: ObjectId) -> Result<Document, Status> {
+        self.sagas_collection()
+            .find_one(doc! { "_id": saga_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get saga: {}", e)))?
+            .ok_or_else(|| Status::not_found("Saga not found"))
+    }
+
+    async fn update_saga(&self, saga_id: ObjectId, mut update: Document) -> Result<(), Status> {
+        let set = update.entry("$set".to_string()).or_insert(doc! {}.into());
+        if let Some(set) = set.as_document_mut() {
+            set.insert("updated_at", DateTime::now());
+        }
+
+        self.sagas_collection()
+            .update_one(doc! { "_id": saga_id }, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update saga: {}", e)))?;
+        Ok(())
+    }
+
+    // Persists a new saga and runs it until it completes, returning the
+    // final saga, or until it is compensated, returning the error that
+    // caused it
+    pub async fn run_saga(&self, saga_type: &str, payload: Document) -> Result<Document, Status> {
+        let definition = self
+            .saga_definition(saga_type)
+            .ok_or_else(|| Status::internal(format!("Unknown saga type: {}", saga_type)))?;
+
+        let saga_id = self.create_saga(&definition, payload).await?;
+        info!("Started {} saga {}", saga_type, saga_id);
+        self.drive_saga(&definition, saga_id).await
+    }
+
+    // The new saga is leased to the instance creating it
+    async fn create_saga(
+        &self,
+        definition: &SagaDefinition,
+        payload: Document,
+    ) -> Result<ObjectId, Status> {
+        let steps: Vec<Document> = definition
+            .steps
+            .iter()
+            .map(|definition| {
+                doc! {
+                    "name": definition.step.name(),
+                    "status": STEP_PENDING,
+                    "attempts": 0,
+                }
+            })
+            .collect();
+        let saga = doc! {
+            "saga_type": definition.saga_type,
+            "status": SAGA_RUNNING,
+            "payload": payload,
+            "context": {},
+            "steps": steps,
+            "current_step": 0,
+            "locked_until": self.lease_expiry(),
+            "created_at": DateTime::now(),
+            "updated_at": DateTime::now(),
+        };
+        Ok(self
+            .sagas_collection()
+            .insert_one(saga, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create saga: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap())
+    }
+
+    async fn drive_saga(
+        &self,
+        definition: &SagaDefinition,
+        saga_id: ObjectId,
+    ) -> Result<Document, Status> {
+        loop {
+            let saga_doc = self.get_saga(saga_id).await?;
+            let status = saga_doc.get_str("status").unwrap();
+            let current_step = saga_doc.get_i32("current_step").unwrap_or_default() as usize;
+            let saga = Saga {
+                id: saga_id,
+                created_at: *saga_doc.get_datetime("created_at").unwrap(),
+                payload: saga_doc
+                    .get_document("payload")
+                    .cloned()
+                    .unwrap_or_default(),
+                context: saga_doc
+                    .get_document("context")
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+
+            match next_action(status, current_step, definition.steps.len()) {
+                SagaAction::Complete => {
+                    self.update_saga(
+                        saga_id,
+                        doc! {
+                            "$set": { "status": SAGA_COMPLETED, "completed_at": DateTime::now() },
+                            "$unset": { "locked_until": "" },
+                        },
+                    )
+                    .await?;
+                    info!("Completed {} saga {}", definition.saga_type, saga_id);
+                    return self.get_saga(saga_id).await;
+                }
+                SagaAction::Execute(index) => {
+                    let step = &definition.steps[index];
+                    let outcome = self.run_step(&saga, index, step, false).await?;
+                    let (_, next_step) = after_step(index, false, &outcome);
+                    match outcome {
+                        StepOutcome::Done(context) => {
+                            let mut set = doc! {
+                                format!("steps.{}.status", index): STEP_DONE,
+                                "current_step": next_step as i32,
+                            };
+                            for (key, value) in context {
+                                set.insert(format!("context.{}", key), value);
+                            }
+                            self.update_saga(saga_id, doc! { "$set": set }).await?;
+                        }
+                        StepOutcome::Rejected(status) => {
+                            self.fail_saga(saga_id, index, next_step, &status).await?;
+                        }
+                        StepOutcome::Exhausted(e) => {
+                            let status =
+                                Status::unavailable(format!("{} failed: {}", step.step.name(), e));
+                            self.fail_saga(saga_id, index, next_step, &status).await?;
+                        }
+                    }
+                }
+                SagaAction::FinishCompensation => {
+                    self.update_saga(
+                        saga_id,
+                        doc! {
+                            "$set": { "status": SAGA_COMPENSATED, "completed_at": DateTime::now() },
+                            "$unset": { "locked_until": "" },
+                        },
+                    )
+                    .await?;
+                    info!("Compensated {} saga {}", definition.saga_type, saga_id);
+                    return Err(saga_error(&saga_doc));
+                }
+                SagaAction::Compensate(index) => {
+                    let step = &definition.steps[index];
+                    let outcome = self.run_step(&saga, index, step, true).await?;
+                    let (_, next_step) = after_step(index, true, &outcome);
+                    let failure = match outcome {
+                        StepOutcome::Done(_) => None,
+                        StepOutcome::Rejected(status) => Some(status.message().to_string()),
+                        StepOutcome::Exhausted(e) => Some(e),
+                    };
+
+                    match failure {
+                        None => {
+                            self.update_saga(
+                                saga_id,
+                                doc! {
+                                    "$set": {
+                                        format!("steps.{}.status", index): STEP_COMPENSATED,
+                                        "current_step": next_step as i32,
+                                    }
+                                },
+                            )
+                            .await?;
+                        }
+                        Some(e) => {
+                            error!(
+                                "Failed to compensate step {} of saga {}, the saga needs manual attention: {}",
+                                step.step.name(),
+                                saga_id,
+                                e
+                            );
+                            self.update_saga(
+                                saga_id,
+                                doc! {
+                                    "$set": { "status": SAGA_FAILED, "compensation_error": &e },
+                                    "$unset": { "locked_until": "" },
+                                },
+                            )
+                            .await?;
+                            return Err(Status::internal(format!(
+                                "Saga {} failed and could not be compensated",
+                                saga_id
+                            )));
+                        }
+                    }
+                }
+                SagaAction::Stop if status == SAGA_COMPLETED => return Ok(saga_doc),
+                SagaAction::Stop => return Err(saga_error(&saga_doc)),
+            }
+        }
+    }
+
+    // Compensation starts from the current step given by `after_step`
+    async fn fail_saga(
+        &self,
+        saga_id: ObjectId,
+        index: usize,
+        current_step: usize,
+        status: &Status,
+    ) -> Result<(), Status> {
+        warn!(
+            "Saga {} failed at step {}, compensating: {}",
+            saga_id,
+            index,
+            status.message()
+        );
+
+        self.update_saga(
+            saga_id,
+            doc! {
+                "$set": {
+                    "status": SAGA_COMPENSATING,
+                    format!("steps.{}.status", index): STEP_FAILED,
+                    "current_step": current_step as i32,
+                    "error": status.message(),
+                    "error_code": status.code() as i32,
+                }
+            },
+        )
+        .await
+    }
+
+    async fn run_step(
+        &self,
+        saga: &Saga,
+        index: usize,
+        definition: &StepDefinition,
+        compensate: bool,
+    ) -> Result<StepOutcome, Status> {
+        let policy = &definition.policy;
+        let (action, attempts_key) = if compensate {
+            ("Compensation of step", "compensation_attempts")
+        } else {
+            ("Step", "attempts")
+        };
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.update_saga(
+                saga.id,
+                doc! {
+                    "$set": {
+                        "locked_until": self.lease_expiry(),
+                        format!("steps.{}.{}", index, attempts_key): attempt as i32,
+                    }
+                },
+            )
+            .await?;
+
+            let result = if compensate {
+                tokio::time::timeout(policy.timeout, definition.step.compensate(self, saga))
+                    .await
+                    .map(|result| result.map(|_| Document::new()))
+            } else {
+                tokio::time::timeout(policy.timeout, definition.step.execute(self, saga)).await
+            };
+            let e = match result {
+                Ok(Ok(context)) => return Ok(StepOutcome::Done(context)),
+                Ok(Err(StepError::Rejected(status))) => return Ok(StepOutcome::Rejected(*status)),
+                Ok(Err(StepError::Retryable(e))) => e,
+                Err(_) => format!("Timed out after {:?}", policy.timeout),
+            };
+
+            warn!(
+                "{} {} of saga {} failed (attempt {}/{}): {}",
+                action,
+                definition.step.name(),
+                saga.id,
+                attempt,
+                policy.max_attempts,
+                e
+            );
+            self.update_saga(
+                saga.id,
+                doc! { "$set": { format!("steps.{}.error", index): &e } },
+            )
+            .await?;
+
+            if attempt >= policy.max_attempts {
+                return Ok(StepOutcome::Exhausted(e));
+            }
+            tokio::time::sleep(policy.retry_delay * attempt).await;
+        }
+    }
+
+    // Resumes sagas whose lease expired, which happens when the instance
+    // running them stopped before they were finished
+    pub async fn recover_sagas(&self) -> Result<usize, Status> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let mut recovered = 0;
+        loop {
+            let saga = self
+                .sagas_collection()
+                .find_one_and_update(
+                    doc! {
+                        "status": { "$in": [SAGA_RUNNING, SAGA_COMPENSATING] },
+                        "locked_until": { "$lt": DateTime::now() },
+                    },
+                    doc! { "$set": { "locked_until": self.lease_expiry() } },
+                    options.clone(),
+                )
+                .await
+                .map_err(|e| Status::internal(format!("Failed to claim saga: {}", e)))?;
+            let saga = match saga {
+                Some(saga) => saga,
+                None => return Ok(recovered),
+            };
+            recovered += 1;
+
+            let saga_id = saga.get_object_id("_id").unwrap();
+            let saga_type = saga.get_str("saga_type").unwrap_or_default();
+            warn!(
+                "Resuming {} saga {} ({})",
+                saga_type,
+                saga_id,
+                saga.get_str("status").unwrap_or_default()
+            );
+
+            let definition = match self.saga_definition(saga_type) {
+                Some(definition) => definition,
+                None => {
+                    error!("Unknown saga type {} for saga {}", saga_type, saga_id);
+                    self.update_saga(
+                        saga_id,
+                        doc! {
+                            "$set": { "status": SAGA_FAILED, "error": "Unknown saga type" },
+                            "$unset": { "locked_until": "" },
+                        },
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+            match self.drive_saga(&definition, saga_id).await {
+                Ok(_) => info!("Recovered saga {}", saga_id),
+                Err(status) => warn!("Recovered saga {}: {}", saga_id, status.message()),
+            }
+        }
+    }
+
+    pub async fn run_saga_recovery(self) {
+        info!(
+            "Saga recovery started, checking every {:?}",
+            self.saga_config.recovery_interval
+        );
+
+        // The first tick is immediate, so sagas left behind by the previous
+        // run are resumed at startup
+        let mut interval = tokio::time::interval(self.saga_config.recovery_interval);
+        loop {
+            interval.tick().await;
+            match self.recover_sagas().await {
+                Ok(0) => {}
+                Ok(recovered) => info!("Recovered {} saga(s)", recovered),
+                Err(e) => error!("Failed to recover sagas: {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use crate::beneficiary::BeneficiaryPolicy;
+    use crate::fx::{FxConfig, StaticRateProvider};
+    use bank_common::approval::ApprovalConfig;
+    use bank_common::event_publisher::InMemoryPublisher;
+    use bank_common::outbox::OutboxConfig;
+    use bank_common::validation::ValidationPolicy;
+
+    const TEST_SAGA: &str = "TEST";
+
+    // How an attempt of a step or of its compensation ends
+    #[derive(Debug, Clone, Copy)]
+    enum Attempt {
+        Succeed,
+        Fail,
+        Reject,
+        // Starts and never finishes, so the attempt times out partway
+        Hang,
+    }
+    use Attempt::*;
+
+    // Plays its attempts in order, repeating the last one, and logs every
+    // attempt as it starts
+    #[derive(Debug)]
+    struct ScriptedStep {
+        name: &'static str,
+        execute: Vec<Attempt>,
+        compensate: Vec<Attempt>,
+        executions: AtomicUsize,
+        compensations: AtomicUsize,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ScriptedStep {
+        async fn attempt(
+            &self,
+            action: &str,
+            script: &[Attempt],
+            attempts: &AtomicUsize,
+        ) -> Result<(), StepError> {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", action, self.name));
+
+            match script[attempt.min(script.len() - 1)] {
+                Succeed => Ok(()),
+                Fail => Err(StepError::Retryable(format!("{} failed", self.name))),
+                Reject => Err(StepError::Rejected(Box::new(Status::failed_precondition(
+                    format!("{} rejected", self.name),
+                )))),
+                Hang => {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl SagaStep for ScriptedStep {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn execute(
+            &self,
+            _service: &MyDepositService,
+            _saga: &Saga,
+        ) -> Result<Document, StepError> {
+            self.attempt("execute", &self.execute, &self.executions)
+                .await?;
+            Ok(doc! { self.name: true })
+        }
+
+        async fn compensate(
+            &self,
+            _service: &MyDepositService,
+            _saga: &Saga,
+        ) -> Result<(), StepError> {
+            self.attempt("compensate", &self.compensate, &self.compensations)
+                .await
+        }
+    }
+
+    fn policy() -> StepPolicy {
+        StepPolicy {
+            timeout: Duration::from_millis(200),
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(10),
+        }
+    }
+
+    // The steps of the test saga, with every attempt succeeding unless
+    // scripted otherwise
+    struct Script {
+        log: Arc<Mutex<Vec<String>>>,
+        steps: Vec<ScriptedStep>,
+    }
+
+    impl Script {
+        fn new() -> Self {
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let steps = ["reserve", "debit", "credit", "notify"]
+                .into_iter()
+                .map(|name| ScriptedStep {
+                    name,
+                    execute: vec![Succeed],
+                    compensate: vec![Succeed],
+                    executions: AtomicUsize::new(0),
+                    compensations: AtomicUsize::new(0),
+                    log: log.clone(),
+                })
+                .collect();
+            Self { log, steps }
+        }
+
+        fn execute(mut self, name: &str, attempts: &[Attempt]) -> Self {
+            self.step(name).execute = attempts.to_vec();
+            self
+        }
+
+        fn compensate(mut self, name: &str, attempts: &[Attempt]) -> Self {
+            self.step(name).compensate = attempts.to_vec();
+            self
+        }
+
+        fn step(&mut self, name: &str) -> &mut ScriptedStep {
+            self.steps
+                .iter_mut()
+                .find(|step| step.name == name)
+                .unwrap()
+        }
+
+        fn definition(self) -> (SagaDefinition, Arc<Mutex<Vec<String>>>) {
+            let definition = SagaDefinition {
+                saga_type: TEST_SAGA,
+                steps: self
+                    .steps
+                    .into_iter()
+                    .map(|step| StepDefinition {
+                        step: Arc::new(step),
+                        policy: policy(),
+                    })
+                    .collect(),
+            };
+            (definition, self.log)
+        }
+    }
+
+    async fn test_service(definition: SagaDefinition) -> MyDepositService {
+        let uri = std::env::var("MONGODB_URI")
+            .unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+        let mut service = MyDepositService::new(
+            &uri,
+            BeneficiaryPolicy {
+                cooling_off_period: chrono::Duration::hours(24),
+                cooling_off_limit: 1000.0,
+            },
+            None,
+            ValidationPolicy {
+                default_currency: "EUR".to_string(),
+                supported_currencies: vec!["EUR".to_string()],
+            },
+            FxConfig {
+                provider: Arc::new(StaticRateProvider::default()),
+                spread: 0.5,
+                quote_ttl: chrono::Duration::seconds(30),
+            },
+            ApprovalConfig {
+                ttl: chrono::Duration::hours(24),
+                expiry_interval: Duration::from_secs(60),
+            },
+            OutboxConfig {
+                publisher: Arc::new(InMemoryPublisher::default()),
+                relay_interval: Duration::from_secs(1),
+                batch_size: 100,
+            },
+            SagaConfig {
+                policy: policy(),
+                lease: chrono::Duration::seconds(60),
+                recovery_interval: Duration::from_secs(30),
+            },
+        )
+        .await
+        .unwrap();
+        service.db = Arc::new(
+            service
+                .client
+                .database(&format!("deposit_saga_test_{}", ObjectId::new())),
+        );
+        service.saga_definitions.insert(TEST_SAGA, definition);
+        service
+    }
+
+    fn log(log: &Arc<Mutex<Vec<String>>>) -> Vec<String> {
+        log.lock().unwrap().clone()
+    }
+
+    async fn only_saga(service: &MyDepositService) -> Document {
+        service
+            .sagas_collection()
+            .find_one(doc! {}, None)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    fn step_status(saga: &Document, index: usize) -> &str {
+        saga.get_array("steps").unwrap()[index]
+            .as_document()
+            .unwrap()
+            .get_str("status")
+            .unwrap()
+    }
+
+    fn step_attempts(saga: &Document, index: usize, key: &str) -> i32 {
+        saga.get_array("steps").unwrap()[index]
+            .as_document()
+            .unwrap()
+            .get_i32(key)
+            .unwrap_or_default()
+    }
+
+    // Leaves the saga as an instance that stopped before `current_step`
+    // would have, with its lease expired
+    async fn interrupt(service: &MyDepositService, status: &str, current_step: usize) {
+        let mut set = doc! {
+            "status": status,
+            "current_step": current_step as i32,
+            "locked_until": DateTime::from_millis(0),
+        };
+        for index in 0..current_step {
+            set.insert(format!("steps.{}.status", index), STEP_DONE);
+        }
+        service
+            .sagas_collection()
+            .update_one(doc! {}, doc! { "$set": set }, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn runs_every_step_in_order_and_keeps_their_context() {
+        let (definition, steps) = Script::new().definition();
+        let service = test_service(definition).await;
+
+        let saga = service.run_saga(TEST_SAGA, doc! {}).await.unwrap();
+
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_COMPLETED);
+        assert!(saga.get("locked_until").is_none());
+        let context = saga.get_document("context").unwrap();
+        assert_eq!(context.keys().count(), 4);
+        assert_eq!(
+            log(&steps),
+            [
+                "execute reserve",
+                "execute debit",
+                "execute credit",
+                "execute notify"
+            ]
+        );
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn retries_a_failing_step_until_it_succeeds() {
+        let (definition, steps) = Script::new()
+            .execute("debit", &[Fail, Hang, Succeed])
+            .definition();
+        let service = test_service(definition).await;
+
+        let saga = service.run_saga(TEST_SAGA, doc! {}).await.unwrap();
+
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_COMPLETED);
+        assert_eq!(step_attempts(&saga, 1, "attempts"), 3);
+        assert_eq!(log(&steps)[1..4], ["execute debit"; 3]);
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn compensates_the_steps_before_a_rejected_step_in_reverse_order() {
+        let (definition, steps) = Script::new().execute("credit", &[Reject]).definition();
+        let service = test_service(definition).await;
+
+        let status = service.run_saga(TEST_SAGA, doc! {}).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(status.message(), "credit rejected");
+        let saga = only_saga(&service).await;
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_COMPENSATED);
+        assert_eq!(step_status(&saga, 2), STEP_FAILED);
+        assert_eq!(step_status(&saga, 1), STEP_COMPENSATED);
+        assert_eq!(step_status(&saga, 0), STEP_COMPENSATED);
+        assert_eq!(
+            log(&steps),
+            [
+                "execute reserve",
+                "execute debit",
+                "execute credit",
+                "compensate debit",
+                "compensate reserve",
+            ]
+        );
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn compensates_a_step_that_timed_out_on_every_attempt_as_well() {
+        let (definition, steps) = Script::new().execute("credit", &[Hang]).definition();
+        let service = test_service(definition).await;
+
+        let status = service.run_saga(TEST_SAGA, doc! {}).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(status.message().contains("Timed out"));
+        let saga = only_saga(&service).await;
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_COMPENSATED);
+        assert_eq!(step_attempts(&saga, 2, "attempts"), 3);
+        assert_eq!(
+            log(&steps)[5..],
+            [
+                "compensate credit",
+                "compensate debit",
+                "compensate reserve"
+            ]
+        );
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn retries_a_compensation_that_timed_out_partway() {
+        let (definition, steps) = Script::new()
+            .execute("notify", &[Reject])
+            .compensate("debit", &[Hang, Succeed])
+            .definition();
+        let service = test_service(definition).await;
+
+        service.run_saga(TEST_SAGA, doc! {}).await.unwrap_err();
+
+        let saga = only_saga(&service).await;
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_COMPENSATED);
+        assert_eq!(step_attempts(&saga, 1, "compensation_attempts"), 2);
+        assert_eq!(
+            log(&steps)[4..],
+            [
+                "compensate credit",
+                "compensate debit",
+                "compensate debit",
+                "compensate reserve"
+            ]
+        );
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn fails_the_saga_when_a_compensation_keeps_timing_out() {
+        let (definition, steps) = Script::new()
+            .execute("credit", &[Reject])
+            .compensate("debit", &[Hang])
+            .definition();
+        let service = test_service(definition).await;
+
+        let status = service.run_saga(TEST_SAGA, doc! {}).await.unwrap_err();
+
+        assert_eq!(status.code(), Code::Internal);
+        let saga = only_saga(&service).await;
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_FAILED);
+        assert!(saga
+            .get_str("compensation_error")
+            .unwrap()
+            .contains("Timed out"));
+        assert!(saga.get("locked_until").is_none());
+        assert_eq!(step_attempts(&saga, 1, "compensation_attempts"), 3);
+        // The failed compensation is not skipped over
+        assert!(!log(&steps).contains(&"compensate reserve".to_string()));
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn recovers_a_saga_whose_lease_expired_at_the_step_it_stopped_in() {
+        let (definition, steps) = Script::new().definition();
+        let service = test_service(definition.clone()).await;
+        service.create_saga(&definition, doc! {}).await.unwrap();
+        interrupt(&service, SAGA_RUNNING, 2).await;
+
+        assert_eq!(service.recover_sagas().await.unwrap(), 1);
+
+        let saga = only_saga(&service).await;
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_COMPLETED);
+        assert_eq!(log(&steps), ["execute credit", "execute notify"]);
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn recovers_an_interrupted_compensation_in_reverse_order() {
+        let (definition, steps) = Script::new().definition();
+        let service = test_service(definition.clone()).await;
+        service.create_saga(&definition, doc! {}).await.unwrap();
+        interrupt(&service, SAGA_COMPENSATING, 2).await;
+
+        assert_eq!(service.recover_sagas().await.unwrap(), 1);
+
+        let saga = only_saga(&service).await;
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_COMPENSATED);
+        assert_eq!(log(&steps), ["compensate debit", "compensate reserve"]);
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn leaves_sagas_with_a_live_lease_to_their_instance() {
+        let (definition, steps) = Script::new().definition();
+        let service = test_service(definition.clone()).await;
+        service.create_saga(&definition, doc! {}).await.unwrap();
+
+        assert_eq!(service.recover_sagas().await.unwrap(), 0);
+
+        let saga = only_saga(&service).await;
+        assert_eq!(saga.get_str("status").unwrap(), SAGA_RUNNING);
+        assert!(log(&steps).is_empty());
+
+        service.db.as_ref().drop(None).await.unwrap();
+    }
+
+    // The driver only acts on the persisted status and current step, which
+    // is what lets a recovered saga carry on where it stopped
+    #[test]
+    fn a_failed_step_is_compensated_from_where_its_outcome_leaves_it() {
+        let rejected = StepOutcome::Rejected(Status::failed_precondition("rejected"));
+        let exhausted = StepOutcome::Exhausted("timed out".to_string());
+
+        let (status, current_step) = after_step(2, false, &rejected);
+        assert_eq!(
+            next_action(status, current_step, 4),
+            SagaAction::Compensate(1)
+        );
+        let (status, current_step) = after_step(2, false, &exhausted);
+        assert_eq!(
+            next_action(status, current_step, 4),
+            SagaAction::Compensate(2)
+        );
+        let (status, current_step) = after_step(1, true, &exhausted);
+        assert_eq!(next_action(status, current_step, 4), SagaAction::Stop);
+    }
+
+    #[test]
+    fn registers_the_transfer_saga() {
+        let definitions = definitions(&policy());
+        let steps: Vec<&str> = definitions[transfer_saga::TRANSFER_SAGA]
+            .steps
+            .iter()
+            .map(|definition| definition.step.name())
+            .collect();
+
+        assert_eq!(
+            steps,
+            [
+                "book_transfer",
+                "post_fee",
+                "record_usage",
+                "notify_overdraft"
+            ]
+        );
+    }
+}
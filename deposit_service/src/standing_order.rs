@@ -0,0 +1,755 @@
+use chrono::{Datelike, Duration, Months, TimeZone, Utc, Weekday};
+use futures::stream::TryStreamExt;
+use log::{error, info, warn};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Collection,
+};
+
+use crate::deposit_service::deposit::{
+    CreateStandingOrderRequest, MakeDepositRequest, StandingOrder, StandingOrderFrequency,
+    StandingOrderStatus,
+};
+use crate::deposit_service::MyDepositService;
+use crate::notifier::Notifier;
+use bank_common::validation::Validator;
+
+// How long a scheduler instance owns a due order before another one may pick it up
+const EXECUTION_LOCK_MINUTES: i64 = 5;
+
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    pub interval: std::time::Duration,
+    pub max_retries: i32,
+    pub retry_delay: Duration,
+    pub notifier: Notifier,
+}
+
+impl Display for StandingOrderFrequency {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let frequency_str = match self {
+            StandingOrderFrequency::Once => "ONCE",
+            StandingOrderFrequency::Daily => "DAILY",
+            StandingOrderFrequency::Weekly => "WEEKLY",
+            StandingOrderFrequency::Monthly => "MONTHLY",
+            StandingOrderFrequency::EndOfMonth => "END_OF_MONTH",
+        };
+
+        write!(f, "{}", frequency_str)
+    }
+}
+
+impl FromStr for StandingOrderFrequency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ONCE" => Ok(StandingOrderFrequency::Once),
+            "DAILY" => Ok(StandingOrderFrequency::Daily),
+            "WEEKLY" => Ok(StandingOrderFrequency::Weekly),
+            "MONTHLY" => Ok(StandingOrderFrequency::Monthly),
+            "END_OF_MONTH" => Ok(StandingOrderFrequency::EndOfMonth),
+            _ => Err(format!("Invalid standing order frequency: {}", s)),
+        }
+    }
+}
+
+impl Display for StandingOrderStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let status_str = match self {
+            StandingOrderStatus::Active => "ACTIVE",
+            StandingOrderStatus::Completed => "COMPLETED",
+            StandingOrderStatus::Cancelled => "CANCELLED",
+            StandingOrderStatus::Failed => "FAILED",
+        };
+
+        write!(f, "{}", status_str)
+    }
+}
+
+impl FromStr for StandingOrderStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ACTIVE" => Ok(StandingOrderStatus::Active),
+            "COMPLETED" => Ok(StandingOrderStatus::Completed),
+            "CANCELLED" => Ok(StandingOrderStatus::Cancelled),
+            "FAILED" => Ok(StandingOrderStatus::Failed),
+            _ => Err(format!("Invalid standing order status: {}", s)),
+        }
+    }
+}
+
+// Unadjusted date of the n-th occurrence. Every occurrence is derived from
+// the start date, so month-end clamping never drifts (31 Jan -> 28 Feb -> 31 Mar).
+pub fn scheduled_at(
+    frequency: StandingOrderFrequency,
+    start: chrono::DateTime<Utc>,
+    occurrence: u32,
+) -> Option<chrono::DateTime<Utc>> {
+    match frequency {
+        StandingOrderFrequency::Once => (occurrence == 0).then_some(start),
+        StandingOrderFrequency::Daily => {
+            start.checked_add_signed(Duration::days(occurrence as i64))
+        }
+        StandingOrderFrequency::Weekly => {
+            start.checked_add_signed(Duration::weeks(occurrence as i64))
+        }
+        StandingOrderFrequency::Monthly => start.checked_add_months(Months::new(occurrence)),
+        StandingOrderFrequency::EndOfMonth => start
+            .with_day(1)?
+            .checked_add_months(Months::new(occurrence + 1))?
+            .checked_sub_signed(Duration::days(1)),
+    }
+}
+
+fn is_business_day(date: &chrono::DateTime<Utc>) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+fn following_business_day(mut date: chrono::DateTime<Utc>) -> chrono::DateTime<Utc> {
+    while !is_business_day(&date) {
+        date += Duration::days(1);
+    }
+    date
+}
+
+fn preceding_business_day(mut date: chrono::DateTime<Utc>) -> chrono::DateTime<Utc> {
+    while !is_business_day(&date) {
+        date -= Duration::days(1);
+    }
+    date
+}
+
+// Moves weekend dates to a business day: end-of-month orders use the
+// preceding business day, monthly orders the modified following convention
+// (following, unless that leaves the month) and everything else the following one.
+pub fn adjust_to_business_day(
+    frequency: StandingOrderFrequency,
+    date: chrono::DateTime<Utc>,
+) -> chrono::DateTime<Utc> {
+    match frequency {
+        StandingOrderFrequency::EndOfMonth => preceding_business_day(date),
+        StandingOrderFrequency::Monthly => {
+            let following = following_business_day(date);
+            if following.month() != date.month() {
+                preceding_business_day(date)
+            } else {
+                following
+            }
+        }
+        _ => following_business_day(date),
+    }
+}
+
+// Returns the first occurrence at or after `occurrence` together with its
+// business-day adjusted execution date. Daily orders skip weekends instead of
+// piling several executions onto the following Monday.
+pub fn next_execution(
+    frequency: StandingOrderFrequency,
+    start: chrono::DateTime<Utc>,
+    mut occurrence: u32,
+) -> Option<(u32, chrono::DateTime<Utc>)> {
+    loop {
+        let scheduled = scheduled_at(frequency, start, occurrence)?;
+        if frequency == StandingOrderFrequency::Daily && !is_business_day(&scheduled) {
+            occurrence += 1;
+            continue;
+        }
+        return Some((occurrence, adjust_to_business_day(frequency, scheduled)));
+    }
+}
+
+fn to_bson_datetime(value: chrono::DateTime<Utc>) -> DateTime {
+    DateTime::from_millis(value.timestamp_millis())
+}
+
+fn from_bson_datetime(value: &DateTime) -> chrono::DateTime<Utc> {
+    Utc.timestamp_millis_opt(value.timestamp_millis()).unwrap()
+}
+
+fn standing_order_from_document(order: &Document) -> Result<StandingOrder, String> {
+    let frequency = StandingOrderFrequency::from_str(order.get_str("frequency").unwrap())?;
+    let status = StandingOrderStatus::from_str(order.get_str("status").unwrap())?;
+
+    Ok(StandingOrder {
+        standing_order_id: order.get_object_id("_id").unwrap().to_hex(),
+        user_id: order.get_str("user_id").unwrap().to_string(),
+        from_account_id: order.get_str("from_account_id").unwrap().to_string(),
+        to_account_id: order.get_str("to_account_id").unwrap().to_string(),
+        amount: order.get_f64("amount").unwrap(),
+        frequency: frequency as i32,
+        status: status as i32,
+        start_timestamp: order.get_datetime("start_at").unwrap().timestamp_millis(),
+        end_timestamp: order
+            .get_datetime("end_at")
+            .map(|end_at| end_at.timestamp_millis())
+            .unwrap_or_default(),
+        next_execution_timestamp: order
+            .get_datetime("next_execution_at")
+            .unwrap()
+            .timestamp_millis(),
+        executions: order.get_i32("executions").unwrap(),
+        failure_count: order.get_i32("failure_count").unwrap(),
+        last_error: order.get_str("last_error").unwrap().to_string(),
+        reference: order.get_str("reference").unwrap().to_string(),
+    })
+}
+
+impl MyDepositService {
+    fn standing_orders_collection(&self) -> Collection<Document> {
+        self.db.collection("standing_orders")
+    }
+
+    pub async fn create_standing_order_for_user(
+        &self,
+        req: CreateStandingOrderRequest,
+    ) -> Result<StandingOrder, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let frequency = StandingOrderFrequency::from_i32(req.frequency)
+            .ok_or_else(|| Status::invalid_argument("Invalid standing order frequency"))?;
+
+        if let Some(status) = Validator::new()
+            .account("from_account_id", &req.from_account_id)
+            .account("to_account_id", &req.to_account_id)
+            .different_accounts("to_account_id", &req.from_account_id, &req.to_account_id)
+            .amount("amount", req.amount)
+            .error()
+        {
+            return Err(status);
+        }
+
+        let from_account_id = self.resolve_account_id(&req.from_account_id).await?;
+        let to_account_id = self.resolve_account_id(&req.to_account_id).await?;
+
+        let from_account_doc = accounts_collection
+            .find_one(doc! { "_id": from_account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get from account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+        accounts_collection
+            .find_one(doc! { "_id": to_account_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get to account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        if from_account_doc.get_str("user_id").unwrap() != req.user_id {
+            error!(
+                "User {} tried to create a standing order on account {}",
+                req.user_id, req.from_account_id
+            );
+            return Err(Status::permission_denied(
+                "Standing orders can only be created on your own accounts",
+            ));
+        }
+
+        let start = if req.start_timestamp == 0 {
+            Utc::now()
+        } else {
+            Utc.timestamp_millis_opt(req.start_timestamp)
+                .single()
+                .ok_or_else(|| Status::invalid_argument("Invalid start timestamp"))?
+        };
+        let end = match req.end_timestamp {
+            0 => None,
+            end_timestamp => Some(
+                Utc.timestamp_millis_opt(end_timestamp)
+                    .single()
+                    .ok_or_else(|| Status::invalid_argument("Invalid end timestamp"))?,
+            ),
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(Status::invalid_argument(
+                "End date must be after start date",
+            ));
+        }
+
+        let (occurrence, next_execution_at) = next_execution(frequency, start, 0)
+            .ok_or_else(|| Status::invalid_argument("Standing order has no executions"))?;
+
+        let new_standing_order = doc! {
+            "user_id": &req.user_id,
+            "from_account_id": from_account_id.to_hex(),
+            "to_account_id": to_account_id.to_hex(),
+            "amount": req.amount,
+            "frequency": frequency.to_string(),
+            "status": StandingOrderStatus::Active.to_string(),
+            "start_at": to_bson_datetime(start),
+            "end_at": end.map(to_bson_datetime),
+            "occurrence": occurrence as i32,
+            "next_execution_at": to_bson_datetime(next_execution_at),
+            "executions": 0,
+            "failure_count": 0,
+            "last_error": "",
+            "reference": &req.reference,
+            "created_at": DateTime::now(),
+        };
+
+        let insert_result = self
+            .standing_orders_collection()
+            .insert_one(new_standing_order, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create standing order: {}", e)))?;
+        let standing_order_id = insert_result.inserted_id.as_object_id().ok_or_else(|| {
+            Status::internal("Failed to create standing order: missing inserted_id")
+        })?;
+
+        info!(
+            "Standing order {} created: {} {} from {} to {}, first execution at {}",
+            standing_order_id,
+            frequency,
+            req.amount,
+            req.from_account_id,
+            req.to_account_id,
+            next_execution_at
+        );
+
+        self.get_standing_order(standing_order_id).await
+    }
+
+    pub async fn list_standing_orders_for_user(
+        &self,
+        user_id: &str,
+        account_id: &str,
+    ) -> Result<Vec<StandingOrder>, Status> {
+        let mut filter = doc! {
+            "user_id": user_id,
+        };
+        if !account_id.is_empty() {
+            let account_id = self.resolve_account_id(account_id).await?;
+            filter.insert("from_account_id", account_id.to_hex());
+        }
+
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! {
+            "created_at": -1,
+        });
+
+        let mut cursor = self
+            .standing_orders_collection()
+            .find(filter, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get standing orders: {}", e)))?;
+
+        let mut standing_orders = Vec::new();
+        while let Some(result) = cursor
+            .try_next()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get standing orders: {}", e)))?
+        {
+            standing_orders.push(standing_order_from_document(&result).map_err(Status::internal)?);
+        }
+
+        Ok(standing_orders)
+    }
+
+    pub async fn cancel_standing_order_for_user(
+        &self,
+        user_id: &str,
+        standing_order_id: &str,
+    ) -> Result<StandingOrder, Status> {
+        let object_id = ObjectId::from_str(standing_order_id)
+            .map_err(|_| Status::invalid_argument("Invalid standing order id"))?;
+
+        let standing_order = self.get_standing_order(object_id).await?;
+        if standing_order.user_id != user_id {
+            return Err(Status::not_found("Standing order not found"));
+        }
+
+        let filter = doc! {
+            "_id": object_id,
+            "status": StandingOrderStatus::Active.to_string(),
+        };
+        let update = doc! {
+            "$set": {
+                "status": StandingOrderStatus::Cancelled.to_string(),
+                "cancelled_at": DateTime::now(),
+            }
+        };
+
+        let update_result = self
+            .standing_orders_collection()
+            .update_one(filter, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to cancel standing order: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Err(Status::failed_precondition(
+                "Only active standing orders can be cancelled",
+            ));
+        }
+
+        info!("Standing order {} cancelled", standing_order_id);
+
+        self.get_standing_order(object_id).await
+    }
+
+    async fn get_standing_order(
+        &self,
+        standing_order_id: ObjectId,
+    ) -> Result<StandingOrder, Status> {
+        let standing_order_doc = self
+            .standing_orders_collection()
+            .find_one(doc! { "_id": standing_order_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get standing order: {}", e)))?
+            .ok_or_else(|| Status::not_found("Standing order not found"))?;
+
+        standing_order_from_document(&standing_order_doc).map_err(Status::internal)
+    }
+
+    pub async fn run_standing_order_scheduler(self, config: SchedulerConfig) {
+        info!(
+            "Standing order scheduler started, polling every {:?}",
+            config.interval
+        );
+
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.execute_due_standing_orders(&config).await {
+                error!("Failed to execute standing orders: {:?}", e);
+            }
+        }
+    }
+
+    async fn execute_due_standing_orders(&self, config: &SchedulerConfig) -> Result<(), Status> {
+        loop {
+            let now = Utc::now();
+
+            // Claim one due order at a time so that several deposit_service
+            // instances never execute the same occurrence twice.
+            let filter = doc! {
+                "status": StandingOrderStatus::Active.to_string(),
+                "next_execution_at": { "$lte": to_bson_datetime(now) },
+                "$or": [
+                    { "locked_until": Bson::Null },
+                    { "locked_until": { "$lt": to_bson_datetime(now) } },
+                ],
+            };
+            let update = doc! {
+                "$set": {
+                    "locked_until": to_bson_datetime(now + Duration::minutes(EXECUTION_LOCK_MINUTES)),
+                }
+            };
+            let options = FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build();
+
+            let standing_order = self
+                .standing_orders_collection()
+                .find_one_and_update(filter, update, options)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to claim standing order: {}", e)))?;
+
+            match standing_order {
+                Some(standing_order) => {
+                    self.execute_standing_order(&standing_order, config).await?
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    async fn execute_standing_order(
+        &self,
+        standing_order: &Document,
+        config: &SchedulerConfig,
+    ) -> Result<(), Status> {
+        let standing_order_id = standing_order.get_object_id("_id").unwrap();
+        let frequency =
+            StandingOrderFrequency::from_str(standing_order.get_str("frequency").unwrap())
+                .map_err(Status::internal)?;
+        let start = from_bson_datetime(standing_order.get_datetime("start_at").unwrap());
+        let end = standing_order
+            .get_datetime("end_at")
+            .ok()
+            .map(from_bson_datetime);
+        let occurrence = standing_order.get_i32("occurrence").unwrap() as u32;
+        let next_execution_at = *standing_order.get_datetime("next_execution_at").unwrap();
+        let failure_count = standing_order.get_i32("failure_count").unwrap();
+
+        let transfer_request = MakeDepositRequest {
+            from_account_id: standing_order
+                .get_str("from_account_id")
+                .unwrap()
+                .to_string(),
+            to_account_id: standing_order.get_str("to_account_id").unwrap().to_string(),
+            amount: standing_order.get_f64("amount").unwrap(),
+            is_bank_agent: false,
+            ..Default::default()
+        };
+
+        // The next occurrence within the order's validity, if there is one
+        let upcoming = next_execution(frequency, start, occurrence + 1)
+            .filter(|(_, execution_at)| end.is_none_or(|end| *execution_at <= end));
+
+        // The schedule moves past this occurrence before it is paid, so that it
+        // is never paid twice, even when recording the outcome fails or the
+        // order was cancelled in the meantime
+        let mut schedule = doc! {};
+        match upcoming {
+            Some((next_occurrence, next_execution_at)) => {
+                schedule.insert("occurrence", next_occurrence as i32);
+                schedule.insert("next_execution_at", to_bson_datetime(next_execution_at));
+            }
+            None => {
+                schedule.insert("status", StandingOrderStatus::Completed.to_string());
+            }
+        }
+        let claimed = self
+            .standing_orders_collection()
+            .find_one_and_update(
+                doc! {
+                    "_id": standing_order_id,
+                    "status": StandingOrderStatus::Active.to_string(),
+                    "occurrence": occurrence as i32,
+                    "next_execution_at": next_execution_at,
+                },
+                doc! { "$set": schedule },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to claim standing order: {}", e)))?;
+        if claimed.is_none() {
+            warn!(
+                "Standing order {} occurrence {} is no longer due",
+                standing_order_id, occurrence
+            );
+            return Ok(());
+        }
+
+        let update = match self.transfer(&transfer_request).await {
+            Ok(_) => {
+                info!(
+                    "Standing order {} executed occurrence {}",
+                    standing_order_id, occurrence
+                );
+
+                doc! {
+                    "$set": {
+                        "failure_count": 0,
+                        "last_error": "",
+                        "last_executed_at": DateTime::now(),
+                    },
+                    "$inc": { "executions": 1 },
+                    "$unset": { "locked_until": "" },
+                }
+            }
+            Err(status) if failure_count < config.max_retries => {
+                let retry_at = Utc::now() + config.retry_delay * (failure_count + 1);
+                warn!(
+                    "Standing order {} failed ({}), retrying at {}",
+                    standing_order_id,
+                    status.message(),
+                    retry_at
+                );
+
+                // Puts the occurrence back on the schedule
+                doc! {
+                    "$set": {
+                        "status": StandingOrderStatus::Active.to_string(),
+                        "occurrence": occurrence as i32,
+                        "next_execution_at": to_bson_datetime(retry_at),
+                        "failure_count": failure_count + 1,
+                        "last_error": status.message(),
+                    },
+                    "$unset": { "locked_until": "" },
+                }
+            }
+            Err(status) => {
+                error!(
+                    "Standing order {} failed after {} retries: {}",
+                    standing_order_id,
+                    failure_count,
+                    status.message()
+                );
+                self.notify_standing_order_failure(standing_order, status.message(), config)
+                    .await;
+
+                // Give up on this occurrence and carry on with the schedule
+                let mut update = doc! {
+                    "failure_count": 0,
+                    "last_error": status.message(),
+                };
+                if upcoming.is_none() {
+                    update.insert("status", StandingOrderStatus::Failed.to_string());
+                }
+                doc! { "$set": update, "$unset": { "locked_until": "" } }
+            }
+        };
+
+        self.standing_orders_collection()
+            .update_one(doc! { "_id": standing_order_id }, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update standing order: {}", e)))?;
+
+        Ok(())
+    }
+
+    // Notifications that cannot be sent are logged, as the outcome of the
+    // execution has to be recorded regardless
+    async fn notify_standing_order_failure(
+        &self,
+        standing_order: &Document,
+        reason: &str,
+        config: &SchedulerConfig,
+    ) {
+        let standing_order_id = standing_order.get_object_id("_id").unwrap().to_hex();
+        let template_data = HashMap::from([
+            ("standing_order_id".to_string(), standing_order_id.clone()),
+            ("reason".to_string(), reason.to_string()),
+        ]);
+
+        match config
+            .notifier
+            .send_template(
+                standing_order.get_str("user_id").unwrap(),
+                "standing_order_failed",
+                template_data,
+            )
+            .await
+        {
+            Ok(Some(notification_id)) => info!(
+                "Failure of standing order {} notified as {}",
+                standing_order_id, notification_id
+            ),
+            Ok(None) => warn!(
+                "Failure of standing order {} not notified, notifications are disabled",
+                standing_order_id
+            ),
+            Err(e) => error!(
+                "Failed to notify the failure of standing order {}: {}",
+                standing_order_id,
+                e.message()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> chrono::DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, 9, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn once_has_a_single_occurrence() {
+        let start = date(2024, 3, 6);
+        assert_eq!(
+            scheduled_at(StandingOrderFrequency::Once, start, 0),
+            Some(start)
+        );
+        assert_eq!(scheduled_at(StandingOrderFrequency::Once, start, 1), None);
+    }
+
+    #[test]
+    fn monthly_clamps_to_month_end_without_drifting() {
+        let start = date(2024, 1, 31);
+        let occurrences: Vec<_> = (0..4)
+            .map(|n| scheduled_at(StandingOrderFrequency::Monthly, start, n).unwrap())
+            .collect();
+        assert_eq!(
+            occurrences,
+            [
+                date(2024, 1, 31),
+                date(2024, 2, 29),
+                date(2024, 3, 31),
+                date(2024, 4, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn end_of_month_uses_the_last_day_of_each_month() {
+        let start = date(2023, 1, 15);
+        let occurrences: Vec<_> = (0..3)
+            .map(|n| scheduled_at(StandingOrderFrequency::EndOfMonth, start, n).unwrap())
+            .collect();
+        assert_eq!(
+            occurrences,
+            [date(2023, 1, 31), date(2023, 2, 28), date(2023, 3, 31)]
+        );
+    }
+
+    #[test]
+    fn weekly_and_daily_add_whole_periods() {
+        let start = date(2024, 3, 4);
+        assert_eq!(
+            scheduled_at(StandingOrderFrequency::Weekly, start, 2),
+            Some(date(2024, 3, 18))
+        );
+        assert_eq!(
+            scheduled_at(StandingOrderFrequency::Daily, start, 3),
+            Some(date(2024, 3, 7))
+        );
+    }
+
+    #[test]
+    fn weekends_move_to_the_following_business_day() {
+        // Saturday 9 March 2024
+        assert_eq!(
+            adjust_to_business_day(StandingOrderFrequency::Weekly, date(2024, 3, 9)),
+            date(2024, 3, 11)
+        );
+        assert_eq!(
+            adjust_to_business_day(StandingOrderFrequency::Weekly, date(2024, 3, 8)),
+            date(2024, 3, 8)
+        );
+    }
+
+    #[test]
+    fn end_of_month_moves_to_the_preceding_business_day() {
+        // Sunday 31 March 2024
+        assert_eq!(
+            adjust_to_business_day(StandingOrderFrequency::EndOfMonth, date(2024, 3, 31)),
+            date(2024, 3, 29)
+        );
+    }
+
+    #[test]
+    fn monthly_uses_modified_following() {
+        // Saturday 15 June 2024 moves forward within the month
+        assert_eq!(
+            adjust_to_business_day(StandingOrderFrequency::Monthly, date(2024, 6, 15)),
+            date(2024, 6, 17)
+        );
+        // Saturday 29 June 2024 would leave the month, so it moves back
+        assert_eq!(
+            adjust_to_business_day(StandingOrderFrequency::Monthly, date(2024, 6, 29)),
+            date(2024, 6, 28)
+        );
+    }
+
+    #[test]
+    fn daily_skips_weekends() {
+        // Friday 8 March 2024
+        let start = date(2024, 3, 8);
+        assert_eq!(
+            next_execution(StandingOrderFrequency::Daily, start, 1),
+            Some((3, date(2024, 3, 11)))
+        );
+    }
+
+    #[test]
+    fn next_execution_keeps_the_occurrence_of_adjusted_dates() {
+        let start = date(2024, 1, 31);
+        // 31 March 2024 is a Sunday: modified following moves it back to Friday
+        assert_eq!(
+            next_execution(StandingOrderFrequency::Monthly, start, 2),
+            Some((2, date(2024, 3, 29)))
+        );
+        assert_eq!(next_execution(StandingOrderFrequency::Once, start, 1), None);
+    }
+}
@@ -0,0 +1,581 @@
+use futures::stream::TryStreamExt;
+use log::{info, warn};
+use std::sync::Arc;
+use tonic::{Code, Status};
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    Collection,
+};
+
+use crate::deposit_service::deposit::{MakeDepositRequest, ReverseTransactionRequest};
+use crate::deposit_service::MyDepositService;
+use crate::fx::Conversion;
+use crate::outbox;
+use crate::saga::{Saga, SagaDefinition, SagaStep, StepDefinition, StepError, StepPolicy};
+use bank_common::fee_engine::{self, FeeQuote, OPERATION_TRANSFER};
+use bank_common::limits;
+use bank_common::outbox::OutboxService;
+use bank_common::overdraft;
+
+// A transfer books both legs, taking the FX quote it was priced with in the
+// same transaction, charges the transfer fee, counts towards the limits of the debited account and
+// alerts its owner when it goes into overdraft. Accounts, limits and fees are
+// checked before the saga starts.
+pub const TRANSFER_SAGA: &str = "TRANSFER";
+
+// Booked transfers are compensated with a reversal
+const COMPENSATION_REASON_CODE: &str = "TECH";
+const COMPENSATION_NOTE: &str = "Transfer saga compensated";
+
+pub fn definition(policy: &StepPolicy) -> SagaDefinition {
+    let steps: Vec<Arc<dyn SagaStep>> = vec![
+        Arc::new(BookTransfer),
+        Arc::new(PostFee),
+        Arc::new(RecordUsage),
+        Arc::new(NotifyOverdraft),
+    ];
+
+    SagaDefinition {
+        saga_type: TRANSFER_SAGA,
+        steps: steps
+            .into_iter()
+            .map(|step| StepDefinition {
+                step,
+                policy: policy.clone(),
+            })
+            .collect(),
+    }
+}
+
+pub fn transfer_payload(
+    req: &MakeDepositRequest,
+    from_account_id: ObjectId,
+    to_account_id: ObjectId,
+    channel: &str,
+    from_balance: f64,
+    fee_quote: &FeeQuote,
+    conversion: &Conversion,
+) -> Document {
+    doc! {
+        "from_account_id": from_account_id,
+        "to_account_id": to_account_id,
+        "amount": req.amount,
+        "is_bank_agent": req.is_bank_agent,
+        "user_id": &req.user_id,
+        "channel": channel,
+        "from_balance": from_balance,
+        "fee": fee_quote.fee,
+        "fee_rule_name": &fee_quote.rule_name,
+        "free_operations_remaining": fee_quote.free_operations_remaining,
+        "from_currency": &conversion.from_currency,
+        "to_currency": &conversion.to_currency,
+        "fx_rate": conversion.rate,
+        "converted_amount": conversion.converted_amount,
+        "fx_quote_id": conversion.quote_id,
+    }
+}
+
+fn payload_fee_quote(payload: &Document) -> FeeQuote {
+    FeeQuote {
+        fee: payload.get_f64("fee").unwrap_or_default(),
+        rule_name: payload
+            .get_str("fee_rule_name")
+            .unwrap_or_default()
+            .to_string(),
+        free_operations_remaining: payload
+            .get_i32("free_operations_remaining")
+            .unwrap_or_default(),
+    }
+}
+
+fn payload_conversion(payload: &Document) -> Conversion {
+    Conversion {
+        from_currency: payload.get_str("from_currency").unwrap().to_string(),
+        to_currency: payload.get_str("to_currency").unwrap().to_string(),
+        rate: payload.get_f64("fx_rate").unwrap(),
+        converted_amount: payload.get_f64("converted_amount").unwrap(),
+        quote_id: payload.get_object_id("fx_quote_id").ok(),
+    }
+}
+
+fn is_bank_agent(saga: &Saga) -> bool {
+    saga.payload.get_bool("is_bank_agent").unwrap_or_default()
+}
+
+impl MyDepositService {
+    // Both legs of the transfer booked by a saga, if any
+    pub(crate) async fn saga_legs(&self, saga_id: ObjectId) -> Result<Vec<Document>, Status> {
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+
+        transactions_collection
+            .find(
+                doc! { "saga_id": saga_id, "reversal": { "$ne": true } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get transactions: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get transactions: {}", e)))
+    }
+
+    // Balances, transactions and their events are written atomically, and
+    // only once for each saga
+    async fn book_transfer(&self, saga: &Saga) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+
+        let legs = self.saga_legs(saga.id).await?;
+        if !legs.is_empty() {
+            let leg_id = |transaction_type: &str| {
+                legs.iter()
+                    .find(|leg| leg.get_str("type") == Ok(transaction_type))
+                    .map(|leg| leg.get_object_id("_id").unwrap())
+            };
+            return Ok(doc! {
+                "deposit_transaction_id": leg_id("Deposit"),
+                "withdrawal_transaction_id": leg_id("Withdrawal"),
+            });
+        }
+
+        let payload = &saga.payload;
+        let from_account_id = payload.get_object_id("from_account_id").unwrap();
+        let to_account_id = payload.get_object_id("to_account_id").unwrap();
+        let amount = payload.get_f64("amount").unwrap();
+        let conversion = payload_conversion(payload);
+
+        let mut session = self.start_transaction().await?;
+
+        if let Some(quote_id) = conversion.quote_id {
+            self.use_fx_quote(quote_id, saga.id, &mut session).await?;
+        }
+
+        // The debit only applies while the funds, including the fee charged
+        // by the next step, are still available. Agent transfers skip the
+        // balance check.
+        let mut from_filter = doc! { "_id": from_account_id };
+        if !is_bank_agent(saga) {
+            let fee = payload.get_f64("fee").unwrap_or_default();
+            from_filter.insert("$expr", overdraft::covers_expr(amount + fee));
+        }
+        let update_result = accounts_collection
+            .update_one_with_session(
+                from_filter,
+                doc! { "$inc": { "balance": -amount } },
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| {
+                Status::internal(format!("Failed to update from account balance: {}", e))
+            })?;
+        if update_result.matched_count == 0 {
+            return Err(Status::failed_precondition(
+                "Insufficient balance for transfer",
+            ));
+        }
+        accounts_collection
+            .update_one_with_session(
+                doc! { "_id": to_account_id },
+                doc! { "$inc": { "balance": conversion.converted_amount } },
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failedto update to account balance: {}", e)))?;
+
+        // Record the transaction
+        let timestamp = DateTime::now();
+
+        let mut new_transaction_deposit = doc! {
+            "from_account_id": from_account_id,
+            "to_account_id": to_account_id,
+            "amount": conversion.converted_amount,
+            "type": "Deposit",
+            "account_id": to_account_id,
+            "saga_id": saga.id,
+            "timestamp": timestamp
+        };
+        new_transaction_deposit.extend(conversion.leg_fields(&conversion.to_currency));
+
+        let mut new_transaction_withdrawal = doc! {
+            "from_account_id": from_account_id,
+            "to_account_id": to_account_id,
+            "amount": -amount,
+            "type": "Withdrawal",
+            "account_id": from_account_id,
+            "saga_id": saga.id,
+            "timestamp": timestamp
+        };
+        new_transaction_withdrawal.extend(conversion.leg_fields(&conversion.from_currency));
+
+        let deposit_transaction_id = transactions_collection
+            .insert_one_with_session(new_transaction_deposit, None, &mut session)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap();
+
+        let withdrawal_transaction_id = transactions_collection
+            .insert_one_with_session(new_transaction_withdrawal, None, &mut session)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap();
+
+        self.record_events(
+            &mut session,
+            vec![
+                outbox::new_event(
+                    outbox::FUNDS_WITHDRAWN,
+                    from_account_id,
+                    doc! {
+                        "transaction_id": withdrawal_transaction_id.to_hex(),
+                        "account_id": from_account_id.to_hex(),
+                        "counterparty_account_id": to_account_id.to_hex(),
+                        "amount": amount,
+                        "currency": &conversion.from_currency,
+                        "timestamp": timestamp.timestamp_millis(),
+                    },
+                ),
+                outbox::new_event(
+                    outbox::FUNDS_DEPOSITED,
+                    to_account_id,
+                    doc! {
+                        "transaction_id": deposit_transaction_id.to_hex(),
+                        "account_id": to_account_id.to_hex(),
+                        "counterparty_account_id": from_account_id.to_hex(),
+                        "amount": conversion.converted_amount,
+                        "currency": &conversion.to_currency,
+                        "timestamp": timestamp.timestamp_millis(),
+                    },
+                ),
+                outbox::new_event(
+                    outbox::TRANSFER_COMPLETED,
+                    from_account_id,
+                    doc! {
+                        "from_account_id": from_account_id.to_hex(),
+                        "to_account_id": to_account_id.to_hex(),
+                        "withdrawal_transaction_id": withdrawal_transaction_id.to_hex(),
+                        "deposit_transaction_id": deposit_transaction_id.to_hex(),
+                        "amount": amount,
+                        "currency": &conversion.from_currency,
+                        "converted_amount": conversion.converted_amount,
+                        "converted_currency": &conversion.to_currency,
+                        "fx_rate": conversion.rate,
+                        "fee": payload.get_f64("fee").unwrap_or_default(),
+                        "timestamp": timestamp.timestamp_millis(),
+                    },
+                ),
+            ],
+        )
+        .await?;
+
+        self.commit_transaction(&mut session).await?;
+
+        info!(
+            "Deposit of {} made from account {} to account {}",
+            amount, from_account_id, to_account_id
+        );
+
+        Ok(doc! {
+            "deposit_transaction_id": deposit_transaction_id,
+            "withdrawal_transaction_id": withdrawal_transaction_id,
+        })
+    }
+
+    async fn reverse_booked_transfer(&self, saga: &Saga) -> Result<(), Status> {
+        let legs = self.saga_legs(saga.id).await?;
+        let withdrawal = match legs
+            .iter()
+            .find(|leg| leg.get_str("type") == Ok("Withdrawal"))
+        {
+            Some(withdrawal) => withdrawal,
+            None => return Ok(()),
+        };
+
+        let request = ReverseTransactionRequest {
+            transaction_id: withdrawal.get_object_id("_id").unwrap().to_hex(),
+            reason_code: COMPENSATION_REASON_CODE.to_string(),
+            note: COMPENSATION_NOTE.to_string(),
+            reversed_by: format!("saga:{}", saga.id),
+        };
+        match self.reverse_transaction(&request).await {
+            Ok(reversal) => {
+                info!(
+                    "Reversed transfer of saga {} with reversal {}",
+                    saga.id, reversal.reversal_id
+                );
+                Ok(())
+            }
+            // Reversed by an earlier attempt, whose reversal committed with
+            // its audit record. Any other refusal leaves the money where it is.
+            Err(status) if status.code() == Code::FailedPrecondition => {
+                if self.is_reversed(withdrawal).await? {
+                    Ok(())
+                } else {
+                    Err(status)
+                }
+            }
+            Err(status) => Err(status),
+        }
+    }
+
+    async fn is_reversed(&self, transaction: &Document) -> Result<bool, Status> {
+        let reversals_collection: Collection<Document> =
+            self.db.collection("transaction_reversals");
+
+        let reversal = reversals_collection
+            .find_one(
+                doc! { "transaction_ids": transaction.get_object_id("_id").unwrap() },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get reversal: {}", e)))?;
+        Ok(reversal.is_some())
+    }
+
+    async fn saga_fee(&self, saga: &Saga) -> Result<Option<Document>, Status> {
+        let fees_collection: Collection<Document> = self.db.collection("fees");
+
+        let reference = match saga.context.get_object_id("withdrawal_transaction_id") {
+            Ok(withdrawal_transaction_id) => withdrawal_transaction_id.to_hex(),
+            Err(_) => return Ok(None),
+        };
+        fees_collection
+            .find_one(
+                doc! { "reference": reference, "operation": OPERATION_TRANSFER },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get fee: {}", e)))
+    }
+}
+
+#[derive(Debug)]
+struct BookTransfer;
+
+#[tonic::async_trait]
+impl SagaStep for BookTransfer {
+    fn name(&self) -> &str {
+        "book_transfer"
+    }
+
+    async fn execute(
+        &self,
+        service: &MyDepositService,
+        saga: &Saga,
+    ) -> Result<Document, StepError> {
+        Ok(service.book_transfer(saga).await?)
+    }
+
+    async fn compensate(&self, service: &MyDepositService, saga: &Saga) -> Result<(), StepError> {
+        service.reverse_booked_transfer(saga).await?;
+        if let Ok(quote_id) = saga.payload.get_object_id("fx_quote_id") {
+            service.release_fx_quote(quote_id, saga.id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct PostFee;
+
+#[tonic::async_trait]
+impl SagaStep for PostFee {
+    fn name(&self) -> &str {
+        "post_fee"
+    }
+
+    async fn execute(
+        &self,
+        service: &MyDepositService,
+        saga: &Saga,
+    ) -> Result<Document, StepError> {
+        let fee_income_account_id = match service.fee_income_account_id {
+            Some(fee_income_account_id) if !is_bank_agent(saga) => fee_income_account_id,
+            _ => return Ok(doc! {}),
+        };
+
+        if let Some(fee) = service.saga_fee(saga).await? {
+            return Ok(doc! { "fee_id": fee.get_object_id("_id").unwrap() });
+        }
+
+        let reference = saga
+            .context
+            .get_object_id("withdrawal_transaction_id")
+            .unwrap()
+            .to_hex();
+        let mut session = service.start_transaction().await?;
+        let fee_id = fee_engine::post_fee(
+            &service.db,
+            &mut session,
+            fee_income_account_id,
+            saga.payload.get_object_id("from_account_id").unwrap(),
+            OPERATION_TRANSFER,
+            &payload_fee_quote(&saga.payload),
+            &reference,
+        )
+        .await?;
+        service.commit_transaction(&mut session).await?;
+
+        Ok(match fee_id {
+            Some(fee_id) => doc! { "fee_id": fee_id },
+            None => doc! {},
+        })
+    }
+
+    async fn compensate(&self, service: &MyDepositService, saga: &Saga) -> Result<(), StepError> {
+        let fee = match service.saga_fee(saga).await? {
+            Some(fee) if fee.get_str("status") == Ok("CHARGED") => fee,
+            _ => return Ok(()),
+        };
+
+        let fee_id = fee.get_object_id("_id").unwrap().to_hex();
+        let reversed_by = format!("saga:{}", saga.id);
+        let mut session = service.start_transaction().await?;
+        match fee_engine::reverse_fee(
+            &service.db,
+            &mut session,
+            &fee_id,
+            COMPENSATION_NOTE,
+            &reversed_by,
+        )
+        .await
+        {
+            Ok(_) => {}
+            // Reversed by an earlier attempt, whose refund committed with it
+            Err(status) if status.code() == Code::FailedPrecondition => return Ok(()),
+            Err(status) => return Err(status.into()),
+        }
+        service.commit_transaction(&mut session).await?;
+        Ok(())
+    }
+}
+
+// Usage counters are not updated atomically, so a transfer interrupted in
+// this step may be counted twice, which only makes its limits stricter
+#[derive(Debug)]
+struct RecordUsage;
+
+#[tonic::async_trait]
+impl SagaStep for RecordUsage {
+    fn name(&self) -> &str {
+        "record_usage"
+    }
+
+    async fn execute(
+        &self,
+        service: &MyDepositService,
+        saga: &Saga,
+    ) -> Result<Document, StepError> {
+        if is_bank_agent(saga) {
+            return Ok(doc! {});
+        }
+
+        let from_account_id = saga.payload.get_object_id("from_account_id").unwrap();
+        if service.fee_income_account_id.is_some() {
+            let mut session = service.start_transaction().await?;
+            fee_engine::record_usage(
+                &service.db,
+                &mut session,
+                from_account_id,
+                OPERATION_TRANSFER,
+            )
+            .await?;
+            service.commit_transaction(&mut session).await?;
+        }
+        limits::record_usage(
+            &service.db,
+            from_account_id,
+            limits::OPERATION_TRANSFER,
+            saga.payload.get_str("channel").unwrap_or_default(),
+            saga.payload.get_f64("amount").unwrap(),
+        )
+        .await?;
+
+        Ok(doc! { "usage_recorded": true })
+    }
+
+    async fn compensate(&self, service: &MyDepositService, saga: &Saga) -> Result<(), StepError> {
+        if !saga.context.get_bool("usage_recorded").unwrap_or_default() {
+            return Ok(());
+        }
+
+        let from_account_id = saga.payload.get_object_id("from_account_id").unwrap();
+        if service.fee_income_account_id.is_some() {
+            let mut session = service.start_transaction().await?;
+            fee_engine::release_usage(
+                &service.db,
+                &mut session,
+                from_account_id,
+                OPERATION_TRANSFER,
+            )
+            .await?;
+            service.commit_transaction(&mut session).await?;
+        }
+        // Usage is given back to the day and month the transfer started in
+        limits::release_usage(
+            &service.db,
+            from_account_id,
+            limits::OPERATION_TRANSFER,
+            saga.payload.get_str("channel").unwrap_or_default(),
+            saga.payload.get_f64("amount").unwrap(),
+            saga.created_at,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+// A missed alert does not undo the transfer, so failures are only logged
+#[derive(Debug)]
+struct NotifyOverdraft;
+
+#[tonic::async_trait]
+impl SagaStep for NotifyOverdraft {
+    fn name(&self) -> &str {
+        "notify_overdraft"
+    }
+
+    async fn execute(
+        &self,
+        service: &MyDepositService,
+        saga: &Saga,
+    ) -> Result<Document, StepError> {
+        let accounts_collection: Collection<Document> = service.db.collection("accounts");
+        let payload = &saga.payload;
+        let from_account_id = payload.get_object_id("from_account_id").unwrap();
+
+        let result = match accounts_collection
+            .find_one(doc! { "_id": from_account_id }, None)
+            .await
+        {
+            Ok(Some(account)) => {
+                let from_balance = payload.get_f64("from_balance").unwrap();
+                let debited =
+                    payload.get_f64("amount").unwrap() + payload.get_f64("fee").unwrap_or_default();
+                overdraft::notify_overdraft(
+                    &service.db,
+                    &account,
+                    from_balance,
+                    from_balance - debited,
+                )
+                .await
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(Status::internal(format!("Failed to get account: {}", e))),
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to send the overdraft alert for saga {}: {:?}",
+                saga.id, e
+            );
+        }
+
+        Ok(doc! {})
+    }
+}
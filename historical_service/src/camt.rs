@@ -0,0 +1,423 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Write;
+
+// ISO 20022 bank-to-customer cash management messages. camt.053 is the
+// end-of-day statement, camt.052 the intraday account report; both share the
+// same body layout and only differ in root/container names and balance codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementKind {
+    EndOfDay,
+    Intraday,
+}
+
+impl StatementKind {
+    fn namespace(&self) -> &'static str {
+        match self {
+            StatementKind::EndOfDay => "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08",
+            StatementKind::Intraday => "urn:iso:std:iso:20022:tech:xsd:camt.052.001.08",
+        }
+    }
+
+    fn root_element(&self) -> &'static str {
+        match self {
+            StatementKind::EndOfDay => "BkToCstmrStmt",
+            StatementKind::Intraday => "BkToCstmrAcctRpt",
+        }
+    }
+
+    fn container_element(&self) -> &'static str {
+        match self {
+            StatementKind::EndOfDay => "Stmt",
+            StatementKind::Intraday => "Rpt",
+        }
+    }
+
+    fn closing_balance_code(&self) -> &'static str {
+        match self {
+            StatementKind::EndOfDay => "CLBD",
+            StatementKind::Intraday => "ITBD",
+        }
+    }
+
+    pub fn message_prefix(&self) -> &'static str {
+        match self {
+            StatementKind::EndOfDay => "CAMT053",
+            StatementKind::Intraday => "CAMT052",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatementEntry {
+    pub entry_reference: String,
+    pub amount: f64,
+    pub credit: bool,
+    pub booked_at: DateTime<Utc>,
+    pub transaction_code: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub message_id: String,
+    pub created_at: DateTime<Utc>,
+    pub account_id: String,
+    pub currency: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub opening_balance: f64,
+    pub closing_balance: f64,
+    pub entries: Vec<StatementEntry>,
+}
+
+// Checks the statement against the facets of the camt.05x.001.08 schema
+// that depend on its data (Max35Text/Max34Text identifiers,
+// ActiveOrHistoricCurrencyCode, and ActiveOrHistoricCurrencyAndAmount:
+// non-negative, 18 total / 5 fraction digits). The element structure is fixed
+// by `render`; the tests validate its output against the ISO 20022 XSDs in
+// testdata/xsd.
+pub fn check_schema_facets(statement: &Statement) -> Result<(), String> {
+    check_max_text("MsgId", &statement.message_id, 35)?;
+    check_max_text("Acct/Id/Othr/Id", &statement.account_id, 34)?;
+
+    if statement.currency.len() != 3 || !statement.currency.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(format!("Invalid currency code: {}", statement.currency));
+    }
+
+    if statement.from > statement.to {
+        return Err("Statement period start is after its end".to_string());
+    }
+
+    check_amount("OpeningBalance", statement.opening_balance)?;
+    check_amount("ClosingBalance", statement.closing_balance)?;
+
+    for entry in &statement.entries {
+        check_max_text("NtryRef", &entry.entry_reference, 35)?;
+        check_max_text("BkTxCd/Prtry/Cd", &entry.transaction_code, 35)?;
+        check_amount("Ntry/Amt", entry.amount)?;
+        if entry.amount < 0.0 {
+            return Err(format!("Negative entry amount for {}", entry.entry_reference));
+        }
+        if entry.booked_at < statement.from || entry.booked_at > statement.to {
+            return Err(format!(
+                "Entry {} is outside of the statement period",
+                entry.entry_reference
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_max_text(field: &str, value: &str, max_length: usize) -> Result<(), String> {
+    if value.is_empty() || value.chars().count() > max_length {
+        return Err(format!(
+            "{} must be between 1 and {} characters",
+            field, max_length
+        ));
+    }
+    Ok(())
+}
+
+fn check_amount(field: &str, value: f64) -> Result<(), String> {
+    if !value.is_finite() {
+        return Err(format!("{} is not a finite amount", field));
+    }
+    // 18 total digits with 2 used for the minor unit
+    if value.abs() >= 1e16 {
+        return Err(format!("{} exceeds the maximum amount", field));
+    }
+    Ok(())
+}
+
+pub fn render(statement: &Statement, kind: StatementKind) -> Result<String, String> {
+    check_schema_facets(statement)?;
+
+    let ccy = escape(&statement.currency);
+    let mut xml = String::new();
+
+    // Writing into a String never fails, so the fmt::Results are ignored.
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(xml, r#"<Document xmlns="{}">"#, kind.namespace());
+    let _ = writeln!(xml, "  <{}>", kind.root_element());
+
+    let _ = writeln!(xml, "    <GrpHdr>");
+    let _ = writeln!(xml, "      <MsgId>{}</MsgId>", escape(&statement.message_id));
+    let _ = writeln!(xml, "      <CreDtTm>{}</CreDtTm>", iso_date_time(&statement.created_at));
+    let _ = writeln!(xml, "    </GrpHdr>");
+
+    let _ = writeln!(xml, "    <{}>", kind.container_element());
+    let _ = writeln!(xml, "      <Id>{}</Id>", escape(&statement.message_id));
+    let _ = writeln!(xml, "      <CreDtTm>{}</CreDtTm>", iso_date_time(&statement.created_at));
+    let _ = writeln!(xml, "      <FrToDt>");
+    let _ = writeln!(xml, "        <FrDtTm>{}</FrDtTm>", iso_date_time(&statement.from));
+    let _ = writeln!(xml, "        <ToDtTm>{}</ToDtTm>", iso_date_time(&statement.to));
+    let _ = writeln!(xml, "      </FrToDt>");
+    let _ = writeln!(xml, "      <Acct>");
+    let _ = writeln!(
+        xml,
+        "        <Id><Othr><Id>{}</Id></Othr></Id>",
+        escape(&statement.account_id)
+    );
+    let _ = writeln!(xml, "        <Ccy>{}</Ccy>", ccy);
+    let _ = writeln!(xml, "      </Acct>");
+
+    write_balance(&mut xml, "OPBD", statement.opening_balance, &ccy, &statement.from);
+    write_balance(
+        &mut xml,
+        kind.closing_balance_code(),
+        statement.closing_balance,
+        &ccy,
+        &statement.to,
+    );
+
+    let credits: f64 = statement.entries.iter().filter(|e| e.credit).map(|e| e.amount).sum();
+    let debits: f64 = statement.entries.iter().filter(|e| !e.credit).map(|e| e.amount).sum();
+    let net = credits - debits;
+
+    let _ = writeln!(xml, "      <TxsSummry>");
+    let _ = writeln!(xml, "        <TtlNtries>");
+    let _ = writeln!(xml, "          <NbOfNtries>{}</NbOfNtries>", statement.entries.len());
+    let _ = writeln!(xml, "          <Sum>{}</Sum>", amount(credits + debits));
+    let _ = writeln!(xml, "          <TtlNetNtry>");
+    let _ = writeln!(xml, "            <Amt>{}</Amt>", amount(net.abs()));
+    let _ = writeln!(xml, "            <CdtDbtInd>{}</CdtDbtInd>", credit_debit(net >= 0.0));
+    let _ = writeln!(xml, "          </TtlNetNtry>");
+    let _ = writeln!(xml, "        </TtlNtries>");
+    let _ = writeln!(xml, "      </TxsSummry>");
+
+    for entry in &statement.entries {
+        let booked_at = iso_date_time(&entry.booked_at);
+        let _ = writeln!(xml, "      <Ntry>");
+        let _ = writeln!(xml, "        <NtryRef>{}</NtryRef>", escape(&entry.entry_reference));
+        let _ = writeln!(xml, r#"        <Amt Ccy="{}">{}</Amt>"#, ccy, amount(entry.amount));
+        let _ = writeln!(xml, "        <CdtDbtInd>{}</CdtDbtInd>", credit_debit(entry.credit));
+        let _ = writeln!(xml, "        <Sts><Cd>BOOK</Cd></Sts>");
+        let _ = writeln!(xml, "        <BookgDt><DtTm>{}</DtTm></BookgDt>", booked_at);
+        let _ = writeln!(xml, "        <ValDt><DtTm>{}</DtTm></ValDt>", booked_at);
+        let _ = writeln!(
+            xml,
+            "        <BkTxCd><Prtry><Cd>{}</Cd></Prtry></BkTxCd>",
+            escape(&entry.transaction_code)
+        );
+        let _ = writeln!(xml, "      </Ntry>");
+    }
+
+    let _ = writeln!(xml, "    </{}>", kind.container_element());
+    let _ = writeln!(xml, "  </{}>", kind.root_element());
+    let _ = writeln!(xml, "</Document>");
+
+    Ok(xml)
+}
+
+fn write_balance(xml: &mut String, code: &str, balance: f64, ccy: &str, at: &DateTime<Utc>) {
+    let _ = writeln!(xml, "      <Bal>");
+    let _ = writeln!(xml, "        <Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>", code);
+    let _ = writeln!(xml, r#"        <Amt Ccy="{}">{}</Amt>"#, ccy, amount(balance.abs()));
+    let _ = writeln!(xml, "        <CdtDbtInd>{}</CdtDbtInd>", credit_debit(balance >= 0.0));
+    let _ = writeln!(xml, "        <Dt><DtTm>{}</DtTm></Dt>", iso_date_time(at));
+    let _ = writeln!(xml, "      </Bal>");
+}
+
+fn credit_debit(credit: bool) -> &'static str {
+    if credit {
+        "CRDT"
+    } else {
+        "DBIT"
+    }
+}
+
+fn amount(value: f64) -> String {
+    format!("{:.2}", value)
+}
+
+fn iso_date_time(value: &DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::Write as _;
+    use std::process::{Command, Stdio};
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, hour, 0, 0).unwrap()
+    }
+
+    fn statement() -> Statement {
+        Statement {
+            message_id: "CAMT0536600c0ffee0000000000000001".to_string(),
+            created_at: at(2, 6),
+            account_id: "ES9121000418450200051332".to_string(),
+            currency: "EUR".to_string(),
+            from: at(1, 0),
+            to: at(1, 23),
+            opening_balance: 100.0,
+            closing_balance: 125.5,
+            entries: vec![
+                StatementEntry {
+                    entry_reference: "6600c0ffee0000000000000a".to_string(),
+                    amount: 50.0,
+                    credit: true,
+                    booked_at: at(1, 9),
+                    transaction_code: "Deposit".to_string(),
+                },
+                StatementEntry {
+                    entry_reference: "6600c0ffee0000000000000b".to_string(),
+                    amount: 24.5,
+                    credit: false,
+                    booked_at: at(1, 17),
+                    transaction_code: "Withdrawal".to_string(),
+                },
+            ],
+        }
+    }
+
+    // The golden files are compared without their line endings, which depend
+    // on how the repository was checked out
+    fn golden(contents: &str) -> String {
+        contents.replace("\r\n", "\n")
+    }
+
+    // Validates a document with xmllint against the ISO 20022 schema of its
+    // message. Returns None when xmllint is not installed, so that the
+    // suite still runs without libxml2's tools.
+    fn xsd_validate(document: &str, schema: &str) -> Option<Result<(), String>> {
+        let schema = format!("{}/testdata/xsd/{}", env!("CARGO_MANIFEST_DIR"), schema);
+        let mut child = match Command::new("xmllint")
+            .args(["--noout", "--schema", &schema, "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(_) => return None,
+        };
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(document.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        if output.status.success() {
+            Some(Ok(()))
+        } else {
+            Some(Err(String::from_utf8_lossy(&output.stderr).into_owned()))
+        }
+    }
+
+    fn assert_schema_valid(document: &str, schema: &str) {
+        match xsd_validate(document, schema) {
+            Some(result) => assert_eq!(result, Ok(())),
+            None => eprintln!("SKIP: xmllint is not installed, {} not checked", schema),
+        }
+    }
+
+    #[test]
+    fn renders_the_end_of_day_statement() {
+        let document = render(&statement(), StatementKind::EndOfDay).unwrap();
+        assert_eq!(document, golden(include_str!("../testdata/camt.053.001.08.xml")));
+    }
+
+    #[test]
+    fn renders_the_intraday_report() {
+        let document = render(&statement(), StatementKind::Intraday).unwrap();
+        assert_eq!(document, golden(include_str!("../testdata/camt.052.001.08.xml")));
+    }
+
+    #[test]
+    fn golden_documents_are_valid_against_the_schemas() {
+        assert_schema_valid(
+            include_str!("../testdata/camt.053.001.08.xml"),
+            "camt.053.001.08.xsd",
+        );
+        assert_schema_valid(
+            include_str!("../testdata/camt.052.001.08.xml"),
+            "camt.052.001.08.xsd",
+        );
+    }
+
+    #[test]
+    fn rendered_documents_are_valid_against_the_schemas() {
+        let mut overdrawn = statement();
+        overdrawn.closing_balance = -40.0;
+        overdrawn.entries[0].transaction_code = "R&D <fee>".to_string();
+
+        let mut empty = statement();
+        empty.entries.clear();
+
+        for statement in [statement(), overdrawn, empty] {
+            let document = render(&statement, StatementKind::EndOfDay).unwrap();
+            assert_schema_valid(&document, "camt.053.001.08.xsd");
+            let document = render(&statement, StatementKind::Intraday).unwrap();
+            assert_schema_valid(&document, "camt.052.001.08.xsd");
+        }
+    }
+
+    #[test]
+    fn schema_validation_rejects_invalid_documents() {
+        let document = render(&statement(), StatementKind::EndOfDay)
+            .unwrap()
+            .replace("<Sts><Cd>BOOK</Cd></Sts>", "<Sts>BOOK</Sts>");
+        if let Some(result) = xsd_validate(&document, "camt.053.001.08.xsd") {
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn escapes_text() {
+        let mut statement = statement();
+        statement.entries[0].transaction_code = "R&D <fee>".to_string();
+        let document = render(&statement, StatementKind::EndOfDay).unwrap();
+        assert!(document.contains("<Cd>R&amp;D &lt;fee&gt;</Cd>"));
+    }
+
+    #[test]
+    fn rejects_identifiers_longer_than_the_schema_allows() {
+        let mut statement = statement();
+        statement.account_id = "X".repeat(35);
+        assert!(render(&statement, StatementKind::EndOfDay).is_err());
+
+        let mut statement = self::statement();
+        statement.message_id = String::new();
+        assert!(render(&statement, StatementKind::EndOfDay).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_currencies_and_amounts() {
+        let mut statement = statement();
+        statement.currency = "eur".to_string();
+        assert!(check_schema_facets(&statement).is_err());
+
+        let mut statement = self::statement();
+        statement.closing_balance = f64::NAN;
+        assert!(check_schema_facets(&statement).is_err());
+
+        let mut statement = self::statement();
+        statement.entries[1].amount = -24.5;
+        assert!(check_schema_facets(&statement).is_err());
+    }
+
+    #[test]
+    fn rejects_entries_outside_of_the_period() {
+        let mut statement = statement();
+        statement.entries[1].booked_at = at(2, 1);
+        assert!(check_schema_facets(&statement).is_err());
+
+        let mut statement = self::statement();
+        statement.from = at(2, 0);
+        assert!(check_schema_facets(&statement).is_err());
+    }
+}
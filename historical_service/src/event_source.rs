@@ -0,0 +1,349 @@
+use futures::stream::StreamExt;
+use lapin::{
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions,
+        BasicRejectOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+    },
+    types::FieldTable,
+    Connection, ConnectionProperties, ExchangeKind,
+};
+use log::{error, info, warn};
+use std::{fmt::Debug, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Mutex};
+use tonic::{Code, Status};
+
+use mongodb::bson::{doc, Bson, DateTime, Document};
+
+use bank_common::health::Dependency;
+use crate::projection::{Projection, EVENT_TYPES};
+
+const CONSUMER: &str = "historical_service";
+
+// A domain event as received from the bus, in the format written by the
+// outbox relays of the publishing services
+#[derive(Debug, Clone)]
+pub struct IncomingEvent {
+    pub event_id: String,
+    pub event_type: String,
+    pub source: String,
+    pub aggregate_id: String,
+    pub occurred_at: i64,
+    pub data: serde_json::Value,
+}
+
+impl IncomingEvent {
+    pub fn from_json(body: &[u8]) -> Result<Self, String> {
+        let event: serde_json::Value =
+            serde_json::from_slice(body).map_err(|e| format!("Invalid event: {}", e))?;
+        let text = |name: &str| {
+            event
+                .get(name)
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("Event has no {}", name))
+        };
+
+        Ok(Self {
+            event_id: text("event_id")?,
+            event_type: text("event_type")?,
+            source: text("source")?,
+            aggregate_id: text("aggregate_id")?,
+            occurred_at: event
+                .get("occurred_at")
+                .and_then(serde_json::Value::as_i64)
+                .ok_or_else(|| "Event has no occurred_at".to_string())?,
+            data: event.get("data").cloned().unwrap_or_default(),
+        })
+    }
+
+    // Events are kept in the event log of the projection as received, keyed
+    // by their id
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "_id": &self.event_id,
+            "event_type": &self.event_type,
+            "source": &self.source,
+            "aggregate_id": &self.aggregate_id,
+            "occurred_at": DateTime::from_millis(self.occurred_at),
+            "data": Bson::try_from(self.data.clone()).unwrap_or(Bson::Null),
+        }
+    }
+
+    pub fn from_document(event: Document) -> Self {
+        Self {
+            event_id: event.get_str("_id").unwrap_or_default().to_string(),
+            event_type: event.get_str("event_type").unwrap_or_default().to_string(),
+            source: event.get_str("source").unwrap_or_default().to_string(),
+            aggregate_id: event
+                .get_str("aggregate_id")
+                .unwrap_or_default()
+                .to_string(),
+            occurred_at: event
+                .get_datetime("occurred_at")
+                .map(|occurred_at| occurred_at.timestamp_millis())
+                .unwrap_or_default(),
+            data: Bson::Document(event.get_document("data").cloned().unwrap_or_default())
+                .into_relaxed_extjson(),
+        }
+    }
+}
+
+// Feeds events into the projection until the source fails. Events are
+// acknowledged once applied, so delivery is at least once and events may
+// arrive in any order.
+#[tonic::async_trait]
+pub trait EventSource: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn consume(&self, projection: &Projection) -> Result<(), String>;
+
+    // Whether events can be received right now
+    async fn check(&self) -> Result<(), String>;
+}
+
+// The history falls behind while the source is down but can still be read,
+// so the service keeps serving its calls
+#[tonic::async_trait]
+impl Dependency for dyn EventSource {
+    fn name(&self) -> &str {
+        EventSource::name(self)
+    }
+
+    fn critical(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        EventSource::check(self).await
+    }
+}
+
+// Malformed events can never be applied, so they are dropped instead of
+// blocking the events behind them
+fn is_malformed(status: &Status) -> bool {
+    status.code() == Code::InvalidArgument
+}
+
+// Consumes from a durable queue bound to the event exchange for the event
+// types of the projection
+#[derive(Debug, Clone)]
+pub struct AmqpEventSource {
+    pub url: String,
+    pub exchange: String,
+    pub queue: String,
+    pub prefetch: u16,
+}
+
+#[tonic::async_trait]
+impl EventSource for AmqpEventSource {
+    fn name(&self) -> &str {
+        "amqp"
+    }
+
+    async fn consume(&self, projection: &Projection) -> Result<(), String> {
+        let amqp_error = |e: lapin::Error| format!("AMQP error on {}: {}", self.queue, e);
+
+        let connection = Connection::connect(&self.url, ConnectionProperties::default())
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", self.exchange, e))?;
+        let channel = connection.create_channel().await.map_err(amqp_error)?;
+        channel
+            .exchange_declare(
+                &self.exchange,
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(amqp_error)?;
+        channel
+            .queue_declare(
+                &self.queue,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(amqp_error)?;
+        for event_type in EVENT_TYPES {
+            channel
+                .queue_bind(
+                    &self.queue,
+                    &self.exchange,
+                    event_type,
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(amqp_error)?;
+        }
+        channel
+            .basic_qos(self.prefetch, BasicQosOptions::default())
+            .await
+            .map_err(amqp_error)?;
+
+        let mut consumer = channel
+            .basic_consume(
+                &self.queue,
+                CONSUMER,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(amqp_error)?;
+        info!("Consuming events from the queue {}", self.queue);
+
+        while let Some(delivery) = consumer.next().await {
+            let delivery = delivery.map_err(amqp_error)?;
+
+            let result = match IncomingEvent::from_json(&delivery.data) {
+                Ok(event) => projection.apply(&event).await,
+                Err(e) => Err(Status::invalid_argument(e)),
+            };
+            match result {
+                Ok(_) => delivery
+                    .ack(BasicAckOptions::default())
+                    .await
+                    .map_err(amqp_error)?,
+                Err(status) if is_malformed(&status) => {
+                    error!("Dropping event: {}", status.message());
+                    delivery
+                        .reject(BasicRejectOptions { requeue: false })
+                        .await
+                        .map_err(amqp_error)?;
+                }
+                Err(status) => {
+                    // The broker redelivers the event once the consumer is back
+                    let _ = delivery
+                        .nack(BasicNackOptions {
+                            requeue: true,
+                            ..Default::default()
+                        })
+                        .await;
+                    return Err(format!("Failed to apply event: {}", status.message()));
+                }
+            }
+        }
+
+        Err(format!("Consumer of {} was cancelled", self.queue))
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let connection = Connection::connect(&self.url, ConnectionProperties::default())
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", self.exchange, e))?;
+        connection
+            .close(0, "Health check")
+            .await
+            .map_err(|e| format!("Failed to close the connection to {}: {}", self.exchange, e))
+    }
+}
+
+// Local stand-in for the bus: events are handed over through an in-process
+// channel, from which they are consumed like from the queue. Events that fail
+// to apply are sent again behind the others, so they may be applied out of
+// order.
+#[derive(Debug)]
+pub struct ChannelEventSource {
+    sender: mpsc::UnboundedSender<IncomingEvent>,
+    receiver: Mutex<mpsc::UnboundedReceiver<IncomingEvent>>,
+}
+
+impl ChannelEventSource {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    // Nothing in the service publishes to the channel yet, events are only
+    // handed over by the tests
+    #[allow(dead_code)]
+    pub fn sender(&self) -> mpsc::UnboundedSender<IncomingEvent> {
+        self.sender.clone()
+    }
+}
+
+impl Default for ChannelEventSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tonic::async_trait]
+impl EventSource for ChannelEventSource {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    async fn consume(&self, projection: &Projection) -> Result<(), String> {
+        let mut receiver = self.receiver.lock().await;
+
+        while let Some(event) = receiver.recv().await {
+            match projection.apply(&event).await {
+                Ok(_) => {}
+                Err(status) if is_malformed(&status) => {
+                    warn!("Dropping event: {}", status.message());
+                }
+                Err(status) => {
+                    let _ = self.sender.send(event);
+                    return Err(format!("Failed to apply event: {}", status.message()));
+                }
+            }
+        }
+
+        Err("Event channel was closed".to_string())
+    }
+
+    // The channel lives as long as the service
+    async fn check(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub async fn run_event_source(
+    projection: Projection,
+    source: Arc<dyn EventSource>,
+    retry_delay: Duration,
+) {
+    info!("Projecting transaction history from {}", source.name());
+
+    loop {
+        if let Err(e) = source.consume(&projection).await {
+            error!("Event source {} failed: {}", source.name(), e);
+        }
+        tokio::time::sleep(retry_delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip_through_the_event_log() {
+        let event = IncomingEvent {
+            event_id: "6553f1c2a1b2c3d4e5f60718".to_string(),
+            event_type: "FundsDeposited".to_string(),
+            source: "deposit".to_string(),
+            aggregate_id: "6553f1c2a1b2c3d4e5f60719".to_string(),
+            occurred_at: 1_700_000_000_000,
+            data: serde_json::json!({ "amount": 12.5, "timestamp": 1_700_000_000_000i64 }),
+        };
+
+        let logged = IncomingEvent::from_document(event.to_document());
+        assert_eq!(logged.event_id, event.event_id);
+        assert_eq!(logged.event_type, event.event_type);
+        assert_eq!(logged.source, event.source);
+        assert_eq!(logged.aggregate_id, event.aggregate_id);
+        assert_eq!(logged.occurred_at, event.occurred_at);
+        assert_eq!(logged.data, event.data);
+    }
+}
@@ -1,12 +1,14 @@
+use std::collections::{HashMap, HashSet};
 use std::{str::FromStr};
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
 
+use chrono::{TimeZone, Utc};
 use futures::stream::TryStreamExt;
-use log::{info};
+use log::{error, info};
 
 use mongodb::{
-    bson::{doc, oid::ObjectId, Document},
+    bson::{doc, oid::ObjectId, DateTime, Document},
     options::FindOptions,
     Collection,
     {options::ClientOptions, Client}
@@ -14,9 +16,16 @@ use mongodb::{
 
 use historical::historical_service_server::HistoricalService;
 use historical::{
-    GetTransactionHistoryRequest, GetTransactionHistoryResponse, Transaction, TransactionType
+    GetAccountStatementRequest, GetAccountStatementResponse, GetTransactionHistoryRequest,
+    GetProjectionStatusRequest, GetProjectionStatusResponse, GetTransactionHistoryResponse,
+    RebuildProjectionRequest, RebuildProjectionResponse, StatementFormat, Transaction,
+    TransactionType
 };
 
+use crate::camt::{self, Statement, StatementEntry, StatementKind};
+use bank_common::health::Dependency;
+use crate::projection::Projection;
+
 pub mod historical {
     tonic::include_proto!("historical");
 }
@@ -24,14 +33,24 @@ pub mod historical {
 #[derive(Debug, Clone)]
 pub struct MyHistoricalService {
     db: Arc<mongodb::Database>,
+    statement_currency: String,
+    pub(crate) projection: Projection,
 }
 
 impl MyHistoricalService {
-    pub async fn new(uri: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub async fn new(
+        uri: &str,
+        statement_currency: &str,
+        event_source: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let client_options = ClientOptions::parse(uri).await?;
         let client = Client::with_options(client_options)?;
-        let db = client.database("bank");
-        Ok(Self { db: Arc::new(db) })
+        let db = Arc::new(client.database("bank"));
+        Ok(Self {
+            db: db.clone(),
+            statement_currency: statement_currency.to_string(),
+            projection: Projection::new(client, db, event_source),
+        })
     }
 
     pub async fn test_connection(&self) -> Result<(), mongodb::error::Error> {
@@ -40,6 +59,19 @@ impl MyHistoricalService {
     }
 }
 
+#[tonic::async_trait]
+impl Dependency for MyHistoricalService {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.test_connection()
+            .await
+            .map_err(|e| format!("Failed to ping MongoDB: {}", e))
+    }
+}
+
 #[tonic::async_trait]
 impl HistoricalService for MyHistoricalService {
     async fn get_transaction_history(
@@ -54,43 +86,312 @@ impl HistoricalService for MyHistoricalService {
             Err(_) => return Err(Status::invalid_argument("Invalid account id")),
         };
 
-        let filter = doc! {
-            "account_id": object_id,
-        };
-
         let mut options = FindOptions::default();
         options.sort = Some(doc! {
             "timestamp": -1, // sort by timestamp in descending order
         });
 
+        // Deposits and withdrawals come from the read model built from domain
+        // events. Bookings that are not published as events (interest, fees,
+        // adjustments, reversals and hold captures) and those booked before the
+        // event log started are still read from the transactions collection.
+        let mut cursor = self
+            .projection
+            .entries()
+            .find(doc! { "account_id": object_id }, options.clone())
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get historical: {}", e)))?;
+
+        let mut transactions = Vec::new();
+        while let Some(entry) = cursor
+            .try_next()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get historical: {}", e)))?
+        {
+            let transaction = Transaction {
+                transaction_id: entry.get_object_id("_id").unwrap().to_string(),
+                account_id: account_id.clone(),
+                transaction_type: match entry.get_str("type").unwrap() {
+                    "Deposit" => TransactionType::Deposit as i32,
+                    _ => TransactionType::Withdrawal as i32,
+                },
+                amount: entry.get_f64("amount").unwrap(),
+                timestamp: entry.get_datetime("timestamp").unwrap().timestamp_millis(),
+                ..Default::default()
+            };
+            transactions.push(transaction);
+        }
+        let projected: HashSet<String> = transactions
+            .iter()
+            .map(|transaction| transaction.transaction_id.clone())
+            .collect();
+
+        let event_log_start = self
+            .projection
+            .event_log_start()
+            .await?
+            .unwrap_or_else(DateTime::now);
+        let filter = doc! {
+            "account_id": object_id,
+            "$or": [
+                { "type": { "$nin": ["Deposit", "Withdrawal"] } },
+                { "reversal": true },
+                { "hold_id": { "$exists": true } },
+                { "timestamp": { "$lt": event_log_start } },
+            ],
+        };
+
         let mut cursor = transactions_collection
             .find(filter, options)
             .await
             .map_err(|e| Status::internal(format!("Failed to get historical: {}", e)))?;
 
-        let mut transactions = Vec::new();
+        let mut reversals = HashMap::new();
         while let Some(result) = cursor
             .try_next()
             .await
             .map_err(|e| Status::internal(format!("Failed to get historical: {}", e)))?
         {
+            if projected.contains(&result.get_object_id("_id").unwrap().to_string()) {
+                continue;
+            }
+
             let transaction = Transaction {
                 transaction_id: result.get_object_id("_id").unwrap().to_string(),
                 account_id: result.get_object_id("account_id").unwrap().to_string(),
                 transaction_type: match result.get_str("type").unwrap() {
                     "Deposit" => TransactionType::Deposit as i32,
                     "Withdrawal" => TransactionType::Withdrawal as i32,
+                    "Interest" => TransactionType::Interest as i32,
+                    "Fee" => TransactionType::Fee as i32,
+                    "Adjustment" => TransactionType::Adjustment as i32,
                     _ => return Err(Status::internal("Invalid account type")),
                 },
                 amount: result.get_f64("amount").unwrap(),
-                timestamp: Default::default()
+                timestamp: result
+                    .get_datetime("timestamp")
+                    .map(|timestamp| timestamp.timestamp_millis())
+                    .unwrap_or_default(),
+                reversal: result.get_bool("reversal").unwrap_or_default(),
+                reversal_of: result
+                    .get_object_id("reversal_of")
+                    .map(|id| id.to_hex())
+                    .unwrap_or_default(),
+                reversal_transaction_id: result
+                    .get_object_id("reversal_transaction_id")
+                    .map(|id| id.to_hex())
+                    .unwrap_or_default(),
+                reversal_reason_code: result
+                    .get_str("reversal_reason_code")
+                    .unwrap_or_default()
+                    .to_string(),
             };
+            if transaction.reversal {
+                reversals.insert(
+                    transaction.reversal_of.clone(),
+                    (
+                        transaction.transaction_id.clone(),
+                        transaction.reversal_reason_code.clone(),
+                    ),
+                );
+            }
             transactions.push(transaction);
         }
 
+        // Projected entries only learn that they were reversed from the
+        // reversal entries pointing at them
+        for transaction in transactions.iter_mut() {
+            if let Some((reversal_transaction_id, reason_code)) =
+                reversals.get(&transaction.transaction_id)
+            {
+                if transaction.reversal_transaction_id.is_empty() {
+                    transaction.reversal_transaction_id = reversal_transaction_id.clone();
+                    transaction.reversal_reason_code = reason_code.clone();
+                }
+            }
+        }
+        transactions.sort_by_key(|transaction| std::cmp::Reverse(transaction.timestamp));
+
         info!("Fetched transaction history for account {}", account_id);
 
         let response = GetTransactionHistoryResponse { transactions };
         Ok(Response::new(response))
     }
+
+    async fn get_account_statement(
+        &self,
+        request: Request<GetAccountStatementRequest>,
+    ) -> Result<Response<GetAccountStatementResponse>, Status> {
+        let req = request.into_inner();
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+
+        let kind = match StatementFormat::from_i32(req.format) {
+            Some(StatementFormat::Camt053) => StatementKind::EndOfDay,
+            Some(StatementFormat::Camt052) => StatementKind::Intraday,
+            None => return Err(Status::invalid_argument("Invalid statement format")),
+        };
+
+        let object_id = match ObjectId::from_str(&req.account_id) {
+            Ok(oid) => oid,
+            Err(_) => return Err(Status::invalid_argument("Invalid account id")),
+        };
+
+        let now = Utc::now();
+        let to = if req.to_timestamp == 0 {
+            now
+        } else {
+            Utc.timestamp_millis_opt(req.to_timestamp)
+                .single()
+                .ok_or_else(|| Status::invalid_argument("Invalid to timestamp"))?
+        };
+        let from = Utc
+            .timestamp_millis_opt(req.from_timestamp)
+            .single()
+            .ok_or_else(|| Status::invalid_argument("Invalid from timestamp"))?;
+
+        if from > to {
+            return Err(Status::invalid_argument(
+                "from_timestamp must not be after to_timestamp",
+            ));
+        }
+
+        info!(
+            "Generating {} statement for account {} from {} to {}",
+            kind.message_prefix(),
+            req.account_id,
+            from,
+            to
+        );
+
+        let account_doc = accounts_collection
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+        let current_balance = account_doc.get_f64("balance").unwrap();
+
+        // Walk every transaction booked from the start of the period onwards:
+        // those inside the period become entries, those after it are rolled
+        // back from the current balance to obtain the closing balance.
+        let filter = doc! {
+            "account_id": object_id,
+            "timestamp": { "$gte": DateTime::from_millis(from.timestamp_millis()) },
+        };
+
+        let mut options = FindOptions::default();
+        options.sort = Some(doc! {
+            "timestamp": 1,
+        });
+
+        let mut cursor = transactions_collection
+            .find(filter, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get historical: {}", e)))?;
+
+        let mut entries = Vec::new();
+        let mut movements_after_period = 0.0;
+        while let Some(result) = cursor
+            .try_next()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get historical: {}", e)))?
+        {
+            let transaction_type = result.get_str("type").unwrap();
+            let credit = match transaction_type {
+                "Deposit" => true,
+                "Withdrawal" => false,
+                // Interest, fee and adjustment entries are signed: overdraft
+                // interest, fee charges and debit adjustments are negative
+                "Interest" | "Fee" | "Adjustment" => result.get_f64("amount").unwrap() >= 0.0,
+                _ => return Err(Status::internal("Invalid transaction type")),
+            };
+            let amount = result.get_f64("amount").unwrap().abs();
+            let booked_at = Utc
+                .timestamp_millis_opt(result.get_datetime("timestamp").unwrap().timestamp_millis())
+                .single()
+                .ok_or_else(|| Status::internal("Invalid transaction timestamp"))?;
+
+            if booked_at > to {
+                movements_after_period += if credit { amount } else { -amount };
+                continue;
+            }
+
+            entries.push(StatementEntry {
+                entry_reference: result.get_object_id("_id").unwrap().to_hex(),
+                amount,
+                credit,
+                booked_at,
+                transaction_code: transaction_type.to_string(),
+            });
+        }
+
+        let closing_balance = current_balance - movements_after_period;
+        let period_movements: f64 = entries
+            .iter()
+            .map(|entry| if entry.credit { entry.amount } else { -entry.amount })
+            .sum();
+
+        let statement = Statement {
+            message_id: format!("{}{}", kind.message_prefix(), ObjectId::new().to_hex()),
+            created_at: now,
+            account_id: req.account_id.clone(),
+            // Accounts opened before currencies existed use the statement currency
+            currency: account_doc
+                .get_str("currency")
+                .map(str::to_string)
+                .unwrap_or_else(|_| self.statement_currency.clone()),
+            from,
+            to,
+            opening_balance: closing_balance - period_movements,
+            closing_balance,
+            entries,
+        };
+
+        let document = camt::render(&statement, kind).map_err(|e| {
+            error!("Statement for account {} failed validation: {}", req.account_id, e);
+            Status::internal(format!("Failed to generate statement: {}", e))
+        })?;
+
+        info!(
+            "Generated statement {} with {} entries for account {}",
+            statement.message_id,
+            statement.entries.len(),
+            req.account_id
+        );
+
+        let response = GetAccountStatementResponse {
+            message_id: statement.message_id,
+            document,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn get_projection_status(
+        &self,
+        _request: Request<GetProjectionStatusRequest>,
+    ) -> Result<Response<GetProjectionStatusResponse>, Status> {
+        let status = self.projection.status().await?;
+
+        let response = GetProjectionStatusResponse {
+            status: Some(status),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn rebuild_projection(
+        &self,
+        _request: Request<RebuildProjectionRequest>,
+    ) -> Result<Response<RebuildProjectionResponse>, Status> {
+        let replayed_events = self.projection.rebuild().await.map_err(|e| {
+            error!("Failed to rebuild the transaction history: {:?}", e);
+            e
+        })?;
+        let status = self.projection.status().await?;
+
+        let response = RebuildProjectionResponse {
+            replayed_events,
+            status: Some(status),
+        };
+        Ok(Response::new(response))
+    }
 }
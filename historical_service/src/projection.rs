@@ -0,0 +1,511 @@
+use futures::stream::TryStreamExt;
+use log::{info, warn};
+use std::{str::FromStr, sync::Arc};
+use tokio::sync::Mutex;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{FindOneOptions, FindOptions, UpdateOptions},
+    Client, Collection, Database, IndexModel,
+};
+
+use crate::event_source::IncomingEvent;
+use crate::historical_service::historical::ProjectionStatus;
+
+// The transaction history read model is built from the events published by
+// the deposit and withdrawal services for each booked transaction
+pub const FUNDS_DEPOSITED: &str = "FundsDeposited";
+pub const FUNDS_WITHDRAWN: &str = "FundsWithdrawn";
+pub const EVENT_TYPES: [&str; 2] = [FUNDS_DEPOSITED, FUNDS_WITHDRAWN];
+
+// Every event received is kept in the event log of this service, so the read
+// model can be rebuilt without going back to the publishing services
+const EVENT_LOG: &str = "historical_event_log";
+pub const TRANSACTION_HISTORY: &str = "transaction_history";
+const REBUILD_COLLECTION: &str = "transaction_history_rebuild";
+const APPLIED_EVENTS: &str = "transaction_history_events";
+const PROJECTION_STATE: &str = "projection_state";
+
+fn event_field<'a>(event: &'a IncomingEvent, name: &str) -> Result<&'a serde_json::Value, String> {
+    event.data.get(name).ok_or_else(|| {
+        format!(
+            "{} event {} has no {}",
+            event.event_type, event.event_id, name
+        )
+    })
+}
+
+fn event_object_id(event: &IncomingEvent, name: &str) -> Result<ObjectId, String> {
+    event_field(event, name)?
+        .as_str()
+        .and_then(|id| ObjectId::from_str(id).ok())
+        .ok_or_else(|| {
+            format!(
+                "{} event {} has an invalid {}",
+                event.event_type, event.event_id, name
+            )
+        })
+}
+
+// Entries are keyed by the id of the transaction they describe and hold the
+// same signed amounts as the transactions collection
+fn entry_from_event(event: &IncomingEvent) -> Result<Document, String> {
+    let (transaction_type, sign) = match event.event_type.as_str() {
+        FUNDS_DEPOSITED => ("Deposit", 1.0),
+        FUNDS_WITHDRAWN => ("Withdrawal", -1.0),
+        other => return Err(format!("Unexpected event type {}", other)),
+    };
+    let amount = event_field(event, "amount")?.as_f64().ok_or_else(|| {
+        format!(
+            "{} event {} has an invalid amount",
+            event.event_type, event.event_id
+        )
+    })?;
+    let timestamp = event_field(event, "timestamp")?.as_i64().ok_or_else(|| {
+        format!(
+            "{} event {} has an invalid timestamp",
+            event.event_type, event.event_id
+        )
+    })?;
+
+    let mut entry = doc! {
+        "_id": event_object_id(event, "transaction_id")?,
+        "account_id": event_object_id(event, "account_id")?,
+        "type": transaction_type,
+        "amount": sign * amount.abs(),
+        "currency": event_field(event, "currency")?.as_str().unwrap_or_default(),
+        "timestamp": DateTime::from_millis(timestamp),
+        "event_id": &event.event_id,
+    };
+    if event.data.get("counterparty_account_id").is_some() {
+        entry.insert(
+            "counterparty_account_id",
+            event_object_id(event, "counterparty_account_id")?,
+        );
+    }
+    Ok(entry)
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct Projection {
+    client: Client,
+    db: Arc<Database>,
+    pub source: String,
+    // Events are applied one at a time, and never while the read model is rebuilt
+    lock: Arc<Mutex<()>>,
+}
+
+impl Projection {
+    pub fn new(client: Client, db: Arc<Database>, source: &str) -> Self {
+        Self {
+            client,
+            db,
+            source: source.to_string(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn event_log(&self) -> Collection<Document> {
+        self.db.collection(EVENT_LOG)
+    }
+
+    pub fn entries(&self) -> Collection<Document> {
+        self.db.collection(TRANSACTION_HISTORY)
+    }
+
+    pub async fn ensure_indexes(&self) -> Result<(), Status> {
+        let index = IndexModel::builder()
+            .keys(doc! { "occurred_at": 1 })
+            .build();
+
+        self.event_log()
+            .create_index(index, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create index: {}", e)))?;
+        self.create_indexes(TRANSACTION_HISTORY).await
+    }
+
+    async fn create_indexes(&self, collection_name: &str) -> Result<(), Status> {
+        let index = IndexModel::builder()
+            .keys(doc! { "account_id": 1, "timestamp": -1 })
+            .build();
+
+        self.db
+            .collection::<Document>(collection_name)
+            .create_index(index, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create index: {}", e)))?;
+        Ok(())
+    }
+
+    // The first event seen for a transaction creates its entry and later
+    // copies of it change nothing, so events can be applied more than once
+    // and in any order. Returns whether the event was new.
+    pub async fn apply(&self, event: &IncomingEvent) -> Result<bool, Status> {
+        let _guard = self.lock.lock().await;
+        entry_from_event(event).map_err(Status::invalid_argument)?;
+        self.log_event(event).await?;
+        self.apply_to(event, TRANSACTION_HISTORY).await
+    }
+
+    // Events are logged before they are applied, and copies of an event
+    // already in the log are not logged again
+    async fn log_event(&self, event: &IncomingEvent) -> Result<(), Status> {
+        match self.event_log().insert_one(event.to_document(), None).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key(&e) => Ok(()),
+            Err(e) => Err(Status::internal(format!("Failed to log event: {}", e))),
+        }
+    }
+
+    async fn apply_to(&self, event: &IncomingEvent, collection_name: &str) -> Result<bool, Status> {
+        let entries: Collection<Document> = self.db.collection(collection_name);
+        let applied_events: Collection<Document> = self.db.collection(APPLIED_EVENTS);
+        let state: Collection<Document> = self.db.collection(PROJECTION_STATE);
+        let upsert = UpdateOptions::builder().upsert(true).build();
+
+        let entry = entry_from_event(event).map_err(Status::invalid_argument)?;
+
+        let applied = applied_events
+            .find_one(doc! { "_id": &event.event_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get applied event: {}", e)))?;
+        if applied.is_some() {
+            state
+                .update_one(
+                    doc! { "_id": TRANSACTION_HISTORY },
+                    doc! { "$inc": { "duplicate_events": 1_i64 } },
+                    upsert,
+                )
+                .await
+                .map_err(|e| {
+                    Status::internal(format!("Failed to update projection state: {}", e))
+                })?;
+            return Ok(false);
+        }
+
+        // The entry is written before the event is marked as applied, so an
+        // event interrupted in between is applied again on redelivery
+        let transaction_id = entry.get_object_id("_id").unwrap();
+        entries
+            .update_one(
+                doc! { "_id": transaction_id },
+                doc! { "$setOnInsert": entry },
+                upsert.clone(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to apply event: {}", e)))?;
+
+        let applied_at = DateTime::now();
+        let applied_event = doc! {
+            "_id": &event.event_id,
+            "event_type": &event.event_type,
+            "source": &event.source,
+            "aggregate_id": &event.aggregate_id,
+            "occurred_at": DateTime::from_millis(event.occurred_at),
+            "applied_at": applied_at,
+        };
+        match applied_events.insert_one(applied_event, None).await {
+            Ok(_) => {}
+            // Applied concurrently by another instance
+            Err(e) if is_duplicate_key(&e) => return Ok(false),
+            Err(e) => {
+                return Err(Status::internal(format!(
+                    "Failed to record applied event: {}",
+                    e
+                )))
+            }
+        }
+
+        state
+            .update_one(
+                doc! { "_id": TRANSACTION_HISTORY },
+                doc! {
+                    "$inc": { "applied_events": 1_i64 },
+                    "$set": {
+                        "last_event_id": &event.event_id,
+                        "last_event_at": DateTime::from_millis(event.occurred_at),
+                        "last_applied_at": applied_at,
+                    },
+                },
+                upsert,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update projection state: {}", e)))?;
+
+        Ok(true)
+    }
+
+    // Replays the whole event log into a new collection that then replaces
+    // the read model, so queries keep seeing the previous history until the
+    // rebuild is complete
+    pub async fn rebuild(&self) -> Result<i64, Status> {
+        let _guard = self.lock.lock().await;
+        let applied_events: Collection<Document> = self.db.collection(APPLIED_EVENTS);
+        let state: Collection<Document> = self.db.collection(PROJECTION_STATE);
+
+        info!("Rebuilding the transaction history from the event log");
+
+        self.db
+            .collection::<Document>(REBUILD_COLLECTION)
+            .drop(None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to drop rebuild collection: {}", e)))?;
+        applied_events
+            .delete_many(doc! {}, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to reset applied events: {}", e)))?;
+        state
+            .delete_one(doc! { "_id": TRANSACTION_HISTORY }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to reset projection state: {}", e)))?;
+        self.create_indexes(REBUILD_COLLECTION).await?;
+
+        let options = FindOptions::builder()
+            .sort(doc! { "occurred_at": 1, "_id": 1 })
+            .build();
+        let mut cursor = self
+            .event_log()
+            .find(
+                doc! { "event_type": { "$in": EVENT_TYPES.to_vec() } },
+                options,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read event log: {}", e)))?;
+
+        let mut replayed = 0;
+        while let Some(event) = cursor
+            .try_next()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read event log: {}", e)))?
+        {
+            let event = IncomingEvent::from_document(event);
+            match self.apply_to(&event, REBUILD_COLLECTION).await {
+                Ok(_) => replayed += 1,
+                Err(status) if status.code() == tonic::Code::InvalidArgument => {
+                    warn!("Skipping event: {}", status.message());
+                }
+                Err(status) => return Err(status),
+            }
+        }
+
+        let db_name = self.db.name();
+        self.client
+            .database("admin")
+            .run_command(
+                doc! {
+                    "renameCollection": format!("{}.{}", db_name, REBUILD_COLLECTION),
+                    "to": format!("{}.{}", db_name, TRANSACTION_HISTORY),
+                    "dropTarget": true,
+                },
+                None,
+            )
+            .await
+            .map_err(|e| {
+                Status::internal(format!("Failed to replace transaction history: {}", e))
+            })?;
+
+        state
+            .update_one(
+                doc! { "_id": TRANSACTION_HISTORY },
+                doc! { "$set": { "rebuilt_at": DateTime::now() } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update projection state: {}", e)))?;
+
+        info!("Rebuilt the transaction history from {} event(s)", replayed);
+        Ok(replayed)
+    }
+
+    // Bookings older than the first event in the log predate the event log
+    pub async fn event_log_start(&self) -> Result<Option<DateTime>, Status> {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "occurred_at": 1 })
+            .build();
+        let first_event = self
+            .event_log()
+            .find_one(
+                doc! { "event_type": { "$in": EVENT_TYPES.to_vec() } },
+                options,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read event log: {}", e)))?;
+
+        Ok(first_event.and_then(|event| event.get_datetime("occurred_at").ok().copied()))
+    }
+
+    pub async fn status(&self) -> Result<ProjectionStatus, Status> {
+        let applied_events: Collection<Document> = self.db.collection(APPLIED_EVENTS);
+        let state: Collection<Document> = self.db.collection(PROJECTION_STATE);
+
+        let state = state
+            .find_one(doc! { "_id": TRANSACTION_HISTORY }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get projection state: {}", e)))?
+            .unwrap_or_default();
+        let logged_events = self
+            .event_log()
+            .count_documents(doc! { "event_type": { "$in": EVENT_TYPES.to_vec() } }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to count events: {}", e)))?;
+        let known_events = applied_events
+            .count_documents(doc! {}, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to count applied events: {}", e)))?;
+
+        let timestamp = |key: &str| {
+            state
+                .get_datetime(key)
+                .map(|timestamp| timestamp.timestamp_millis())
+                .unwrap_or_default()
+        };
+        let last_event_timestamp = timestamp("last_event_at");
+        let last_applied_timestamp = timestamp("last_applied_at");
+
+        Ok(ProjectionStatus {
+            source: self.source.clone(),
+            applied_events: state.get_i64("applied_events").unwrap_or_default(),
+            duplicate_events: state.get_i64("duplicate_events").unwrap_or_default(),
+            pending_events: logged_events.saturating_sub(known_events) as i64,
+            last_event_id: state
+                .get_str("last_event_id")
+                .unwrap_or_default()
+                .to_string(),
+            last_event_timestamp,
+            last_applied_timestamp,
+            lag_ms: (last_applied_timestamp - last_event_timestamp).max(0),
+            rebuilt_timestamp: timestamp("rebuilt_at"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_source::{ChannelEventSource, EventSource};
+    use mongodb::options::ClientOptions;
+    use std::time::Duration;
+
+    fn deposit_event(event_id: &str, transaction_id: ObjectId, amount: f64) -> IncomingEvent {
+        IncomingEvent {
+            event_id: event_id.to_string(),
+            event_type: FUNDS_DEPOSITED.to_string(),
+            source: "deposit".to_string(),
+            aggregate_id: transaction_id.to_hex(),
+            occurred_at: transaction_id.timestamp().timestamp_millis(),
+            data: serde_json::json!({
+                "transaction_id": transaction_id.to_hex(),
+                "account_id": "6553f1c2a1b2c3d4e5f60719",
+                "amount": amount,
+                "currency": "EUR",
+                "timestamp": transaction_id.timestamp().timestamp_millis(),
+            }),
+        }
+    }
+
+    #[test]
+    fn copies_of_an_event_describe_the_same_entry() {
+        let event = deposit_event("6553f1c2a1b2c3d4e5f60720", ObjectId::new(), 10.0);
+
+        assert_eq!(
+            entry_from_event(&event).unwrap(),
+            entry_from_event(&event.clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn withdrawals_are_entered_with_negative_amounts() {
+        let mut event = deposit_event("6553f1c2a1b2c3d4e5f60721", ObjectId::new(), 10.0);
+        event.event_type = FUNDS_WITHDRAWN.to_string();
+
+        let entry = entry_from_event(&event).unwrap();
+        assert_eq!(entry.get_str("type").unwrap(), "Withdrawal");
+        assert_eq!(entry.get_f64("amount").unwrap(), -10.0);
+    }
+
+    async fn test_projection() -> Projection {
+        let uri = std::env::var("MONGODB_URI")
+            .unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+        let client = Client::with_options(ClientOptions::parse(&uri).await.unwrap()).unwrap();
+        let db = client.database(&format!("historical_test_{}", ObjectId::new()));
+        Projection::new(client, Arc::new(db), "memory")
+    }
+
+    async fn history(projection: &Projection) -> Vec<Document> {
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        projection
+            .entries()
+            .find(doc! {}, options)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    // Consumes what was sent so far, the source then waits for more events
+    async fn deliver(projection: &Projection, events: Vec<IncomingEvent>) {
+        let source = ChannelEventSource::new();
+        for event in events {
+            source.sender().send(event).unwrap();
+        }
+        let _ = tokio::time::timeout(Duration::from_millis(500), source.consume(projection)).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn duplicate_deliveries_are_applied_once() {
+        let projection = test_projection().await;
+        let first = deposit_event("6553f1c2a1b2c3d4e5f60722", ObjectId::new(), 10.0);
+        let second = deposit_event("6553f1c2a1b2c3d4e5f60723", ObjectId::new(), 20.0);
+
+        deliver(
+            &projection,
+            vec![first.clone(), second.clone(), first.clone(), second, first],
+        )
+        .await;
+
+        let status = projection.status().await.unwrap();
+        assert_eq!(history(&projection).await.len(), 2);
+        assert_eq!(status.applied_events, 2);
+        assert_eq!(status.duplicate_events, 3);
+        assert_eq!(status.pending_events, 0);
+
+        projection.db.as_ref().drop(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MongoDB at MONGODB_URI"]
+    async fn out_of_order_deliveries_build_the_history_of_the_event_log() {
+        let projection = test_projection().await;
+        let events: Vec<IncomingEvent> = (0..5)
+            .map(|i| {
+                deposit_event(
+                    &ObjectId::new().to_hex(),
+                    ObjectId::new(),
+                    10.0 * (i + 1) as f64,
+                )
+            })
+            .collect();
+
+        deliver(&projection, events.into_iter().rev().collect()).await;
+        let delivered = history(&projection).await;
+        assert_eq!(delivered.len(), 5);
+
+        // Rebuilding replays the event log in the order the events occurred
+        assert_eq!(projection.rebuild().await.unwrap(), 5);
+        assert_eq!(history(&projection).await, delivered);
+
+        projection.db.as_ref().drop(None).await.unwrap();
+    }
+}
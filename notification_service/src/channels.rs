@@ -0,0 +1,299 @@
+use hyper::{Body, Client, Method, Request};
+use std::{fmt::Debug, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedReadHalf, TcpStream},
+};
+
+use bank_common::health::Dependency;
+use crate::notification_service::notification::NotificationType;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// A rendered notification addressed to a single recipient
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Delivers notifications over one channel. Errors are reported as strings;
+// whether a failed delivery is retried is up to the dispatcher.
+#[tonic::async_trait]
+pub trait ChannelBackend: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    // Stand-in backends also accept customers without contact details
+    fn needs_address(&self) -> bool {
+        true
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> Result<(), String>;
+
+    // Whether notifications can be delivered right now
+    async fn check(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Undelivered notifications are retried by the dispatcher, so the service
+// keeps serving its calls while a channel is down
+#[tonic::async_trait]
+impl Dependency for dyn ChannelBackend {
+    fn name(&self) -> &str {
+        ChannelBackend::name(self)
+    }
+
+    fn critical(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        ChannelBackend::check(self).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Channels {
+    pub email: Arc<dyn ChannelBackend>,
+    pub sms: Arc<dyn ChannelBackend>,
+    pub push: Arc<dyn ChannelBackend>,
+}
+
+impl Channels {
+    pub fn backend(&self, notification_type: NotificationType) -> &Arc<dyn ChannelBackend> {
+        match notification_type {
+            NotificationType::Email => &self.email,
+            NotificationType::Sms => &self.sms,
+            NotificationType::PushNotification => &self.push,
+        }
+    }
+}
+
+// Plain SMTP without authentication or TLS, as spoken by local relays and
+// mail catchers
+#[derive(Debug, Clone)]
+pub struct SmtpBackend {
+    pub address: String,
+    pub from: String,
+}
+
+async fn smtp_reply(reader: &mut BufReader<OwnedReadHalf>) -> Result<(u16, String), String> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        let read = tokio::time::timeout(TIMEOUT, reader.read_line(&mut line))
+            .await
+            .map_err(|_| "SMTP server timed out".to_string())?
+            .map_err(|e| format!("Failed to read SMTP reply: {}", e))?;
+        if read == 0 || line.len() < 4 {
+            return Err(format!("Unexpected SMTP reply: {}", reply + &line));
+        }
+        reply.push_str(&line);
+        // Multiline replies continue with "250-" and end with "250 "
+        if line.as_bytes()[3] != b'-' {
+            let code = line[..3]
+                .parse()
+                .map_err(|_| format!("Unexpected SMTP reply: {}", line.trim_end()))?;
+            return Ok((code, reply.trim_end().to_string()));
+        }
+    }
+}
+
+fn smtp_body(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{}\r\n", line)
+            } else {
+                format!("{}\r\n", line)
+            }
+        })
+        .collect()
+}
+
+impl SmtpBackend {
+    // Connects and waits for the greeting of the server
+    async fn connect(
+        &self,
+    ) -> Result<(BufReader<OwnedReadHalf>, tokio::net::tcp::OwnedWriteHalf), String> {
+        let stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(&self.address))
+            .await
+            .map_err(|_| format!("Timed out connecting to {}", self.address))?
+            .map_err(|e| format!("Failed to connect to {}: {}", self.address, e))?;
+        let (read_half, writer) = stream.into_split();
+        let mut reader = BufReader::new(read_half);
+
+        let (code, greeting) = smtp_reply(&mut reader).await?;
+        if code != 220 {
+            return Err(format!("SMTP server is not ready: {}", greeting));
+        }
+        Ok((reader, writer))
+    }
+
+    async fn command(
+        &self,
+        reader: &mut BufReader<OwnedReadHalf>,
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+        command: &str,
+        expected: &[u16],
+    ) -> Result<(), String> {
+        writer
+            .write_all(command.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write to SMTP server: {}", e))?;
+        let (code, reply) = smtp_reply(reader).await?;
+        if !expected.contains(&code) {
+            return Err(format!(
+                "SMTP server rejected {}: {}",
+                command.split_whitespace().next().unwrap_or_default(),
+                reply
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl ChannelBackend for SmtpBackend {
+    fn name(&self) -> &str {
+        "smtp"
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> Result<(), String> {
+        let (mut reader, mut writer) = self.connect().await?;
+
+        self.command(&mut reader, &mut writer, "EHLO simple-bank\r\n", &[250])
+            .await?;
+        self.command(
+            &mut reader,
+            &mut writer,
+            &format!("MAIL FROM:<{}>\r\n", self.from),
+            &[250],
+        )
+        .await?;
+        self.command(
+            &mut reader,
+            &mut writer,
+            &format!("RCPT TO:<{}>\r\n", message.recipient),
+            &[250, 251],
+        )
+        .await?;
+        self.command(&mut reader, &mut writer, "DATA\r\n", &[354])
+            .await?;
+
+        let data = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\r\n{}.\r\n",
+            self.from,
+            message.recipient,
+            message.subject,
+            chrono::Utc::now().to_rfc2822(),
+            smtp_body(&message.body)
+        );
+        self.command(&mut reader, &mut writer, &data, &[250])
+            .await?;
+
+        // The message is accepted at this point, so QUIT errors do not matter
+        let _ = self
+            .command(&mut reader, &mut writer, "QUIT\r\n", &[221])
+            .await;
+
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let (mut reader, mut writer) = self.connect().await?;
+        let _ = self
+            .command(&mut reader, &mut writer, "QUIT\r\n", &[221])
+            .await;
+        Ok(())
+    }
+}
+
+// SMS gateways and push services that accept a JSON POST over plain HTTP
+#[derive(Debug, Clone)]
+pub struct HttpBackend {
+    pub name: String,
+    pub url: String,
+}
+
+#[tonic::async_trait]
+impl ChannelBackend for HttpBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> Result<(), String> {
+        let payload = serde_json::json!({
+            "to": message.recipient,
+            "subject": message.subject,
+            "message": message.body,
+        });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .map_err(|e| format!("Invalid {} request: {}", self.name, e))?;
+
+        let response = tokio::time::timeout(TIMEOUT, Client::new().request(request))
+            .await
+            .map_err(|_| format!("Timed out calling {}", self.url))?
+            .map_err(|e| format!("Failed to call {}: {}", self.url, e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("{} responded with {}", self.url, response.status()));
+        }
+        Ok(())
+    }
+}
+
+// Local stand-in for every channel: notifications are appended to a file, or
+// printed to stdout when no file is configured
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+    pub name: String,
+    pub path: Option<PathBuf>,
+}
+
+#[tonic::async_trait]
+impl ChannelBackend for FileBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn needs_address(&self) -> bool {
+        false
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> Result<(), String> {
+        let line = format!(
+            "{} [{}] to {}: {} | {}\n",
+            chrono::Utc::now().to_rfc3339(),
+            self.name,
+            message.recipient,
+            message.subject,
+            message.body.replace('\n', " ")
+        );
+
+        match &self.path {
+            Some(path) => {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+                file.write_all(line.as_bytes())
+                    .await
+                    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+            }
+            None => {
+                print!("{}", line);
+                Ok(())
+            }
+        }
+    }
+}
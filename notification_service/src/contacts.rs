@@ -0,0 +1,151 @@
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    options::UpdateOptions,
+    Collection,
+};
+
+use crate::notification_service::notification::{ContactDetails, NotificationType};
+use crate::notification_service::MyNotificationService;
+
+// Where a customer's notifications go, one document per customer in
+// `notification_contacts`
+fn contact_field(notification_type: NotificationType) -> &'static str {
+    match notification_type {
+        NotificationType::Email => "email",
+        NotificationType::Sms => "phone_number",
+        NotificationType::PushNotification => "push_token",
+    }
+}
+
+fn validate_contacts(contact_details: &ContactDetails) -> Result<(), String> {
+    let email = contact_details.email.trim();
+    if !email.is_empty() {
+        let valid = match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !email.contains(char::is_whitespace)
+            }
+            None => false,
+        };
+        if !valid {
+            return Err(format!("Invalid email address: {}", email));
+        }
+    }
+
+    let phone_number = contact_details.phone_number.trim();
+    if !phone_number.is_empty() {
+        let digits = phone_number.strip_prefix('+').unwrap_or_default();
+        if !(8..=15).contains(&digits.len()) || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(
+                "Phone numbers must be in international format, e.g. +34600111222".to_string(),
+            );
+        }
+    }
+
+    if contact_details.push_token.contains(char::is_whitespace) {
+        return Err("Push tokens cannot contain whitespace".to_string());
+    }
+
+    Ok(())
+}
+
+fn contacts_to_proto(contacts: &Document) -> ContactDetails {
+    ContactDetails {
+        email: contacts.get_str("email").unwrap_or_default().to_string(),
+        phone_number: contacts
+            .get_str("phone_number")
+            .unwrap_or_default()
+            .to_string(),
+        push_token: contacts
+            .get_str("push_token")
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+impl MyNotificationService {
+    pub async fn get_contacts(&self, customer_id: &str) -> Result<ContactDetails, Status> {
+        let contacts_collection: Collection<Document> = self.db.collection("notification_contacts");
+
+        if customer_id.is_empty() {
+            return Err(Status::invalid_argument("Customer id is required"));
+        }
+
+        let contacts = contacts_collection
+            .find_one(doc! { "customer_id": customer_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get contact details: {}", e)))?;
+
+        Ok(contacts
+            .map(|contacts| contacts_to_proto(&contacts))
+            .unwrap_or_default())
+    }
+
+    pub async fn set_contacts(
+        &self,
+        customer_id: &str,
+        contact_details: ContactDetails,
+    ) -> Result<ContactDetails, Status> {
+        let contacts_collection: Collection<Document> = self.db.collection("notification_contacts");
+
+        if customer_id.is_empty() {
+            return Err(Status::invalid_argument("Customer id is required"));
+        }
+        validate_contacts(&contact_details).map_err(Status::invalid_argument)?;
+
+        let mut set = doc! { "updated_at": DateTime::now() };
+        let mut unset = doc! {};
+        for (field, value) in [
+            ("email", contact_details.email.trim()),
+            ("phone_number", contact_details.phone_number.trim()),
+            ("push_token", contact_details.push_token.trim()),
+        ] {
+            if value.is_empty() {
+                unset.insert(field, "");
+            } else {
+                set.insert(field, value);
+            }
+        }
+
+        let mut update = doc! { "$set": set };
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+
+        contacts_collection
+            .update_one(
+                doc! { "customer_id": customer_id },
+                update,
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to set contact details: {}", e)))?;
+
+        self.get_contacts(customer_id).await
+    }
+
+    pub async fn recipient(
+        &self,
+        customer_id: &str,
+        notification_type: NotificationType,
+    ) -> Result<Option<String>, Status> {
+        let contacts_collection: Collection<Document> = self.db.collection("notification_contacts");
+
+        let contacts = contacts_collection
+            .find_one(doc! { "customer_id": customer_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get contact details: {}", e)))?;
+
+        Ok(contacts.and_then(|contacts| {
+            contacts
+                .get_str(contact_field(notification_type))
+                .ok()
+                .map(|recipient| recipient.to_string())
+        }))
+    }
+}
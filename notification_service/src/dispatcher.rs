@@ -0,0 +1,214 @@
+use std::str::FromStr;
+use tonic::Status;
+
+use log::{error, info, warn};
+
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use crate::channels::OutgoingMessage;
+use crate::notification_service::notification::{DeliveryStatus, NotificationType};
+use crate::notification_service::MyNotificationService;
+use crate::templates::DEFAULT_SUBJECT;
+
+const BATCH_SIZE: usize = 100;
+
+// Failed deliveries are retried with exponential backoff, starting at
+// `base_delay` and capped at `max_delay`, until `max_attempts` is reached.
+// A claimed notification is leased so that a crashed attempt is picked up
+// again once the lease runs out.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: i32,
+    pub base_delay: chrono::Duration,
+    pub max_delay: chrono::Duration,
+    pub lease: chrono::Duration,
+    pub interval: std::time::Duration,
+}
+
+impl RetryPolicy {
+    pub fn backoff(&self, attempts: i32) -> chrono::Duration {
+        let exponent = (attempts - 1).clamp(0, 20) as u32;
+        let delay = self
+            .base_delay
+            .num_seconds()
+            .saturating_mul(2i64.pow(exponent));
+        chrono::Duration::seconds(delay.min(self.max_delay.num_seconds()))
+    }
+}
+
+fn after(duration: chrono::Duration) -> DateTime {
+    DateTime::from_millis((chrono::Utc::now() + duration).timestamp_millis())
+}
+
+impl MyNotificationService {
+    // Takes the lease on a pending notification that is due, counting the attempt
+    pub async fn claim_notification(
+        &self,
+        mut filter: Document,
+    ) -> Result<Option<Document>, Status> {
+        let notifications_collection: Collection<Document> = self.db.collection("notifications");
+
+        filter.insert("status", DeliveryStatus::Pending.to_string());
+        filter.insert(
+            "$or",
+            vec![
+                doc! { "next_attempt_at": { "$exists": false } },
+                doc! { "next_attempt_at": { "$lte": DateTime::now() } },
+            ],
+        );
+
+        notifications_collection
+            .find_one_and_update(
+                filter,
+                doc! {
+                    "$set": { "next_attempt_at": after(self.retry_policy.lease) },
+                    "$inc": { "attempts": 1 },
+                },
+                FindOneAndUpdateOptions::builder()
+                    .sort(doc! { "created_at": 1 })
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to claim notification: {}", e)))
+    }
+
+    // Attempts delivery of a claimed notification and records the outcome
+    pub async fn deliver(&self, notification: &Document) -> Result<DeliveryStatus, Status> {
+        let notifications_collection: Collection<Document> = self.db.collection("notifications");
+
+        let notification_id = notification.get_object_id("_id").unwrap();
+        let customer_id = notification.get_str("customer_id").unwrap_or_default();
+        let attempts = notification.get_i32("attempts").unwrap_or(1);
+
+        // Permanent errors are not retried
+        let (channel, outcome) = match notification
+            .get_str("notification_type")
+            .map_err(|e| e.to_string())
+            .and_then(NotificationType::from_str)
+        {
+            Ok(notification_type) => {
+                let backend = self.channels.backend(notification_type);
+                let recipient = match self.recipient(customer_id, notification_type).await? {
+                    Some(recipient) => Some(recipient),
+                    None if !backend.needs_address() => Some(format!("customer {}", customer_id)),
+                    None => None,
+                };
+                let outcome = match recipient {
+                    Some(recipient) => {
+                        let message = OutgoingMessage {
+                            recipient,
+                            subject: notification
+                                .get_str("subject")
+                                .unwrap_or(DEFAULT_SUBJECT)
+                                .to_string(),
+                            body: notification
+                                .get_str("message")
+                                .unwrap_or_default()
+                                .to_string(),
+                        };
+                        backend.send(&message).await.map_err(|e| (e, false))
+                    }
+                    None => Err((
+                        format!(
+                            "No {} contact details for customer {}",
+                            notification_type, customer_id
+                        ),
+                        true,
+                    )),
+                };
+                (backend.name().to_string(), outcome)
+            }
+            Err(e) => (String::new(), Err((e, true))),
+        };
+
+        let (status, update) = match outcome {
+            Ok(()) => (
+                DeliveryStatus::Sent,
+                doc! {
+                    "$set": {
+                        "status": DeliveryStatus::Sent.to_string(),
+                        "channel": &channel,
+                        "sent_at": DateTime::now(),
+                    },
+                    "$unset": { "next_attempt_at": "", "last_error": "" },
+                },
+            ),
+            Err((e, permanent)) if permanent || attempts >= self.retry_policy.max_attempts => (
+                DeliveryStatus::Failed,
+                doc! {
+                    "$set": {
+                        "status": DeliveryStatus::Failed.to_string(),
+                        "channel": &channel,
+                        "last_error": e,
+                    },
+                    "$unset": { "next_attempt_at": "" },
+                },
+            ),
+            Err((e, _)) => (
+                DeliveryStatus::Pending,
+                doc! {
+                    "$set": {
+                        "channel": &channel,
+                        "last_error": e,
+                        "next_attempt_at": after(self.retry_policy.backoff(attempts)),
+                    },
+                },
+            ),
+        };
+
+        notifications_collection
+            .update_one(doc! { "_id": notification_id }, update, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update notification: {}", e)))?;
+
+        match status {
+            DeliveryStatus::Sent => info!(
+                "Notification {} sent to customer {} via {}",
+                notification_id, customer_id, channel
+            ),
+            DeliveryStatus::Failed => error!(
+                "Notification {} failed after {} attempt(s)",
+                notification_id, attempts
+            ),
+            DeliveryStatus::Pending => warn!(
+                "Notification {} attempt {} failed, retrying later",
+                notification_id, attempts
+            ),
+        }
+
+        Ok(status)
+    }
+
+    pub async fn dispatch_pending(&self) -> Result<usize, Status> {
+        let mut dispatched = 0;
+        while dispatched < BATCH_SIZE {
+            let notification = match self.claim_notification(doc! {}).await? {
+                Some(notification) => notification,
+                None => break,
+            };
+            self.deliver(&notification).await?;
+            dispatched += 1;
+        }
+        Ok(dispatched)
+    }
+
+    pub async fn run_dispatcher(self) {
+        info!(
+            "Notification dispatcher started, running every {:?}",
+            self.retry_policy.interval
+        );
+
+        let mut interval = tokio::time::interval(self.retry_policy.interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.dispatch_pending().await {
+                error!("Failed to dispatch notifications: {:?}", e);
+            }
+        }
+    }
+}
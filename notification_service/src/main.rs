@@ -0,0 +1,172 @@
+use dotenv::dotenv;
+use env_logger::Env;
+use log::{error, info};
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
+use tonic::transport::Server;
+
+mod channels;
+mod contacts;
+mod dispatcher;
+mod notification_service;
+mod templates;
+use bank_common::health::{Dependency, HealthService};
+use bank_common::registry::Registrar;
+use channels::{ChannelBackend, Channels, FileBackend, HttpBackend, SmtpBackend};
+use dispatcher::RetryPolicy;
+use notification_service::{
+    notification::notification_service_server::NotificationServiceServer, MyNotificationService,
+};
+use templates::TemplateStore;
+
+// Descriptors of the protos of the service, written by build.rs
+const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptor");
+
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Every channel defaults to the stdout stand-in; `file` appends to
+// NOTIFICATION_OUTBOX_FILE instead
+fn channel_backend(
+    channel: &str,
+    backend_var: &str,
+    url_var: &str,
+) -> Result<Arc<dyn ChannelBackend>, String> {
+    let outbox_file = env::var("NOTIFICATION_OUTBOX_FILE").ok().map(PathBuf::from);
+
+    match env::var(backend_var)
+        .unwrap_or_else(|_| "stdout".to_string())
+        .as_str()
+    {
+        "stdout" => Ok(Arc::new(FileBackend {
+            name: format!("{}-stdout", channel),
+            path: None,
+        })),
+        "file" => Ok(Arc::new(FileBackend {
+            name: format!("{}-file", channel),
+            path: Some(
+                outbox_file.ok_or("NOTIFICATION_OUTBOX_FILE is required for file backends")?,
+            ),
+        })),
+        "smtp" if channel == "email" => Ok(Arc::new(SmtpBackend {
+            address: format!(
+                "{}:{}",
+                env::var("SMTP_HOST").map_err(|_| "SMTP_HOST is required for the smtp backend")?,
+                env_number("SMTP_PORT", 25)
+            ),
+            from: env::var("SMTP_FROM").unwrap_or_else(|_| "no-reply@simplebank.local".to_string()),
+        })),
+        "http" if channel != "email" => Ok(Arc::new(HttpBackend {
+            name: format!("{}-http", channel),
+            url: env::var(url_var)
+                .map_err(|_| format!("{} is required for http backends", url_var))?,
+        })),
+        backend => Err(format!("Unsupported {} backend: {}", channel, backend)),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Initialize the logger
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    dotenv().ok(); // Load environment variables from .env file
+
+    let mongodb_uri =
+        env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+    let addr = env::var("GRPC_SERVER_ADDRESS")
+        .unwrap_or_else(|_| "0.0.0.0:50056".to_string())
+        .parse()
+        .unwrap();
+
+    let channels = Channels {
+        email: channel_backend("email", "EMAIL_BACKEND", "")?,
+        sms: channel_backend("sms", "SMS_BACKEND", "SMS_GATEWAY_URL")?,
+        push: channel_backend("push", "PUSH_BACKEND", "PUSH_GATEWAY_URL")?,
+    };
+    info!(
+        "Notification channels: email={}, sms={}, push={}",
+        channels.email.name(),
+        channels.sms.name(),
+        channels.push.name()
+    );
+
+    let mut templates = TemplateStore::with_defaults();
+    if let Ok(templates_dir) = env::var("NOTIFICATION_TEMPLATES_DIR") {
+        let loaded = templates.load_dir(&PathBuf::from(&templates_dir))?;
+        info!(
+            "Loaded {} notification template(s) from {}",
+            loaded, templates_dir
+        );
+    }
+
+    let retry_policy = RetryPolicy {
+        max_attempts: env_number("NOTIFICATION_MAX_ATTEMPTS", 5),
+        base_delay: chrono::Duration::seconds(env_number("NOTIFICATION_RETRY_BASE_SECS", 30)),
+        max_delay: chrono::Duration::seconds(env_number("NOTIFICATION_RETRY_MAX_SECS", 3600)),
+        lease: chrono::Duration::minutes(5),
+        interval: std::time::Duration::from_secs(env_number(
+            "NOTIFICATION_DISPATCH_INTERVAL_SECS",
+            10,
+        )),
+    };
+
+    let notification_service =
+        MyNotificationService::new(&mongodb_uri, channels.clone(), templates, retry_policy).await?;
+
+    // Test MongoDB connection
+    match notification_service.test_connection().await {
+        Ok(_) => info!("✅ Connection to MongoDB is successful!"),
+        Err(e) => {
+            error!("❌ Failed to connect to MongoDB: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+
+    tokio::spawn(notification_service.clone().run_dispatcher());
+
+    // Serves grpc.health.v1 from periodic checks of the dependencies, and
+    // server reflection for tools like grpcurl
+    let dependencies: Vec<Arc<dyn Dependency>> = vec![
+        Arc::new(notification_service.clone()),
+        Arc::new(channels.email),
+        Arc::new(channels.sms),
+        Arc::new(channels.push),
+    ];
+    let health = HealthService::new(
+        "notification.NotificationService",
+        dependencies,
+        Duration::from_secs(env_number("HEALTH_CHECK_INTERVAL_SECS", 10)),
+        Duration::from_secs(env_number("HEALTH_CHECK_TIMEOUT_SECS", 5)),
+    );
+    health.check().await;
+    tokio::spawn(health.clone().run_checks());
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(bank_common::health::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    // Announce this instance to the registry the gateway discovers it from
+    let registrar = match Registrar::from_env("notification", &addr) {
+        Ok(registrar) => registrar,
+        Err(e) => {
+            error!("❌ Failed to set up the service registry: {}", e);
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(registrar.clone().run_heartbeat());
+
+    info!("✅ Server started successfully");
+
+    Server::builder()
+        .add_service(health.server())
+        .add_service(reflection)
+        .add_service(NotificationServiceServer::new(notification_service))
+        .serve_with_shutdown(addr, registrar.shutdown_signal())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,345 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+use futures::stream::TryStreamExt;
+use log::{error, info};
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::FindOptions,
+    Collection,
+    {options::ClientOptions, Client},
+};
+
+use notification::notification_service_server::NotificationService;
+use notification::{
+    DeliveryStatus, GetContactDetailsRequest, GetContactDetailsResponse, GetNotificationRequest,
+    GetNotificationResponse, ListNotificationsRequest, ListNotificationsResponse, Notification,
+    NotificationType, SendNotificationRequest, SendNotificationResponse, SetContactDetailsRequest,
+    SetContactDetailsResponse,
+};
+
+use crate::channels::Channels;
+use crate::dispatcher::RetryPolicy;
+use bank_common::health::Dependency;
+use crate::templates::{TemplateStore, DEFAULT_SUBJECT};
+
+pub mod notification {
+    tonic::include_proto!("notification");
+}
+
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+
+#[derive(Debug, Clone)]
+pub struct MyNotificationService {
+    pub(crate) db: Arc<mongodb::Database>,
+    pub(crate) channels: Channels,
+    templates: Arc<TemplateStore>,
+    pub(crate) retry_policy: RetryPolicy,
+}
+
+impl Display for NotificationType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let notification_type_str = match self {
+            NotificationType::Email => "EMAIL",
+            NotificationType::Sms => "SMS",
+            NotificationType::PushNotification => "PUSH_NOTIFICATION",
+        };
+
+        write!(f, "{}", notification_type_str)
+    }
+}
+
+impl FromStr for NotificationType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "EMAIL" => Ok(NotificationType::Email),
+            "SMS" => Ok(NotificationType::Sms),
+            "PUSH_NOTIFICATION" => Ok(NotificationType::PushNotification),
+            _ => Err(format!("Invalid notification type: {}", s)),
+        }
+    }
+}
+
+impl Display for DeliveryStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let delivery_status_str = match self {
+            DeliveryStatus::Pending => "PENDING",
+            DeliveryStatus::Sent => "SENT",
+            DeliveryStatus::Failed => "FAILED",
+        };
+
+        write!(f, "{}", delivery_status_str)
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PENDING" => Ok(DeliveryStatus::Pending),
+            "SENT" => Ok(DeliveryStatus::Sent),
+            "FAILED" => Ok(DeliveryStatus::Failed),
+            _ => Err(format!("Invalid delivery status: {}", s)),
+        }
+    }
+}
+
+fn timestamp(notification: &Document, key: &str) -> i64 {
+    notification
+        .get_datetime(key)
+        .map(|datetime| datetime.timestamp_millis() / 1000)
+        .unwrap_or_default()
+}
+
+// Notifications written by other services only carry a customer, a type, a
+// message and a status, so every other field is optional
+pub fn notification_to_proto(notification: &Document) -> Notification {
+    let notification_type = notification
+        .get_str("notification_type")
+        .ok()
+        .and_then(|value| NotificationType::from_str(value).ok())
+        .unwrap_or(NotificationType::Email);
+    let status = notification
+        .get_str("status")
+        .ok()
+        .and_then(|value| DeliveryStatus::from_str(value).ok())
+        .unwrap_or(DeliveryStatus::Pending);
+
+    Notification {
+        notification_id: notification.get_object_id("_id").unwrap().to_hex(),
+        customer_id: notification
+            .get_str("customer_id")
+            .unwrap_or_default()
+            .to_string(),
+        notification_type: notification_type as i32,
+        subject: notification
+            .get_str("subject")
+            .unwrap_or(DEFAULT_SUBJECT)
+            .to_string(),
+        message: notification
+            .get_str("message")
+            .unwrap_or_default()
+            .to_string(),
+        status: status as i32,
+        attempts: notification.get_i32("attempts").unwrap_or_default(),
+        last_error: notification
+            .get_str("last_error")
+            .unwrap_or_default()
+            .to_string(),
+        channel: notification
+            .get_str("channel")
+            .unwrap_or_default()
+            .to_string(),
+        created_timestamp: timestamp(notification, "created_at"),
+        sent_timestamp: timestamp(notification, "sent_at"),
+        next_attempt_timestamp: timestamp(notification, "next_attempt_at"),
+    }
+}
+
+impl MyNotificationService {
+    pub async fn new(
+        uri: &str,
+        channels: Channels,
+        templates: TemplateStore,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let client_options = ClientOptions::parse(uri).await?;
+        let client = Client::with_options(client_options)?;
+        let db = client.database("bank");
+        Ok(Self {
+            db: Arc::new(db),
+            channels,
+            templates: Arc::new(templates),
+            retry_policy,
+        })
+    }
+
+    pub async fn test_connection(&self) -> Result<(), mongodb::error::Error> {
+        let _ = self.db.run_command(doc! { "ping": 1 }, None).await?;
+        Ok(())
+    }
+
+    fn render(&self, req: &SendNotificationRequest) -> Result<(String, String), String> {
+        if !req.template.is_empty() {
+            return self.templates.render(&req.template, &req.template_data);
+        }
+        if req.message.trim().is_empty() {
+            return Err("Either a message or a template is required".to_string());
+        }
+        Ok((DEFAULT_SUBJECT.to_string(), req.message.clone()))
+    }
+
+    async fn get_notification_doc(&self, notification_id: &str) -> Result<Document, Status> {
+        let notifications_collection: Collection<Document> = self.db.collection("notifications");
+
+        let notification_id = ObjectId::parse_str(notification_id)
+            .map_err(|_| Status::invalid_argument("Invalid notification id"))?;
+
+        notifications_collection
+            .find_one(doc! { "_id": notification_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get notification: {}", e)))?
+            .ok_or_else(|| Status::not_found("Notification not found"))
+    }
+}
+
+#[tonic::async_trait]
+impl Dependency for MyNotificationService {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.test_connection()
+            .await
+            .map_err(|e| format!("Failed to ping MongoDB: {}", e))
+    }
+}
+
+#[tonic::async_trait]
+impl NotificationService for MyNotificationService {
+    async fn send_notification(
+        &self,
+        request: Request<SendNotificationRequest>,
+    ) -> Result<Response<SendNotificationResponse>, Status> {
+        let req = request.into_inner();
+        let notifications_collection: Collection<Document> = self.db.collection("notifications");
+
+        if req.customer_id.is_empty() {
+            return Err(Status::invalid_argument("Customer id is required"));
+        }
+        let notification_type = NotificationType::from_i32(req.notification_type)
+            .ok_or_else(|| Status::invalid_argument("Invalid notification type"))?;
+        let (subject, message) = self.render(&req).map_err(Status::invalid_argument)?;
+
+        let mut notification = doc! {
+            "customer_id": &req.customer_id,
+            "notification_type": notification_type.to_string(),
+            "subject": subject,
+            "message": message,
+            "status": DeliveryStatus::Pending.to_string(),
+            "attempts": 0,
+            "created_at": DateTime::now(),
+        };
+        if !req.template.is_empty() {
+            notification.insert("template", &req.template);
+        }
+
+        let notification_id = notifications_collection
+            .insert_one(notification, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create notification: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap();
+
+        info!(
+            "Notification {} created for customer {}",
+            notification_id, req.customer_id
+        );
+
+        // The first attempt is made straight away; failures are left to the dispatcher
+        let status = match self
+            .claim_notification(doc! { "_id": notification_id })
+            .await?
+        {
+            Some(notification) => self.deliver(&notification).await?,
+            None => DeliveryStatus::Pending,
+        };
+
+        Ok(Response::new(SendNotificationResponse {
+            success: status == DeliveryStatus::Sent,
+            notification_id: notification_id.to_hex(),
+            status: status as i32,
+        }))
+    }
+
+    async fn get_notification(
+        &self,
+        request: Request<GetNotificationRequest>,
+    ) -> Result<Response<GetNotificationResponse>, Status> {
+        let notification = self
+            .get_notification_doc(&request.into_inner().notification_id)
+            .await?;
+
+        Ok(Response::new(GetNotificationResponse {
+            notification: Some(notification_to_proto(&notification)),
+        }))
+    }
+
+    async fn list_notifications(
+        &self,
+        request: Request<ListNotificationsRequest>,
+    ) -> Result<Response<ListNotificationsResponse>, Status> {
+        let req = request.into_inner();
+        let notifications_collection: Collection<Document> = self.db.collection("notifications");
+
+        let mut filter = doc! {};
+        if !req.customer_id.is_empty() {
+            filter.insert("customer_id", &req.customer_id);
+        }
+        if !req.status.is_empty() {
+            let status = DeliveryStatus::from_str(&req.status).map_err(Status::invalid_argument)?;
+            filter.insert("status", status.to_string());
+        }
+        let limit = match req.limit {
+            0 => DEFAULT_LIST_LIMIT,
+            limit if limit < 0 => {
+                return Err(Status::invalid_argument("Limit cannot be negative"));
+            }
+            limit => limit.min(MAX_LIST_LIMIT),
+        };
+
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": -1 })
+            .limit(limit)
+            .build();
+
+        let mut cursor = notifications_collection
+            .find(filter, options)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to list notifications: {}", e)))?;
+
+        let mut notifications = Vec::new();
+        while let Some(notification) = cursor.try_next().await.map_err(|e| {
+            error!("Failed to read notification: {}", e);
+            Status::internal(format!("Failed to list notifications: {}", e))
+        })? {
+            notifications.push(notification_to_proto(&notification));
+        }
+
+        Ok(Response::new(ListNotificationsResponse { notifications }))
+    }
+
+    async fn set_contact_details(
+        &self,
+        request: Request<SetContactDetailsRequest>,
+    ) -> Result<Response<SetContactDetailsResponse>, Status> {
+        let req = request.into_inner();
+        let contact_details = self
+            .set_contacts(&req.customer_id, req.contact_details.unwrap_or_default())
+            .await?;
+
+        Ok(Response::new(SetContactDetailsResponse {
+            contact_details: Some(contact_details),
+        }))
+    }
+
+    async fn get_contact_details(
+        &self,
+        request: Request<GetContactDetailsRequest>,
+    ) -> Result<Response<GetContactDetailsResponse>, Status> {
+        let contact_details = self.get_contacts(&request.into_inner().customer_id).await?;
+
+        Ok(Response::new(GetContactDetailsResponse {
+            contact_details: Some(contact_details),
+        }))
+    }
+}
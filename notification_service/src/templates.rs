@@ -0,0 +1,117 @@
+use std::{collections::HashMap, fs, path::Path};
+
+pub const DEFAULT_SUBJECT: &str = "Simple Bank notification";
+
+// Templates use `{{ name }}` placeholders. A template file is named after the
+// template (`<name>.txt`) and may start with a `Subject: ...` line.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TemplateStore {
+    templates: HashMap<String, Template>,
+}
+
+fn template(subject: &str, body: &str) -> Template {
+    Template {
+        subject: subject.to_string(),
+        body: body.to_string(),
+    }
+}
+
+pub fn render_text(text: &str, data: &HashMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .map(|end| start + end)
+            .ok_or_else(|| "Unclosed placeholder in template".to_string())?;
+        let name = rest[start + 2..end].trim();
+        let value = data
+            .get(name)
+            .ok_or_else(|| format!("Missing template value: {}", name))?;
+
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(value);
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+impl TemplateStore {
+    pub fn with_defaults() -> Self {
+        let templates = HashMap::from([
+            (
+                "overdraft_alert".to_string(),
+                template("Overdraft alert", "Your account {{ account_number }} {{ alert }}"),
+            ),
+            (
+                "standing_order_failed".to_string(),
+                template(
+                    "Standing order failed",
+                    "Standing order {{ standing_order_id }} could not be executed: {{ reason }}",
+                ),
+            ),
+            (
+                "approval_requested".to_string(),
+                template(
+                    "Approval required",
+                    "{{ summary }} is waiting for your approval",
+                ),
+            ),
+        ]);
+
+        Self { templates }
+    }
+
+    // Templates in `dir` replace the built-in ones with the same name
+    pub fn load_dir(&mut self, dir: &Path) -> Result<usize, std::io::Error> {
+        let mut loaded = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("txt") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            let contents = fs::read_to_string(&path)?;
+            let (subject, body) = match contents.strip_prefix("Subject:") {
+                Some(rest) => {
+                    let (subject, body) = rest.split_once('\n').unwrap_or((rest, ""));
+                    (subject.trim(), body.trim_start_matches(['\r', '\n']))
+                }
+                None => (DEFAULT_SUBJECT, contents.as_str()),
+            };
+            self.templates
+                .insert(name, template(subject, body.trim_end()));
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    pub fn render(
+        &self,
+        name: &str,
+        data: &HashMap<String, String>,
+    ) -> Result<(String, String), String> {
+        let template = self
+            .templates
+            .get(name)
+            .ok_or_else(|| format!("Unknown template: {}", name))?;
+
+        Ok((
+            render_text(&template.subject, data)?,
+            render_text(&template.body, data)?,
+        ))
+    }
+}
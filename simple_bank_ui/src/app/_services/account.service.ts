@@ -25,11 +25,11 @@ export class AccountService {
     return this.http.get(API_URL + 'accounts');
   }
 
-  updateAccount(accountId: string, accountName: string, newBalance: number): Observable<any> {
+  updateAccount(accountId: string, accountName: string, nickname: string): Observable<any> {
     return this.http.put(API_URL + 'update', {
       account_id: accountId,
       account_name: accountName,
-      balance: newBalance,
+      nickname: nickname,
     });
   }
 
@@ -35,7 +35,7 @@ export class HistoryComponent implements OnInit {
         this.transactions = response.data.transactions.map((t: any) => {
           return {
             ...t,
-            transaction_type: t.transaction_type === 0 ? 'Deposit' : 'Withdrawal',
+            transaction_type: ['Deposit', 'Withdrawal', 'Interest', 'Fee', 'Adjustment'][t.transaction_type] ?? 'Unknown',
           };
         });
         console.log(this.transactions)
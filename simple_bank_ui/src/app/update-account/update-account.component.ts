@@ -9,13 +9,13 @@ import { AccountService } from '../_services/account.service';
 export class UpdateAccountComponent {
   accountId: string = '';
   accountName: string = '';
-  balance: number = 0;
+  nickname: string = '';
 
   constructor(private accountService: AccountService) {}
 
   onSubmit() {
     this.accountService
-      .updateAccount(this.accountId, this.accountName, this.balance)
+      .updateAccount(this.accountId, this.accountName, this.nickname)
       .subscribe(
         (response) => {
           console.log('Account updated successfully', response);
@@ -2,6 +2,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
         .build_server(true)
         .build_client(false)
+        // Kept for server reflection
+        .file_descriptor_set_path(
+            std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("descriptor.bin"),
+        )
         .compile(&["proto/user_service.proto"], &["proto/"])?;
     Ok(())
 }
@@ -1,13 +1,18 @@
 use dotenv::dotenv;
 use env_logger::Env;
 use log::{error, info};
-use std::env;
+use std::{env, sync::Arc};
 use tonic::transport::Server;
 
 mod mongodb_client;
 mod user_service;
+use bank_common::health::{Dependency, HealthService};
+use bank_common::registry::Registrar;
 use user_service::{user_service::user_service_server::UserServiceServer, MyUserService};
 
+// Descriptors of the protos of the service, written by build.rs
+const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptor");
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize the logger
@@ -31,11 +36,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // Serves grpc.health.v1 from periodic checks of the dependencies, and
+    // server reflection for tools like grpcurl
+    let dependencies: Vec<Arc<dyn Dependency>> = vec![Arc::new(user_service.clone())];
+    let health = HealthService::new(
+        "user_service.UserService",
+        dependencies,
+        std::time::Duration::from_secs(
+            env::var("HEALTH_CHECK_INTERVAL_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(10),
+        ),
+        std::time::Duration::from_secs(
+            env::var("HEALTH_CHECK_TIMEOUT_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(5),
+        ),
+    );
+    health.check().await;
+    tokio::spawn(health.clone().run_checks());
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(bank_common::health::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    // Announce this instance to the registry the gateway discovers it from
+    let registrar = match Registrar::from_env("user", &addr) {
+        Ok(registrar) => registrar,
+        Err(e) => {
+            error!("❌ Failed to set up the service registry: {}", e);
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(registrar.clone().run_heartbeat());
+
     info!("✅ Server started successfully");
 
     Server::builder()
+        .add_service(health.server())
+        .add_service(reflection)
         .add_service(UserServiceServer::new(user_service))
-        .serve(addr)
+        .serve_with_shutdown(addr, registrar.shutdown_signal())
         .await?;
 
     Ok(())
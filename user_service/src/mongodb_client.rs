@@ -1,28 +1,13 @@
-use mongodb::{
-    Collection,
-    bson::{doc, Document},
-    {options::ClientOptions, Client}
-};
+use mongodb::{options::ClientOptions, Client, Database};
 use std::env;
 
 lazy_static::lazy_static! {
     static ref DATABASE: String = env::var("DATABASE").unwrap_or_else(|_| "bank".to_string());
 }
 
-pub async fn get_collection(name: &str) -> Result<Collection<Document>, mongodb::error::Error> {
+pub async fn get_database() -> Result<Database, mongodb::error::Error> {
     let mongodb_uri = env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
     let client_options = ClientOptions::parse(&mongodb_uri).await?;
     let client = Client::with_options(client_options)?;
-    let db = client.database(&*DATABASE);
-    let collection = db.collection(name);
-    Ok(collection)
+    Ok(client.database(&DATABASE))
 }
-
-pub async fn test_connection() -> Result<(), mongodb::error::Error> {
-    let mongodb_uri = env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
-    let client_options = ClientOptions::parse(&mongodb_uri).await?;
-    let client = Client::with_options(client_options)?;
-    let _ = client.database(&*DATABASE).run_command(doc! { "ping": 1 }, None).await?;
-    Ok(())
-}
- 
\ No newline at end of file
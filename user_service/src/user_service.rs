@@ -5,9 +5,10 @@ use uuid::Uuid;
 
 use mongodb::{
     bson::{doc, oid::ObjectId, Document},
-    Collection,
+    Collection, Database,
 };
 
+#[allow(clippy::module_inception)]
 pub mod user_service {
     tonic::include_proto!("user_service");
 }
@@ -19,25 +20,44 @@ use user_service::{
     UpdateUserResponse,
 };
 
-use crate::mongodb_client::{get_collection, test_connection};
+use bank_common::health::Dependency;
+use crate::mongodb_client::get_database;
 
 #[derive(Debug, Clone)]
 pub struct MyUserService {
+    db: Database,
     users_collection: Collection<Document>,
 }
 
 impl MyUserService {
     pub async fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let users_collection: Collection<Document> = get_collection("users").await?;
-        Ok(Self { users_collection })
+        let db = get_database().await?;
+        let users_collection: Collection<Document> = db.collection("users");
+        Ok(Self {
+            db,
+            users_collection,
+        })
     }
 
     pub async fn test_connection(&self) -> Result<(), mongodb::error::Error> {
-        let _ = test_connection;
+        let _ = self.db.run_command(doc! { "ping": 1 }, None).await?;
         Ok(())
     }
 }
 
+#[tonic::async_trait]
+impl Dependency for MyUserService {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.test_connection()
+            .await
+            .map_err(|e| format!("Failed to ping MongoDB: {}", e))
+    }
+}
+
 #[tonic::async_trait]
 impl UserService for MyUserService {
     async fn create_user(
@@ -0,0 +1,144 @@
+use dotenv::dotenv;
+use env_logger::Env;
+use log::{error, info, warn};
+use std::{env, process, time::Duration};
+
+pub mod withdrawal {
+    tonic::include_proto!("withdrawal");
+}
+
+use withdrawal::withdrawal_service_client::WithdrawalServiceClient;
+use withdrawal::{CancelWithdrawalRequest, ConfirmWithdrawalRequest, ReserveWithdrawalRequest};
+
+// Simulated cash machine for trying out two-phase withdrawals against a
+// running withdrawal_service:
+//
+//     atm_client <account_id> <amount> [dispense|jam|walk-away]
+//
+// `dispense` confirms the withdrawal once the cash is out, `jam` cancels it as
+// a failed dispense, and `walk-away` never answers so the reservation times
+// out; the late confirmation it then sends must be rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Dispense,
+    Jam,
+    WalkAway,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: atm_client <account_id> <amount> [dispense|jam|walk-away]");
+    process::exit(2);
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    dotenv().ok();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 || args.len() > 3 {
+        usage();
+    }
+    let account_id = args[0].clone();
+    let amount: f64 = args[1].parse().unwrap_or_else(|_| usage());
+    let outcome = match args.get(2).map(String::as_str) {
+        None | Some("dispense") => Outcome::Dispense,
+        Some("jam") => Outcome::Jam,
+        Some("walk-away") => Outcome::WalkAway,
+        Some(_) => usage(),
+    };
+
+    let address = env::var("WITHDRAWAL_SERVICE_ADDRESS")
+        .unwrap_or_else(|_| "http://localhost:50054".to_string());
+    let terminal_id = env::var("ATM_TERMINAL_ID").unwrap_or_else(|_| "ATM-SIM-01".to_string());
+    let dispense_delay = Duration::from_millis(
+        env::var("ATM_DISPENSE_DELAY_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1000),
+    );
+
+    let mut client = WithdrawalServiceClient::connect(address).await?;
+
+    let reservation = match client
+        .reserve_withdrawal(ReserveWithdrawalRequest {
+            account_id,
+            amount,
+            currency: String::new(),
+            terminal_id,
+        })
+        .await
+    {
+        Ok(response) => response.into_inner().reservation.unwrap(),
+        Err(e) => {
+            error!("❌ Withdrawal refused: {}", e.message());
+            process::exit(1);
+        }
+    };
+    info!(
+        "Reserved {} (fee {}) as {}, expires at {}",
+        reservation.amount,
+        reservation.fee,
+        reservation.reservation_id,
+        reservation.expires_timestamp
+    );
+
+    info!("Dispensing cash...");
+    tokio::time::sleep(dispense_delay).await;
+
+    match outcome {
+        Outcome::Dispense => {
+            let confirmed = client
+                .confirm_withdrawal(ConfirmWithdrawalRequest {
+                    reservation_id: reservation.reservation_id,
+                })
+                .await?
+                .into_inner();
+            info!(
+                "✅ Cash dispensed, transaction {} (fee {})",
+                confirmed.transaction_id, confirmed.fee
+            );
+        }
+        Outcome::Jam => {
+            let cancelled = client
+                .cancel_withdrawal(CancelWithdrawalRequest {
+                    reservation_id: reservation.reservation_id,
+                    reason: "Cash dispenser jammed".to_string(),
+                })
+                .await?
+                .into_inner()
+                .reservation
+                .unwrap();
+            warn!(
+                "Dispenser jammed, reservation {} is now {:?}",
+                cancelled.reservation_id,
+                cancelled.status()
+            );
+        }
+        Outcome::WalkAway => {
+            let now = chrono::Utc::now().timestamp_millis();
+            let wait = (reservation.expires_timestamp - now).max(0) as u64;
+            info!(
+                "Not answering, waiting {} ms for the reservation to time out",
+                wait
+            );
+            tokio::time::sleep(Duration::from_millis(wait + 1000)).await;
+
+            match client
+                .confirm_withdrawal(ConfirmWithdrawalRequest {
+                    reservation_id: reservation.reservation_id,
+                })
+                .await
+            {
+                Ok(_) => {
+                    error!("❌ Late confirmation was accepted");
+                    process::exit(1);
+                }
+                Err(e) => info!("✅ Late confirmation rejected: {}", e.message()),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use mongodb::bson::{doc, oid::ObjectId, DateTime, Document};
+use mongodb::{Client, Database};
+
+use crate::withdrawal_service::MyWithdrawalService;
+use bank_common::outbox::{self, OutboxConfig, OutboxService};
+
+// Events published by this service. They are recorded and relayed by
+// bank_common::outbox.
+pub const FUNDS_WITHDRAWN: &str = "FundsWithdrawn";
+
+const SOURCE: &str = "withdrawal";
+
+pub fn new_event(event_type: &str, aggregate_id: ObjectId, data: Document) -> Document {
+    outbox::new_event(SOURCE, event_type, aggregate_id, data)
+}
+
+impl OutboxService for MyWithdrawalService {
+    const SOURCE: &'static str = SOURCE;
+
+    fn outbox_client(&self) -> &Client {
+        &self.client
+    }
+
+    fn outbox_db(&self) -> &Database {
+        &self.db
+    }
+
+    fn outbox_config(&self) -> &OutboxConfig {
+        &self.outbox_config
+    }
+}
+
+impl MyWithdrawalService {
+    pub fn funds_withdrawn_event(
+        &self,
+        account: &Document,
+        transaction_id: &str,
+        amount: f64,
+        timestamp: DateTime,
+    ) -> Document {
+        let account_id = account.get_object_id("_id").unwrap();
+        let currency = account
+            .get_str("currency")
+            .unwrap_or(&self.validation_policy.default_currency);
+
+        new_event(
+            FUNDS_WITHDRAWN,
+            account_id,
+            doc! {
+                "transaction_id": transaction_id,
+                "account_id": account_id.to_hex(),
+                "amount": amount,
+                "currency": currency,
+                "timestamp": timestamp.timestamp_millis(),
+            },
+        )
+    }
+}
@@ -0,0 +1,500 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use log::{error, info};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use tonic::Status;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use crate::withdrawal_service::withdrawal::{
+    ReservationStatus, ReserveWithdrawalRequest, WithdrawalReservation,
+};
+use crate::withdrawal_service::{CheckedWithdrawal, MyWithdrawalService};
+use bank_common::fee_engine::{self, OPERATION_WITHDRAWAL};
+use bank_common::limits;
+use bank_common::outbox::OutboxService;
+use bank_common::overdraft;
+use bank_common::validation;
+
+// Two-phase withdrawals, for cash machines that can fail to dispense. The
+// amount and its fee are added to the account's `held_amount` when reserved,
+// so they stop being available, and are only debited once the machine
+// confirms the cash was dispensed. The amount counts towards the ATM limits
+// from the moment it is reserved, so open reservations cannot be used to get
+// past them. Cancelled and timed-out reservations hand the funds and the limit
+// usage back.
+#[derive(Debug, Clone)]
+pub struct ReservationConfig {
+    pub timeout: Duration,
+    pub expiry_interval: std::time::Duration,
+}
+
+impl Display for ReservationStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let reservation_status_str = match self {
+            ReservationStatus::Reserved => "RESERVED",
+            ReservationStatus::Confirmed => "CONFIRMED",
+            ReservationStatus::Cancelled => "CANCELLED",
+            ReservationStatus::Expired => "EXPIRED",
+        };
+
+        write!(f, "{}", reservation_status_str)
+    }
+}
+
+impl FromStr for ReservationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "RESERVED" => Ok(ReservationStatus::Reserved),
+            "CONFIRMED" => Ok(ReservationStatus::Confirmed),
+            "CANCELLED" => Ok(ReservationStatus::Cancelled),
+            "EXPIRED" => Ok(ReservationStatus::Expired),
+            _ => Err(format!("Invalid reservation status: {}", s)),
+        }
+    }
+}
+
+pub fn reservation_to_proto(reservation: &Document) -> WithdrawalReservation {
+    WithdrawalReservation {
+        reservation_id: reservation.get_object_id("_id").unwrap().to_hex(),
+        account_id: reservation.get_object_id("account_id").unwrap().to_hex(),
+        amount: reservation.get_f64("amount").unwrap(),
+        fee: reservation.get_f64("fee").unwrap_or_default(),
+        channel: reservation
+            .get_str("channel")
+            .unwrap_or_default()
+            .to_string(),
+        terminal_id: reservation
+            .get_str("terminal_id")
+            .unwrap_or_default()
+            .to_string(),
+        status: ReservationStatus::from_str(reservation.get_str("status").unwrap()).unwrap() as i32,
+        transaction_id: reservation
+            .get_str("transaction_id")
+            .unwrap_or_default()
+            .to_string(),
+        created_timestamp: reservation
+            .get_datetime("created_at")
+            .unwrap()
+            .timestamp_millis(),
+        expires_timestamp: reservation
+            .get_datetime("expires_at")
+            .unwrap()
+            .timestamp_millis(),
+    }
+}
+
+fn reserved_amount(reservation: &Document) -> f64 {
+    reservation.get_f64("amount").unwrap() + reservation.get_f64("fee").unwrap_or_default()
+}
+
+impl MyWithdrawalService {
+    fn reservations_collection(&self) -> Collection<Document> {
+        self.db.collection("withdrawal_reservations")
+    }
+
+    async fn get_reservation(&self, reservation_id: &str) -> Result<Document, Status> {
+        let reservation_id = ObjectId::parse_str(reservation_id)
+            .map_err(|_| Status::invalid_argument("Invalid reservation id"))?;
+
+        self.reservations_collection()
+            .find_one(doc! { "_id": reservation_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get reservation: {}", e)))?
+            .ok_or_else(|| Status::not_found("Reservation not found"))
+    }
+
+    async fn release_funds(&self, account_id: ObjectId, amount: f64) -> Result<(), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        accounts_collection
+            .update_one(
+                doc! { "_id": account_id },
+                doc! { "$inc": { "held_amount": -amount } },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to release funds: {}", e)))?;
+
+        Ok(())
+    }
+
+    // Ends a reservation that is still open and gives its funds back
+    async fn end_reservation(
+        &self,
+        reservation: &Document,
+        status: ReservationStatus,
+        reason: &str,
+    ) -> Result<bool, Status> {
+        let update_result = self
+            .reservations_collection()
+            .update_one(
+                doc! {
+                    "_id": reservation.get_object_id("_id").unwrap(),
+                    "status": ReservationStatus::Reserved.to_string(),
+                },
+                doc! {
+                    "$set": {
+                        "status": status.to_string(),
+                        "reason": reason,
+                        "updated_at": DateTime::now(),
+                    }
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update reservation: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Ok(false);
+        }
+
+        let account_id = reservation.get_object_id("account_id").unwrap();
+        self.release_funds(account_id, reserved_amount(reservation))
+            .await?;
+        if reservation.get_bool("usage_recorded") == Ok(true) {
+            limits::release_usage(
+                &self.db,
+                account_id,
+                limits::OPERATION_WITHDRAWAL,
+                reservation.get_str("channel").unwrap_or_default(),
+                reservation.get_f64("amount").unwrap(),
+                *reservation.get_datetime("created_at").unwrap(),
+            )
+            .await?;
+        }
+
+        Ok(true)
+    }
+
+    pub async fn reserve_withdrawal(
+        &self,
+        req: &ReserveWithdrawalRequest,
+    ) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        // Reservations are the terminal path, so they are the only ATM withdrawals
+        if req.terminal_id.trim().is_empty() {
+            return Err(validation::invalid_field(
+                "terminal_id",
+                "The terminal reserving the withdrawal is required",
+            ));
+        }
+
+        let CheckedWithdrawal {
+            account_id,
+            account_doc,
+            channel,
+            fee_quote,
+        } = self
+            .check_withdrawal(
+                &req.account_id,
+                req.amount,
+                limits::ATM_CHANNEL,
+                &req.currency,
+            )
+            .await?;
+        let amount = req.amount + fee_quote.fee;
+
+        // The funds are only reserved if they are still available at this moment
+        let filter = doc! {
+            "_id": account_id,
+            "$expr": {
+                "$gte": [
+                    { "$subtract": ["$balance", { "$ifNull": ["$held_amount", 0.0] }] },
+                    amount - overdraft::overdraft_limit(&account_doc),
+                ]
+            },
+        };
+        let update_result = accounts_collection
+            .update_one(filter, doc! { "$inc": { "held_amount": amount } }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to reserve funds: {}", e)))?;
+        if update_result.modified_count == 0 {
+            return Err(Status::failed_precondition(
+                "Insufficient balance for withdrawal",
+            ));
+        }
+
+        let created_at = DateTime::now();
+        let expires_at = Utc::now() + self.reservation_config.timeout;
+        let reservation = doc! {
+            "account_id": account_id,
+            "amount": req.amount,
+            "fee": fee_quote.fee,
+            "fee_rule_name": &fee_quote.rule_name,
+            "channel": &channel,
+            "terminal_id": &req.terminal_id,
+            "status": ReservationStatus::Reserved.to_string(),
+            "expires_at": DateTime::from_millis(expires_at.timestamp_millis()),
+            "usage_recorded": true,
+            "created_at": created_at,
+        };
+
+        let reservation_id = match self
+            .reservations_collection()
+            .insert_one(reservation, None)
+            .await
+        {
+            Ok(result) => result.inserted_id.as_object_id().unwrap(),
+            Err(e) => {
+                self.release_funds(account_id, amount).await?;
+                return Err(Status::internal(format!(
+                    "Failed to create reservation: {}",
+                    e
+                )));
+            }
+        };
+
+        limits::record_usage(
+            &self.db,
+            account_id,
+            limits::OPERATION_WITHDRAWAL,
+            &channel,
+            req.amount,
+        )
+        .await?;
+
+        info!(
+            "Reserved withdrawal {} of {} on account {} until {}",
+            reservation_id, req.amount, account_id, expires_at
+        );
+
+        self.get_reservation(&reservation_id.to_hex()).await
+    }
+
+    // Books the withdrawal once the cash has been dispensed. Returns the
+    // transaction id and the fee charged.
+    pub async fn confirm_withdrawal(&self, reservation_id: &str) -> Result<(String, f64), Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+        let transactions_collection: Collection<Document> = self.db.collection("transactions");
+
+        let reservation = self.get_reservation(reservation_id).await?;
+        let reservation_id = reservation.get_object_id("_id").unwrap();
+        let fee = reservation.get_f64("fee").unwrap_or_default();
+
+        // Machines retry confirmations they got no answer for
+        if reservation.get_str("status") == Ok("CONFIRMED") {
+            if let Ok(transaction_id) = reservation.get_str("transaction_id") {
+                return Ok((transaction_id.to_string(), fee));
+            }
+        }
+
+        // Confirming, booking the withdrawal and its event happen atomically
+        let mut session = self.start_transaction().await?;
+
+        let update_result = self
+            .reservations_collection()
+            .update_one_with_session(
+                doc! {
+                    "_id": reservation_id,
+                    "status": ReservationStatus::Reserved.to_string(),
+                    "expires_at": { "$gt": DateTime::now() },
+                },
+                doc! {
+                    "$set": {
+                        "status": ReservationStatus::Confirmed.to_string(),
+                        "updated_at": DateTime::now(),
+                    }
+                },
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update reservation: {}", e)))?;
+        if update_result.modified_count == 0 {
+            let status = reservation.get_str("status").unwrap_or_default();
+            return Err(Status::failed_precondition(if status == "RESERVED" {
+                "Reservation has expired".to_string()
+            } else {
+                format!("Reservation is {}", status)
+            }));
+        }
+
+        let account_id = reservation.get_object_id("account_id").unwrap();
+        let amount = reservation.get_f64("amount").unwrap();
+
+        // The whole reservation is released; fee_engine charges the fee below
+        let account_doc = accounts_collection
+            .find_one_and_update_with_session(
+                doc! { "_id": account_id },
+                doc! {
+                    "$inc": { "balance": -amount, "held_amount": -reserved_amount(&reservation) }
+                },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::Before)
+                    .build(),
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update account balance: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        let timestamp = DateTime::now();
+        let new_transaction = doc! {
+            "account_id": account_id,
+            "amount": amount,
+            "type": "Withdrawal",
+            "reservation_id": reservation_id,
+            "timestamp": timestamp,
+        };
+        let transaction_id = transactions_collection
+            .insert_one_with_session(new_transaction, None, &mut session)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?
+            .inserted_id
+            .as_object_id()
+            .unwrap()
+            .to_string();
+
+        self.reservations_collection()
+            .update_one_with_session(
+                doc! { "_id": reservation_id },
+                doc! { "$set": { "transaction_id": &transaction_id } },
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to update reservation: {}", e)))?;
+
+        // The fee quoted at reserve time is charged with the withdrawal
+        if let Some(fee_income_account_id) = self.fee_income_account_id {
+            let fee_quote = fee_engine::FeeQuote {
+                fee,
+                rule_name: reservation
+                    .get_str("fee_rule_name")
+                    .unwrap_or_default()
+                    .to_string(),
+                ..Default::default()
+            };
+            fee_engine::post_fee(
+                &self.db,
+                &mut session,
+                fee_income_account_id,
+                account_id,
+                OPERATION_WITHDRAWAL,
+                &fee_quote,
+                &transaction_id,
+            )
+            .await?;
+            fee_engine::record_usage(&self.db, &mut session, account_id, OPERATION_WITHDRAWAL)
+                .await?;
+        }
+
+        let event = self.funds_withdrawn_event(&account_doc, &transaction_id, amount, timestamp);
+        self.record_events(&mut session, vec![event]).await?;
+        self.commit_transaction(&mut session).await?;
+
+        // Reservations made before usage was counted at reserve time
+        if reservation.get_bool("usage_recorded") != Ok(true) {
+            limits::record_usage(
+                &self.db,
+                account_id,
+                limits::OPERATION_WITHDRAWAL,
+                reservation.get_str("channel").unwrap_or_default(),
+                amount,
+            )
+            .await?;
+        }
+
+        let previous_balance = account_doc.get_f64("balance").unwrap();
+        overdraft::notify_overdraft(
+            &self.db,
+            &account_doc,
+            previous_balance,
+            previous_balance - amount - fee,
+        )
+        .await?;
+
+        info!(
+            "Confirmed withdrawal {} as transaction {}",
+            reservation_id, transaction_id
+        );
+
+        Ok((transaction_id, fee))
+    }
+
+    // Cancelling is idempotent: a reservation that already ended is returned as is
+    pub async fn cancel_withdrawal(
+        &self,
+        reservation_id: &str,
+        reason: &str,
+    ) -> Result<Document, Status> {
+        let reservation = self.get_reservation(reservation_id).await?;
+
+        if reservation.get_str("status") == Ok("CONFIRMED") {
+            return Err(Status::failed_precondition(
+                "Reservation has already been confirmed",
+            ));
+        }
+
+        if self
+            .end_reservation(&reservation, ReservationStatus::Cancelled, reason)
+            .await?
+        {
+            info!(
+                "Cancelled withdrawal {}: {}",
+                reservation.get_object_id("_id").unwrap(),
+                reason
+            );
+        }
+
+        self.get_reservation(reservation_id).await
+    }
+
+    pub async fn expire_reservations(&self) -> Result<(), Status> {
+        let expired_reservations: Vec<Document> = self
+            .reservations_collection()
+            .find(
+                doc! {
+                    "status": ReservationStatus::Reserved.to_string(),
+                    "expires_at": { "$lte": DateTime::now() },
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get reservations: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get reservations: {}", e)))?;
+
+        for reservation in expired_reservations {
+            if self
+                .end_reservation(
+                    &reservation,
+                    ReservationStatus::Expired,
+                    "Not confirmed in time",
+                )
+                .await?
+            {
+                info!(
+                    "Withdrawal reservation {} expired",
+                    reservation.get_object_id("_id").unwrap()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn run_reservation_expiry(self) {
+        info!(
+            "Withdrawal reservation expiry started, running every {:?}",
+            self.reservation_config.expiry_interval
+        );
+
+        let mut interval = tokio::time::interval(self.reservation_config.expiry_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.expire_reservations().await {
+                error!("Failed to expire withdrawal reservations: {:?}", e);
+            }
+        }
+    }
+}
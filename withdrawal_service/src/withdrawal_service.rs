@@ -1,12 +1,22 @@
 use tonic::{Request, Response, Status};
-use std::{str::FromStr, sync::Arc};
+use std::sync::Arc;
 
 use mongodb::{
     Collection,
-    bson::{doc, Document, oid::ObjectId},
+    bson::{doc, DateTime, Document, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
     {options::ClientOptions, Client}
 };
 
+use bank_common::account_number;
+use bank_common::fee_engine::{self, FeeQuote, OPERATION_WITHDRAWAL};
+use bank_common::health::Dependency;
+use bank_common::limits::{self, LimitStatus, Limits};
+use bank_common::outbox::{OutboxConfig, OutboxService};
+use bank_common::overdraft;
+use crate::reservation::{self, ReservationConfig};
+use bank_common::validation::{self, ValidationPolicy, Validator};
+
 pub mod withdrawal {
     tonic::include_proto!("withdrawal");
 }
@@ -14,26 +24,181 @@ pub mod withdrawal {
 use withdrawal::withdrawal_service_server::WithdrawalService;
 use withdrawal::{
     MakeWithdrawalRequest, MakeWithdrawalResponse,
-    CheckAccountBalanceRequest, CheckAccountBalanceResponse
+    CheckAccountBalanceRequest, CheckAccountBalanceResponse,
+    PreviewWithdrawalFeeRequest, PreviewWithdrawalFeeResponse,
+    GetWithdrawalLimitsRequest, GetWithdrawalLimitsResponse,
+    SetWithdrawalLimitsRequest, SetWithdrawalLimitsResponse, TransactionLimits,
+    ReserveWithdrawalRequest, ReserveWithdrawalResponse,
+    ConfirmWithdrawalRequest, ConfirmWithdrawalResponse,
+    CancelWithdrawalRequest, CancelWithdrawalResponse
 };
 
+fn transaction_limits(status: &LimitStatus) -> TransactionLimits {
+    TransactionLimits {
+        channel: status.channel.clone(),
+        per_transaction_limit: status.effective.per_transaction.unwrap_or_default(),
+        daily_limit: status.effective.daily.unwrap_or_default(),
+        monthly_limit: status.effective.monthly.unwrap_or_default(),
+        daily_used: status.usage.daily,
+        monthly_used: status.usage.monthly,
+        daily_remaining: status.daily_remaining().unwrap_or_default(),
+        monthly_remaining: status.monthly_remaining().unwrap_or_default(),
+        max_per_transaction_limit: status.maximum.per_transaction.unwrap_or_default(),
+        max_daily_limit: status.maximum.daily.unwrap_or_default(),
+        max_monthly_limit: status.maximum.monthly.unwrap_or_default(),
+    }
+}
+
+// A withdrawal that passed the checks shared by immediate and reserved
+// withdrawals, with the fee it will be charged
+pub struct CheckedWithdrawal {
+    pub account_id: ObjectId,
+    pub account_doc: Document,
+    pub channel: String,
+    pub fee_quote: FeeQuote,
+}
+
 #[derive(Debug, Clone)]
 pub struct MyWithdrawalService {
-    db: Arc<mongodb::Database>
+    pub(crate) client: Client,
+    pub(crate) db: Arc<mongodb::Database>,
+    pub(crate) fee_income_account_id: Option<ObjectId>,
+    pub(crate) validation_policy: ValidationPolicy,
+    pub(crate) reservation_config: ReservationConfig,
+    pub(crate) outbox_config: OutboxConfig,
 }
 
 impl MyWithdrawalService {
-    pub async fn new(uri: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub async fn new(
+        uri: &str,
+        fee_income_account_id: Option<ObjectId>,
+        validation_policy: ValidationPolicy,
+        reservation_config: ReservationConfig,
+        outbox_config: OutboxConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let client_options = ClientOptions::parse(uri).await?;
         let client = Client::with_options(client_options)?;
         let db = client.database("bank");
-        Ok(Self { db: Arc::new(db) })
+        Ok(Self {
+            client,
+            db: Arc::new(db),
+            fee_income_account_id,
+            validation_policy,
+            reservation_config,
+            outbox_config,
+        })
     }
 
     pub async fn test_connection(&self) -> Result<(), mongodb::error::Error> {
         let _ = self.db.run_command(doc! { "ping": 1 }, None).await?;
         Ok(())
     }
+
+    // Accounts can be referenced either by their id or by their IBAN-style
+    // account number
+    async fn resolve_account_id(&self, account: &str) -> Result<ObjectId, Status> {
+        account_number::resolve_account_id(&self.db, account).await
+    }
+
+    async fn get_account(&self, account: &str) -> Result<Document, Status> {
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        let object_id = self.resolve_account_id(account).await?;
+
+        accounts_collection
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))
+    }
+
+    pub async fn check_fee_income_account(&self) -> Result<(), Status> {
+        match self.fee_income_account_id {
+            Some(fee_income_account_id) => {
+                fee_engine::check_fee_income_account(&self.db, fee_income_account_id).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Fees are only charged when a fee-income account is configured
+    async fn quote_withdrawal_fee(
+        &self,
+        account_doc: &Document,
+        amount: f64,
+    ) -> Result<FeeQuote, Status> {
+        if self.fee_income_account_id.is_none() {
+            return Ok(FeeQuote::default());
+        }
+
+        fee_engine::quote_fee(&self.db, account_doc, OPERATION_WITHDRAWAL, amount).await
+    }
+
+    pub async fn check_withdrawal(
+        &self,
+        account_id: &str,
+        amount: f64,
+        channel: &str,
+        currency: &str,
+    ) -> Result<CheckedWithdrawal, Status> {
+        // Rejects negative, non-finite and over-precise amounts before any balance is read
+        if let Some(status) = Validator::new()
+            .account("account_id", account_id)
+            .amount("amount", amount)
+            .currency("currency", currency, &self.validation_policy)
+            .error()
+        {
+            return Err(status);
+        }
+
+        let account_doc = self.get_account(account_id).await?;
+        let account_id = account_doc.get_object_id("_id").unwrap();
+        if account_doc.get_str("status") == Ok("CLOSED") {
+            return Err(Status::failed_precondition("Account is closed"));
+        }
+
+        // Cash is always withdrawn in the currency the account is held in
+        let account_currency = account_doc
+            .get_str("currency")
+            .unwrap_or(&self.validation_policy.default_currency);
+        if !currency.is_empty() && !currency.eq_ignore_ascii_case(account_currency) {
+            return Err(validation::invalid_field(
+                "currency",
+                &format!("Withdrawals are made in the account currency ({})", account_currency),
+            ));
+        }
+
+        let channel = channel.to_string();
+        let limit_status = limits::limit_status(
+            &self.db,
+            &account_doc,
+            limits::OPERATION_WITHDRAWAL,
+            &channel,
+        )
+        .await?;
+        limit_status.check(amount).map_err(Status::failed_precondition)?;
+
+        let fee_quote = self.quote_withdrawal_fee(&account_doc, amount).await?;
+
+        if overdraft::available_funds(&account_doc) < amount + fee_quote.fee {
+            return Err(Status::failed_precondition("Insufficient balance for withdrawal"));
+        }
+
+        Ok(CheckedWithdrawal { account_id, account_doc, channel, fee_quote })
+    }
+}
+
+#[tonic::async_trait]
+impl Dependency for MyWithdrawalService {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.test_connection()
+            .await
+            .map_err(|e| format!("Failed to ping MongoDB: {}", e))
+    }
 }
 
 #[tonic::async_trait]
@@ -45,58 +210,111 @@ impl WithdrawalService for MyWithdrawalService {
         let req = request.into_inner();
         let accounts_collection: Collection<Document> = self.db.collection("accounts");
         let transactions_collection: Collection<Document> = self.db.collection("transactions");
-    
-        let object_id = match ObjectId::from_str(&req.account_id) {
-            Ok(oid) => oid,
-            Err(_) => return Err(Status::invalid_argument("Invalid account id")),
+
+        let CheckedWithdrawal {
+            account_id: object_id,
+            account_doc,
+            channel,
+            ..
+        } = self
+            .check_withdrawal(&req.account_id, req.amount, limits::ONLINE_CHANNEL, &req.currency)
+            .await?;
+
+        // The balance, the transaction, its fee and its event are written atomically
+        let mut session = self.start_transaction().await?;
+
+        // The fee is quoted again in the transaction, so the free quota it
+        // uses is counted by the same commit as the withdrawal and its fee
+        let fee_quote = match self.fee_income_account_id {
+            Some(_) => {
+                fee_engine::quote_fee_with_session(
+                    &self.db,
+                    &mut session,
+                    &account_doc,
+                    OPERATION_WITHDRAWAL,
+                    req.amount,
+                )
+                .await?
+            }
+            None => FeeQuote::default(),
         };
-    
+
+        // The debit only applies while the funds are still available, so a
+        // concurrent movement cannot take the account past its limit
         let filter = doc! {
             "_id": object_id,
+            "$expr": overdraft::covers_expr(req.amount + fee_quote.fee),
         };
-    
-        let account_doc_option = accounts_collection
-            .find_one(filter.clone(), None)
+        let account_doc = accounts_collection
+            .find_one_and_update_with_session(
+                filter,
+                doc! { "$inc": { "balance": -req.amount } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::Before)
+                    .build(),
+                &mut session,
+            )
             .await
-            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?;
-    
-        let account_doc = match account_doc_option {
-            Some(doc) => doc,
-            None => return Err(Status::not_found("Account not found")),
-        };
-    
+            .map_err(|e| Status::internal(format!("Failed to update account balance: {}", e)))?
+            .ok_or_else(|| Status::failed_precondition("Insufficient balance for withdrawal"))?;
         let current_balance = account_doc.get_f64("balance").unwrap();
-        if current_balance < req.amount {
-            return Err(Status::failed_precondition("Insufficient balance for withdrawal"));
-        }
-    
-        let new_balance = current_balance - req.amount;
-        let update = doc! {
-            "$set": {
-                "balance": new_balance,
-            }
-        };
-    
-        // Update the account balance
-        accounts_collection
-            .update_one(filter, update, None)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to update account balance: {}", e)))?;
-    
+
         // Record the transaction
+        let timestamp = DateTime::now();
         let new_transaction = doc! {
             "account_id": object_id,
             "amount": req.amount,
             "type": "Withdrawal",
+            "timestamp": timestamp,
         };
-    
+
         let insert_result = transactions_collection
-            .insert_one(new_transaction, None)
+            .insert_one_with_session(new_transaction, None, &mut session)
             .await
             .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?;
-    
+
+        let transaction_id = insert_result.inserted_id.as_object_id().unwrap().to_string();
+
+        if let Some(fee_income_account_id) = self.fee_income_account_id {
+            fee_engine::post_fee(
+                &self.db,
+                &mut session,
+                fee_income_account_id,
+                object_id,
+                OPERATION_WITHDRAWAL,
+                &fee_quote,
+                &transaction_id,
+            )
+            .await?;
+            fee_engine::record_usage(&self.db, &mut session, object_id, OPERATION_WITHDRAWAL)
+                .await?;
+        }
+
+        let event =
+            self.funds_withdrawn_event(&account_doc, &transaction_id, req.amount, timestamp);
+        self.record_events(&mut session, vec![event]).await?;
+        self.commit_transaction(&mut session).await?;
+
+        limits::record_usage(
+            &self.db,
+            object_id,
+            limits::OPERATION_WITHDRAWAL,
+            &channel,
+            req.amount,
+        )
+        .await?;
+
+        overdraft::notify_overdraft(
+            &self.db,
+            &account_doc,
+            current_balance,
+            current_balance - req.amount - fee_quote.fee,
+        )
+        .await?;
+
         let response = MakeWithdrawalResponse {
-            transaction_id: insert_result.inserted_id.as_object_id().unwrap().to_string(),
+            transaction_id,
+            fee: fee_quote.fee,
         };
     
         Ok(Response::new(response))
@@ -109,10 +327,7 @@ impl WithdrawalService for MyWithdrawalService {
         let req = request.into_inner();
         let accounts_collection: Collection<Document> = self.db.collection("accounts");
 
-        let object_id = match ObjectId::from_str(&req.account_id) {
-            Ok(oid) => oid,
-            Err(_) => return Err(Status::invalid_argument("Invalid account id")),
-        };
+        let object_id = self.resolve_account_id(&req.account_id).await?;
 
         let filter = doc! {
             "_id": object_id,
@@ -133,4 +348,152 @@ impl WithdrawalService for MyWithdrawalService {
             Err(Status::not_found("Account not found"))
         }
     }
+
+    async fn preview_withdrawal_fee(
+        &self,
+        request: Request<PreviewWithdrawalFeeRequest>,
+    ) -> Result<Response<PreviewWithdrawalFeeResponse>, Status> {
+        let req = request.into_inner();
+        let accounts_collection: Collection<Document> = self.db.collection("accounts");
+
+        if let Some(status) = Validator::new()
+            .account("account_id", &req.account_id)
+            .amount("amount", req.amount)
+            .error()
+        {
+            return Err(status);
+        }
+
+        let object_id = self.resolve_account_id(&req.account_id).await?;
+
+        let account_doc = accounts_collection
+            .find_one(doc! { "_id": object_id }, None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to get account: {}", e)))?
+            .ok_or_else(|| Status::not_found("Account not found"))?;
+
+        let quote = self.quote_withdrawal_fee(&account_doc, req.amount).await?;
+
+        let response = PreviewWithdrawalFeeResponse {
+            fee: quote.fee,
+            rule_name: quote.rule_name,
+            free_operations_remaining: quote.free_operations_remaining,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_withdrawal_limits(
+        &self,
+        request: Request<GetWithdrawalLimitsRequest>,
+    ) -> Result<Response<GetWithdrawalLimitsResponse>, Status> {
+        let req = request.into_inner();
+
+        let account_doc = self.get_account(&req.account_id).await?;
+
+        // Without a channel the limits of every channel are returned
+        let channels = if req.channel.is_empty() {
+            limits::CHANNELS.iter().map(|channel| channel.to_string()).collect()
+        } else {
+            vec![limits::normalize_channel(&req.channel).map_err(Status::invalid_argument)?]
+        };
+
+        let mut withdrawal_limits = Vec::new();
+        for channel in channels {
+            let limit_status = limits::limit_status(
+                &self.db,
+                &account_doc,
+                limits::OPERATION_WITHDRAWAL,
+                &channel,
+            )
+            .await?;
+            withdrawal_limits.push(transaction_limits(&limit_status));
+        }
+
+        let response = GetWithdrawalLimitsResponse {
+            limits: withdrawal_limits,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn set_withdrawal_limits(
+        &self,
+        request: Request<SetWithdrawalLimitsRequest>,
+    ) -> Result<Response<SetWithdrawalLimitsResponse>, Status> {
+        let req = request.into_inner();
+
+        let account_doc = self.get_account(&req.account_id).await?;
+        let channel = limits::normalize_channel(&req.channel).map_err(Status::invalid_argument)?;
+
+        limits::set_user_limits(
+            &self.db,
+            &req.user_id,
+            &account_doc,
+            limits::OPERATION_WITHDRAWAL,
+            &channel,
+            Limits::new(req.per_transaction_limit, req.daily_limit, req.monthly_limit),
+        )
+        .await?;
+
+        let limit_status = limits::limit_status(
+            &self.db,
+            &account_doc,
+            limits::OPERATION_WITHDRAWAL,
+            &channel,
+        )
+        .await?;
+
+        let response = SetWithdrawalLimitsResponse {
+            limits: Some(transaction_limits(&limit_status)),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn reserve_withdrawal(
+        &self,
+        request: Request<ReserveWithdrawalRequest>,
+    ) -> Result<Response<ReserveWithdrawalResponse>, Status> {
+        let req = request.into_inner();
+
+        let reservation = self.reserve_withdrawal(&req).await?;
+
+        let response = ReserveWithdrawalResponse {
+            reservation: Some(reservation::reservation_to_proto(&reservation)),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn confirm_withdrawal(
+        &self,
+        request: Request<ConfirmWithdrawalRequest>,
+    ) -> Result<Response<ConfirmWithdrawalResponse>, Status> {
+        let req = request.into_inner();
+
+        let (transaction_id, fee) = self.confirm_withdrawal(&req.reservation_id).await?;
+
+        let response = ConfirmWithdrawalResponse {
+            transaction_id,
+            fee,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn cancel_withdrawal(
+        &self,
+        request: Request<CancelWithdrawalRequest>,
+    ) -> Result<Response<CancelWithdrawalResponse>, Status> {
+        let req = request.into_inner();
+
+        let reservation = self.cancel_withdrawal(&req.reservation_id, &req.reason).await?;
+
+        let response = CancelWithdrawalResponse {
+            reservation: Some(reservation::reservation_to_proto(&reservation)),
+        };
+
+        Ok(Response::new(response))
+    }
 }